@@ -5,7 +5,7 @@ use anyhow::anyhow;
 use phantom::{
 	app::{MouseOrbit, Resources, State, StateResult, Transition},
 	gui::{
-		egui::{self, global_dark_light_mode_switch, menu, LayerId, SelectableLabel, Ui},
+		egui::{self, global_dark_light_mode_switch, menu, SelectableLabel, Ui},
 		egui_gizmo::{GizmoMode, GizmoOrientation},
 		GizmoWidget,
 	},
@@ -13,7 +13,9 @@ use phantom::{
 		legion::EntityStore,
 		nalgebra_glm as glm,
 		petgraph::{graph::NodeIndex, Direction::Outgoing},
-		Ecs, Entity, EntitySceneGraph, Name, RigidBody, Transform,
+		rapier3d::geometry::InteractionGroups,
+		Camera, Decal, Ecs, Entity, EntitySceneGraph, Environment, Fog, MouseRayConfiguration,
+		Name, RenderTarget, RigidBody, Transform, Viewport,
 	},
 };
 use rfd::FileDialog;
@@ -24,6 +26,7 @@ pub struct Editor {
 	selected_entities: Vec<Entity>,
 	commands: CommandList,
 	gizmo: GizmoWidget,
+	viewport: Viewport,
 }
 impl Default for Editor {
 	fn default() -> Self {
@@ -32,11 +35,14 @@ impl Default for Editor {
 			selected_entities: Vec::new(),
 			commands: CommandList::default(),
 			gizmo: GizmoWidget::new(),
+			viewport: Viewport::default(),
 		}
 	}
 }
 
 impl Editor {
+	const VIEWPORT_TARGET: &'static str = "Editor Viewport";
+
 	fn top_panel(&mut self, resources: &mut Resources) {
 		let ctx = &resources.gui.context.clone();
 		egui::TopBottomPanel::top("top_panel")
@@ -98,6 +104,38 @@ impl Editor {
 						}
 					});
 
+					ui.menu_button("View", |ui| {
+						ui.checkbox(
+							&mut resources.config.graphics.editor_icons,
+							"Light and Camera Icons",
+						);
+						let selection_outline = &mut resources.config.graphics.selection_outline;
+						ui.checkbox(&mut selection_outline.enabled, "Selection Outline");
+						ui.add(
+							egui::Slider::new(&mut selection_outline.width, 1.0..=16.0)
+								.text("Outline Width"),
+						);
+						ui.checkbox(
+							&mut resources.config.graphics.occlusion_culling,
+							"Occlusion Culling",
+						);
+
+						let ambient_occlusion = &mut resources.config.graphics.ambient_occlusion;
+						ui.checkbox(&mut ambient_occlusion.enabled, "Ambient Occlusion");
+						ui.add(
+							egui::Slider::new(&mut ambient_occlusion.radius, 0.05..=2.0)
+								.text("AO Radius"),
+						);
+						ui.add(
+							egui::Slider::new(&mut ambient_occlusion.intensity, 0.0..=4.0)
+								.text("AO Intensity"),
+						);
+						ui.add(
+							egui::Slider::new(&mut ambient_occlusion.sample_count, 1..=64)
+								.text("AO Samples"),
+						);
+					});
+
 					ui.add_enabled_ui(self.commands.has_undo_commands(), |ui| {
 						if ui.button("Undo").clicked() {
 							self.commands.undo(resources).unwrap();
@@ -120,10 +158,19 @@ impl Editor {
 			.show(ctx, |ui| {
 				ui.heading("Tools");
 
+				if ui.button("Add Decal").clicked() {
+					self.add_decal(resources)
+						.expect("Failed to add a decal to the scene!");
+				}
+
 				ui.heading("Scene Explorer");
 
 				ui.label(format!("Scene Name: {}", &resources.world.scene.name));
 
+				ui.collapsing("Environment", |ui| {
+					environment_editor(ui, &mut resources.world.scene.environment);
+				});
+
 				ui.heading("Entities");
 
 				let scene = &mut resources.world.scene;
@@ -217,43 +264,129 @@ impl Editor {
 	}
 
 	fn viewport_panel(&mut self, resources: &mut Resources) {
-		let context = &resources.gui.context;
-
-		egui::Area::new("Viewport")
-			.fixed_pos((0.0, 0.0))
-			.show(context, |ui| {
-				ui.with_layer_id(LayerId::background(), |ui| {
-					for entity in self.selected_entities.iter() {
-						let (projection, view) = resources
-							.world
-							.active_camera_matrices(resources.system.aspect_ratio())
-							.expect("Failed to get camera matrices!");
-						let transform = resources
-							.world
-							.entity_global_transform(*entity)
-							.expect("Failed to get entity transform!");
-						if let Some(gizmo_result) =
-							self.gizmo.render(ui, transform.matrix(), view, projection)
-						{
-							let model_matrix: glm::Mat4 =
-								gizmo_result.transform_cols_array_2d().into();
-							let gizmo_transform = Transform::from(model_matrix);
-							let mut entry = resources.world.ecs.entry_mut(*entity).unwrap();
-							let mut transform = entry.get_component_mut::<Transform>().unwrap();
-							transform.translation = gizmo_transform.translation;
-							transform.rotation = gizmo_transform.rotation;
-							transform.scale = gizmo_transform.scale;
-							if entry.get_component::<RigidBody>().is_ok() {
-								resources
-									.world
-									.sync_rigid_body_to_transform(*entity)
-									.expect("Failed to sync rigid body to transform!");
-							}
+		let context = resources.gui.context.clone();
+
+		egui::CentralPanel::default()
+			.frame(egui::Frame::none())
+			.show(&context, |ui| {
+				let rect = ui.available_rect_before_wrap();
+				let pixels_per_point = context.pixels_per_point();
+				self.viewport = Viewport {
+					x: rect.left() * pixels_per_point,
+					y: rect.top() * pixels_per_point,
+					width: rect.width() * pixels_per_point,
+					height: rect.height() * pixels_per_point,
+				};
+				self.update_viewport_target(resources)
+					.expect("Failed to update the viewport render target!");
+
+				let response = resources
+					.renderer
+					.render_target_texture_id(Self::VIEWPORT_TARGET)
+					.map(|texture_id| {
+						ui.add(
+							egui::Image::new(texture_id, rect.size()).sense(egui::Sense::click()),
+						)
+					});
+
+				let mut gizmo_active = false;
+				for entity in self.selected_entities.iter() {
+					let (projection, view) = resources
+						.world
+						.active_camera_matrices(self.viewport.aspect_ratio())
+						.expect("Failed to get camera matrices!");
+					let transform = resources
+						.world
+						.entity_global_transform(*entity)
+						.expect("Failed to get entity transform!");
+					if let Some(gizmo_result) =
+						self.gizmo.render(ui, transform.matrix(), view, projection)
+					{
+						gizmo_active = true;
+						let model_matrix: glm::Mat4 = gizmo_result.transform_cols_array_2d().into();
+						let gizmo_transform = Transform::from(model_matrix);
+						let mut entry = resources.world.ecs.entry_mut(*entity).unwrap();
+						let mut transform = entry.get_component_mut::<Transform>().unwrap();
+						transform.translation = gizmo_transform.translation;
+						transform.rotation = gizmo_transform.rotation;
+						transform.scale = gizmo_transform.scale;
+						if entry.get_component::<RigidBody>().is_ok() {
+							resources
+								.world
+								.sync_rigid_body_to_transform(*entity)
+								.expect("Failed to sync rigid body to transform!");
 						}
 					}
-				});
+				}
+
+				let clicked = response.map_or(false, |response| response.clicked());
+				if clicked && !gizmo_active {
+					self.pick_entity(resources)
+						.expect("Failed to pick an entity in the viewport!");
+				}
 			});
 	}
+
+	fn pick_entity(&mut self, resources: &mut Resources) -> StateResult<()> {
+		let configuration = self.mouse_ray_configuration(resources)?;
+		// Decals have no colliders, so their boxes are picked separately
+		let picked_entity = match resources.world.pick_decal(&configuration)? {
+			Some(entity) => Some(entity),
+			None => {
+				resources
+					.world
+					.pick_object(&configuration, f32::MAX, InteractionGroups::all())?
+			}
+		};
+		if let Some(entity) = picked_entity {
+			self.selected_entities = vec![entity];
+		}
+		Ok(())
+	}
+
+	fn add_decal(&mut self, resources: &mut Resources) -> StateResult<()> {
+		let entity = resources.world.ecs.push((
+			Name("Decal".to_string()),
+			Transform::default(),
+			Decal::default(),
+		));
+		resources
+			.world
+			.scene
+			.default_scenegraph_mut()?
+			.add_root_node(entity);
+		self.selected_entities = vec![entity];
+		Ok(())
+	}
+
+	/// Points the main camera at the offscreen texture shown in the viewport panel
+	fn update_viewport_target(&self, resources: &mut Resources) -> StateResult<()> {
+		if !resources.world.active_camera_is_main()? {
+			return Ok(());
+		}
+		let camera_entity = resources.world.active_camera()?;
+		let mut entry = resources.world.ecs.entry_mut(camera_entity)?;
+		let camera = entry.get_component_mut::<Camera>()?;
+		camera.target = RenderTarget::texture(
+			Self::VIEWPORT_TARGET,
+			self.viewport.width as u32,
+			self.viewport.height as u32,
+		);
+		Ok(())
+	}
+
+	/// Builds a mouse ray configuration relative to the viewport panel
+	fn mouse_ray_configuration(&self, resources: &Resources) -> StateResult<MouseRayConfiguration> {
+		let (projection_matrix, view_matrix) = resources
+			.world
+			.active_camera_matrices(self.viewport.aspect_ratio())?;
+		Ok(MouseRayConfiguration {
+			viewport: self.viewport,
+			projection_matrix,
+			view_matrix,
+			mouse_position: resources.input.mouse.position,
+		})
+	}
 }
 
 impl State for Editor {
@@ -263,6 +396,7 @@ impl State for Editor {
 
 	fn on_start(&mut self, resources: &mut Resources) -> StateResult<()> {
 		resources.config.graphics.debug_grid_active = true;
+		resources.config.graphics.editor_icons = true;
 		Ok(())
 	}
 
@@ -281,6 +415,9 @@ impl State for Editor {
 		self.right_panel(resources);
 		self.bottom_panel(resources);
 		self.viewport_panel(resources);
+		resources
+			.renderer
+			.set_outlined_entities(&self.selected_entities);
 		Ok(Transition::None)
 	}
 
@@ -298,27 +435,6 @@ impl State for Editor {
 		Ok(Transition::None)
 	}
 
-	// fn on_mouse(
-	//     &mut self,
-	//     resources: &mut Resources,
-	//     button: &MouseButton,
-	//     button_state: &ElementState,
-	// ) -> StateResult<Transition> {
-	//     log::trace!("Mouse event: {:#?} {:#?}", button, button_state);
-	//     if (MouseButton::Left, ElementState::Pressed) == (*button, *button_state) {
-	//         let interact_distance = f32::MAX;
-	//         let picked_entity = resources.world.pick_object(
-	//             &resources.mouse_ray_configuration()?,
-	//             interact_distance,
-	//             EDITOR_COLLISION_GROUP,
-	//         )?;
-	//         if let Some(entity) = picked_entity {
-	//             self.select_entity(entity, resources)?;
-	//         }
-	//     }
-	//     Ok(Transition::None)
-	// }
-
 	fn on_key(
 		&mut self,
 		_resources: &mut Resources,
@@ -349,3 +465,99 @@ impl State for Editor {
 		Ok(Transition::None)
 	}
 }
+
+fn environment_editor(ui: &mut Ui, environment: &mut Environment) {
+	color_editor(ui, "Clear Color", &mut environment.clear_color);
+	color_editor(ui, "Ambient Color", &mut environment.ambient_color);
+	ui.add(
+		egui::Slider::new(&mut environment.ambient_intensity, 0.0..=2.0).text("Ambient Intensity"),
+	);
+
+	let color = environment.fog.color();
+	let fog_presets = [
+		("No Fog", Fog::None),
+		(
+			"Linear",
+			Fog::Linear {
+				color,
+				start: 10.0,
+				end: 100.0,
+			},
+		),
+		(
+			"Exponential",
+			Fog::Exponential {
+				color,
+				density: 0.02,
+			},
+		),
+		(
+			"Height",
+			Fog::Height {
+				color,
+				density: 0.05,
+				base_height: 0.0,
+				falloff: 0.2,
+			},
+		),
+	];
+	ui.horizontal(|ui| {
+		for (label, preset) in fog_presets {
+			let selected =
+				std::mem::discriminant(&environment.fog) == std::mem::discriminant(&preset);
+			if ui.selectable_label(selected, label).clicked() && !selected {
+				environment.fog = preset;
+			}
+		}
+	});
+
+	match &mut environment.fog {
+		Fog::None => {}
+		Fog::Linear { color, start, end } => {
+			color_editor(ui, "Fog Color", color);
+			ui.add(egui::DragValue::new(start).speed(0.1).prefix("Start: "));
+			ui.add(egui::DragValue::new(end).speed(0.1).prefix("End: "));
+		}
+		Fog::Exponential { color, density } => {
+			color_editor(ui, "Fog Color", color);
+			ui.add(
+				egui::DragValue::new(density)
+					.speed(0.001)
+					.prefix("Density: "),
+			);
+		}
+		Fog::Height {
+			color,
+			density,
+			base_height,
+			falloff,
+		} => {
+			color_editor(ui, "Fog Color", color);
+			ui.add(
+				egui::DragValue::new(density)
+					.speed(0.001)
+					.prefix("Density: "),
+			);
+			ui.add(
+				egui::DragValue::new(base_height)
+					.speed(0.1)
+					.prefix("Base Height: "),
+			);
+			ui.add(
+				egui::DragValue::new(falloff)
+					.speed(0.01)
+					.prefix("Falloff: "),
+			);
+		}
+	}
+}
+
+fn color_editor(ui: &mut Ui, label: &str, color: &mut glm::Vec3) {
+	ui.horizontal(|ui| {
+		let mut rgb = [color.x, color.y, color.z];
+		if ui.color_edit_button_rgb(&mut rgb).changed() {
+			*color = glm::vec3(rgb[0], rgb[1], rgb[2]);
+		}
+		ui.label(label);
+	});
+}
@@ -2,15 +2,84 @@ use phantom::{
 	app::{MouseLook, Resources, State, StateResult, Transition},
 	window::winit::event::{ElementState, KeyboardInput, VirtualKeyCode},
 	world::{
-		legion::EntityStore, nalgebra_glm as glm, Camera, Entity, PerspectiveCamera, Projection,
-		Transform,
+		legion::EntityStore, nalgebra_glm as glm, Camera, CaptureFormat, Entity, FrameCapture,
+		PerspectiveCamera, Projection, RenderTarget, Transform, Viewport,
 	},
 };
+use std::{
+	path::{Path, PathBuf},
+	sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError},
+	time::{SystemTime, UNIX_EPOCH},
+};
 
 #[derive(Default)]
 pub struct Game {
 	player: Option<Entity>,
 	camera: MouseLook,
+	captures: Vec<(PathBuf, Receiver<FrameCapture>)>,
+	recording: Option<usize>,
+	encoder: Option<SyncSender<(PathBuf, FrameCapture)>>,
+}
+
+impl Game {
+	const SCREENSHOT_KEY: VirtualKeyCode = VirtualKeyCode::F12;
+	const RECORD_KEY: VirtualKeyCode = VirtualKeyCode::F9;
+
+	/// Finished captures queued for encoding before the game waits on the encoder
+	const ENCODER_QUEUE_LENGTH: usize = 8;
+
+	fn capture(&mut self, resources: &mut Resources, path: PathBuf) {
+		let receiver = resources.renderer.capture_frame(RenderTarget::Surface);
+		self.captures.push((path, receiver));
+	}
+
+	fn take_screenshot(&mut self, resources: &mut Resources) {
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|duration| duration.as_millis())
+			.unwrap_or_default();
+		let path = PathBuf::from(format!("screenshots/screenshot_{timestamp}.png"));
+		self.capture(resources, path);
+	}
+
+	fn toggle_recording(&mut self) {
+		self.recording = match self.recording {
+			Some(frames) => {
+				log::info!("Stopped recording after {frames} frames");
+				None
+			}
+			None => {
+				log::info!("Started recording frames to 'recording/'");
+				Some(0)
+			}
+		};
+	}
+
+	/// Hands finished captures to a single encoder thread so encoding doesn't stall the frame.
+	/// The queue is bounded, so a recording outpacing the encoder waits rather than piling up.
+	fn save_captures(&mut self) {
+		let encoder = self.encoder.get_or_insert_with(|| {
+			let (sender, receiver) =
+				sync_channel::<(PathBuf, FrameCapture)>(Self::ENCODER_QUEUE_LENGTH);
+			std::thread::spawn(move || {
+				for (path, capture) in receiver {
+					save_capture(&path, &capture);
+				}
+			});
+			sender
+		});
+		self.captures
+			.retain(|(path, receiver)| match receiver.try_recv() {
+				Ok(capture) => {
+					if encoder.send((path.clone(), capture)).is_err() {
+						log::error!("Failed to save frame capture, the encoder thread stopped");
+					}
+					false
+				}
+				Err(TryRecvError::Empty) => true,
+				Err(TryRecvError::Disconnected) => false,
+			});
+	}
 }
 
 impl State for Game {
@@ -46,6 +115,12 @@ impl State for Game {
 			update_player(resources, *player)?;
 			self.camera.update(resources, *player)?;
 		}
+		if let Some(frame) = self.recording.as_mut() {
+			let path = PathBuf::from(format!("recording/frame_{frame:05}.png"));
+			*frame += 1;
+			self.capture(resources, path);
+		}
+		self.save_captures();
 		Ok(Transition::None)
 	}
 
@@ -54,15 +129,31 @@ impl State for Game {
 		resources: &mut Resources,
 		input: KeyboardInput,
 	) -> StateResult<Transition> {
-		if let (Some(VirtualKeyCode::Escape), ElementState::Pressed) =
-			(input.virtual_keycode, input.state)
-		{
-			resources.system.exit_requested = true;
+		match (input.virtual_keycode, input.state) {
+			(Some(VirtualKeyCode::Escape), ElementState::Pressed) => {
+				resources.system.exit_requested = true;
+			}
+			(Some(Self::SCREENSHOT_KEY), ElementState::Pressed) => self.take_screenshot(resources),
+			(Some(Self::RECORD_KEY), ElementState::Pressed) => self.toggle_recording(),
+			_ => {}
 		}
 		Ok(Transition::None)
 	}
 }
 
+fn save_capture(path: &Path, capture: &FrameCapture) {
+	if let Some(parent) = path.parent() {
+		if let Err(error) = std::fs::create_dir_all(parent) {
+			log::error!("Failed to create capture directory: {error}");
+			return;
+		}
+	}
+	match capture.save(path, CaptureFormat::Png) {
+		Ok(()) => log::info!("Saved frame capture to '{}'", path.display()),
+		Err(error) => log::error!("Failed to save frame capture: {error}"),
+	}
+}
+
 fn update_player(resources: &mut Resources, entity: Entity) -> StateResult<()> {
 	let speed = 2.0 * resources.system.delta_time as f32;
 	{
@@ -115,6 +206,8 @@ fn activate_first_person(resources: &mut Resources, entity: Entity) -> StateResu
 				z_near: 0.001,
 			}),
 			enabled: true,
+			viewport: Viewport::full(),
+			target: RenderTarget::Surface,
 		});
 
 	Ok(())
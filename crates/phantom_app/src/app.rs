@@ -59,6 +59,9 @@ pub enum ApplicationError {
 	#[error("Failed to to update the gui!")]
 	UpdateGui(#[source] Box<dyn std::error::Error>),
 
+	#[error("Failed to update the world's global transforms!")]
+	UpdateGlobalTransforms(#[source] WorldError),
+
 	#[error("Failed to to resize the renderer!")]
 	ResizeRenderer(#[source] Box<dyn std::error::Error>),
 }
@@ -221,6 +224,11 @@ fn run_loop(
 				.update(&mut resources)
 				.map_err(ApplicationError::UpdateStateMachine)?;
 
+			resources
+				.world
+				.update_global_transforms()
+				.map_err(ApplicationError::UpdateGlobalTransforms)?;
+
 			let mut gui_frame_resources = GuiFrame {
 				textures_delta: &textures_delta,
 				screen_descriptor: &screen_descriptor,
@@ -9,6 +9,65 @@ pub struct Config {
 pub struct Graphics {
 	pub post_processing: PostProcessing,
 	pub debug_grid_active: bool,
+
+	/// Draws icons at lights and cameras, which are otherwise invisible
+	pub editor_icons: bool,
+
+	/// Skips drawing primitives hidden behind the previous frame's depth
+	pub occlusion_culling: bool,
+
+	pub ambient_occlusion: AmbientOcclusion,
+
+	pub selection_outline: SelectionOutline,
+}
+
+/// The outline drawn around the entities passed to the renderer for highlighting
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SelectionOutline {
+	pub enabled: bool,
+	pub color: [f32; 4],
+
+	/// Thickness in pixels
+	pub width: f32,
+
+	/// Scales the outline's alpha where the outlined entity is hidden behind other geometry
+	pub occluded_alpha: f32,
+}
+
+impl Default for SelectionOutline {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			color: [1.0, 0.6, 0.1, 1.0],
+			width: 3.0,
+			occluded_alpha: 0.3,
+		}
+	}
+}
+
+/// Screen-space ambient occlusion darkening the ambient light in creases and corners
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AmbientOcclusion {
+	pub enabled: bool,
+
+	/// World space radius of the hemisphere sampled around each pixel
+	pub radius: f32,
+
+	/// Scales how much the occluded fraction darkens ambient light
+	pub intensity: f32,
+
+	pub sample_count: u32,
+}
+
+impl Default for AmbientOcclusion {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			radius: 0.5,
+			intensity: 1.0,
+			sample_count: 16,
+		}
+	}
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
@@ -1,11 +1,18 @@
 use phantom_config::Config;
-use phantom_gui::GuiFrame;
-use phantom_world::World;
-use std::error::Error;
+use phantom_gui::{egui::TextureId, GuiFrame};
+use phantom_world::{Entity, FrameCapture, RenderTarget, World};
+use std::{error::Error, sync::mpsc::Receiver};
 
 pub trait GpuDevice {
 	fn load_world(&mut self, world: &World) -> Result<(), Box<dyn Error>>;
 	fn resize(&mut self, dimensions: [u32; 2]) -> Result<(), Box<dyn Error>>;
+	fn render_target_texture_id(&self, name: &str) -> Option<TextureId>;
+	/// Copies the next rendered frame of a target into a CPU buffer.
+	/// The receiver yields the capture once the GPU readback completes.
+	fn capture_frame(&mut self, target: RenderTarget) -> Receiver<FrameCapture>;
+	/// Outlines the entities and their descendants from the next frame on,
+	/// replacing any previously outlined entities
+	fn set_outlined_entities(&mut self, entities: &[Entity]);
 	fn render_frame(
 		&mut self,
 		world: &mut World,
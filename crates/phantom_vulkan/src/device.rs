@@ -1,6 +1,8 @@
+use phantom_gui::egui::TextureId;
 use phantom_render_traits::GpuDevice;
-use phantom_world::Viewport;
+use phantom_world::{Entity, FrameCapture, RenderTarget, Viewport};
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use std::sync::mpsc::{channel, Receiver};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -44,6 +46,17 @@ impl GpuDevice for VulkanGpuDevice {
 		Ok(())
 	}
 
+	fn render_target_texture_id(&self, _name: &str) -> Option<TextureId> {
+		None
+	}
+
+	fn capture_frame(&mut self, _target: RenderTarget) -> Receiver<FrameCapture> {
+		let (_sender, receiver) = channel();
+		receiver
+	}
+
+	fn set_outlined_entities(&mut self, _entities: &[Entity]) {}
+
 	fn render_frame(
 		&mut self,
 		_world: &mut phantom_world::World,
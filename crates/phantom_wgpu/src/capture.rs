@@ -0,0 +1,274 @@
+use phantom_world::{FrameCapture, RenderTarget};
+use std::{
+	borrow::Cow,
+	num::NonZeroU32,
+	sync::mpsc::{channel, Receiver, Sender, TryRecvError},
+};
+use wgpu::{self, BufferAsyncError, CommandEncoder, Device, TextureFormat};
+
+pub struct CaptureRequest {
+	pub target: RenderTarget,
+	pub sender: Sender<FrameCapture>,
+}
+
+/// A texture copy waiting for its readback buffer to be mapped
+pub struct PendingCapture {
+	sender: Sender<FrameCapture>,
+	buffer: wgpu::Buffer,
+	width: u32,
+	height: u32,
+	padded_bytes_per_row: u32,
+	swizzle: bool,
+	mapped: Option<Receiver<Result<(), BufferAsyncError>>>,
+}
+
+impl PendingCapture {
+	const BYTES_PER_PIXEL: u32 = 4;
+
+	/// Encodes a copy of the texture into a readback buffer,
+	/// returning `None` if the texture format can't be captured
+	pub fn new(
+		device: &Device,
+		encoder: &mut CommandEncoder,
+		texture: &wgpu::Texture,
+		format: TextureFormat,
+		(width, height): (u32, u32),
+		sender: Sender<FrameCapture>,
+	) -> Option<Self> {
+		let swizzle = match format {
+			TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+			TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+			_ => {
+				log::warn!("Frame capture is not supported for texture format {format:?}");
+				return None;
+			}
+		};
+
+		// Rows copied out of a texture must be aligned to 256 bytes
+		let unpadded_bytes_per_row = width * Self::BYTES_PER_PIXEL;
+		let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+		let padded_bytes_per_row =
+			((unpadded_bytes_per_row + alignment - 1) / alignment) * alignment;
+
+		let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Frame Capture Buffer"),
+			size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+			usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+			mapped_at_creation: false,
+		});
+
+		encoder.copy_texture_to_buffer(
+			texture.as_image_copy(),
+			wgpu::ImageCopyBuffer {
+				buffer: &buffer,
+				layout: wgpu::ImageDataLayout {
+					offset: 0,
+					bytes_per_row: NonZeroU32::new(padded_bytes_per_row),
+					rows_per_image: NonZeroU32::new(height),
+				},
+			},
+			wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+		);
+
+		Some(Self {
+			sender,
+			buffer,
+			width,
+			height,
+			padded_bytes_per_row,
+			swizzle,
+			mapped: None,
+		})
+	}
+
+	/// Requests the readback buffer be mapped. Must be called after the copy is submitted.
+	pub fn map(&mut self) {
+		if self.mapped.is_some() {
+			return;
+		}
+		let (sender, receiver) = channel();
+		self.buffer
+			.slice(..)
+			.map_async(wgpu::MapMode::Read, move |result| {
+				let _ = sender.send(result);
+			});
+		self.mapped = Some(receiver);
+	}
+
+	/// Sends the captured frame once the buffer is mapped,
+	/// returning `true` when the capture no longer needs to be polled
+	pub fn try_finish(&self) -> bool {
+		let mapped = match self.mapped.as_ref() {
+			Some(mapped) => mapped,
+			None => return false,
+		};
+		match mapped.try_recv() {
+			Ok(Ok(())) => {
+				let _ = self.sender.send(self.read_pixels());
+				self.buffer.unmap();
+				true
+			}
+			Ok(Err(error)) => {
+				log::warn!("Failed to map frame capture buffer: {error}");
+				true
+			}
+			Err(TryRecvError::Empty) => false,
+			Err(TryRecvError::Disconnected) => true,
+		}
+	}
+
+	fn read_pixels(&self) -> FrameCapture {
+		let unpadded_bytes_per_row = (self.width * Self::BYTES_PER_PIXEL) as usize;
+		let data = self.buffer.slice(..).get_mapped_range();
+		let mut pixels = Vec::with_capacity(unpadded_bytes_per_row * self.height as usize);
+		for row in data.chunks(self.padded_bytes_per_row as usize) {
+			pixels.extend_from_slice(&row[..unpadded_bytes_per_row]);
+		}
+		drop(data);
+
+		if self.swizzle {
+			pixels
+				.chunks_exact_mut(4)
+				.for_each(|pixel| pixel.swap(0, 2));
+		}
+
+		FrameCapture {
+			width: self.width,
+			height: self.height,
+			pixels,
+		}
+	}
+}
+
+/// Stands in for the surface on frames captured from surfaces that can't be copied out of.
+/// The frame is rendered here, copied for the capture, then drawn onto the surface.
+pub struct SurfaceCopy {
+	pub texture: wgpu::Texture,
+	pub view: wgpu::TextureView,
+	pub dimensions: (u32, u32),
+	bind_group: wgpu::BindGroup,
+	pipeline: wgpu::RenderPipeline,
+}
+
+impl SurfaceCopy {
+	pub fn new(device: &Device, format: TextureFormat, (width, height): (u32, u32)) -> Self {
+		let texture = device.create_texture(&wgpu::TextureDescriptor {
+			label: Some("Surface Copy Texture"),
+			size: wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+				| wgpu::TextureUsages::TEXTURE_BINDING
+				| wgpu::TextureUsages::COPY_SRC,
+			view_formats: &[],
+		});
+		let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+		let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Surface Copy Bind Group Layout"),
+			entries: &[wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Texture {
+					sample_type: wgpu::TextureSampleType::Float { filterable: false },
+					view_dimension: wgpu::TextureViewDimension::D2,
+					multisampled: false,
+				},
+				count: None,
+			}],
+		});
+		let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Surface Copy Bind Group"),
+			layout: &bind_group_layout,
+			entries: &[wgpu::BindGroupEntry {
+				binding: 0,
+				resource: wgpu::BindingResource::TextureView(&view),
+			}],
+		});
+
+		let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+			label: Some("Surface Copy Shader"),
+			source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(BLIT_SHADER_SOURCE)),
+		});
+		let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("Surface Copy Pipeline Layout"),
+			bind_group_layouts: &[&bind_group_layout],
+			push_constant_ranges: &[],
+		});
+		let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("Surface Copy Pipeline"),
+			layout: Some(&pipeline_layout),
+			vertex: wgpu::VertexState {
+				module: &shader_module,
+				entry_point: "vertex_main",
+				buffers: &[],
+			},
+			primitive: wgpu::PrimitiveState::default(),
+			depth_stencil: None,
+			multisample: wgpu::MultisampleState::default(),
+			fragment: Some(wgpu::FragmentState {
+				module: &shader_module,
+				entry_point: "fragment_main",
+				targets: &[Some(wgpu::ColorTargetState {
+					format,
+					blend: None,
+					write_mask: wgpu::ColorWrites::ALL,
+				})],
+			}),
+			multiview: None,
+		});
+
+		Self {
+			texture,
+			view,
+			dimensions: (width, height),
+			bind_group,
+			pipeline,
+		}
+	}
+
+	/// Draws the rendered frame onto the surface
+	pub fn blit(&self, encoder: &mut CommandEncoder, surface_view: &wgpu::TextureView) {
+		let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+			label: Some("Surface Copy Render Pass"),
+			color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+				view: surface_view,
+				resolve_target: None,
+				ops: wgpu::Operations {
+					load: wgpu::LoadOp::Load,
+					store: true,
+				},
+			})],
+			depth_stencil_attachment: None,
+		});
+		render_pass.set_pipeline(&self.pipeline);
+		render_pass.set_bind_group(0, &self.bind_group, &[]);
+		render_pass.draw(0..3, 0..1);
+	}
+}
+
+const BLIT_SHADER_SOURCE: &str = "
+@group(0) @binding(0)
+var frame: texture_2d<f32>;
+
+@vertex
+fn vertex_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fragment_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return textureLoad(frame, vec2<i32>(position.xy), 0);
+}
+";
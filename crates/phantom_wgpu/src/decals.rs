@@ -0,0 +1,531 @@
+use super::{
+	world::UniformBinding,
+	world_texture::{create_rgba_texture, WorldTextures},
+};
+use nalgebra_glm as glm;
+use phantom_world::{legion::IntoQuery, Decal, Entity, RenderTarget, Viewport, World};
+use std::{borrow::Cow, collections::HashMap, mem::size_of};
+use wgpu::{
+	self,
+	util::{BufferInitDescriptor, DeviceExt},
+	BindGroup, BindGroupLayout, Buffer, BufferAddress, Device, Face, Queue, RenderPass,
+	RenderPipeline, TextureFormat, TextureView,
+};
+
+pub struct DecalRender {
+	pub pipeline: RenderPipeline,
+	pub bind_group_layout: BindGroupLayout,
+	pub sampler: wgpu::Sampler,
+	pub cube_buffer: Buffer,
+	pub white_texture_view: TextureView,
+	pub flat_normal_texture_view: TextureView,
+	pub decals: HashMap<Entity, DecalBinding>,
+}
+
+impl DecalRender {
+	const CUBE_VERTEX_COUNT: u32 = 36;
+
+	pub fn new(
+		device: &Device,
+		queue: &Queue,
+		surface_format: TextureFormat,
+		uniform: &UniformBinding,
+		depth_bind_group_layout: &BindGroupLayout,
+		environment_bind_group_layout: &BindGroupLayout,
+	) -> Self {
+		let bind_group_layout = create_bind_group_layout(device);
+		let pipeline = create_pipeline(
+			device,
+			surface_format,
+			&[
+				&uniform.bind_group_layout,
+				&bind_group_layout,
+				depth_bind_group_layout,
+				environment_bind_group_layout,
+			],
+		);
+		let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("Decal Sampler"),
+			mag_filter: wgpu::FilterMode::Linear,
+			min_filter: wgpu::FilterMode::Linear,
+			..Default::default()
+		});
+		let cube_buffer = device.create_buffer_init(&BufferInitDescriptor {
+			label: Some("Decal Cube Buffer"),
+			contents: bytemuck::cast_slice(&cube_vertices()),
+			usage: wgpu::BufferUsages::VERTEX,
+		});
+		let white_texture_view = create_rgba_texture(
+			device,
+			queue,
+			"Decal Texture",
+			TextureFormat::Rgba8UnormSrgb,
+			(1, 1),
+			&[255, 255, 255, 255],
+		)
+		.create_view(&wgpu::TextureViewDescriptor::default());
+		let flat_normal_texture_view = create_rgba_texture(
+			device,
+			queue,
+			"Decal Texture",
+			TextureFormat::Rgba8Unorm,
+			(1, 1),
+			&[128, 128, 255, 255],
+		)
+		.create_view(&wgpu::TextureViewDescriptor::default());
+		Self {
+			pipeline,
+			bind_group_layout,
+			sampler,
+			cube_buffer,
+			white_texture_view,
+			flat_normal_texture_view,
+			decals: HashMap::new(),
+		}
+	}
+
+	/// Creates, recreates and frees decal bindings to match the world
+	pub fn update(
+		&mut self,
+		device: &Device,
+		queue: &Queue,
+		world: &World,
+		textures: &mut WorldTextures,
+	) {
+		let mut query = <(Entity, &Decal)>::query();
+		let decals = query
+			.iter(&world.ecs)
+			.filter(|(_, decal)| decal.enabled)
+			.map(|(entity, decal)| (*entity, decal.clone()))
+			.collect::<Vec<_>>();
+
+		self.decals.retain(|entity, _| {
+			decals
+				.iter()
+				.any(|(decal_entity, _)| decal_entity == entity)
+		});
+
+		for (entity, decal) in decals.iter() {
+			let model = match world.entity_global_transform_matrix(*entity) {
+				Ok(model) => model,
+				Err(error) => {
+					log::warn!("Failed to get decal transform: {error}");
+					continue;
+				}
+			};
+
+			let needs_binding = match self.decals.get(entity) {
+				Some(binding) => {
+					binding.base_color_texture != decal.base_color_texture
+						|| binding.normal_texture != decal.normal_texture
+				}
+				None => true,
+			};
+			if needs_binding {
+				let base_color_view = textures.view(
+					device,
+					queue,
+					world,
+					decal.base_color_texture,
+					TextureFormat::Rgba8UnormSrgb,
+					"Decal",
+				);
+				let normal_view = textures.view(
+					device,
+					queue,
+					world,
+					decal.normal_texture,
+					TextureFormat::Rgba8Unorm,
+					"Decal",
+				);
+				let binding = DecalBinding::new(device, self, decal, base_color_view, normal_view);
+				self.decals.insert(*entity, binding);
+			}
+
+			if let Some(binding) = self.decals.get(entity) {
+				let uniform = DecalUniform {
+					model,
+					inverse_model: glm::inverse(&model),
+					color: decal.color,
+					cos_fade_angle: decal.fade_angle.cos(),
+					padding: [0.0; 3],
+				};
+				queue.write_buffer(&binding.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+			}
+		}
+	}
+
+	pub fn render<'rp>(
+		&'rp self,
+		render_pass: &mut RenderPass<'rp>,
+		uniform: &'rp UniformBinding,
+		views: &[(RenderTarget, Viewport)],
+		target: &RenderTarget,
+		depth_bind_group: &'rp BindGroup,
+		environment_bind_group: &'rp BindGroup,
+	) {
+		if self.decals.is_empty() {
+			return;
+		}
+
+		render_pass.set_pipeline(&self.pipeline);
+		render_pass.set_vertex_buffer(0, self.cube_buffer.slice(..));
+		render_pass.set_bind_group(2, depth_bind_group, &[]);
+		render_pass.set_bind_group(3, environment_bind_group, &[]);
+
+		for (view_index, (view_target, viewport)) in views.iter().enumerate() {
+			if view_target != target {
+				continue;
+			}
+			render_pass.set_viewport(
+				viewport.x,
+				viewport.y,
+				viewport.width,
+				viewport.height,
+				0.0,
+				1.0,
+			);
+			let view_offset = uniform.view_offset(view_index);
+			render_pass.set_bind_group(0, &uniform.bind_group, &[view_offset]);
+
+			for binding in self.decals.values() {
+				render_pass.set_bind_group(1, &binding.bind_group, &[]);
+				render_pass.draw(0..Self::CUBE_VERTEX_COUNT, 0..1);
+			}
+		}
+	}
+}
+
+pub struct DecalBinding {
+	pub base_color_texture: Option<usize>,
+	pub normal_texture: Option<usize>,
+	pub uniform_buffer: Buffer,
+	pub bind_group: BindGroup,
+}
+
+impl DecalBinding {
+	fn new(
+		device: &Device,
+		decal_render: &DecalRender,
+		decal: &Decal,
+		base_color_view: Option<TextureView>,
+		normal_view: Option<TextureView>,
+	) -> Self {
+		let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Decal Uniform Buffer"),
+			size: size_of::<DecalUniform>() as BufferAddress,
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Decal Bind Group"),
+			layout: &decal_render.bind_group_layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: uniform_buffer.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: wgpu::BindingResource::TextureView(
+						base_color_view
+							.as_ref()
+							.unwrap_or(&decal_render.white_texture_view),
+					),
+				},
+				wgpu::BindGroupEntry {
+					binding: 2,
+					resource: wgpu::BindingResource::TextureView(
+						normal_view
+							.as_ref()
+							.unwrap_or(&decal_render.flat_normal_texture_view),
+					),
+				},
+				wgpu::BindGroupEntry {
+					binding: 3,
+					resource: wgpu::BindingResource::Sampler(&decal_render.sampler),
+				},
+			],
+		});
+
+		Self {
+			base_color_texture: decal.base_color_texture,
+			normal_texture: decal.normal_texture,
+			uniform_buffer,
+			bind_group,
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DecalUniform {
+	pub model: glm::Mat4,
+	pub inverse_model: glm::Mat4,
+	pub color: glm::Vec4,
+	pub cos_fade_angle: f32,
+	pub padding: [f32; 3],
+}
+
+/// The triangles of a unit cube centered on the origin
+fn cube_vertices() -> Vec<glm::Vec3> {
+	let corners = [
+		glm::vec3(-0.5, -0.5, -0.5),
+		glm::vec3(0.5, -0.5, -0.5),
+		glm::vec3(0.5, 0.5, -0.5),
+		glm::vec3(-0.5, 0.5, -0.5),
+		glm::vec3(-0.5, -0.5, 0.5),
+		glm::vec3(0.5, -0.5, 0.5),
+		glm::vec3(0.5, 0.5, 0.5),
+		glm::vec3(-0.5, 0.5, 0.5),
+	];
+	// Counter-clockwise when viewed from outside the cube
+	let faces: [[usize; 4]; 6] = [
+		[4, 5, 6, 7],
+		[1, 0, 3, 2],
+		[5, 1, 2, 6],
+		[0, 4, 7, 3],
+		[7, 6, 2, 3],
+		[0, 1, 5, 4],
+	];
+	faces
+		.iter()
+		.flat_map(|[a, b, c, d]| [a, b, c, a, c, d])
+		.map(|index| corners[*index])
+		.collect()
+}
+
+fn create_bind_group_layout(device: &Device) -> BindGroupLayout {
+	let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+		binding,
+		visibility: wgpu::ShaderStages::FRAGMENT,
+		ty: wgpu::BindingType::Texture {
+			sample_type: wgpu::TextureSampleType::Float { filterable: true },
+			view_dimension: wgpu::TextureViewDimension::D2,
+			multisampled: false,
+		},
+		count: None,
+	};
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Decal Bind Group Layout"),
+		entries: &[
+			wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Uniform,
+					has_dynamic_offset: false,
+					min_binding_size: wgpu::BufferSize::new(size_of::<DecalUniform>() as _),
+				},
+				count: None,
+			},
+			texture_entry(1),
+			texture_entry(2),
+			wgpu::BindGroupLayoutEntry {
+				binding: 3,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+				count: None,
+			},
+		],
+	})
+}
+
+fn create_pipeline(
+	device: &Device,
+	surface_format: TextureFormat,
+	bind_group_layouts: &[&BindGroupLayout],
+) -> RenderPipeline {
+	let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+		label: Some("Decal Shader"),
+		source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER_SOURCE)),
+	});
+
+	let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+		label: Some("Decal Pipeline Layout"),
+		bind_group_layouts,
+		push_constant_ranges: &[],
+	});
+
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some("Decal Pipeline"),
+		layout: Some(&pipeline_layout),
+		vertex: wgpu::VertexState {
+			module: &shader_module,
+			entry_point: "vertex_main",
+			buffers: &[wgpu::VertexBufferLayout {
+				array_stride: size_of::<glm::Vec3>() as wgpu::BufferAddress,
+				step_mode: wgpu::VertexStepMode::Vertex,
+				attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+			}],
+		},
+		// Drawing the back faces keeps the decal visible when the camera is inside its box
+		primitive: wgpu::PrimitiveState {
+			front_face: wgpu::FrontFace::Ccw,
+			cull_mode: Some(Face::Front),
+			..Default::default()
+		},
+		depth_stencil: Some(wgpu::DepthStencilState {
+			format: wgpu::TextureFormat::Depth32Float,
+			depth_write_enabled: false,
+			depth_compare: wgpu::CompareFunction::GreaterEqual,
+			stencil: wgpu::StencilState::default(),
+			bias: wgpu::DepthBiasState::default(),
+		}),
+		multisample: wgpu::MultisampleState::default(),
+		fragment: Some(wgpu::FragmentState {
+			module: &shader_module,
+			entry_point: "fragment_main",
+			targets: &[Some(wgpu::ColorTargetState {
+				format: surface_format,
+				blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+				write_mask: wgpu::ColorWrites::ALL,
+			})],
+		}),
+		multiview: None,
+	})
+}
+
+const SHADER_SOURCE: &str = "
+struct Light {
+    position: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Uniform {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    light: Light,
+    inverse_view_projection: mat4x4<f32>,
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> ubo: Uniform;
+
+struct Decal {
+    model: mat4x4<f32>,
+    inverse_model: mat4x4<f32>,
+    color: vec4<f32>,
+    cos_fade_angle: f32,
+    padding_0: f32,
+    padding_1: f32,
+    padding_2: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> decal: Decal;
+
+@group(1) @binding(1)
+var base_color_texture: texture_2d<f32>;
+
+@group(1) @binding(2)
+var normal_texture: texture_2d<f32>;
+
+@group(1) @binding(3)
+var decal_sampler: sampler;
+
+@group(2) @binding(0)
+var scene_depth: texture_depth_2d;
+
+struct Environment {
+    ambient: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_parameters: vec4<f32>,
+    fog_mode: u32,
+    padding_0: u32,
+    padding_1: u32,
+    padding_2: u32,
+};
+
+@group(3) @binding(0)
+var<uniform> environment: Environment;
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let offset = world_position - ubo.camera_position.xyz;
+    let distance = length(offset);
+    let parameters = environment.fog_parameters;
+    var visibility = 1.0;
+    switch (environment.fog_mode) {
+        case 1u: {
+            let range = max(parameters.y - parameters.x, 0.0001);
+            visibility = clamp((parameters.y - distance) / range, 0.0, 1.0);
+        }
+        case 2u: {
+            visibility = exp(-parameters.x * distance);
+        }
+        case 3u: {
+            // Integrates the density along the view ray as it thins out with height
+            let camera_height = ubo.camera_position.y - parameters.y;
+            let camera_density = parameters.x * exp(-parameters.z * camera_height);
+            let rise = parameters.z * offset.y;
+            var thinning = 1.0;
+            if (abs(rise) > 0.0001) {
+                thinning = (1.0 - exp(-rise)) / rise;
+            }
+            visibility = exp(-camera_density * distance * thinning);
+        }
+        default: {}
+    }
+    return mix(environment.fog_color.rgb, color, visibility);
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+};
+
+@vertex
+fn vertex_main(@location(0) position: vec3<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = ubo.projection * ubo.view * decal.model * vec4(position, 1.0);
+    return out;
+}
+
+@fragment
+fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Reconstruct the world position of the opaque surface behind this pixel
+    let depth = textureLoad(scene_depth, vec2<i32>(in.position.xy), 0);
+    let uv = (in.position.xy - ubo.viewport.xy) / ubo.viewport.zw;
+    let ndc = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = ubo.inverse_view_projection * ndc;
+    let world_position = world.xyz / world.w;
+
+    var surface_normal = normalize(cross(dpdy(world_position), dpdx(world_position)));
+    if (dot(surface_normal, ubo.camera_position.xyz - world_position) < 0.0) {
+        surface_normal = -surface_normal;
+    }
+
+    let local_position = (decal.inverse_model * vec4(world_position, 1.0)).xyz;
+    if (depth >= 1.0 || any(abs(local_position) > vec3(0.5))) {
+        discard;
+    }
+
+    let tangent = normalize(decal.model[0].xyz);
+    let bitangent = normalize(decal.model[1].xyz);
+    let projector_normal = normalize(decal.model[2].xyz);
+
+    let facing = dot(surface_normal, projector_normal);
+    let fade_range = max(1.0 - decal.cos_fade_angle, 0.0001);
+    let fade = clamp((facing - decal.cos_fade_angle) / fade_range, 0.0, 1.0);
+
+    let decal_uv = vec2(local_position.x + 0.5, 0.5 - local_position.y);
+    let base_color = textureSampleLevel(base_color_texture, decal_sampler, decal_uv, 0.0)
+        * decal.color;
+    let tangent_normal = textureSampleLevel(normal_texture, decal_sampler, decal_uv, 0.0).xyz
+        * 2.0 - 1.0;
+    let normal = normalize(
+        tangent * tangent_normal.x
+        + bitangent * tangent_normal.y
+        + projector_normal * tangent_normal.z
+    );
+
+    let ambient_color = environment.ambient.rgb;
+    let light_dir = normalize(ubo.light.position.xyz - world_position);
+    let diffuse_color = ubo.light.color.rgb * max(dot(normal, light_dir), 0.0);
+
+    let result = base_color.rgb * (ambient_color + diffuse_color);
+    return vec4(apply_fog(result, world_position), base_color.a * fade);
+}
+";
@@ -1,9 +1,18 @@
-use super::{gui::GuiRender, world::WorldRender};
+use super::{
+	capture::{CaptureRequest, PendingCapture, SurfaceCopy},
+	gui::GuiRender,
+	target::{create_depth_texture, OffscreenTarget},
+	world::WorldRender,
+};
 use phantom_config::Config;
-use phantom_gui::GuiFrame;
+use phantom_gui::{egui::TextureId, GuiFrame};
 use phantom_render_traits::GpuDevice;
-use phantom_world::{Viewport, World};
+use phantom_world::{CameraView, Entity, FrameCapture, RenderTarget, Viewport, World};
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+use std::{
+	collections::{HashMap, HashSet},
+	sync::mpsc::{channel, Receiver},
+};
 use thiserror::Error;
 use wgpu::{
 	self, Backends, Device, InstanceDescriptor, Queue, RequestDeviceError, Surface,
@@ -35,11 +44,26 @@ pub struct WgpuRenderer {
 	pub gui: GuiRender,
 	pub depth_texture_view: wgpu::TextureView,
 	pub world_render: Option<WorldRender>,
+	pub render_targets: HashMap<String, OffscreenTarget>,
+	/// Target names claimed by cameras requesting different sizes, warned about once
+	pub conflicting_targets: HashSet<String>,
+	pub capture_requests: Vec<CaptureRequest>,
+	pub pending_captures: Vec<PendingCapture>,
+	/// Whether frame captures can copy directly out of the surface texture
+	pub surface_copy_src: bool,
+	/// Renders captured frames when the surface can't be copied out of
+	pub surface_copy: Option<SurfaceCopy>,
+	pub outlined_entities: Vec<Entity>,
 }
 
 impl GpuDevice for WgpuRenderer {
 	fn load_world(&mut self, world: &World) -> Result<(), Box<dyn std::error::Error>> {
-		self.world_render = Some(WorldRender::new(&self.device, self.config.format, world));
+		self.world_render = Some(WorldRender::new(
+			&self.device,
+			&self.queue,
+			self.config.format,
+			world,
+		));
 		Ok(())
 	}
 
@@ -55,15 +79,36 @@ impl GpuDevice for WgpuRenderer {
 		self.config.width = dimensions[0];
 		self.config.height = dimensions[1];
 		self.surface.configure(&self.device, &self.config);
-		self.depth_texture_view =
-			create_depth_texture(&self.config, &self.device, Self::DEPTH_FORMAT);
+		self.depth_texture_view = create_depth_texture(
+			&self.device,
+			Self::DEPTH_FORMAT,
+			self.config.width,
+			self.config.height,
+		);
 		Ok(())
 	}
 
+	fn render_target_texture_id(&self, name: &str) -> Option<TextureId> {
+		self.render_targets
+			.get(name)
+			.map(|render_target| render_target.texture_id)
+	}
+
+	fn capture_frame(&mut self, target: RenderTarget) -> Receiver<FrameCapture> {
+		let (sender, receiver) = channel();
+		self.capture_requests
+			.push(CaptureRequest { target, sender });
+		receiver
+	}
+
+	fn set_outlined_entities(&mut self, entities: &[Entity]) {
+		self.outlined_entities = entities.to_vec();
+	}
+
 	fn render_frame(
 		&mut self,
 		world: &mut World,
-		_config: &Config,
+		config: &Config,
 		gui_frame: &mut GuiFrame,
 	) -> Result<(), Box<dyn std::error::Error>> {
 		let mut encoder = self
@@ -87,9 +132,22 @@ impl GpuDevice for WgpuRenderer {
 			screen_descriptor,
 		);
 
-		let aspect_ratio = self.aspect_ratio();
+		let mut views = world.camera_views(self.config.width as f32, self.config.height as f32)?;
+		self.sync_render_targets(&mut views);
 		if let Some(world_render) = self.world_render.as_mut() {
-			world_render.update(&self.queue, aspect_ratio, world);
+			world_render.occlusion.enabled =
+				config.graphics.occlusion_culling && world_render.indirect.mode.supports_indirect();
+			world_render.sprites.show_editor_icons = config.graphics.editor_icons;
+			world_render
+				.ssao
+				.update(&self.queue, &config.graphics.ambient_occlusion);
+			world_render.outline.update(
+				&self.queue,
+				&config.graphics.selection_outline,
+				world,
+				&self.outlined_entities,
+			);
+			world_render.update(&self.device, &self.queue, &views, world);
 		}
 
 		let surface_texture = self
@@ -101,44 +159,204 @@ impl GpuDevice for WgpuRenderer {
 			.texture
 			.create_view(&TextureViewDescriptor::default());
 
-		{
-			encoder.insert_debug_marker("Render scene");
-			let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-				label: Some("Render Pass"),
-				color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-					view: &view,
-					resolve_target: None,
-					ops: wgpu::Operations {
-						load: wgpu::LoadOp::Clear(wgpu::Color {
-							r: 0.1,
-							g: 0.2,
-							b: 0.3,
-							a: 1.0,
-						}),
-						store: true,
-					},
-				})],
-				depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-					view: &self.depth_texture_view,
-					depth_ops: Some(wgpu::Operations {
-						load: wgpu::LoadOp::Clear(1.0),
-						store: true,
+		// Surfaces that can't be copied out of are captured by rendering the frame offscreen
+		let capturing_surface = !self.surface_copy_src
+			&& self
+				.capture_requests
+				.iter()
+				.any(|request| matches!(request.target, RenderTarget::Surface));
+		let dimensions = (self.config.width, self.config.height);
+		let surface_copy = match capturing_surface {
+			true => Some(
+				self.surface_copy
+					.take()
+					.filter(|surface_copy| surface_copy.dimensions == dimensions)
+					.unwrap_or_else(|| {
+						SurfaceCopy::new(&self.device, self.config.format, dimensions)
 					}),
-					stencil_ops: None,
-				}),
-			});
+			),
+			false => None,
+		};
+		let frame_view = surface_copy
+			.as_ref()
+			.map_or(&view, |surface_copy| &surface_copy.view);
+
+		let clear_color = world.scene.environment.clear_color;
+		let clear_color = wgpu::Color {
+			r: clear_color.x as f64,
+			g: clear_color.y as f64,
+			b: clear_color.z as f64,
+			a: 1.0,
+		};
 
+		if let Some(world_render) = self.world_render.as_ref() {
+			world_render.particles.simulate(&mut encoder);
+			world_render.occlusion.cull(&self.device, &mut encoder);
+		}
+
+		for render_target in self.render_targets.values() {
+			if let Some(world_render) = self.world_render.as_mut() {
+				world_render.ssao.prepare_target(
+					&self.device,
+					&render_target.target,
+					(render_target.width, render_target.height),
+				);
+				world_render.render_ambient_occlusion(&mut encoder, &render_target.target);
+			}
+
+			encoder.insert_debug_marker("Render scene to texture");
+			{
+				let mut render_pass = begin_scene_pass(
+					&mut encoder,
+					&render_target.color_view,
+					&render_target.depth_view,
+					clear_color,
+				);
+				if let Some(world_render) = self.world_render.as_ref() {
+					world_render.render(&mut render_pass, &render_target.target)?;
+				}
+			}
+			if let Some(world_render) = self.world_render.as_mut() {
+				world_render.occlusion.build_depth_pyramid(
+					&self.device,
+					&mut encoder,
+					&render_target.target,
+					&render_target.depth_view,
+					(render_target.width, render_target.height),
+				);
+				world_render.render_disoccluded(
+					&self.device,
+					&mut encoder,
+					&render_target.target,
+					&render_target.color_view,
+					&render_target.depth_view,
+				);
+				world_render.outline.prepare_target(
+					&self.device,
+					&render_target.target,
+					(render_target.width, render_target.height),
+				);
+			}
+			if let Some(world_render) = self.world_render.as_ref() {
+				let depth_bind_group =
+					world_render.create_depth_bind_group(&self.device, &render_target.depth_view);
+				world_render.render_outline(&mut encoder, &render_target.target, &depth_bind_group);
+				let mut render_pass = begin_overlay_pass(
+					&mut encoder,
+					&render_target.color_view,
+					&render_target.depth_view,
+				);
+				world_render.render_overlays(
+					&mut render_pass,
+					&render_target.target,
+					&depth_bind_group,
+				);
+			}
+		}
+
+		if let Some(world_render) = self.world_render.as_mut() {
+			world_render.ssao.prepare_target(
+				&self.device,
+				&RenderTarget::Surface,
+				(self.config.width, self.config.height),
+			);
+			world_render.render_ambient_occlusion(&mut encoder, &RenderTarget::Surface);
+		}
+
+		encoder.insert_debug_marker("Render scene");
+		{
+			let mut render_pass = begin_scene_pass(
+				&mut encoder,
+				frame_view,
+				&self.depth_texture_view,
+				clear_color,
+			);
 			if let Some(world_render) = self.world_render.as_ref() {
-				world_render.render(&mut render_pass, world)?;
+				world_render.render(&mut render_pass, &RenderTarget::Surface)?;
 			}
+		}
 
+		if let Some(world_render) = self.world_render.as_mut() {
+			world_render.occlusion.build_depth_pyramid(
+				&self.device,
+				&mut encoder,
+				&RenderTarget::Surface,
+				&self.depth_texture_view,
+				(self.config.width, self.config.height),
+			);
+			world_render.render_disoccluded(
+				&self.device,
+				&mut encoder,
+				&RenderTarget::Surface,
+				frame_view,
+				&self.depth_texture_view,
+			);
+			world_render.outline.prepare_target(
+				&self.device,
+				&RenderTarget::Surface,
+				(self.config.width, self.config.height),
+			);
+		}
+
+		{
+			let depth_bind_group = self.world_render.as_ref().map(|world_render| {
+				world_render.create_depth_bind_group(&self.device, &self.depth_texture_view)
+			});
+			if let (Some(world_render), Some(depth_bind_group)) =
+				(self.world_render.as_ref(), depth_bind_group.as_ref())
+			{
+				world_render.render_outline(&mut encoder, &RenderTarget::Surface, depth_bind_group);
+			}
+			let mut render_pass =
+				begin_overlay_pass(&mut encoder, frame_view, &self.depth_texture_view);
+
+			if let (Some(world_render), Some(depth_bind_group)) =
+				(self.world_render.as_ref(), depth_bind_group.as_ref())
+			{
+				world_render.render_overlays(
+					&mut render_pass,
+					&RenderTarget::Surface,
+					depth_bind_group,
+				);
+			}
+
+			render_pass.set_viewport(
+				0.0,
+				0.0,
+				self.config.width as f32,
+				self.config.height as f32,
+				0.0,
+				1.0,
+			);
 			self.gui
 				.render(&mut render_pass, paint_jobs, screen_descriptor);
 		}
 
+		if let Some(surface_copy) = surface_copy.as_ref() {
+			surface_copy.blit(&mut encoder, &view);
+		}
+
+		let first_new_capture = self.pending_captures.len();
+		let captured_surface = surface_copy
+			.as_ref()
+			.map_or(&surface_texture.texture, |surface_copy| {
+				&surface_copy.texture
+			});
+		self.encode_captures(&mut encoder, captured_surface);
+		if surface_copy.is_some() {
+			self.surface_copy = surface_copy;
+		}
+
 		self.queue.submit(std::iter::once(encoder.finish()));
 		surface_texture.present();
 
+		self.pending_captures[first_new_capture..]
+			.iter_mut()
+			.for_each(PendingCapture::map);
+		self.device.poll(wgpu::Maintain::Poll);
+		self.pending_captures
+			.retain(|pending_capture| !pending_capture.try_finish());
+
 		Ok(())
 	}
 }
@@ -179,8 +397,18 @@ impl WgpuRenderer {
 			.first()
 			.ok_or(Error::NoSupportedSwapchainFormat)?;
 
+		// Frame captures copy directly out of the surface texture where the surface allows it
+		let surface_copy_src = surface
+			.get_capabilities(&adapter)
+			.usages
+			.contains(wgpu::TextureUsages::COPY_SRC);
+		let usage = match surface_copy_src {
+			true => wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+			false => wgpu::TextureUsages::RENDER_ATTACHMENT,
+		};
+
 		let config = SurfaceConfiguration {
-			usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+			usage,
 			format: swapchain_format,
 			width: viewport.width as _,
 			height: viewport.height as _,
@@ -192,7 +420,8 @@ impl WgpuRenderer {
 
 		let gui = GuiRender::new(&device, config.format, Some(Self::DEPTH_FORMAT), 1);
 
-		let depth_texture_view = create_depth_texture(&config, &device, Self::DEPTH_FORMAT);
+		let depth_texture_view =
+			create_depth_texture(&device, Self::DEPTH_FORMAT, config.width, config.height);
 
 		Ok(Self {
 			surface,
@@ -202,11 +431,117 @@ impl WgpuRenderer {
 			gui,
 			depth_texture_view,
 			world_render: None,
+			render_targets: HashMap::new(),
+			conflicting_targets: HashSet::new(),
+			capture_requests: Vec::new(),
+			pending_captures: Vec::new(),
+			surface_copy_src,
+			surface_copy: None,
+			outlined_entities: Vec::new(),
 		})
 	}
 
-	fn aspect_ratio(&self) -> f32 {
-		self.config.width as f32 / std::cmp::max(1, self.config.height) as f32
+	/// Creates, resizes and frees offscreen targets to match the cameras rendering to textures.
+	/// The first camera to name a target decides its size, and later cameras naming it
+	/// with a different size are dropped, rather than resizing the target every frame.
+	fn sync_render_targets(&mut self, views: &mut Vec<CameraView>) {
+		let Self {
+			device,
+			config,
+			gui,
+			render_targets,
+			conflicting_targets,
+			..
+		} = self;
+
+		let mut active_targets: Vec<&RenderTarget> = Vec::new();
+		let mut conflicts = Vec::new();
+		let mut conflicting_names = Vec::new();
+		for view in views.iter() {
+			let name = match &view.target {
+				RenderTarget::Surface => continue,
+				RenderTarget::Texture { name, .. } => name,
+			};
+			let claimed = active_targets.iter().find(|target| match target {
+				RenderTarget::Texture { name: claimed, .. } => claimed == name,
+				RenderTarget::Surface => false,
+			});
+			match claimed {
+				Some(target) if **target == view.target => continue,
+				Some(_) => {
+					if conflicting_targets.insert(name.to_string()) {
+						log::warn!(
+							"Cameras request different sizes for render target '{}', \
+							 only the first camera's size is rendered",
+							name
+						);
+					}
+					conflicts.push(view.entity);
+					conflicting_names.push(name.to_string());
+					continue;
+				}
+				None => active_targets.push(&view.target),
+			}
+			match render_targets.get_mut(name) {
+				Some(render_target) if render_target.target == view.target => {}
+				Some(render_target) => render_target.resize(
+					device,
+					gui,
+					config.format,
+					Self::DEPTH_FORMAT,
+					view.target.clone(),
+				),
+				None => {
+					let render_target = OffscreenTarget::new(
+						device,
+						gui,
+						config.format,
+						Self::DEPTH_FORMAT,
+						view.target.clone(),
+					);
+					render_targets.insert(name.to_string(), render_target);
+				}
+			}
+		}
+
+		render_targets.retain(|_, render_target| {
+			let active = active_targets.contains(&&render_target.target);
+			if !active {
+				gui.renderer.free_texture(&render_target.texture_id);
+			}
+			active
+		});
+		conflicting_targets.retain(|name| conflicting_names.contains(name));
+		views.retain(|view| !conflicts.contains(&view.entity));
+	}
+
+	/// Copies the textures of this frame's capture requests into readback buffers
+	fn encode_captures(&mut self, encoder: &mut wgpu::CommandEncoder, surface: &wgpu::Texture) {
+		for CaptureRequest { target, sender } in self.capture_requests.drain(..) {
+			let (texture, dimensions) = match &target {
+				RenderTarget::Surface => (surface, (self.config.width, self.config.height)),
+				RenderTarget::Texture { name, .. } => match self.render_targets.get(name) {
+					Some(render_target) => (
+						&render_target.color_texture,
+						(render_target.width, render_target.height),
+					),
+					None => {
+						log::warn!("Failed to capture frame, render target '{name}' not found");
+						continue;
+					}
+				},
+			};
+			if let Some(pending_capture) = PendingCapture::new(
+				&self.device,
+				encoder,
+				texture,
+				self.config.format,
+				dimensions,
+				sender,
+			) {
+				self.pending_captures.push(pending_capture);
+			}
+		}
 	}
 
 	fn required_limits(adapter: &wgpu::Adapter) -> wgpu::Limits {
@@ -221,7 +556,7 @@ impl WgpuRenderer {
 	}
 
 	fn optional_features() -> wgpu::Features {
-		wgpu::Features::empty()
+		wgpu::Features::MULTI_DRAW_INDIRECT | wgpu::Features::INDIRECT_FIRST_INSTANCE
 	}
 
 	async fn create_adapter(
@@ -252,29 +587,55 @@ impl WgpuRenderer {
 	}
 }
 
-fn create_depth_texture(
-	config: &SurfaceConfiguration,
-	device: &wgpu::Device,
-	format: wgpu::TextureFormat,
-) -> wgpu::TextureView {
-	let size = wgpu::Extent3d {
-		width: config.width,
-		height: config.height,
-		depth_or_array_layers: 1,
-	};
-
-	let texture_descriptor = wgpu::TextureDescriptor {
-		label: Some("Depth Texture"),
-		size,
-		mip_level_count: 1,
-		sample_count: 1,
-		dimension: wgpu::TextureDimension::D2,
-		format,
-		usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-		view_formats: &[format],
-	};
-
-	let texture = device.create_texture(&texture_descriptor);
-
-	texture.create_view(&wgpu::TextureViewDescriptor::default())
+/// Begins a pass that clears the color and depth attachments before drawing the scene
+fn begin_scene_pass<'a>(
+	encoder: &'a mut wgpu::CommandEncoder,
+	color_view: &'a wgpu::TextureView,
+	depth_view: &'a wgpu::TextureView,
+	clear_color: wgpu::Color,
+) -> wgpu::RenderPass<'a> {
+	encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+		label: Some("Scene Render Pass"),
+		color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+			view: color_view,
+			resolve_target: None,
+			ops: wgpu::Operations {
+				load: wgpu::LoadOp::Clear(clear_color),
+				store: true,
+			},
+		})],
+		depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+			view: depth_view,
+			depth_ops: Some(wgpu::Operations {
+				load: wgpu::LoadOp::Clear(1.0),
+				store: true,
+			}),
+			stencil_ops: None,
+		}),
+	})
+}
+
+/// Begins a pass drawing over the scene with a read-only depth attachment,
+/// so the depth texture can also be sampled while it is bound
+fn begin_overlay_pass<'a>(
+	encoder: &'a mut wgpu::CommandEncoder,
+	color_view: &'a wgpu::TextureView,
+	depth_view: &'a wgpu::TextureView,
+) -> wgpu::RenderPass<'a> {
+	encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+		label: Some("Overlay Render Pass"),
+		color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+			view: color_view,
+			resolve_target: None,
+			ops: wgpu::Operations {
+				load: wgpu::LoadOp::Load,
+				store: true,
+			},
+		})],
+		depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+			view: depth_view,
+			depth_ops: None,
+			stencil_ops: None,
+		}),
+	})
 }
@@ -0,0 +1,152 @@
+use super::occlusion::{CullPhase, OcclusionCulling};
+use phantom_world::EntityMetadata;
+use std::{mem::size_of, ops::Range};
+use wgpu::{self, util::DrawIndexedIndirect, Buffer, BufferAddress, Device, Queue, RenderPass};
+
+/// How primitives are submitted, chosen from the features the adapter supports
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IndirectDrawMode {
+	/// Every primitive of a view in a single call
+	MultiDraw,
+
+	/// One indirect call per primitive
+	Indirect,
+
+	/// One direct call per primitive, built on the CPU
+	Direct,
+}
+
+impl IndirectDrawMode {
+	/// Indirect draws read their draw data by instance index,
+	/// so they need first instances other than zero
+	pub fn from_features(features: wgpu::Features) -> Self {
+		if !features.contains(wgpu::Features::INDIRECT_FIRST_INSTANCE) {
+			Self::Direct
+		} else if features.contains(wgpu::Features::MULTI_DRAW_INDIRECT) {
+			Self::MultiDraw
+		} else {
+			Self::Indirect
+		}
+	}
+
+	pub fn supports_indirect(&self) -> bool {
+		*self != Self::Direct
+	}
+}
+
+/// The indirect arguments drawing every primitive of the world.
+/// Each draw's first instance is its index, which shaders use to look up its draw data.
+pub struct IndirectDraws {
+	pub mode: IndirectDrawMode,
+	pub buffer: Buffer,
+	pub capacity: usize,
+	pub number_of_draws: u32,
+}
+
+impl IndirectDraws {
+	const INITIAL_CAPACITY: usize = 1024;
+
+	pub fn new(device: &Device) -> Self {
+		let mode = IndirectDrawMode::from_features(device.features());
+		log::info!("Drawing primitives with mode: {mode:?}");
+		let capacity = Self::INITIAL_CAPACITY;
+		Self {
+			mode,
+			buffer: create_indirect_buffer(device, capacity),
+			capacity,
+			number_of_draws: 0,
+		}
+	}
+
+	/// Builds the draw arguments for this frame's primitives
+	pub fn update(&mut self, device: &Device, queue: &Queue, metadata: &[EntityMetadata]) {
+		self.number_of_draws = metadata.len() as u32;
+		if !self.mode.supports_indirect() || metadata.is_empty() {
+			return;
+		}
+
+		if metadata.len() > self.capacity {
+			self.capacity = metadata.len().next_power_of_two();
+			self.buffer = create_indirect_buffer(device, self.capacity);
+		}
+
+		let draws = metadata
+			.iter()
+			.enumerate()
+			.map(|(draw_index, entity_metadata)| DrawIndexedIndirect {
+				vertex_count: entity_metadata.index_range.len() as u32,
+				// Custom materials are drawn with their own pipelines
+				instance_count: u32::from(entity_metadata.custom_material.is_none()),
+				base_index: entity_metadata.index_range.start,
+				vertex_offset: 0,
+				base_instance: draw_index as u32,
+			})
+			.collect::<Vec<_>>();
+		let bytes = draws
+			.iter()
+			.flat_map(|draw| draw.as_bytes().iter().copied())
+			.collect::<Vec<_>>();
+		queue.write_buffer(&self.buffer, 0, &bytes);
+	}
+
+	/// Draws a range of primitives for a view with the bound pipeline,
+	/// using the arguments written by a culling phase when occlusion culling is enabled
+	pub fn draw<'rp>(
+		&'rp self,
+		render_pass: &mut RenderPass<'rp>,
+		metadata: &[EntityMetadata],
+		occlusion: &'rp OcclusionCulling,
+		phase: CullPhase,
+		view_index: usize,
+		draws: Range<u32>,
+	) {
+		let draws = draws.start..draws.end.min(self.number_of_draws);
+		if draws.is_empty() {
+			return;
+		}
+
+		let (buffer, first_offset) = if occlusion.enabled {
+			(
+				&occlusion.indirect_buffer,
+				occlusion.draw_offset(phase, view_index),
+			)
+		} else {
+			(&self.buffer, 0)
+		};
+		let stride = size_of::<DrawIndexedIndirect>() as BufferAddress;
+		let offset = first_offset + draws.start as BufferAddress * stride;
+
+		match self.mode {
+			IndirectDrawMode::MultiDraw => {
+				render_pass.multi_draw_indexed_indirect(buffer, offset, draws.len() as u32);
+			}
+			IndirectDrawMode::Indirect => {
+				for draw_index in 0..draws.len() as BufferAddress {
+					render_pass.draw_indexed_indirect(buffer, offset + draw_index * stride);
+				}
+			}
+			IndirectDrawMode::Direct => {
+				for draw_index in draws {
+					let entity_metadata = &metadata[draw_index as usize];
+					if entity_metadata.custom_material.is_some() {
+						continue;
+					}
+					render_pass.draw_indexed(
+						entity_metadata.index_range.clone(),
+						0,
+						draw_index..draw_index + 1,
+					);
+				}
+			}
+		}
+	}
+}
+
+fn create_indirect_buffer(device: &Device, capacity: usize) -> Buffer {
+	device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Indirect Draw Buffer"),
+		size: (capacity * size_of::<DrawIndexedIndirect>()) as BufferAddress,
+		usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	})
+}
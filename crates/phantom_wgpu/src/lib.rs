@@ -1,5 +1,18 @@
+mod capture;
+mod decals;
 mod device;
 mod gui;
+mod indirect;
+mod material;
+mod normal_map;
+mod occlusion;
+mod outline;
+mod particles;
+mod sprites;
+mod ssao;
+mod target;
+mod terrain;
 mod world;
+mod world_texture;
 
 pub use self::device::*;
@@ -0,0 +1,456 @@
+use super::world::{
+	create_vertex_attributes, create_vertex_description, DrawBinding, UniformBinding,
+};
+use phantom_world::{AlphaMode, CustomMaterial, EntityMetadata, RenderTarget, Viewport, World};
+use std::{borrow::Cow, collections::HashMap};
+use wgpu::{
+	self, BindGroup, BindGroupLayout, Buffer, BufferAddress, Device, Face, PipelineLayout, Queue,
+	RenderPass, RenderPipeline, TextureFormat,
+};
+
+/// The pipeline state a custom material is drawn with, built on first use and cached
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PipelineVariant {
+	pub skinned: bool,
+	pub blended: bool,
+	pub double_sided: bool,
+}
+
+/// Draws primitives with the custom materials registered in the world
+pub struct MaterialRender {
+	pub surface_format: TextureFormat,
+	pub bind_group_layout: BindGroupLayout,
+	pub pipeline_layout: PipelineLayout,
+	pub materials: Vec<MaterialBinding>,
+}
+
+impl MaterialRender {
+	pub fn new(
+		device: &Device,
+		surface_format: TextureFormat,
+		uniform: &UniformBinding,
+		draw_data: &DrawBinding,
+		environment_bind_group_layout: &BindGroupLayout,
+	) -> Self {
+		let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Custom Material Bind Group Layout"),
+			entries: &[wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Uniform,
+					has_dynamic_offset: false,
+					min_binding_size: None,
+				},
+				count: None,
+			}],
+		});
+		let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("Custom Material Pipeline Layout"),
+			bind_group_layouts: &[
+				&uniform.bind_group_layout,
+				&draw_data.bind_group_layout,
+				&bind_group_layout,
+				environment_bind_group_layout,
+			],
+			push_constant_ranges: &[],
+		});
+		Self {
+			surface_format,
+			bind_group_layout,
+			pipeline_layout,
+			materials: Vec::new(),
+		}
+	}
+
+	/// Uploads the materials' uniforms and builds the pipeline variants this frame's draws use.
+	/// Changing a material's shader or pipeline state discards its cached pipelines.
+	/// Variants whose shader fails to validate are logged once and not drawn.
+	pub fn update(
+		&mut self,
+		device: &Device,
+		queue: &Queue,
+		world: &World,
+		metadata: &[EntityMetadata],
+	) {
+		self.materials.truncate(world.custom_materials.len());
+		for (index, material) in world.custom_materials.iter().enumerate() {
+			let is_current = self
+				.materials
+				.get(index)
+				.map_or(false, |binding| binding.matches(material));
+			if !is_current {
+				let binding = MaterialBinding::new(device, &self.bind_group_layout, material);
+				match self.materials.get_mut(index) {
+					Some(existing) => *existing = binding,
+					None => self.materials.push(binding),
+				}
+			}
+			self.materials[index].upload(device, queue, &self.bind_group_layout, material);
+		}
+
+		for entity_metadata in metadata.iter() {
+			let material_index = match entity_metadata.custom_material {
+				Some(material_index) => material_index,
+				None => continue,
+			};
+			let material = &world.custom_materials[material_index];
+			let variant = PipelineVariant {
+				skinned: entity_metadata.skinned,
+				blended: material.alpha_mode == AlphaMode::Blend,
+				double_sided: material.double_sided,
+			};
+			if self.materials[material_index]
+				.pipelines
+				.contains_key(&variant)
+			{
+				continue;
+			}
+			let pipeline = create_pipeline(
+				device,
+				&self.pipeline_layout,
+				self.surface_format,
+				material,
+				variant,
+			);
+			self.materials[material_index]
+				.pipelines
+				.insert(variant, pipeline);
+		}
+	}
+
+	/// Draws the primitives with custom materials for the views drawing to the target,
+	/// opaque materials first and blended materials after them.
+	/// The draw data and environment are expected to be bound at groups 1 and 3.
+	pub fn render<'rp>(
+		&'rp self,
+		render_pass: &mut RenderPass<'rp>,
+		uniform: &'rp UniformBinding,
+		views: &[(RenderTarget, Viewport)],
+		target: &RenderTarget,
+		metadata: &[EntityMetadata],
+	) {
+		for (view_index, (view_target, viewport)) in views.iter().enumerate() {
+			if view_target != target {
+				continue;
+			}
+			render_pass.set_viewport(
+				viewport.x,
+				viewport.y,
+				viewport.width,
+				viewport.height,
+				0.0,
+				1.0,
+			);
+			let view_offset = uniform.view_offset(view_index);
+			render_pass.set_bind_group(0, &uniform.bind_group, &[view_offset]);
+
+			for blended in [false, true] {
+				for (draw_index, entity_metadata) in metadata.iter().enumerate() {
+					let binding = match entity_metadata
+						.custom_material
+						.and_then(|material_index| self.materials.get(material_index))
+					{
+						Some(binding) => binding,
+						None => continue,
+					};
+					let variant = PipelineVariant {
+						skinned: entity_metadata.skinned,
+						blended: binding.alpha_mode == AlphaMode::Blend,
+						double_sided: binding.double_sided,
+					};
+					if variant.blended != blended {
+						continue;
+					}
+					let pipeline = match binding.pipelines.get(&variant) {
+						Some(Some(pipeline)) => pipeline,
+						_ => continue,
+					};
+					render_pass.set_pipeline(pipeline);
+					render_pass.set_bind_group(2, &binding.bind_group, &[]);
+					let draw_index = draw_index as u32;
+					render_pass.draw_indexed(
+						entity_metadata.index_range.clone(),
+						0,
+						draw_index..draw_index + 1,
+					);
+				}
+			}
+		}
+	}
+}
+
+/// A custom material's uniform buffer and the pipelines built from its shader
+pub struct MaterialBinding {
+	pub shader: String,
+	pub alpha_mode: AlphaMode,
+	pub double_sided: bool,
+	pub buffer: Buffer,
+	pub bind_group: BindGroup,
+	/// `None` for variants that failed to build, so they aren't rebuilt every frame
+	pub pipelines: HashMap<PipelineVariant, Option<RenderPipeline>>,
+}
+
+impl MaterialBinding {
+	fn new(
+		device: &Device,
+		bind_group_layout: &BindGroupLayout,
+		material: &CustomMaterial,
+	) -> Self {
+		let size = material.uniform_bytes().len() as BufferAddress;
+		let (buffer, bind_group) = create_uniform(device, bind_group_layout, size);
+		Self {
+			shader: material.shader.clone(),
+			alpha_mode: material.alpha_mode,
+			double_sided: material.double_sided,
+			buffer,
+			bind_group,
+			pipelines: HashMap::new(),
+		}
+	}
+
+	fn matches(&self, material: &CustomMaterial) -> bool {
+		self.shader == material.shader
+			&& self.alpha_mode == material.alpha_mode
+			&& self.double_sided == material.double_sided
+	}
+
+	fn upload(
+		&mut self,
+		device: &Device,
+		queue: &Queue,
+		bind_group_layout: &BindGroupLayout,
+		material: &CustomMaterial,
+	) {
+		let bytes = material.uniform_bytes();
+		if bytes.is_empty() {
+			return;
+		}
+		if bytes.len() as BufferAddress > self.buffer.size() {
+			let (buffer, bind_group) =
+				create_uniform(device, bind_group_layout, bytes.len() as BufferAddress);
+			self.buffer = buffer;
+			self.bind_group = bind_group;
+		}
+		queue.write_buffer(&self.buffer, 0, &bytes);
+	}
+}
+
+/// Materials without uniform fields still bind a small buffer
+fn create_uniform(
+	device: &Device,
+	bind_group_layout: &BindGroupLayout,
+	size: BufferAddress,
+) -> (Buffer, BindGroup) {
+	let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Custom Material Uniform Buffer"),
+		size: size.max(16),
+		usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	});
+	let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Custom Material Bind Group"),
+		layout: bind_group_layout,
+		entries: &[wgpu::BindGroupEntry {
+			binding: 0,
+			resource: buffer.as_entire_binding(),
+		}],
+	});
+	(buffer, bind_group)
+}
+
+/// Builds a material's pipeline inside a validation error scope,
+/// since game shaders are compiled at runtime and may not be valid
+fn create_pipeline(
+	device: &Device,
+	pipeline_layout: &PipelineLayout,
+	surface_format: TextureFormat,
+	material: &CustomMaterial,
+	variant: PipelineVariant,
+) -> Option<RenderPipeline> {
+	device.push_error_scope(wgpu::ErrorFilter::Validation);
+	let pipeline =
+		create_unchecked_pipeline(device, pipeline_layout, surface_format, material, variant);
+	match pollster::block_on(device.pop_error_scope()) {
+		Some(error) => {
+			log::error!(
+				"Failed to build custom material '{}' for {variant:?}, skipping its draws: {error}",
+				material.name
+			);
+			None
+		}
+		None => Some(pipeline),
+	}
+}
+
+fn create_unchecked_pipeline(
+	device: &Device,
+	pipeline_layout: &PipelineLayout,
+	surface_format: TextureFormat,
+	material: &CustomMaterial,
+	variant: PipelineVariant,
+) -> RenderPipeline {
+	let skin_source = if variant.skinned {
+		SKINNED_SOURCE
+	} else {
+		UNSKINNED_SOURCE
+	};
+	let source = format!("{PRELUDE_SOURCE}{skin_source}{}", material.shader);
+	let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+		label: Some(&format!("Custom Material Shader: {}", material.name)),
+		source: wgpu::ShaderSource::Wgsl(Cow::Owned(source)),
+	});
+
+	let blend = if variant.blended {
+		wgpu::BlendState::ALPHA_BLENDING
+	} else {
+		wgpu::BlendState::REPLACE
+	};
+
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some(&format!("Custom Material Pipeline: {}", material.name)),
+		layout: Some(pipeline_layout),
+		vertex: wgpu::VertexState {
+			module: &shader_module,
+			entry_point: "vertex_main",
+			buffers: &[create_vertex_description(&create_vertex_attributes())],
+		},
+		primitive: wgpu::PrimitiveState {
+			front_face: wgpu::FrontFace::Ccw,
+			cull_mode: if variant.double_sided {
+				None
+			} else {
+				Some(Face::Back)
+			},
+			..Default::default()
+		},
+		depth_stencil: Some(wgpu::DepthStencilState {
+			format: wgpu::TextureFormat::Depth32Float,
+			depth_write_enabled: !variant.blended,
+			depth_compare: wgpu::CompareFunction::Less,
+			stencil: wgpu::StencilState::default(),
+			bias: wgpu::DepthBiasState::default(),
+		}),
+		multisample: wgpu::MultisampleState::default(),
+		fragment: Some(wgpu::FragmentState {
+			module: &shader_module,
+			entry_point: "fragment_main",
+			targets: &[Some(wgpu::ColorTargetState {
+				format: surface_format,
+				blend: Some(blend),
+				write_mask: wgpu::ColorWrites::ALL,
+			})],
+		}),
+		multiview: None,
+	})
+}
+
+const PRELUDE_SOURCE: &str = "
+struct Light {
+    position: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Uniform {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    light: Light,
+    inverse_view_projection: mat4x4<f32>,
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> ubo: Uniform;
+
+struct DrawData {
+    model: mat4x4<f32>,
+    joint_offset: u32,
+    padding_0: u32,
+    padding_1: u32,
+    padding_2: u32,
+};
+
+@group(1) @binding(0)
+var<storage, read> draws: array<DrawData>;
+
+@group(1) @binding(1)
+var<storage, read> joints: array<mat4x4<f32>>;
+
+struct Environment {
+    ambient: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_parameters: vec4<f32>,
+    fog_mode: u32,
+    padding_0: u32,
+    padding_1: u32,
+    padding_2: u32,
+};
+
+@group(3) @binding(0)
+var<uniform> environment: Environment;
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let offset = world_position - ubo.camera_position.xyz;
+    let distance = length(offset);
+    let parameters = environment.fog_parameters;
+    var visibility = 1.0;
+    switch (environment.fog_mode) {
+        case 1u: {
+            let range = max(parameters.y - parameters.x, 0.0001);
+            visibility = clamp((parameters.y - distance) / range, 0.0, 1.0);
+        }
+        case 2u: {
+            visibility = exp(-parameters.x * distance);
+        }
+        case 3u: {
+            // Integrates the density along the view ray as it thins out with height
+            let camera_height = ubo.camera_position.y - parameters.y;
+            let camera_density = parameters.x * exp(-parameters.z * camera_height);
+            let rise = parameters.z * offset.y;
+            var thinning = 1.0;
+            if (abs(rise) > 0.0001) {
+                thinning = (1.0 - exp(-rise)) / rise;
+            }
+            visibility = exp(-camera_density * distance * thinning);
+        }
+        default: {}
+    }
+    return mix(environment.fog_color.rgb, color, visibility);
+}
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv_0: vec2<f32>,
+    @location(3) uv_1: vec2<f32>,
+    @location(4) joint_0: vec4<f32>,
+    @location(5) weight_0: vec4<f32>,
+    @location(6) color_0: vec3<f32>,
+};
+
+fn model_matrix(vert: VertexInput, draw_index: u32) -> mat4x4<f32> {
+    return draws[draw_index].model * skin_matrix(vert, draw_index);
+}
+";
+
+const UNSKINNED_SOURCE: &str = "
+fn skin_matrix(vert: VertexInput, draw_index: u32) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        vec4<f32>(1.0, 0.0, 0.0, 0.0),
+        vec4<f32>(0.0, 1.0, 0.0, 0.0),
+        vec4<f32>(0.0, 0.0, 1.0, 0.0),
+        vec4<f32>(0.0, 0.0, 0.0, 1.0)
+    );
+}
+";
+
+const SKINNED_SOURCE: &str = "
+fn skin_matrix(vert: VertexInput, draw_index: u32) -> mat4x4<f32> {
+    let offset = draws[draw_index].joint_offset;
+    return vert.weight_0.x * joints[offset + u32(vert.joint_0.x)]
+        + vert.weight_0.y * joints[offset + u32(vert.joint_0.y)]
+        + vert.weight_0.z * joints[offset + u32(vert.joint_0.z)]
+        + vert.weight_0.w * joints[offset + u32(vert.joint_0.w)];
+}
+";
@@ -0,0 +1,203 @@
+use super::world::{EnvironmentBinding, EnvironmentUniform};
+use phantom_world::{EntityMetadata, TextureFormat as WorldTextureFormat, World};
+use std::{collections::HashMap, mem::size_of, ops::Range};
+use wgpu::{
+	self, util::DeviceExt, BindGroup, BindGroupLayout, Device, Queue, Sampler, TextureView,
+};
+
+/// Binds the environment and each primitive's normal map for the scene pipeline.
+/// Primitives are sorted by normal map every frame,
+/// so each map's draws are contiguous and submitted together.
+pub struct NormalMaps {
+	pub bind_group_layout: BindGroupLayout,
+	pub sampler: Sampler,
+	pub flat_texture_view: TextureView,
+	pub textures: HashMap<usize, TextureView>,
+	pub bind_groups: HashMap<Option<usize>, BindGroup>,
+	/// This frame's ranges of draws sharing a normal map, `None` for primitives without one
+	pub batches: Vec<(Option<usize>, Range<u32>)>,
+}
+
+impl NormalMaps {
+	pub fn new(device: &Device, queue: &Queue) -> Self {
+		let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("Normal Map Sampler"),
+			address_mode_u: wgpu::AddressMode::Repeat,
+			address_mode_v: wgpu::AddressMode::Repeat,
+			mag_filter: wgpu::FilterMode::Linear,
+			min_filter: wgpu::FilterMode::Linear,
+			..Default::default()
+		});
+		// Points straight out of the surface, leaving the vertex normal as it is
+		let flat_texture_view = create_normal_texture(device, queue, (1, 1), &[128, 128, 255, 255])
+			.create_view(&wgpu::TextureViewDescriptor::default());
+		Self {
+			bind_group_layout: create_bind_group_layout(device),
+			sampler,
+			flat_texture_view,
+			textures: HashMap::new(),
+			bind_groups: HashMap::new(),
+			batches: Vec::new(),
+		}
+	}
+
+	/// Sorts this frame's primitives by normal map and batches them,
+	/// uploading the maps used for the first time
+	pub fn update(
+		&mut self,
+		device: &Device,
+		queue: &Queue,
+		world: &World,
+		environment: &EnvironmentBinding,
+		metadata: &mut [EntityMetadata],
+	) {
+		metadata.sort_by_key(|entity_metadata| normal_texture(world, entity_metadata));
+
+		self.batches.clear();
+		for (draw_index, entity_metadata) in metadata.iter().enumerate() {
+			let texture_index = normal_texture(world, entity_metadata);
+			let draw_index = draw_index as u32;
+			match self.batches.last_mut() {
+				Some((batch_texture, draws)) if *batch_texture == texture_index => {
+					draws.end = draw_index + 1;
+				}
+				_ => self
+					.batches
+					.push((texture_index, draw_index..draw_index + 1)),
+			}
+		}
+
+		let new_textures = self
+			.batches
+			.iter()
+			.map(|(texture_index, _)| *texture_index)
+			.filter(|texture_index| !self.bind_groups.contains_key(texture_index))
+			.collect::<Vec<_>>();
+		for texture_index in new_textures {
+			if let Some(texture_index) = texture_index {
+				if let Some(view) = upload_texture(device, queue, world, texture_index) {
+					self.textures.insert(texture_index, view);
+				}
+			}
+			let view = texture_index
+				.and_then(|texture_index| self.textures.get(&texture_index))
+				.unwrap_or(&self.flat_texture_view);
+			let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+				label: Some("Normal Map Bind Group"),
+				layout: &self.bind_group_layout,
+				entries: &[
+					wgpu::BindGroupEntry {
+						binding: 0,
+						resource: environment.buffer.as_entire_binding(),
+					},
+					wgpu::BindGroupEntry {
+						binding: 1,
+						resource: wgpu::BindingResource::TextureView(view),
+					},
+					wgpu::BindGroupEntry {
+						binding: 2,
+						resource: wgpu::BindingResource::Sampler(&self.sampler),
+					},
+				],
+			});
+			self.bind_groups.insert(texture_index, bind_group);
+		}
+	}
+}
+
+/// The world texture holding a primitive's normal map
+pub fn normal_texture(world: &World, entity_metadata: &EntityMetadata) -> Option<usize> {
+	let material = world.materials.get(entity_metadata.material?)?;
+	usize::try_from(material.normal_texture_index).ok()
+}
+
+fn upload_texture(
+	device: &Device,
+	queue: &Queue,
+	world: &World,
+	texture_index: usize,
+) -> Option<TextureView> {
+	let texture = match world.textures.get(texture_index) {
+		Some(texture) => texture,
+		None => {
+			log::warn!("Normal map texture {texture_index} not found");
+			return None;
+		}
+	};
+	let pixels = match texture.format {
+		WorldTextureFormat::R8G8B8A8 => texture.pixels.clone(),
+		WorldTextureFormat::R8G8B8 => texture
+			.pixels
+			.chunks_exact(3)
+			.flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+			.collect(),
+		format => {
+			log::warn!("Normal map texture format {format:?} is not supported");
+			return None;
+		}
+	};
+	let texture = create_normal_texture(device, queue, (texture.width, texture.height), &pixels);
+	Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+}
+
+fn create_normal_texture(
+	device: &Device,
+	queue: &Queue,
+	(width, height): (u32, u32),
+	pixels: &[u8],
+) -> wgpu::Texture {
+	// Normals are stored linearly, unlike colors
+	let format = wgpu::TextureFormat::Rgba8Unorm;
+	device.create_texture_with_data(
+		queue,
+		&wgpu::TextureDescriptor {
+			label: Some("Normal Map Texture"),
+			size: wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format,
+			usage: wgpu::TextureUsages::TEXTURE_BINDING,
+			view_formats: &[format],
+		},
+		pixels,
+	)
+}
+
+fn create_bind_group_layout(device: &Device) -> BindGroupLayout {
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Normal Map Bind Group Layout"),
+		entries: &[
+			wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Uniform,
+					has_dynamic_offset: false,
+					min_binding_size: wgpu::BufferSize::new(size_of::<EnvironmentUniform>() as _),
+				},
+				count: None,
+			},
+			wgpu::BindGroupLayoutEntry {
+				binding: 1,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Texture {
+					sample_type: wgpu::TextureSampleType::Float { filterable: true },
+					view_dimension: wgpu::TextureViewDimension::D2,
+					multisampled: false,
+				},
+				count: None,
+			},
+			wgpu::BindGroupLayoutEntry {
+				binding: 2,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+				count: None,
+			},
+		],
+	})
+}
@@ -0,0 +1,847 @@
+use super::world::UniformBinding;
+use nalgebra_glm as glm;
+use phantom_world::{RenderTarget, Viewport};
+use std::{borrow::Cow, mem::size_of};
+use wgpu::{
+	self, util::DeviceExt, BindGroup, BindGroupLayout, Buffer, BufferAddress, CommandEncoder,
+	ComputePipeline, Device, Queue, TextureView,
+};
+
+/// Culls primitives hidden behind the depth buffer in two phases.
+/// The first phase tests primitives against the previous frame's depth pyramid before the scene
+/// pass. Each target's depth is then reduced into a pyramid of farthest depths, and the second
+/// phase re-tests the primitives the first phase hid against it, drawing the ones revealed
+/// since the last frame. The pyramid is built before the second phase draws, so it is
+/// conservative for the next frame rather than exact.
+/// Requires indirect draws with first instances, see `IndirectDrawMode`.
+pub struct OcclusionCulling {
+	pub enabled: bool,
+	pub copy_depth_pipeline: ComputePipeline,
+	pub downsample_pipeline: ComputePipeline,
+	pub cull_pipeline: ComputePipeline,
+	pub copy_depth_bind_group_layout: BindGroupLayout,
+	pub downsample_bind_group_layout: BindGroupLayout,
+	pub cull_bind_group_layout: BindGroupLayout,
+	pub pyramids: Vec<(RenderTarget, DepthPyramid)>,
+	pub empty_pyramid_view: TextureView,
+	pub uniform_buffer: Buffer,
+	pub uniform_stride: BufferAddress,
+	pub object_buffer: Buffer,
+	pub indirect_buffer: Buffer,
+	pub capacity: usize,
+	pub number_of_objects: u32,
+	pub views: Vec<(RenderTarget, glm::Mat4)>,
+	pub previous_view_projections: Vec<glm::Mat4>,
+}
+
+/// The culling pass that wrote a set of indirect draws
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CullPhase {
+	/// Primitives visible against the previous frame's depth pyramid
+	Previous,
+
+	/// Primitives the first phase hid that are visible against this frame's depth pyramid
+	Disoccluded,
+}
+
+impl CullPhase {
+	fn index(&self) -> usize {
+		match self {
+			Self::Previous => 0,
+			Self::Disoccluded => 1,
+		}
+	}
+}
+
+impl OcclusionCulling {
+	const WORKGROUP_SIZE: u32 = 64;
+	const PYRAMID_WORKGROUP_SIZE: u32 = 8;
+	const INITIAL_CAPACITY: usize = 1024;
+	const PYRAMID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+	pub fn new(device: &Device, queue: &Queue) -> Self {
+		let copy_depth_bind_group_layout = create_copy_depth_bind_group_layout(device);
+		let downsample_bind_group_layout = create_downsample_bind_group_layout(device);
+		let cull_bind_group_layout = create_cull_bind_group_layout(device);
+
+		let copy_depth_pipeline = create_compute_pipeline(
+			device,
+			"Depth Pyramid Copy",
+			COPY_DEPTH_SHADER_SOURCE,
+			&copy_depth_bind_group_layout,
+			"copy_depth_main",
+		);
+		let downsample_pipeline = create_compute_pipeline(
+			device,
+			"Depth Pyramid Downsample",
+			DOWNSAMPLE_SHADER_SOURCE,
+			&downsample_bind_group_layout,
+			"downsample_main",
+		);
+		let cull_pipeline = create_compute_pipeline(
+			device,
+			"Occlusion Cull",
+			CULL_SHADER_SOURCE,
+			&cull_bind_group_layout,
+			"cull_main",
+		);
+
+		let uniform_stride = wgpu::util::align_to(
+			size_of::<CullUniform>() as BufferAddress,
+			device.limits().min_uniform_buffer_offset_alignment as BufferAddress,
+		);
+		let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Occlusion Cull Uniform Buffer"),
+			size: 2 * UniformBinding::MAX_NUMBER_OF_VIEWS as BufferAddress * uniform_stride,
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		let capacity = Self::INITIAL_CAPACITY;
+		let (object_buffer, indirect_buffer) = create_draw_buffers(device, capacity);
+
+		Self {
+			enabled: false,
+			copy_depth_pipeline,
+			downsample_pipeline,
+			cull_pipeline,
+			copy_depth_bind_group_layout,
+			downsample_bind_group_layout,
+			cull_bind_group_layout,
+			pyramids: Vec::new(),
+			empty_pyramid_view: create_empty_pyramid(device, queue),
+			uniform_buffer,
+			uniform_stride,
+			object_buffer,
+			indirect_buffer,
+			capacity,
+			number_of_objects: 0,
+			views: Vec::new(),
+			previous_view_projections: Vec::new(),
+		}
+	}
+
+	/// Uploads this frame's primitives and the views they are culled for.
+	/// The first phase tests views with the previous frame's view projection
+	/// to match the depth pyramid, and the second phase with this frame's.
+	pub fn update(
+		&mut self,
+		device: &Device,
+		queue: &Queue,
+		views: &[(RenderTarget, Viewport, glm::Mat4)],
+		objects: &[CullObject],
+	) {
+		if !self.enabled {
+			// Depth from before culling was disabled no longer matches the scene
+			self.pyramids.clear();
+			self.previous_view_projections.clear();
+			return;
+		}
+
+		if objects.len() > self.capacity {
+			self.capacity = objects.len().next_power_of_two();
+			let (object_buffer, indirect_buffer) = create_draw_buffers(device, self.capacity);
+			self.object_buffer = object_buffer;
+			self.indirect_buffer = indirect_buffer;
+		}
+		self.number_of_objects = objects.len() as u32;
+		queue.write_buffer(&self.object_buffer, 0, bytemuck::cast_slice(objects));
+
+		self.views.clear();
+		for (view_index, (target, viewport, view_projection)) in views.iter().enumerate() {
+			let culling_view_projection = self
+				.previous_view_projections
+				.get(view_index)
+				.copied()
+				.unwrap_or(*view_projection);
+			let first_phase_offset = draw_region(CullPhase::Previous, view_index, self.capacity);
+			for (phase, view_projection) in [
+				(CullPhase::Previous, culling_view_projection),
+				(CullPhase::Disoccluded, *view_projection),
+			] {
+				let uniform = CullUniform {
+					view_projection,
+					viewport: viewport.as_glm_vec(),
+					number_of_objects: self.number_of_objects,
+					draw_offset: draw_region(phase, view_index, self.capacity) as u32,
+					first_phase_offset: first_phase_offset as u32,
+					phase: phase.index() as u32,
+				};
+				queue.write_buffer(
+					&self.uniform_buffer,
+					self.uniform_offset(phase, view_index),
+					bytemuck::cast_slice(&[uniform]),
+				);
+			}
+			self.views.push((target.clone(), culling_view_projection));
+		}
+		self.previous_view_projections = views
+			.iter()
+			.map(|(_, _, view_projection)| *view_projection)
+			.collect();
+
+		self.pyramids
+			.retain(|(target, _)| views.iter().any(|(view_target, ..)| view_target == target));
+	}
+
+	/// Records the first phase, writing each view's indirect draws and
+	/// zeroing the instance count of primitives outside the frustum or behind the pyramid
+	pub fn cull(&self, device: &Device, encoder: &mut CommandEncoder) {
+		if !self.enabled || self.number_of_objects == 0 {
+			return;
+		}
+
+		let bind_groups = self
+			.views
+			.iter()
+			.enumerate()
+			.map(|(view_index, (target, _))| {
+				self.create_cull_bind_group(device, CullPhase::Previous, view_index, target)
+			})
+			.collect::<Vec<_>>();
+		self.dispatch_cull(encoder, &bind_groups);
+	}
+
+	/// Records the second phase for the views rendering to a target,
+	/// after its depth pyramid has been rebuilt from this frame's first phase draws
+	pub fn cull_disoccluded(
+		&self,
+		device: &Device,
+		encoder: &mut CommandEncoder,
+		target: &RenderTarget,
+	) {
+		if !self.enabled || self.number_of_objects == 0 {
+			return;
+		}
+
+		let bind_groups = self
+			.views
+			.iter()
+			.enumerate()
+			.filter(|(_, (view_target, _))| view_target == target)
+			.map(|(view_index, (target, _))| {
+				self.create_cull_bind_group(device, CullPhase::Disoccluded, view_index, target)
+			})
+			.collect::<Vec<_>>();
+		self.dispatch_cull(encoder, &bind_groups);
+	}
+
+	fn create_cull_bind_group(
+		&self,
+		device: &Device,
+		phase: CullPhase,
+		view_index: usize,
+		target: &RenderTarget,
+	) -> BindGroup {
+		let pyramid_view = self
+			.pyramid(target)
+			.map_or(&self.empty_pyramid_view, |pyramid| &pyramid.view);
+		device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Occlusion Cull Bind Group"),
+			layout: &self.cull_bind_group_layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+						buffer: &self.uniform_buffer,
+						offset: self.uniform_offset(phase, view_index),
+						size: wgpu::BufferSize::new(size_of::<CullUniform>() as _),
+					}),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: self.object_buffer.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 2,
+					resource: self.indirect_buffer.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 3,
+					resource: wgpu::BindingResource::TextureView(pyramid_view),
+				},
+			],
+		})
+	}
+
+	fn dispatch_cull(&self, encoder: &mut CommandEncoder, bind_groups: &[BindGroup]) {
+		let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+			label: Some("Occlusion Cull Pass"),
+		});
+		compute_pass.set_pipeline(&self.cull_pipeline);
+		let workgroups = (self.number_of_objects + Self::WORKGROUP_SIZE - 1) / Self::WORKGROUP_SIZE;
+		for bind_group in bind_groups.iter() {
+			compute_pass.set_bind_group(0, bind_group, &[]);
+			compute_pass.dispatch_workgroups(workgroups, 1, 1);
+		}
+	}
+
+	/// Records the passes reducing a target's freshly rendered depth into its pyramid
+	/// for the next frame to cull against
+	pub fn build_depth_pyramid(
+		&mut self,
+		device: &Device,
+		encoder: &mut CommandEncoder,
+		target: &RenderTarget,
+		depth_view: &TextureView,
+		(width, height): (u32, u32),
+	) {
+		if !self.enabled {
+			return;
+		}
+
+		let needs_pyramid = match self.pyramid(target) {
+			Some(pyramid) => pyramid.width != width || pyramid.height != height,
+			None => true,
+		};
+		if needs_pyramid {
+			let pyramid = DepthPyramid::new(device, self, width, height);
+			self.pyramids
+				.retain(|(pyramid_target, _)| pyramid_target != target);
+			self.pyramids.push((target.clone(), pyramid));
+		}
+		let pyramid = match self.pyramid(target) {
+			Some(pyramid) => pyramid,
+			None => return,
+		};
+
+		// The depth view is recreated on resize, so its bind group is made every frame
+		let copy_depth_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Depth Pyramid Copy Bind Group"),
+			layout: &self.copy_depth_bind_group_layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::TextureView(depth_view),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: wgpu::BindingResource::TextureView(&pyramid.mip_views[0]),
+				},
+			],
+		});
+
+		let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+			label: Some("Depth Pyramid Pass"),
+		});
+		compute_pass.set_pipeline(&self.copy_depth_pipeline);
+		compute_pass.set_bind_group(0, &copy_depth_bind_group, &[]);
+		compute_pass.dispatch_workgroups(pyramid_workgroups(width), pyramid_workgroups(height), 1);
+
+		compute_pass.set_pipeline(&self.downsample_pipeline);
+		for (mip, bind_group) in pyramid.downsample_bind_groups.iter().enumerate() {
+			let (mip_width, mip_height) = mip_dimensions(width, height, mip as u32 + 1);
+			compute_pass.set_bind_group(0, bind_group, &[]);
+			compute_pass.dispatch_workgroups(
+				pyramid_workgroups(mip_width),
+				pyramid_workgroups(mip_height),
+				1,
+			);
+		}
+	}
+
+	/// The byte offset of the first indirect draw a phase wrote for a view
+	pub fn draw_offset(&self, phase: CullPhase, view_index: usize) -> BufferAddress {
+		(draw_region(phase, view_index, self.capacity)
+			* size_of::<wgpu::util::DrawIndexedIndirect>()) as BufferAddress
+	}
+
+	fn uniform_offset(&self, phase: CullPhase, view_index: usize) -> BufferAddress {
+		((phase.index() * UniformBinding::MAX_NUMBER_OF_VIEWS + view_index) as BufferAddress)
+			* self.uniform_stride
+	}
+
+	fn pyramid(&self, target: &RenderTarget) -> Option<&DepthPyramid> {
+		self.pyramids
+			.iter()
+			.find(|(pyramid_target, _)| pyramid_target == target)
+			.map(|(_, pyramid)| pyramid)
+	}
+}
+
+/// Begins a pass drawing disoccluded primitives over a target's scene pass,
+/// keeping its color and depth
+pub fn begin_disoccluded_pass<'a>(
+	encoder: &'a mut CommandEncoder,
+	color_view: &'a TextureView,
+	depth_view: &'a TextureView,
+) -> wgpu::RenderPass<'a> {
+	encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+		label: Some("Disoccluded Render Pass"),
+		color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+			view: color_view,
+			resolve_target: None,
+			ops: wgpu::Operations {
+				load: wgpu::LoadOp::Load,
+				store: true,
+			},
+		})],
+		depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+			view: depth_view,
+			depth_ops: Some(wgpu::Operations {
+				load: wgpu::LoadOp::Load,
+				store: true,
+			}),
+			stencil_ops: None,
+		}),
+	})
+}
+
+pub struct DepthPyramid {
+	pub width: u32,
+	pub height: u32,
+	pub texture: wgpu::Texture,
+	pub view: TextureView,
+	pub mip_views: Vec<TextureView>,
+	pub downsample_bind_groups: Vec<BindGroup>,
+}
+
+impl DepthPyramid {
+	fn new(device: &Device, occlusion: &OcclusionCulling, width: u32, height: u32) -> Self {
+		let mip_level_count = u32::BITS - width.max(height).max(1).leading_zeros();
+		let texture = device.create_texture(&wgpu::TextureDescriptor {
+			label: Some("Depth Pyramid Texture"),
+			size: wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format: OcclusionCulling::PYRAMID_FORMAT,
+			usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+			view_formats: &[OcclusionCulling::PYRAMID_FORMAT],
+		});
+		let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+		let mip_views = (0..mip_level_count)
+			.map(|mip| {
+				texture.create_view(&wgpu::TextureViewDescriptor {
+					label: Some("Depth Pyramid Mip View"),
+					base_mip_level: mip,
+					mip_level_count: std::num::NonZeroU32::new(1),
+					..Default::default()
+				})
+			})
+			.collect::<Vec<_>>();
+
+		let downsample_bind_groups = mip_views
+			.windows(2)
+			.map(|views| {
+				device.create_bind_group(&wgpu::BindGroupDescriptor {
+					label: Some("Depth Pyramid Downsample Bind Group"),
+					layout: &occlusion.downsample_bind_group_layout,
+					entries: &[
+						wgpu::BindGroupEntry {
+							binding: 0,
+							resource: wgpu::BindingResource::TextureView(&views[0]),
+						},
+						wgpu::BindGroupEntry {
+							binding: 1,
+							resource: wgpu::BindingResource::TextureView(&views[1]),
+						},
+					],
+				})
+			})
+			.collect();
+
+		Self {
+			width,
+			height,
+			texture,
+			view,
+			mip_views,
+			downsample_bind_groups,
+		}
+	}
+}
+
+/// A primitive's draw arguments and bounds for the culling pass
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CullObject {
+	pub model: glm::Mat4,
+	pub bounding_box_min: glm::Vec4,
+	pub bounding_box_max: glm::Vec4,
+	pub first_index: u32,
+	pub number_of_indices: u32,
+	/// Zero for primitives drawn outside of the default pipeline
+	pub instance_count: u32,
+	pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CullUniform {
+	pub view_projection: glm::Mat4,
+	pub viewport: glm::Vec4,
+	pub number_of_objects: u32,
+	pub draw_offset: u32,
+	pub first_phase_offset: u32,
+	pub phase: u32,
+}
+
+/// The index of the first indirect draw a phase writes for a view.
+/// Each phase holds a region of draws for every view, so the second phase can read the first's.
+fn draw_region(phase: CullPhase, view_index: usize, capacity: usize) -> usize {
+	(phase.index() * UniformBinding::MAX_NUMBER_OF_VIEWS + view_index) * capacity
+}
+
+fn mip_dimensions(width: u32, height: u32, mip: u32) -> (u32, u32) {
+	((width >> mip).max(1), (height >> mip).max(1))
+}
+
+fn pyramid_workgroups(size: u32) -> u32 {
+	(size + OcclusionCulling::PYRAMID_WORKGROUP_SIZE - 1) / OcclusionCulling::PYRAMID_WORKGROUP_SIZE
+}
+
+fn create_draw_buffers(device: &Device, capacity: usize) -> (Buffer, Buffer) {
+	let object_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Occlusion Cull Object Buffer"),
+		size: (capacity * size_of::<CullObject>()) as BufferAddress,
+		usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	});
+	let indirect_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Occlusion Cull Indirect Buffer"),
+		size: (2
+			* UniformBinding::MAX_NUMBER_OF_VIEWS
+			* capacity
+			* size_of::<wgpu::util::DrawIndexedIndirect>()) as BufferAddress,
+		usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+		mapped_at_creation: false,
+	});
+	(object_buffer, indirect_buffer)
+}
+
+/// A single texel at the far plane, used by targets that have no pyramid yet
+fn create_empty_pyramid(device: &Device, queue: &Queue) -> TextureView {
+	let format = OcclusionCulling::PYRAMID_FORMAT;
+	device
+		.create_texture_with_data(
+			queue,
+			&wgpu::TextureDescriptor {
+				label: Some("Empty Depth Pyramid Texture"),
+				size: wgpu::Extent3d {
+					width: 1,
+					height: 1,
+					depth_or_array_layers: 1,
+				},
+				mip_level_count: 1,
+				sample_count: 1,
+				dimension: wgpu::TextureDimension::D2,
+				format,
+				usage: wgpu::TextureUsages::TEXTURE_BINDING,
+				view_formats: &[format],
+			},
+			bytemuck::cast_slice(&[1.0_f32]),
+		)
+		.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn pyramid_texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+	wgpu::BindGroupLayoutEntry {
+		binding,
+		visibility: wgpu::ShaderStages::COMPUTE,
+		ty: wgpu::BindingType::Texture {
+			sample_type: wgpu::TextureSampleType::Float { filterable: false },
+			view_dimension: wgpu::TextureViewDimension::D2,
+			multisampled: false,
+		},
+		count: None,
+	}
+}
+
+fn pyramid_storage_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+	wgpu::BindGroupLayoutEntry {
+		binding,
+		visibility: wgpu::ShaderStages::COMPUTE,
+		ty: wgpu::BindingType::StorageTexture {
+			access: wgpu::StorageTextureAccess::WriteOnly,
+			format: OcclusionCulling::PYRAMID_FORMAT,
+			view_dimension: wgpu::TextureViewDimension::D2,
+		},
+		count: None,
+	}
+}
+
+fn storage_entry(binding: u32, read_only: bool, element_size: usize) -> wgpu::BindGroupLayoutEntry {
+	wgpu::BindGroupLayoutEntry {
+		binding,
+		visibility: wgpu::ShaderStages::COMPUTE,
+		ty: wgpu::BindingType::Buffer {
+			ty: wgpu::BufferBindingType::Storage { read_only },
+			has_dynamic_offset: false,
+			min_binding_size: wgpu::BufferSize::new(element_size as _),
+		},
+		count: None,
+	}
+}
+
+fn create_copy_depth_bind_group_layout(device: &Device) -> BindGroupLayout {
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Depth Pyramid Copy Bind Group Layout"),
+		entries: &[
+			wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::COMPUTE,
+				ty: wgpu::BindingType::Texture {
+					sample_type: wgpu::TextureSampleType::Depth,
+					view_dimension: wgpu::TextureViewDimension::D2,
+					multisampled: false,
+				},
+				count: None,
+			},
+			pyramid_storage_entry(1),
+		],
+	})
+}
+
+fn create_downsample_bind_group_layout(device: &Device) -> BindGroupLayout {
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Depth Pyramid Downsample Bind Group Layout"),
+		entries: &[pyramid_texture_entry(0), pyramid_storage_entry(1)],
+	})
+}
+
+fn create_cull_bind_group_layout(device: &Device) -> BindGroupLayout {
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Occlusion Cull Bind Group Layout"),
+		entries: &[
+			wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::COMPUTE,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Uniform,
+					has_dynamic_offset: false,
+					min_binding_size: wgpu::BufferSize::new(size_of::<CullUniform>() as _),
+				},
+				count: None,
+			},
+			storage_entry(1, true, size_of::<CullObject>()),
+			storage_entry(2, false, size_of::<wgpu::util::DrawIndexedIndirect>()),
+			pyramid_texture_entry(3),
+		],
+	})
+}
+
+fn create_compute_pipeline(
+	device: &Device,
+	label: &str,
+	source: &'static str,
+	bind_group_layout: &BindGroupLayout,
+	entry_point: &str,
+) -> ComputePipeline {
+	let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+		label: Some(&format!("{label} Shader")),
+		source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(source)),
+	});
+
+	let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+		label: Some(&format!("{label} Pipeline Layout")),
+		bind_group_layouts: &[bind_group_layout],
+		push_constant_ranges: &[],
+	});
+
+	device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+		label: Some(&format!("{label} Pipeline")),
+		layout: Some(&pipeline_layout),
+		module: &shader_module,
+		entry_point,
+	})
+}
+
+const COPY_DEPTH_SHADER_SOURCE: &str = "
+@group(0) @binding(0)
+var scene_depth: texture_depth_2d;
+
+@group(0) @binding(1)
+var pyramid_mip: texture_storage_2d<r32float, write>;
+
+@compute @workgroup_size(8, 8)
+fn copy_depth_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let coords = vec2<i32>(id.xy);
+    let size = vec2<i32>(textureDimensions(pyramid_mip));
+    if (coords.x >= size.x || coords.y >= size.y) {
+        return;
+    }
+    let depth = textureLoad(scene_depth, coords, 0);
+    textureStore(pyramid_mip, coords, vec4<f32>(depth, 0.0, 0.0, 0.0));
+}
+";
+
+const DOWNSAMPLE_SHADER_SOURCE: &str = "
+@group(0) @binding(0)
+var source_mip: texture_2d<f32>;
+
+@group(0) @binding(1)
+var pyramid_mip: texture_storage_2d<r32float, write>;
+
+@compute @workgroup_size(8, 8)
+fn downsample_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let coords = vec2<i32>(id.xy);
+    let size = vec2<i32>(textureDimensions(pyramid_mip));
+    if (coords.x >= size.x || coords.y >= size.y) {
+        return;
+    }
+
+    // The last texel in each direction also covers the leftover row or column of odd sources
+    let source_size = vec2<i32>(textureDimensions(source_mip));
+    let first = coords * 2;
+    var last = min(first + vec2<i32>(1, 1), source_size - vec2<i32>(1, 1));
+    if (coords.x == size.x - 1) {
+        last.x = source_size.x - 1;
+    }
+    if (coords.y == size.y - 1) {
+        last.y = source_size.y - 1;
+    }
+
+    var farthest = 0.0;
+    for (var y = first.y; y <= last.y; y = y + 1) {
+        for (var x = first.x; x <= last.x; x = x + 1) {
+            farthest = max(farthest, textureLoad(source_mip, vec2<i32>(x, y), 0).r);
+        }
+    }
+    textureStore(pyramid_mip, coords, vec4<f32>(farthest, 0.0, 0.0, 0.0));
+}
+";
+
+const CULL_SHADER_SOURCE: &str = "
+struct CullUniform {
+    view_projection: mat4x4<f32>,
+    viewport: vec4<f32>,
+    number_of_objects: u32,
+    draw_offset: u32,
+    first_phase_offset: u32,
+    phase: u32,
+};
+
+struct CullObject {
+    model: mat4x4<f32>,
+    bounding_box_min: vec4<f32>,
+    bounding_box_max: vec4<f32>,
+    first_index: u32,
+    number_of_indices: u32,
+    instance_count: u32,
+    padding: u32,
+};
+
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> view: CullUniform;
+
+@group(0) @binding(1)
+var<storage, read> objects: array<CullObject>;
+
+@group(0) @binding(2)
+var<storage, read_write> draws: array<DrawIndexedIndirect>;
+
+@group(0) @binding(3)
+var depth_pyramid: texture_2d<f32>;
+
+fn is_visible(cull_object: CullObject) -> bool {
+    var ndc_min = vec3<f32>(3.4e38, 3.4e38, 3.4e38);
+    var ndc_max = vec3<f32>(-3.4e38, -3.4e38, -3.4e38);
+    for (var corner = 0u; corner < 8u; corner = corner + 1u) {
+        let selector = vec3<f32>(
+            f32(corner & 1u),
+            f32((corner >> 1u) & 1u),
+            f32((corner >> 2u) & 1u)
+        );
+        let position = mix(cull_object.bounding_box_min.xyz, cull_object.bounding_box_max.xyz, selector);
+        let clip = view.view_projection * cull_object.model * vec4<f32>(position, 1.0);
+
+        // Bounds crossing the near plane can't be projected, so they are always drawn
+        if (clip.w <= 0.0) {
+            return true;
+        }
+        let ndc = clip.xyz / clip.w;
+        ndc_min = min(ndc_min, ndc);
+        ndc_max = max(ndc_max, ndc);
+    }
+
+    if (ndc_max.x < -1.0 || ndc_min.x > 1.0 || ndc_max.y < -1.0 || ndc_min.y > 1.0 || ndc_min.z > 1.0) {
+        return false;
+    }
+
+    // Project the bounds onto the viewport's region of the target in pixels
+    let uv_min = clamp(vec2<f32>(ndc_min.x, -ndc_max.y) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+    let uv_max = clamp(vec2<f32>(ndc_max.x, -ndc_min.y) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+    let pixel_min = view.viewport.xy + uv_min * view.viewport.zw;
+    let pixel_max = view.viewport.xy + uv_max * view.viewport.zw;
+
+    // Pick the mip where the bounds span at most two texels in each direction
+    let extent = max(pixel_max.x - pixel_min.x, pixel_max.y - pixel_min.y);
+    let max_level = i32(textureNumLevels(depth_pyramid)) - 1;
+    let level = clamp(i32(ceil(log2(max(extent, 1.0)))), 0, max_level);
+    let mip_size = vec2<i32>(textureDimensions(depth_pyramid, level));
+    let scale = 1.0 / f32(1 << u32(level));
+    let texel_min = clamp(vec2<i32>(pixel_min * scale), vec2<i32>(0, 0), mip_size - vec2<i32>(1, 1));
+    let texel_max = clamp(vec2<i32>(pixel_max * scale), vec2<i32>(0, 0), mip_size - vec2<i32>(1, 1));
+
+    let occluder_depth = max(
+        max(
+            textureLoad(depth_pyramid, texel_min, level).r,
+            textureLoad(depth_pyramid, vec2<i32>(texel_max.x, texel_min.y), level).r
+        ),
+        max(
+            textureLoad(depth_pyramid, vec2<i32>(texel_min.x, texel_max.y), level).r,
+            textureLoad(depth_pyramid, texel_max, level).r
+        )
+    );
+    return ndc_min.z <= occluder_depth;
+}
+
+@compute @workgroup_size(64)
+fn cull_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= view.number_of_objects) {
+        return;
+    }
+
+    let cull_object = objects[index];
+    var visible = is_visible(cull_object);
+
+    // The second phase only draws what the first phase hid
+    if (view.phase == 1u) {
+        visible = visible && draws[view.first_phase_offset + index].instance_count == 0u;
+    }
+
+    var draw: DrawIndexedIndirect;
+    draw.index_count = cull_object.number_of_indices;
+    draw.instance_count = select(0u, cull_object.instance_count, visible);
+    draw.first_index = cull_object.first_index;
+    draw.base_vertex = 0;
+    draw.first_instance = index;
+    draws[view.draw_offset + index] = draw;
+}
+";
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn phases_write_separate_draw_regions() {
+		let capacity = 4;
+		let regions = [CullPhase::Previous, CullPhase::Disoccluded]
+			.into_iter()
+			.flat_map(|phase| {
+				(0..UniformBinding::MAX_NUMBER_OF_VIEWS)
+					.map(move |view_index| draw_region(phase, view_index, capacity))
+			})
+			.collect::<Vec<_>>();
+		assert!(regions.windows(2).all(|pair| pair[1] == pair[0] + capacity));
+		assert_eq!(
+			regions.last().copied(),
+			Some((2 * UniformBinding::MAX_NUMBER_OF_VIEWS - 1) * capacity)
+		);
+	}
+}
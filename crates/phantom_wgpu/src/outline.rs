@@ -0,0 +1,704 @@
+use super::world::{
+	create_vertex_attributes, create_vertex_description, DrawBinding, UniformBinding,
+};
+use phantom_config::SelectionOutline;
+use phantom_world::{Entity, RenderTarget, Viewport, World};
+use std::{borrow::Cow, collections::HashSet, mem::size_of};
+use wgpu::{
+	self, BindGroup, BindGroupLayout, Buffer, BufferAddress, CommandEncoder, Device, Queue,
+	RenderPass, RenderPipeline, TextureFormat, TextureView,
+};
+
+/// Outlines entities by rendering their silhouettes into a mask,
+/// then jump flooding the mask so every pixel knows its distance to the nearest silhouette.
+/// Silhouettes hidden behind other geometry are outlined with a reduced alpha.
+pub struct OutlineRender {
+	pub enabled: bool,
+	/// The outlined entities and their descendants
+	pub entities: HashSet<Entity>,
+	pub mask_pipeline: RenderPipeline,
+	pub seed_pipeline: RenderPipeline,
+	pub jump_pipeline: RenderPipeline,
+	pub composite_pipeline: RenderPipeline,
+	pub input_bind_group_layout: BindGroupLayout,
+	pub settings_buffer: Buffer,
+	pub step_buffer: Buffer,
+	pub step_alignment: BufferAddress,
+	pub number_of_steps: u32,
+	pub targets: Vec<(RenderTarget, OutlineTarget)>,
+}
+
+impl OutlineRender {
+	const MASK_FORMAT: TextureFormat = TextureFormat::Rg8Unorm;
+	const JUMP_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+	const MAX_WIDTH: f32 = 64.0;
+	const MAX_NUMBER_OF_STEPS: u32 = 8;
+
+	pub fn new(
+		device: &Device,
+		surface_format: TextureFormat,
+		uniform: &UniformBinding,
+		draw_data: &DrawBinding,
+		depth_bind_group_layout: &BindGroupLayout,
+	) -> Self {
+		let input_bind_group_layout = create_input_bind_group_layout(device);
+		let mask_pipeline =
+			create_mask_pipeline(device, uniform, draw_data, depth_bind_group_layout);
+		let layouts = [&uniform.bind_group_layout, &input_bind_group_layout];
+		let seed_pipeline =
+			create_fullscreen_pipeline(device, "Outline Seed", "seed_main", &layouts, None);
+		let jump_pipeline =
+			create_fullscreen_pipeline(device, "Outline Jump", "jump_main", &layouts, None);
+		let composite_pipeline = create_fullscreen_pipeline(
+			device,
+			"Outline Composite",
+			"composite_main",
+			&layouts,
+			Some(surface_format),
+		);
+
+		let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Outline Settings Buffer"),
+			size: size_of::<OutlineSettings>() as BufferAddress,
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		let step_alignment = device.limits().min_uniform_buffer_offset_alignment as BufferAddress;
+		let step_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Outline Step Buffer"),
+			size: step_alignment * Self::MAX_NUMBER_OF_STEPS as BufferAddress,
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		Self {
+			enabled: false,
+			entities: HashSet::new(),
+			mask_pipeline,
+			seed_pipeline,
+			jump_pipeline,
+			composite_pipeline,
+			input_bind_group_layout,
+			settings_buffer,
+			step_buffer,
+			step_alignment,
+			number_of_steps: 0,
+			targets: Vec::new(),
+		}
+	}
+
+	/// Collects the outlined entities with their descendants
+	/// and uploads the settings for this frame's outline passes
+	pub fn update(
+		&mut self,
+		queue: &Queue,
+		settings: &SelectionOutline,
+		world: &World,
+		entities: &[Entity],
+	) {
+		self.entities.clear();
+		for graph in world.scene.graphs.iter() {
+			for entity in entities.iter() {
+				if let Some(node_index) = graph.find_node(*entity) {
+					self.entities.extend(
+						graph
+							.subtree(node_index)
+							.into_iter()
+							.map(|node_index| graph[node_index]),
+					);
+				}
+			}
+		}
+
+		self.enabled = settings.enabled && !self.entities.is_empty();
+		if !self.enabled {
+			self.targets.clear();
+			return;
+		}
+
+		// Each step halves the jump distance, starting from the smallest power of two
+		// covering the outline's width
+		let width = settings.width.clamp(1.0, Self::MAX_WIDTH);
+		let first_step = (width.ceil() as u32).next_power_of_two();
+		self.number_of_steps = first_step.trailing_zeros() + 1;
+		let words_per_step = (self.step_alignment / size_of::<u32>() as BufferAddress) as usize;
+		let mut steps = vec![0_u32; words_per_step * self.number_of_steps as usize];
+		for step in 0..self.number_of_steps as usize {
+			steps[step * words_per_step] = first_step >> step;
+		}
+		queue.write_buffer(&self.step_buffer, 0, bytemuck::cast_slice(&steps));
+
+		let settings = OutlineSettings {
+			color: settings.color,
+			width,
+			occluded_alpha: settings.occluded_alpha.clamp(0.0, 1.0),
+			padding: [0.0; 2],
+		};
+		queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+	}
+
+	/// Creates or resizes the textures a target's outline is rendered into
+	pub fn prepare_target(
+		&mut self,
+		device: &Device,
+		target: &RenderTarget,
+		(width, height): (u32, u32),
+	) {
+		if !self.enabled {
+			return;
+		}
+		let is_current = self.target(target).map_or(false, |outline_target| {
+			outline_target.width == width && outline_target.height == height
+		});
+		if is_current {
+			return;
+		}
+		let outline_target = OutlineTarget::new(device, self, width, height);
+		self.targets
+			.retain(|(outline_target, _)| outline_target != target);
+		self.targets.push((target.clone(), outline_target));
+	}
+
+	/// Frees the textures of targets no longer rendered to
+	pub fn retain_targets(&mut self, views: &[(RenderTarget, Viewport)]) {
+		self.targets
+			.retain(|(target, _)| views.iter().any(|(view_target, _)| view_target == target));
+	}
+
+	pub fn target(&self, target: &RenderTarget) -> Option<&OutlineTarget> {
+		self.targets
+			.iter()
+			.find(|(outline_target, _)| outline_target == target)
+			.map(|(_, outline_target)| outline_target)
+	}
+
+	/// Records the seed and jump flood passes for each view drawing to the target,
+	/// after its silhouette mask has been rendered
+	pub fn render_jump_flood(
+		&self,
+		encoder: &mut CommandEncoder,
+		uniform: &UniformBinding,
+		views: &[(RenderTarget, Viewport)],
+		target: &RenderTarget,
+	) {
+		let outline_target = match self.target(target) {
+			Some(outline_target) if self.enabled => outline_target,
+			_ => return,
+		};
+
+		// The seed pass writes the first jump texture while binding the second
+		let mut passes = vec![(
+			&self.seed_pipeline,
+			&outline_target.jump_bind_groups[1],
+			0,
+			&outline_target.jump_views[0],
+		)];
+		for step in 0..self.number_of_steps as usize {
+			passes.push((
+				&self.jump_pipeline,
+				&outline_target.jump_bind_groups[step % 2],
+				step as wgpu::DynamicOffset * self.step_alignment as wgpu::DynamicOffset,
+				&outline_target.jump_views[(step + 1) % 2],
+			));
+		}
+
+		for (pipeline, bind_group, step_offset, output_view) in passes {
+			let mut render_pass = begin_outline_pass(encoder, output_view);
+			render_pass.set_pipeline(pipeline);
+			render_pass.set_bind_group(1, bind_group, &[step_offset]);
+			for (view_index, (view_target, viewport)) in views.iter().enumerate() {
+				if view_target != target {
+					continue;
+				}
+				set_viewport(&mut render_pass, viewport);
+				let view_offset = uniform.view_offset(view_index);
+				render_pass.set_bind_group(0, &uniform.bind_group, &[view_offset]);
+				render_pass.draw(0..3, 0..1);
+			}
+		}
+	}
+
+	/// Blends the outline over each view drawing to the target
+	pub fn composite<'rp>(
+		&'rp self,
+		render_pass: &mut RenderPass<'rp>,
+		uniform: &'rp UniformBinding,
+		views: &[(RenderTarget, Viewport)],
+		target: &RenderTarget,
+	) {
+		let outline_target = match self.target(target) {
+			Some(outline_target) if self.enabled => outline_target,
+			_ => return,
+		};
+
+		render_pass.set_pipeline(&self.composite_pipeline);
+		let final_bind_group = &outline_target.jump_bind_groups[self.number_of_steps as usize % 2];
+		render_pass.set_bind_group(1, final_bind_group, &[0]);
+		for (view_index, (view_target, viewport)) in views.iter().enumerate() {
+			if view_target != target {
+				continue;
+			}
+			set_viewport(render_pass, viewport);
+			let view_offset = uniform.view_offset(view_index);
+			render_pass.set_bind_group(0, &uniform.bind_group, &[view_offset]);
+			render_pass.draw(0..3, 0..1);
+		}
+	}
+}
+
+pub struct OutlineTarget {
+	pub width: u32,
+	pub height: u32,
+	pub mask_view: TextureView,
+	pub jump_views: [TextureView; 2],
+	/// Bind the mask with the jump texture of the same index
+	pub jump_bind_groups: [BindGroup; 2],
+}
+
+impl OutlineTarget {
+	fn new(device: &Device, outline: &OutlineRender, width: u32, height: u32) -> Self {
+		let mask_view = create_texture(
+			device,
+			"Outline Mask Texture",
+			OutlineRender::MASK_FORMAT,
+			width,
+			height,
+		);
+		let jump_views = [0, 1].map(|_| {
+			create_texture(
+				device,
+				"Outline Jump Flood Texture",
+				OutlineRender::JUMP_FORMAT,
+				width,
+				height,
+			)
+		});
+		let jump_bind_groups = [0, 1]
+			.map(|index| create_input_bind_group(device, outline, &mask_view, &jump_views[index]));
+		Self {
+			width,
+			height,
+			mask_view,
+			jump_views,
+			jump_bind_groups,
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct OutlineSettings {
+	pub color: [f32; 4],
+	pub width: f32,
+	pub occluded_alpha: f32,
+	pub padding: [f32; 2],
+}
+
+/// Begins the pass that renders the outlined silhouettes,
+/// marking the pixels where they are in front of the scene's depth
+pub fn begin_mask_pass<'a>(
+	encoder: &'a mut CommandEncoder,
+	outline_target: &'a OutlineTarget,
+) -> RenderPass<'a> {
+	begin_outline_pass(encoder, &outline_target.mask_view)
+}
+
+fn begin_outline_pass<'a>(
+	encoder: &'a mut CommandEncoder,
+	output_view: &'a TextureView,
+) -> RenderPass<'a> {
+	encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+		label: Some("Outline Pass"),
+		color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+			view: output_view,
+			resolve_target: None,
+			ops: wgpu::Operations {
+				load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+				store: true,
+			},
+		})],
+		depth_stencil_attachment: None,
+	})
+}
+
+fn set_viewport(render_pass: &mut RenderPass, viewport: &Viewport) {
+	render_pass.set_viewport(
+		viewport.x,
+		viewport.y,
+		viewport.width,
+		viewport.height,
+		0.0,
+		1.0,
+	);
+}
+
+fn create_texture(
+	device: &Device,
+	label: &str,
+	format: TextureFormat,
+	width: u32,
+	height: u32,
+) -> TextureView {
+	device
+		.create_texture(&wgpu::TextureDescriptor {
+			label: Some(label),
+			size: wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+			view_formats: &[format],
+		})
+		.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_input_bind_group(
+	device: &Device,
+	outline: &OutlineRender,
+	mask_view: &TextureView,
+	jump_view: &TextureView,
+) -> BindGroup {
+	device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Outline Input Bind Group"),
+		layout: &outline.input_bind_group_layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: outline.settings_buffer.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+					buffer: &outline.step_buffer,
+					offset: 0,
+					size: wgpu::BufferSize::new(size_of::<[u32; 4]>() as _),
+				}),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: wgpu::BindingResource::TextureView(mask_view),
+			},
+			wgpu::BindGroupEntry {
+				binding: 3,
+				resource: wgpu::BindingResource::TextureView(jump_view),
+			},
+		],
+	})
+}
+
+fn create_input_bind_group_layout(device: &Device) -> BindGroupLayout {
+	let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+		binding,
+		visibility: wgpu::ShaderStages::FRAGMENT,
+		ty: wgpu::BindingType::Texture {
+			sample_type: wgpu::TextureSampleType::Float { filterable: false },
+			view_dimension: wgpu::TextureViewDimension::D2,
+			multisampled: false,
+		},
+		count: None,
+	};
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Outline Input Bind Group Layout"),
+		entries: &[
+			wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Uniform,
+					has_dynamic_offset: false,
+					min_binding_size: wgpu::BufferSize::new(size_of::<OutlineSettings>() as _),
+				},
+				count: None,
+			},
+			wgpu::BindGroupLayoutEntry {
+				binding: 1,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Uniform,
+					has_dynamic_offset: true,
+					min_binding_size: wgpu::BufferSize::new(size_of::<[u32; 4]>() as _),
+				},
+				count: None,
+			},
+			texture_entry(2),
+			texture_entry(3),
+		],
+	})
+}
+
+fn create_mask_pipeline(
+	device: &Device,
+	uniform: &UniformBinding,
+	draw_data: &DrawBinding,
+	depth_bind_group_layout: &BindGroupLayout,
+) -> RenderPipeline {
+	let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+		label: Some("Outline Mask Shader"),
+		source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(MASK_SHADER_SOURCE)),
+	});
+
+	let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+		label: Some("Outline Mask Pipeline Layout"),
+		bind_group_layouts: &[
+			&uniform.bind_group_layout,
+			&draw_data.bind_group_layout,
+			depth_bind_group_layout,
+		],
+		push_constant_ranges: &[],
+	});
+
+	// Overlapping surfaces keep the highest coverage and visibility
+	let max_blend = wgpu::BlendComponent {
+		src_factor: wgpu::BlendFactor::One,
+		dst_factor: wgpu::BlendFactor::One,
+		operation: wgpu::BlendOperation::Max,
+	};
+
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some("Outline Mask Pipeline"),
+		layout: Some(&pipeline_layout),
+		vertex: wgpu::VertexState {
+			module: &shader_module,
+			entry_point: "vertex_main",
+			buffers: &[create_vertex_description(&create_vertex_attributes())],
+		},
+		// Back faces are kept so the silhouette of hidden geometry stays closed
+		primitive: wgpu::PrimitiveState {
+			cull_mode: None,
+			..Default::default()
+		},
+		depth_stencil: None,
+		multisample: wgpu::MultisampleState::default(),
+		fragment: Some(wgpu::FragmentState {
+			module: &shader_module,
+			entry_point: "fragment_main",
+			targets: &[Some(wgpu::ColorTargetState {
+				format: OutlineRender::MASK_FORMAT,
+				blend: Some(wgpu::BlendState {
+					color: max_blend,
+					alpha: max_blend,
+				}),
+				write_mask: wgpu::ColorWrites::ALL,
+			})],
+		}),
+		multiview: None,
+	})
+}
+
+fn create_fullscreen_pipeline(
+	device: &Device,
+	label: &str,
+	fragment_entry_point: &str,
+	bind_group_layouts: &[&BindGroupLayout],
+	surface_format: Option<TextureFormat>,
+) -> RenderPipeline {
+	let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+		label: Some(&format!("{label} Shader")),
+		source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(JUMP_FLOOD_SHADER_SOURCE)),
+	});
+
+	let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+		label: Some(&format!("{label} Pipeline Layout")),
+		bind_group_layouts,
+		push_constant_ranges: &[],
+	});
+
+	// Jump flood passes write seed positions, while the composite blends onto the surface
+	let color_target = match surface_format {
+		Some(format) => wgpu::ColorTargetState {
+			format,
+			blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+			write_mask: wgpu::ColorWrites::ALL,
+		},
+		None => wgpu::ColorTargetState {
+			format: OutlineRender::JUMP_FORMAT,
+			blend: None,
+			write_mask: wgpu::ColorWrites::ALL,
+		},
+	};
+
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some(&format!("{label} Pipeline")),
+		layout: Some(&pipeline_layout),
+		vertex: wgpu::VertexState {
+			module: &shader_module,
+			entry_point: "vertex_main",
+			buffers: &[],
+		},
+		primitive: wgpu::PrimitiveState::default(),
+		depth_stencil: None,
+		multisample: wgpu::MultisampleState::default(),
+		fragment: Some(wgpu::FragmentState {
+			module: &shader_module,
+			entry_point: fragment_entry_point,
+			targets: &[Some(color_target)],
+		}),
+		multiview: None,
+	})
+}
+
+const MASK_SHADER_SOURCE: &str = "
+struct Light {
+    position: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Uniform {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    light: Light,
+    inverse_view_projection: mat4x4<f32>,
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> ubo: Uniform;
+
+struct DrawData {
+    model: mat4x4<f32>,
+    joint_offset: u32,
+    padding_0: u32,
+    padding_1: u32,
+    padding_2: u32,
+};
+
+@group(1) @binding(0)
+var<storage, read> draws: array<DrawData>;
+
+@group(2) @binding(0)
+var scene_depth: texture_depth_2d;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv_0: vec2<f32>,
+    @location(3) uv_1: vec2<f32>,
+    @location(4) joint_0: vec4<f32>,
+    @location(5) weight_0: vec4<f32>,
+    @location(6) color_0: vec3<f32>,
+};
+
+@vertex
+fn vertex_main(vert: VertexInput, @builtin(instance_index) draw_index: u32) -> @builtin(position) vec4<f32> {
+    return ubo.projection * ubo.view * draws[draw_index].model * vec4(vert.position, 1.0);
+}
+
+@fragment
+fn fragment_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let depth = textureLoad(scene_depth, vec2<i32>(position.xy), 0);
+    let visible = select(0.0, 1.0, position.z <= depth + 0.00001);
+    return vec4<f32>(1.0, visible, 0.0, 0.0);
+}
+";
+
+const JUMP_FLOOD_SHADER_SOURCE: &str = "
+struct Light {
+    position: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Uniform {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    light: Light,
+    inverse_view_projection: mat4x4<f32>,
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> ubo: Uniform;
+
+struct OutlineSettings {
+    color: vec4<f32>,
+    width: f32,
+    occluded_alpha: f32,
+    padding_0: f32,
+    padding_1: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> settings: OutlineSettings;
+
+struct JumpStep {
+    step: u32,
+    padding_0: u32,
+    padding_1: u32,
+    padding_2: u32,
+};
+
+@group(1) @binding(1)
+var<uniform> jump: JumpStep;
+
+@group(1) @binding(2)
+var mask: texture_2d<f32>;
+
+// Each pixel holds the position of its nearest silhouette pixel, whether that pixel is visible,
+// and a one in the last component once a silhouette pixel has been found
+@group(1) @binding(3)
+var nearest_seeds: texture_2d<f32>;
+
+@vertex
+fn vertex_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn seed_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let coverage = textureLoad(mask, vec2<i32>(position.xy), 0);
+    if (coverage.r <= 0.0) {
+        return vec4<f32>(0.0);
+    }
+    return vec4<f32>(position.xy, coverage.g, 1.0);
+}
+
+@fragment
+fn jump_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let minimum = vec2<i32>(ubo.viewport.xy);
+    let maximum = vec2<i32>(ubo.viewport.xy + ubo.viewport.zw) - vec2(1, 1);
+    let step = i32(jump.step);
+
+    var nearest = vec4<f32>(0.0);
+    var nearest_distance = 1.0e20;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let sample_pixel = pixel + vec2(x, y) * step;
+            if (any(sample_pixel < minimum) || any(sample_pixel > maximum)) {
+                continue;
+            }
+            let seed = textureLoad(nearest_seeds, sample_pixel, 0);
+            let distance = length(seed.xy - position.xy);
+            if (seed.w > 0.0 && distance < nearest_distance) {
+                nearest = seed;
+                nearest_distance = distance;
+            }
+        }
+    }
+    return nearest;
+}
+
+@fragment
+fn composite_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let seed = textureLoad(nearest_seeds, pixel, 0);
+    let inside = textureLoad(mask, pixel, 0).r > 0.0;
+    let distance = length(seed.xy - position.xy);
+    if (inside || seed.w <= 0.0 || distance > settings.width + 0.5) {
+        discard;
+    }
+
+    // Fades the outer edge over a pixel to soften aliasing
+    let coverage = clamp(settings.width + 0.5 - distance, 0.0, 1.0);
+    let visibility = select(settings.occluded_alpha, 1.0, seed.z > 0.5);
+    return vec4<f32>(settings.color.rgb, settings.color.a * coverage * visibility);
+}
+";
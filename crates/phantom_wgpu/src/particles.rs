@@ -0,0 +1,752 @@
+use super::{
+	world::UniformBinding,
+	world_texture::{create_rgba_texture, WorldTextures},
+};
+use nalgebra_glm as glm;
+use phantom_world::{legion::IntoQuery, Entity, ParticleEmitter, RenderTarget, Viewport, World};
+use std::{borrow::Cow, collections::HashMap, mem::size_of, time::Instant};
+use wgpu::{
+	self,
+	util::{BufferInitDescriptor, DeviceExt},
+	BindGroup, BindGroupLayout, Buffer, BufferAddress, CommandEncoder, ComputePipeline, Device,
+	Queue, RenderPass, RenderPipeline, TextureFormat, TextureView,
+};
+
+pub struct ParticleRender {
+	pub simulate_pipeline: ComputePipeline,
+	pub render_pipeline: RenderPipeline,
+	pub simulate_bind_group_layout: BindGroupLayout,
+	pub render_bind_group_layout: BindGroupLayout,
+	pub sampler: wgpu::Sampler,
+	pub default_texture_view: TextureView,
+	pub emitters: HashMap<Entity, EmitterBinding>,
+	pub last_update: Instant,
+}
+
+impl ParticleRender {
+	const WORKGROUP_SIZE: u32 = 64;
+	const MAX_DELTA_TIME: f32 = 0.1;
+	const SPRITE_SIZE: u32 = 32;
+
+	pub fn new(
+		device: &Device,
+		queue: &Queue,
+		surface_format: TextureFormat,
+		uniform: &UniformBinding,
+		depth_bind_group_layout: &BindGroupLayout,
+		environment_bind_group_layout: &BindGroupLayout,
+	) -> Self {
+		let simulate_bind_group_layout = create_simulate_bind_group_layout(device);
+		let render_bind_group_layout = create_render_bind_group_layout(device);
+		let simulate_pipeline = create_simulate_pipeline(device, &simulate_bind_group_layout);
+		let render_pipeline = create_render_pipeline(
+			device,
+			surface_format,
+			&[
+				&uniform.bind_group_layout,
+				&render_bind_group_layout,
+				depth_bind_group_layout,
+				environment_bind_group_layout,
+			],
+		);
+		let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("Particle Sampler"),
+			mag_filter: wgpu::FilterMode::Linear,
+			min_filter: wgpu::FilterMode::Linear,
+			..Default::default()
+		});
+		let default_texture_view = create_rgba_texture(
+			device,
+			queue,
+			"Particle Texture",
+			TextureFormat::Rgba8UnormSrgb,
+			(Self::SPRITE_SIZE, Self::SPRITE_SIZE),
+			&soft_sprite_pixels(Self::SPRITE_SIZE),
+		)
+		.create_view(&wgpu::TextureViewDescriptor::default());
+		Self {
+			simulate_pipeline,
+			render_pipeline,
+			simulate_bind_group_layout,
+			render_bind_group_layout,
+			sampler,
+			default_texture_view,
+			emitters: HashMap::new(),
+			last_update: Instant::now(),
+		}
+	}
+
+	/// Creates, recreates and frees emitter buffers to match the world
+	/// and uploads the parameters for this frame's simulation step
+	pub fn update(
+		&mut self,
+		device: &Device,
+		queue: &Queue,
+		world: &World,
+		textures: &mut WorldTextures,
+	) {
+		let now = Instant::now();
+		let delta_time = now
+			.duration_since(self.last_update)
+			.as_secs_f32()
+			.min(Self::MAX_DELTA_TIME);
+		self.last_update = now;
+
+		let mut query = <(Entity, &ParticleEmitter)>::query();
+		let emitters = query
+			.iter(&world.ecs)
+			.map(|(entity, emitter)| (*entity, emitter.clone()))
+			.collect::<Vec<_>>();
+
+		self.emitters.retain(|entity, _| {
+			emitters
+				.iter()
+				.any(|(emitter_entity, _)| emitter_entity == entity)
+		});
+
+		for (entity, emitter) in emitters.iter() {
+			let model = match world.entity_global_transform_matrix(*entity) {
+				Ok(model) => model,
+				Err(error) => {
+					log::warn!("Failed to get particle emitter transform: {error}");
+					continue;
+				}
+			};
+
+			let needs_binding = match self.emitters.get(entity) {
+				Some(binding) => {
+					binding.capacity != emitter.particle_capacity()
+						|| binding.texture != emitter.texture
+				}
+				None => true,
+			};
+			if needs_binding {
+				// Falls back to the default sprite when the texture can't be used
+				let texture_view = textures.view(
+					device,
+					queue,
+					world,
+					emitter.texture,
+					TextureFormat::Rgba8UnormSrgb,
+					"Particle",
+				);
+				let binding = EmitterBinding::new(device, self, texture_view, emitter);
+				self.emitters.insert(*entity, binding);
+			}
+
+			if let Some(binding) = self.emitters.get_mut(entity) {
+				binding.update(queue, emitter, model, delta_time);
+			}
+		}
+	}
+
+	/// Records the compute pass that advances every emitter's particles
+	pub fn simulate(&self, encoder: &mut CommandEncoder) {
+		if self.emitters.is_empty() {
+			return;
+		}
+		let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+			label: Some("Particle Simulation Pass"),
+		});
+		compute_pass.set_pipeline(&self.simulate_pipeline);
+		for binding in self.emitters.values() {
+			compute_pass.set_bind_group(0, &binding.simulate_bind_group, &[]);
+			let workgroups = (binding.capacity + Self::WORKGROUP_SIZE - 1) / Self::WORKGROUP_SIZE;
+			compute_pass.dispatch_workgroups(workgroups, 1, 1);
+		}
+	}
+
+	pub fn render<'rp>(
+		&'rp self,
+		render_pass: &mut RenderPass<'rp>,
+		uniform: &'rp UniformBinding,
+		views: &[(RenderTarget, Viewport)],
+		target: &RenderTarget,
+		depth_bind_group: &'rp BindGroup,
+		environment_bind_group: &'rp BindGroup,
+	) {
+		if self.emitters.is_empty() {
+			return;
+		}
+
+		render_pass.set_pipeline(&self.render_pipeline);
+		render_pass.set_bind_group(2, depth_bind_group, &[]);
+		render_pass.set_bind_group(3, environment_bind_group, &[]);
+
+		for (view_index, (view_target, viewport)) in views.iter().enumerate() {
+			if view_target != target {
+				continue;
+			}
+			render_pass.set_viewport(
+				viewport.x,
+				viewport.y,
+				viewport.width,
+				viewport.height,
+				0.0,
+				1.0,
+			);
+			let view_offset = uniform.view_offset(view_index);
+			render_pass.set_bind_group(0, &uniform.bind_group, &[view_offset]);
+
+			for binding in self.emitters.values() {
+				render_pass.set_bind_group(1, &binding.render_bind_group, &[]);
+				render_pass.draw(0..6, 0..binding.capacity);
+			}
+		}
+	}
+}
+
+pub struct EmitterBinding {
+	pub capacity: u32,
+	pub texture: Option<usize>,
+	pub uniform_buffer: Buffer,
+	pub particle_buffer: Buffer,
+	pub simulate_bind_group: BindGroup,
+	pub render_bind_group: BindGroup,
+	pub spawn_accumulator: f32,
+	pub spawn_cursor: u32,
+	pub seed: u32,
+}
+
+impl EmitterBinding {
+	fn new(
+		device: &Device,
+		particle_render: &ParticleRender,
+		texture_view: Option<TextureView>,
+		emitter: &ParticleEmitter,
+	) -> Self {
+		let capacity = emitter.particle_capacity();
+
+		let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Particle Emitter Uniform Buffer"),
+			size: size_of::<EmitterUniform>() as BufferAddress,
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		// Zeroed particles have no lifetime, so every particle starts out dead
+		let particle_buffer = device.create_buffer_init(&BufferInitDescriptor {
+			label: Some("Particle Buffer"),
+			contents: bytemuck::cast_slice(&vec![Particle::default(); capacity as usize]),
+			usage: wgpu::BufferUsages::STORAGE,
+		});
+
+		let simulate_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Particle Simulation Bind Group"),
+			layout: &particle_render.simulate_bind_group_layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: uniform_buffer.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: particle_buffer.as_entire_binding(),
+				},
+			],
+		});
+
+		let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Particle Render Bind Group"),
+			layout: &particle_render.render_bind_group_layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: uniform_buffer.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: particle_buffer.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 2,
+					resource: wgpu::BindingResource::TextureView(
+						texture_view
+							.as_ref()
+							.unwrap_or(&particle_render.default_texture_view),
+					),
+				},
+				wgpu::BindGroupEntry {
+					binding: 3,
+					resource: wgpu::BindingResource::Sampler(&particle_render.sampler),
+				},
+			],
+		});
+
+		Self {
+			capacity,
+			texture: emitter.texture,
+			uniform_buffer,
+			particle_buffer,
+			simulate_bind_group,
+			render_bind_group,
+			spawn_accumulator: 0.0,
+			spawn_cursor: 0,
+			seed: 0,
+		}
+	}
+
+	fn update(
+		&mut self,
+		queue: &Queue,
+		emitter: &ParticleEmitter,
+		model: glm::Mat4,
+		delta_time: f32,
+	) {
+		// Particles are spawned into a ring so the oldest are recycled first
+		let mut spawn_count = 0;
+		if emitter.enabled {
+			self.spawn_accumulator += emitter.spawn_rate * delta_time;
+			spawn_count = (self.spawn_accumulator.floor() as u32).min(self.capacity);
+			self.spawn_accumulator -= spawn_count as f32;
+		}
+		let spawn_start = self.spawn_cursor;
+		self.spawn_cursor = (self.spawn_cursor + spawn_count) % self.capacity;
+		self.seed = self.seed.wrapping_add(1);
+
+		let uniform = EmitterUniform {
+			model,
+			gravity: glm::vec3_to_vec4(&emitter.gravity),
+			start_color: emitter.start_color,
+			end_color: emitter.end_color,
+			speed: emitter.speed,
+			cone_angle: emitter.cone_angle,
+			lifetime: emitter.lifetime,
+			delta_time,
+			start_size: emitter.start_size,
+			end_size: emitter.end_size,
+			soft_fade_distance: emitter.soft_fade_distance,
+			padding: 0.0,
+			spawn_start,
+			spawn_count,
+			capacity: self.capacity,
+			seed: self.seed,
+		};
+		queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+	}
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Particle {
+	/// The `w` component is the particle's age
+	pub position: glm::Vec4,
+	/// The `w` component is the particle's lifetime
+	pub velocity: glm::Vec4,
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct EmitterUniform {
+	pub model: glm::Mat4,
+	pub gravity: glm::Vec4,
+	pub start_color: glm::Vec4,
+	pub end_color: glm::Vec4,
+	pub speed: f32,
+	pub cone_angle: f32,
+	pub lifetime: f32,
+	pub delta_time: f32,
+	pub start_size: f32,
+	pub end_size: f32,
+	pub soft_fade_distance: f32,
+	pub padding: f32,
+	pub spawn_start: u32,
+	pub spawn_count: u32,
+	pub capacity: u32,
+	pub seed: u32,
+}
+
+/// A white sprite whose alpha falls off smoothly from the center
+fn soft_sprite_pixels(size: u32) -> Vec<u8> {
+	let center = (size as f32 - 1.0) / 2.0;
+	(0..size * size)
+		.flat_map(|index| {
+			let x = (index % size) as f32 - center;
+			let y = (index / size) as f32 - center;
+			let distance = (x * x + y * y).sqrt() / center;
+			let alpha = (1.0 - distance).clamp(0.0, 1.0).powf(2.0);
+			[255, 255, 255, (alpha * 255.0) as u8]
+		})
+		.collect()
+}
+
+fn create_simulate_bind_group_layout(device: &Device) -> BindGroupLayout {
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Particle Simulation Bind Group Layout"),
+		entries: &[
+			uniform_layout_entry(0, wgpu::ShaderStages::COMPUTE),
+			storage_layout_entry(1, wgpu::ShaderStages::COMPUTE, false),
+		],
+	})
+}
+
+fn create_render_bind_group_layout(device: &Device) -> BindGroupLayout {
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Particle Render Bind Group Layout"),
+		entries: &[
+			uniform_layout_entry(0, wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT),
+			storage_layout_entry(1, wgpu::ShaderStages::VERTEX, true),
+			wgpu::BindGroupLayoutEntry {
+				binding: 2,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Texture {
+					sample_type: wgpu::TextureSampleType::Float { filterable: true },
+					view_dimension: wgpu::TextureViewDimension::D2,
+					multisampled: false,
+				},
+				count: None,
+			},
+			wgpu::BindGroupLayoutEntry {
+				binding: 3,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+				count: None,
+			},
+		],
+	})
+}
+
+fn uniform_layout_entry(
+	binding: u32,
+	visibility: wgpu::ShaderStages,
+) -> wgpu::BindGroupLayoutEntry {
+	wgpu::BindGroupLayoutEntry {
+		binding,
+		visibility,
+		ty: wgpu::BindingType::Buffer {
+			ty: wgpu::BufferBindingType::Uniform,
+			has_dynamic_offset: false,
+			min_binding_size: wgpu::BufferSize::new(size_of::<EmitterUniform>() as _),
+		},
+		count: None,
+	}
+}
+
+fn storage_layout_entry(
+	binding: u32,
+	visibility: wgpu::ShaderStages,
+	read_only: bool,
+) -> wgpu::BindGroupLayoutEntry {
+	wgpu::BindGroupLayoutEntry {
+		binding,
+		visibility,
+		ty: wgpu::BindingType::Buffer {
+			ty: wgpu::BufferBindingType::Storage { read_only },
+			has_dynamic_offset: false,
+			min_binding_size: wgpu::BufferSize::new(size_of::<Particle>() as _),
+		},
+		count: None,
+	}
+}
+
+fn create_simulate_pipeline(
+	device: &Device,
+	bind_group_layout: &BindGroupLayout,
+) -> ComputePipeline {
+	let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+		label: Some("Particle Simulation Shader"),
+		source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SIMULATE_SHADER_SOURCE)),
+	});
+
+	let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+		label: Some("Particle Simulation Pipeline Layout"),
+		bind_group_layouts: &[bind_group_layout],
+		push_constant_ranges: &[],
+	});
+
+	device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+		label: Some("Particle Simulation Pipeline"),
+		layout: Some(&pipeline_layout),
+		module: &shader_module,
+		entry_point: "simulate_main",
+	})
+}
+
+fn create_render_pipeline(
+	device: &Device,
+	surface_format: TextureFormat,
+	bind_group_layouts: &[&BindGroupLayout],
+) -> RenderPipeline {
+	let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+		label: Some("Particle Render Shader"),
+		source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(RENDER_SHADER_SOURCE)),
+	});
+
+	let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+		label: Some("Particle Render Pipeline Layout"),
+		bind_group_layouts,
+		push_constant_ranges: &[],
+	});
+
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some("Particle Render Pipeline"),
+		layout: Some(&pipeline_layout),
+		vertex: wgpu::VertexState {
+			module: &shader_module,
+			entry_point: "vertex_main",
+			buffers: &[],
+		},
+		primitive: wgpu::PrimitiveState::default(),
+		// Particles are depth tested against the scene but never occlude each other
+		depth_stencil: Some(wgpu::DepthStencilState {
+			format: wgpu::TextureFormat::Depth32Float,
+			depth_write_enabled: false,
+			depth_compare: wgpu::CompareFunction::LessEqual,
+			stencil: wgpu::StencilState::default(),
+			bias: wgpu::DepthBiasState::default(),
+		}),
+		multisample: wgpu::MultisampleState::default(),
+		fragment: Some(wgpu::FragmentState {
+			module: &shader_module,
+			entry_point: "fragment_main",
+			targets: &[Some(wgpu::ColorTargetState {
+				format: surface_format,
+				blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+				write_mask: wgpu::ColorWrites::ALL,
+			})],
+		}),
+		multiview: None,
+	})
+}
+
+const SIMULATE_SHADER_SOURCE: &str = "
+struct Particle {
+    position: vec4<f32>,
+    velocity: vec4<f32>,
+};
+
+struct Emitter {
+    model: mat4x4<f32>,
+    gravity: vec4<f32>,
+    start_color: vec4<f32>,
+    end_color: vec4<f32>,
+    speed: f32,
+    cone_angle: f32,
+    lifetime: f32,
+    delta_time: f32,
+    start_size: f32,
+    end_size: f32,
+    soft_fade_distance: f32,
+    padding: f32,
+    spawn_start: u32,
+    spawn_count: u32,
+    capacity: u32,
+    seed: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> emitter: Emitter;
+
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+
+fn hash(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random(seed: ptr<function, u32>) -> f32 {
+    *seed = hash(*seed);
+    return f32(*seed) / 4294967295.0;
+}
+
+@compute @workgroup_size(64)
+fn simulate_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= emitter.capacity) {
+        return;
+    }
+
+    var particle = particles[index];
+    let spawn_offset = (index + emitter.capacity - emitter.spawn_start) % emitter.capacity;
+    if (spawn_offset < emitter.spawn_count) {
+        var seed = hash(index ^ hash(emitter.seed));
+        let phi = random(&seed) * 6.2831853;
+        let cos_theta = mix(1.0, cos(emitter.cone_angle), random(&seed));
+        let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+        let local_direction = vec3(sin_theta * cos(phi), cos_theta, sin_theta * sin(phi));
+        let direction = normalize((emitter.model * vec4(local_direction, 0.0)).xyz);
+        let origin = (emitter.model * vec4(0.0, 0.0, 0.0, 1.0)).xyz;
+        particle.position = vec4(origin, 0.0);
+        particle.velocity = vec4(direction * emitter.speed, emitter.lifetime);
+    } else if (particle.position.w < particle.velocity.w) {
+        let velocity = particle.velocity.xyz + emitter.gravity.xyz * emitter.delta_time;
+        let position = particle.position.xyz + velocity * emitter.delta_time;
+        particle.position = vec4(position, particle.position.w + emitter.delta_time);
+        particle.velocity = vec4(velocity, particle.velocity.w);
+    }
+    particles[index] = particle;
+}
+";
+
+const RENDER_SHADER_SOURCE: &str = "
+struct Light {
+    position: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Uniform {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    light: Light,
+    inverse_view_projection: mat4x4<f32>,
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> ubo: Uniform;
+
+struct Particle {
+    position: vec4<f32>,
+    velocity: vec4<f32>,
+};
+
+struct Emitter {
+    model: mat4x4<f32>,
+    gravity: vec4<f32>,
+    start_color: vec4<f32>,
+    end_color: vec4<f32>,
+    speed: f32,
+    cone_angle: f32,
+    lifetime: f32,
+    delta_time: f32,
+    start_size: f32,
+    end_size: f32,
+    soft_fade_distance: f32,
+    padding: f32,
+    spawn_start: u32,
+    spawn_count: u32,
+    capacity: u32,
+    seed: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> emitter: Emitter;
+
+@group(1) @binding(1)
+var<storage, read> particles: array<Particle>;
+
+@group(1) @binding(2)
+var particle_texture: texture_2d<f32>;
+
+@group(1) @binding(3)
+var particle_sampler: sampler;
+
+@group(2) @binding(0)
+var scene_depth: texture_depth_2d;
+
+struct Environment {
+    ambient: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_parameters: vec4<f32>,
+    fog_mode: u32,
+    padding_0: u32,
+    padding_1: u32,
+    padding_2: u32,
+};
+
+@group(3) @binding(0)
+var<uniform> environment: Environment;
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let offset = world_position - ubo.camera_position.xyz;
+    let distance = length(offset);
+    let parameters = environment.fog_parameters;
+    var visibility = 1.0;
+    switch (environment.fog_mode) {
+        case 1u: {
+            let range = max(parameters.y - parameters.x, 0.0001);
+            visibility = clamp((parameters.y - distance) / range, 0.0, 1.0);
+        }
+        case 2u: {
+            visibility = exp(-parameters.x * distance);
+        }
+        case 3u: {
+            // Integrates the density along the view ray as it thins out with height
+            let camera_height = ubo.camera_position.y - parameters.y;
+            let camera_density = parameters.x * exp(-parameters.z * camera_height);
+            let rise = parameters.z * offset.y;
+            var thinning = 1.0;
+            if (abs(rise) > 0.0001) {
+                thinning = (1.0 - exp(-rise)) / rise;
+            }
+            visibility = exp(-camera_density * distance * thinning);
+        }
+        default: {}
+    }
+    return mix(environment.fog_color.rgb, color, visibility);
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) view_depth: f32,
+    @location(3) world_position: vec3<f32>,
+};
+
+@vertex
+fn vertex_main(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32
+) -> VertexOutput {
+    var out: VertexOutput;
+
+    let particle = particles[instance_index];
+    if (particle.position.w >= particle.velocity.w) {
+        // Dead particles are moved outside of the clip volume
+        out.position = vec4(0.0, 0.0, 2.0, 1.0);
+        return out;
+    }
+
+    var corners = array<vec2<f32>, 6>(
+        vec2(-1.0, -1.0),
+        vec2(1.0, -1.0),
+        vec2(1.0, 1.0),
+        vec2(-1.0, -1.0),
+        vec2(1.0, 1.0),
+        vec2(-1.0, 1.0)
+    );
+    let corner = corners[vertex_index];
+
+    let life = particle.position.w / particle.velocity.w;
+    let size = mix(emitter.start_size, emitter.end_size, life);
+
+    // Billboards face the camera using the rows of the view matrix
+    let right = vec3(ubo.view[0][0], ubo.view[1][0], ubo.view[2][0]);
+    let up = vec3(ubo.view[0][1], ubo.view[1][1], ubo.view[2][1]);
+    let world_position = particle.position.xyz + (right * corner.x + up * corner.y) * size;
+    let view_position = ubo.view * vec4(world_position, 1.0);
+
+    out.position = ubo.projection * view_position;
+    out.uv = corner * vec2(0.5, -0.5) + 0.5;
+    out.color = mix(emitter.start_color, emitter.end_color, life);
+    out.view_depth = -view_position.z;
+    out.world_position = world_position;
+    return out;
+}
+
+fn linear_depth(depth: f32) -> f32 {
+    if (ubo.projection[2][3] == 0.0) {
+        return (ubo.projection[3][2] - depth) / ubo.projection[2][2];
+    }
+    return ubo.projection[3][2] / (depth + ubo.projection[2][2]);
+}
+
+@fragment
+fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = textureSample(particle_texture, particle_sampler, in.uv);
+
+    let scene_depth = linear_depth(textureLoad(scene_depth, vec2<i32>(in.position.xy), 0));
+    let distance = scene_depth - in.view_depth;
+    var fade = 1.0;
+    if (emitter.soft_fade_distance > 0.0) {
+        fade = clamp(distance / emitter.soft_fade_distance, 0.0, 1.0);
+    }
+
+    let color = in.color * texel;
+    return vec4(apply_fog(color.rgb, in.world_position), color.a * fade);
+}
+";
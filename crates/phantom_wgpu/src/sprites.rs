@@ -0,0 +1,618 @@
+use super::world::UniformBinding;
+use nalgebra_glm as glm;
+use phantom_world::{
+	legion::IntoQuery, BillboardMode, Camera, Entity, Light, RenderTarget, Sprite,
+	TextureFormat as WorldTextureFormat, Viewport, World,
+};
+use std::{
+	borrow::Cow,
+	collections::{BTreeMap, HashMap},
+	mem::size_of,
+	ops::Range,
+	time::Instant,
+};
+use wgpu::{
+	self, util::DeviceExt, BindGroup, BindGroupLayout, Buffer, BufferAddress, Device, Queue,
+	RenderPass, RenderPipeline, TextureFormat,
+};
+
+/// The texture a batch of sprites is drawn with
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SpriteTexture {
+	Blank,
+	World(usize),
+	EditorIcons,
+}
+
+pub struct SpriteRender {
+	pub pipeline: RenderPipeline,
+	pub bind_group_layout: BindGroupLayout,
+	pub sampler: wgpu::Sampler,
+	pub blank_bind_group: BindGroup,
+	pub editor_icons_bind_group: BindGroup,
+	pub textures: HashMap<usize, BindGroup>,
+	pub instance_buffer: Buffer,
+	pub capacity: usize,
+	pub batches: Vec<(SpriteTexture, Range<u32>)>,
+	/// Draws icons for lights and cameras, which have no geometry of their own
+	pub show_editor_icons: bool,
+	pub start_time: Instant,
+}
+
+impl SpriteRender {
+	const INITIAL_CAPACITY: usize = 256;
+	const QUAD_VERTEX_COUNT: u32 = 6;
+	const EDITOR_ICON_SIZE: u32 = 32;
+	const EDITOR_ICON_WORLD_SIZE: f32 = 0.5;
+
+	pub fn new(
+		device: &Device,
+		queue: &Queue,
+		surface_format: TextureFormat,
+		uniform: &UniformBinding,
+		environment_bind_group_layout: &BindGroupLayout,
+	) -> Self {
+		let bind_group_layout = create_bind_group_layout(device);
+		let pipeline = create_pipeline(
+			device,
+			surface_format,
+			&[
+				&uniform.bind_group_layout,
+				&bind_group_layout,
+				environment_bind_group_layout,
+			],
+		);
+		let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("Sprite Sampler"),
+			mag_filter: wgpu::FilterMode::Linear,
+			min_filter: wgpu::FilterMode::Linear,
+			..Default::default()
+		});
+		let blank_bind_group = create_texture_bind_group(
+			device,
+			queue,
+			&bind_group_layout,
+			&sampler,
+			(1, 1),
+			&[255, 255, 255, 255],
+		);
+		let editor_icons_bind_group = create_texture_bind_group(
+			device,
+			queue,
+			&bind_group_layout,
+			&sampler,
+			(
+				Self::EDITOR_ICON_SIZE * EditorIcon::COUNT,
+				Self::EDITOR_ICON_SIZE,
+			),
+			&editor_icon_pixels(Self::EDITOR_ICON_SIZE),
+		);
+		let capacity = Self::INITIAL_CAPACITY;
+		let instance_buffer = create_instance_buffer(device, capacity);
+		Self {
+			pipeline,
+			bind_group_layout,
+			sampler,
+			blank_bind_group,
+			editor_icons_bind_group,
+			textures: HashMap::new(),
+			instance_buffer,
+			capacity,
+			batches: Vec::new(),
+			show_editor_icons: false,
+			start_time: Instant::now(),
+		}
+	}
+
+	/// Gathers the world's sprites into one batch of instances per texture
+	pub fn update(&mut self, device: &Device, queue: &Queue, world: &World) {
+		let time = self.start_time.elapsed().as_secs_f32();
+		let mut batches = BTreeMap::<SpriteTexture, Vec<SpriteInstance>>::new();
+
+		let mut query = <(Entity, &Sprite)>::query();
+		for (entity, sprite) in query.iter(&world.ecs).filter(|(_, sprite)| sprite.enabled) {
+			let model = match world.entity_global_transform_matrix(*entity) {
+				Ok(model) => model,
+				Err(error) => {
+					log::warn!("Failed to get sprite transform: {error}");
+					continue;
+				}
+			};
+			let texture = match sprite.texture {
+				Some(texture_index) if self.load_texture(device, queue, world, texture_index) => {
+					SpriteTexture::World(texture_index)
+				}
+				_ => SpriteTexture::Blank,
+			};
+			batches
+				.entry(texture)
+				.or_default()
+				.push(SpriteInstance::new(&model, sprite, sprite.uv_rect(time)));
+		}
+
+		if self.show_editor_icons {
+			let mut icons = Vec::new();
+			let mut query = <(Entity, &Light)>::query();
+			for (entity, light) in query.iter(&world.ecs) {
+				let color = light.color / glm::comp_max(&light.color).max(1.0);
+				icons.push((*entity, EditorIcon::Light, glm::vec3_to_vec4(&color)));
+			}
+			let mut query = <(Entity, &Camera)>::query();
+			for (entity, _) in query.iter(&world.ecs) {
+				icons.push((*entity, EditorIcon::Camera, glm::vec4(1.0, 1.0, 1.0, 1.0)));
+			}
+
+			for (entity, icon, color) in icons {
+				let model = match world.entity_global_transform_matrix(entity) {
+					Ok(model) => model,
+					Err(_) => continue,
+				};
+				// Icons keep the same size however the entity is scaled
+				let translation = glm::translation(&glm::column(&model, 3).xyz());
+				let sprite = Sprite {
+					color,
+					size: glm::vec2(Self::EDITOR_ICON_WORLD_SIZE, Self::EDITOR_ICON_WORLD_SIZE),
+					..Default::default()
+				};
+				batches
+					.entry(SpriteTexture::EditorIcons)
+					.or_default()
+					.push(SpriteInstance::new(&translation, &sprite, icon.uv_rect()));
+			}
+		}
+
+		self.batches.clear();
+		let mut instances = Vec::new();
+		for (texture, batch) in batches {
+			let start = instances.len() as u32;
+			instances.extend(batch);
+			self.batches.push((texture, start..instances.len() as u32));
+		}
+
+		if instances.len() > self.capacity {
+			self.capacity = instances.len().next_power_of_two();
+			self.instance_buffer = create_instance_buffer(device, self.capacity);
+		}
+		if !instances.is_empty() {
+			queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+		}
+	}
+
+	pub fn render<'rp>(
+		&'rp self,
+		render_pass: &mut RenderPass<'rp>,
+		uniform: &'rp UniformBinding,
+		views: &[(RenderTarget, Viewport)],
+		target: &RenderTarget,
+		environment_bind_group: &'rp BindGroup,
+	) {
+		if self.batches.is_empty() {
+			return;
+		}
+
+		render_pass.set_pipeline(&self.pipeline);
+		render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+		render_pass.set_bind_group(2, environment_bind_group, &[]);
+
+		for (view_index, (view_target, viewport)) in views.iter().enumerate() {
+			if view_target != target {
+				continue;
+			}
+			render_pass.set_viewport(
+				viewport.x,
+				viewport.y,
+				viewport.width,
+				viewport.height,
+				0.0,
+				1.0,
+			);
+			let view_offset = uniform.view_offset(view_index);
+			render_pass.set_bind_group(0, &uniform.bind_group, &[view_offset]);
+
+			for (texture, instances) in self.batches.iter() {
+				let bind_group = match texture {
+					SpriteTexture::Blank => &self.blank_bind_group,
+					SpriteTexture::World(texture_index) => &self.textures[texture_index],
+					SpriteTexture::EditorIcons => &self.editor_icons_bind_group,
+				};
+				render_pass.set_bind_group(1, bind_group, &[]);
+				render_pass.draw(0..Self::QUAD_VERTEX_COUNT, instances.clone());
+			}
+		}
+	}
+
+	/// Uploads a world texture the first time it is used,
+	/// returning whether it can be drawn with
+	fn load_texture(
+		&mut self,
+		device: &Device,
+		queue: &Queue,
+		world: &World,
+		texture_index: usize,
+	) -> bool {
+		if self.textures.contains_key(&texture_index) {
+			return true;
+		}
+		let texture = match world.textures.get(texture_index) {
+			Some(texture) if texture.format == WorldTextureFormat::R8G8B8A8 => texture,
+			Some(texture) => {
+				log::warn!(
+					"Sprite texture format {:?} is not supported",
+					texture.format
+				);
+				return false;
+			}
+			None => {
+				log::warn!("Sprite texture {texture_index} not found");
+				return false;
+			}
+		};
+		let bind_group = create_texture_bind_group(
+			device,
+			queue,
+			&self.bind_group_layout,
+			&self.sampler,
+			(texture.width, texture.height),
+			&texture.pixels,
+		);
+		self.textures.insert(texture_index, bind_group);
+		true
+	}
+}
+
+/// The icons in the editor icon sheet, from left to right
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum EditorIcon {
+	Light,
+	Camera,
+}
+
+impl EditorIcon {
+	const COUNT: u32 = 2;
+
+	fn uv_rect(&self) -> glm::Vec4 {
+		let width = 1.0 / Self::COUNT as f32;
+		glm::vec4(*self as u32 as f32 * width, 0.0, width, 1.0)
+	}
+
+	/// Whether a point in the icon, from -1 to 1 on both axes, is covered
+	fn covers(&self, x: f32, y: f32) -> bool {
+		match self {
+			// A disc surrounded by rays
+			Self::Light => {
+				let radius = (x * x + y * y).sqrt();
+				let angle = y.atan2(x);
+				radius < 0.45 || (radius > 0.6 && radius < 0.9 && (angle * 8.0).cos() > 0.7)
+			}
+			// A body with a lens sticking out of its right side
+			Self::Camera => {
+				let body = (-0.8..0.3).contains(&x) && y.abs() < 0.4;
+				let lens = (0.3..0.85).contains(&x) && y.abs() < 0.15 + (x - 0.3) * 0.4;
+				body || lens
+			}
+		}
+	}
+}
+
+/// White icons on a transparent background, laid out side by side
+fn editor_icon_pixels(icon_size: u32) -> Vec<u8> {
+	let width = icon_size * EditorIcon::COUNT;
+	(0..icon_size)
+		.flat_map(|row| (0..width).map(move |column| (column, row)))
+		.flat_map(|(column, row)| {
+			let icon = match column / icon_size {
+				0 => EditorIcon::Light,
+				_ => EditorIcon::Camera,
+			};
+			let to_icon_space =
+				|value: u32| (value % icon_size) as f32 / (icon_size - 1) as f32 * 2.0 - 1.0;
+			let x = to_icon_space(column);
+			let y = -to_icon_space(row);
+			let alpha = if icon.covers(x, y) { 255 } else { 0 };
+			[255, 255, 255, alpha]
+		})
+		.collect()
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SpriteInstance {
+	pub position: glm::Vec3,
+	pub alpha_cutoff: f32,
+	/// The axis cylindrical billboards turn around
+	pub axis: glm::Vec3,
+	/// Zero for spherical billboards and one for cylindrical billboards
+	pub billboard_mode: u32,
+	pub size: glm::Vec2,
+	pub pivot: glm::Vec2,
+	pub color: glm::Vec4,
+	/// The offset and scale of the drawn region of the texture
+	pub uv_rect: glm::Vec4,
+}
+
+impl SpriteInstance {
+	pub fn new(model: &glm::Mat4, sprite: &Sprite, uv_rect: glm::Vec4) -> Self {
+		let x_axis = glm::column(model, 0).xyz();
+		let y_axis = glm::column(model, 1).xyz();
+		let scale = glm::vec2(glm::length(&x_axis), glm::length(&y_axis));
+		Self {
+			position: glm::column(model, 3).xyz(),
+			alpha_cutoff: sprite.alpha_cutoff,
+			axis: y_axis.normalize(),
+			billboard_mode: match sprite.billboard {
+				BillboardMode::Spherical => 0,
+				BillboardMode::Cylindrical => 1,
+			},
+			size: sprite.size.component_mul(&scale),
+			pivot: sprite.pivot,
+			color: sprite.color,
+			uv_rect,
+		}
+	}
+}
+
+fn create_instance_buffer(device: &Device, capacity: usize) -> Buffer {
+	device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Sprite Instance Buffer"),
+		size: (capacity * size_of::<SpriteInstance>()) as BufferAddress,
+		usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	})
+}
+
+fn create_texture_bind_group(
+	device: &Device,
+	queue: &Queue,
+	bind_group_layout: &BindGroupLayout,
+	sampler: &wgpu::Sampler,
+	(width, height): (u32, u32),
+	pixels: &[u8],
+) -> BindGroup {
+	let format = TextureFormat::Rgba8UnormSrgb;
+	let texture = device.create_texture_with_data(
+		queue,
+		&wgpu::TextureDescriptor {
+			label: Some("Sprite Texture"),
+			size: wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format,
+			usage: wgpu::TextureUsages::TEXTURE_BINDING,
+			view_formats: &[format],
+		},
+		pixels,
+	);
+	let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+	device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Sprite Texture Bind Group"),
+		layout: bind_group_layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: wgpu::BindingResource::TextureView(&view),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: wgpu::BindingResource::Sampler(sampler),
+			},
+		],
+	})
+}
+
+fn create_bind_group_layout(device: &Device) -> BindGroupLayout {
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Sprite Bind Group Layout"),
+		entries: &[
+			wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Texture {
+					sample_type: wgpu::TextureSampleType::Float { filterable: true },
+					view_dimension: wgpu::TextureViewDimension::D2,
+					multisampled: false,
+				},
+				count: None,
+			},
+			wgpu::BindGroupLayoutEntry {
+				binding: 1,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+				count: None,
+			},
+		],
+	})
+}
+
+fn create_pipeline(
+	device: &Device,
+	surface_format: TextureFormat,
+	bind_group_layouts: &[&BindGroupLayout],
+) -> RenderPipeline {
+	let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+		label: Some("Sprite Shader"),
+		source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER_SOURCE)),
+	});
+
+	let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+		label: Some("Sprite Pipeline Layout"),
+		bind_group_layouts,
+		push_constant_ranges: &[],
+	});
+
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some("Sprite Pipeline"),
+		layout: Some(&pipeline_layout),
+		vertex: wgpu::VertexState {
+			module: &shader_module,
+			entry_point: "vertex_main",
+			buffers: &[wgpu::VertexBufferLayout {
+				array_stride: size_of::<SpriteInstance>() as wgpu::BufferAddress,
+				step_mode: wgpu::VertexStepMode::Instance,
+				attributes: &wgpu::vertex_attr_array![
+					0 => Float32x3, // position
+					1 => Float32,   // alpha_cutoff
+					2 => Float32x3, // axis
+					3 => Uint32,    // billboard_mode
+					4 => Float32x2, // size
+					5 => Float32x2, // pivot
+					6 => Float32x4, // color
+					7 => Float32x4, // uv_rect
+				],
+			}],
+		},
+		// Billboards are seen from both sides
+		primitive: wgpu::PrimitiveState {
+			cull_mode: None,
+			..Default::default()
+		},
+		depth_stencil: Some(wgpu::DepthStencilState {
+			format: wgpu::TextureFormat::Depth32Float,
+			depth_write_enabled: true,
+			depth_compare: wgpu::CompareFunction::Less,
+			stencil: wgpu::StencilState::default(),
+			bias: wgpu::DepthBiasState::default(),
+		}),
+		multisample: wgpu::MultisampleState::default(),
+		fragment: Some(wgpu::FragmentState {
+			module: &shader_module,
+			entry_point: "fragment_main",
+			targets: &[Some(wgpu::ColorTargetState {
+				format: surface_format,
+				blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+				write_mask: wgpu::ColorWrites::ALL,
+			})],
+		}),
+		multiview: None,
+	})
+}
+
+const SHADER_SOURCE: &str = "
+struct Light {
+    position: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Uniform {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    light: Light,
+    inverse_view_projection: mat4x4<f32>,
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> ubo: Uniform;
+
+@group(1) @binding(0)
+var sprite_texture: texture_2d<f32>;
+
+@group(1) @binding(1)
+var sprite_sampler: sampler;
+
+struct Environment {
+    ambient: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_parameters: vec4<f32>,
+    fog_mode: u32,
+    padding_0: u32,
+    padding_1: u32,
+    padding_2: u32,
+};
+
+@group(2) @binding(0)
+var<uniform> environment: Environment;
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let offset = world_position - ubo.camera_position.xyz;
+    let distance = length(offset);
+    let parameters = environment.fog_parameters;
+    var visibility = 1.0;
+    switch (environment.fog_mode) {
+        case 1u: {
+            let range = max(parameters.y - parameters.x, 0.0001);
+            visibility = clamp((parameters.y - distance) / range, 0.0, 1.0);
+        }
+        case 2u: {
+            visibility = exp(-parameters.x * distance);
+        }
+        case 3u: {
+            // Integrates the density along the view ray as it thins out with height
+            let camera_height = ubo.camera_position.y - parameters.y;
+            let camera_density = parameters.x * exp(-parameters.z * camera_height);
+            let rise = parameters.z * offset.y;
+            var thinning = 1.0;
+            if (abs(rise) > 0.0001) {
+                thinning = (1.0 - exp(-rise)) / rise;
+            }
+            visibility = exp(-camera_density * distance * thinning);
+        }
+        default: {}
+    }
+    return mix(environment.fog_color.rgb, color, visibility);
+}
+
+struct SpriteInstance {
+    @location(0) position: vec3<f32>,
+    @location(1) alpha_cutoff: f32,
+    @location(2) axis: vec3<f32>,
+    @location(3) billboard_mode: u32,
+    @location(4) size: vec2<f32>,
+    @location(5) pivot: vec2<f32>,
+    @location(6) color: vec4<f32>,
+    @location(7) uv_rect: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) alpha_cutoff: f32,
+};
+
+@vertex
+fn vertex_main(@builtin(vertex_index) vertex_index: u32, sprite: SpriteInstance) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2(0.0, 0.0),
+        vec2(1.0, 0.0),
+        vec2(1.0, 1.0),
+        vec2(0.0, 0.0),
+        vec2(1.0, 1.0),
+        vec2(0.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    var right = vec3(ubo.view[0][0], ubo.view[1][0], ubo.view[2][0]);
+    var up = vec3(ubo.view[0][1], ubo.view[1][1], ubo.view[2][1]);
+    if (sprite.billboard_mode == 1u) {
+        up = sprite.axis;
+        right = normalize(cross(up, ubo.camera_position.xyz - sprite.position));
+    }
+
+    let offset = (corner - sprite.pivot) * sprite.size;
+    let world_position = sprite.position + right * offset.x + up * offset.y;
+
+    var out: VertexOutput;
+    out.position = ubo.projection * ubo.view * vec4(world_position, 1.0);
+    out.uv = sprite.uv_rect.xy + vec2(corner.x, 1.0 - corner.y) * sprite.uv_rect.zw;
+    out.color = sprite.color;
+    out.world_position = world_position;
+    out.alpha_cutoff = sprite.alpha_cutoff;
+    return out;
+}
+
+@fragment
+fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = textureSample(sprite_texture, sprite_sampler, in.uv) * in.color;
+    if (color.a < in.alpha_cutoff) {
+        discard;
+    }
+    return vec4(apply_fog(color.rgb, in.world_position), color.a);
+}
+";
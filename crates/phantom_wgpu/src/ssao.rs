@@ -0,0 +1,727 @@
+use super::{
+	target::create_depth_texture,
+	world::{create_vertex_attributes, create_vertex_description, DrawBinding, UniformBinding},
+};
+use phantom_config::AmbientOcclusion;
+use phantom_world::{RenderTarget, Viewport};
+use std::{borrow::Cow, mem::size_of};
+use wgpu::{
+	self, util::DeviceExt, BindGroup, BindGroupLayout, Buffer, CommandEncoder, Device, Face, Queue,
+	RenderPass, RenderPipeline, TextureFormat, TextureView,
+};
+
+/// Renders screen-space ambient occlusion for each target from a normal and depth prepass.
+/// The blurred result is sampled by the scene shader to darken its ambient term.
+pub struct SsaoRender {
+	pub enabled: bool,
+	pub prepass_pipeline: RenderPipeline,
+	pub occlusion_pipeline: RenderPipeline,
+	pub blur_pipeline: RenderPipeline,
+	pub input_bind_group_layout: BindGroupLayout,
+	pub output_bind_group_layout: BindGroupLayout,
+	pub settings_buffer: Buffer,
+	pub unoccluded_bind_group: BindGroup,
+	pub targets: Vec<(RenderTarget, SsaoTarget)>,
+}
+
+impl SsaoRender {
+	const NORMAL_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+	const OCCLUSION_FORMAT: TextureFormat = TextureFormat::R8Unorm;
+	const DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+	const MAX_SAMPLE_COUNT: u32 = 64;
+
+	pub fn new(
+		device: &Device,
+		queue: &Queue,
+		uniform: &UniformBinding,
+		draw_data: &DrawBinding,
+	) -> Self {
+		let input_bind_group_layout = create_input_bind_group_layout(device);
+		let output_bind_group_layout = create_output_bind_group_layout(device);
+		let prepass_pipeline = create_prepass_pipeline(device, uniform, draw_data);
+		let occlusion_pipeline = create_fullscreen_pipeline(
+			device,
+			"SSAO",
+			"occlusion_main",
+			&[&uniform.bind_group_layout, &input_bind_group_layout],
+		);
+		let blur_pipeline = create_fullscreen_pipeline(
+			device,
+			"SSAO Blur",
+			"blur_main",
+			&[&uniform.bind_group_layout, &input_bind_group_layout],
+		);
+
+		let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("SSAO Settings Buffer"),
+			size: size_of::<SsaoSettings>() as wgpu::BufferAddress,
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		let unoccluded_view = device
+			.create_texture_with_data(
+				queue,
+				&wgpu::TextureDescriptor {
+					label: Some("Unoccluded SSAO Texture"),
+					size: wgpu::Extent3d {
+						width: 1,
+						height: 1,
+						depth_or_array_layers: 1,
+					},
+					mip_level_count: 1,
+					sample_count: 1,
+					dimension: wgpu::TextureDimension::D2,
+					format: Self::OCCLUSION_FORMAT,
+					usage: wgpu::TextureUsages::TEXTURE_BINDING,
+					view_formats: &[Self::OCCLUSION_FORMAT],
+				},
+				&[255],
+			)
+			.create_view(&wgpu::TextureViewDescriptor::default());
+		let unoccluded_bind_group =
+			create_output_bind_group(device, &output_bind_group_layout, &unoccluded_view);
+
+		Self {
+			enabled: false,
+			prepass_pipeline,
+			occlusion_pipeline,
+			blur_pipeline,
+			input_bind_group_layout,
+			output_bind_group_layout,
+			settings_buffer,
+			unoccluded_bind_group,
+			targets: Vec::new(),
+		}
+	}
+
+	/// Uploads the settings for this frame's occlusion passes
+	pub fn update(&mut self, queue: &Queue, settings: &AmbientOcclusion) {
+		self.enabled = settings.enabled;
+		if !self.enabled {
+			self.targets.clear();
+			return;
+		}
+		let settings = SsaoSettings {
+			radius: settings.radius.max(0.0),
+			intensity: settings.intensity.max(0.0),
+			sample_count: settings.sample_count.clamp(1, Self::MAX_SAMPLE_COUNT),
+			padding: 0,
+		};
+		queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[settings]));
+	}
+
+	/// Creates or resizes the textures a target's occlusion is rendered into
+	pub fn prepare_target(
+		&mut self,
+		device: &Device,
+		target: &RenderTarget,
+		(width, height): (u32, u32),
+	) {
+		if !self.enabled {
+			return;
+		}
+		let is_current = self.target(target).map_or(false, |ssao_target| {
+			ssao_target.width == width && ssao_target.height == height
+		});
+		if is_current {
+			return;
+		}
+		let ssao_target = SsaoTarget::new(device, self, width, height);
+		self.targets
+			.retain(|(ssao_target, _)| ssao_target != target);
+		self.targets.push((target.clone(), ssao_target));
+	}
+
+	/// Frees the textures of targets no longer rendered to
+	pub fn retain_targets(&mut self, views: &[(RenderTarget, Viewport)]) {
+		self.targets
+			.retain(|(target, _)| views.iter().any(|(view_target, _)| view_target == target));
+	}
+
+	/// The bind group holding a target's blurred occlusion, which is unoccluded when disabled
+	pub fn output_bind_group(&self, target: &RenderTarget) -> &BindGroup {
+		match self.target(target) {
+			Some(ssao_target) if self.enabled => &ssao_target.output_bind_group,
+			_ => &self.unoccluded_bind_group,
+		}
+	}
+
+	pub fn target(&self, target: &RenderTarget) -> Option<&SsaoTarget> {
+		self.targets
+			.iter()
+			.find(|(ssao_target, _)| ssao_target == target)
+			.map(|(_, ssao_target)| ssao_target)
+	}
+
+	/// Records the occlusion and blur passes for each view drawing to the target,
+	/// after its prepass has been rendered
+	pub fn render(
+		&self,
+		encoder: &mut CommandEncoder,
+		uniform: &UniformBinding,
+		views: &[(RenderTarget, Viewport)],
+		target: &RenderTarget,
+	) {
+		let ssao_target = match self.target(target) {
+			Some(ssao_target) if self.enabled => ssao_target,
+			_ => return,
+		};
+
+		let passes = [
+			(
+				&self.occlusion_pipeline,
+				&ssao_target.occlusion_bind_group,
+				&ssao_target.occlusion_view,
+			),
+			(
+				&self.blur_pipeline,
+				&ssao_target.blur_bind_group,
+				&ssao_target.blurred_view,
+			),
+		];
+		for (pipeline, bind_group, output_view) in passes {
+			let mut render_pass = begin_occlusion_pass(encoder, output_view);
+			render_pass.set_pipeline(pipeline);
+			render_pass.set_bind_group(1, bind_group, &[]);
+			for (view_index, (view_target, viewport)) in views.iter().enumerate() {
+				if view_target != target {
+					continue;
+				}
+				set_viewport(&mut render_pass, viewport);
+				let view_offset = uniform.view_offset(view_index);
+				render_pass.set_bind_group(0, &uniform.bind_group, &[view_offset]);
+				render_pass.draw(0..3, 0..1);
+			}
+		}
+	}
+}
+
+pub struct SsaoTarget {
+	pub width: u32,
+	pub height: u32,
+	pub normal_view: TextureView,
+	pub depth_view: TextureView,
+	pub occlusion_view: TextureView,
+	pub blurred_view: TextureView,
+	pub occlusion_bind_group: BindGroup,
+	pub blur_bind_group: BindGroup,
+	pub output_bind_group: BindGroup,
+}
+
+impl SsaoTarget {
+	fn new(device: &Device, ssao: &SsaoRender, width: u32, height: u32) -> Self {
+		let normal_view = create_texture(
+			device,
+			"SSAO Normal Texture",
+			SsaoRender::NORMAL_FORMAT,
+			width,
+			height,
+		);
+		let depth_view = create_depth_texture(device, SsaoRender::DEPTH_FORMAT, width, height);
+		let occlusion_view = create_texture(
+			device,
+			"SSAO Occlusion Texture",
+			SsaoRender::OCCLUSION_FORMAT,
+			width,
+			height,
+		);
+		let blurred_view = create_texture(
+			device,
+			"SSAO Blurred Texture",
+			SsaoRender::OCCLUSION_FORMAT,
+			width,
+			height,
+		);
+
+		// The occlusion pass never reads its own output, so it binds the blurred texture instead
+		let occlusion_bind_group =
+			create_input_bind_group(device, ssao, &depth_view, &normal_view, &blurred_view);
+		let blur_bind_group =
+			create_input_bind_group(device, ssao, &depth_view, &normal_view, &occlusion_view);
+		let output_bind_group =
+			create_output_bind_group(device, &ssao.output_bind_group_layout, &blurred_view);
+
+		Self {
+			width,
+			height,
+			normal_view,
+			depth_view,
+			occlusion_view,
+			blurred_view,
+			occlusion_bind_group,
+			blur_bind_group,
+			output_bind_group,
+		}
+	}
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SsaoSettings {
+	pub radius: f32,
+	pub intensity: f32,
+	pub sample_count: u32,
+	pub padding: u32,
+}
+
+/// Begins the pass that renders view space normals and depth for the occlusion passes to read
+pub fn begin_prepass<'a>(
+	encoder: &'a mut CommandEncoder,
+	ssao_target: &'a SsaoTarget,
+) -> RenderPass<'a> {
+	encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+		label: Some("SSAO Prepass"),
+		color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+			view: &ssao_target.normal_view,
+			resolve_target: None,
+			ops: wgpu::Operations {
+				load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+				store: true,
+			},
+		})],
+		depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+			view: &ssao_target.depth_view,
+			depth_ops: Some(wgpu::Operations {
+				load: wgpu::LoadOp::Clear(1.0),
+				store: true,
+			}),
+			stencil_ops: None,
+		}),
+	})
+}
+
+/// Begins a pass that clears to unoccluded, so pixels outside every view stay lit
+fn begin_occlusion_pass<'a>(
+	encoder: &'a mut CommandEncoder,
+	output_view: &'a TextureView,
+) -> RenderPass<'a> {
+	encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+		label: Some("SSAO Pass"),
+		color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+			view: output_view,
+			resolve_target: None,
+			ops: wgpu::Operations {
+				load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+				store: true,
+			},
+		})],
+		depth_stencil_attachment: None,
+	})
+}
+
+fn set_viewport(render_pass: &mut RenderPass, viewport: &Viewport) {
+	render_pass.set_viewport(
+		viewport.x,
+		viewport.y,
+		viewport.width,
+		viewport.height,
+		0.0,
+		1.0,
+	);
+}
+
+fn create_texture(
+	device: &Device,
+	label: &str,
+	format: TextureFormat,
+	width: u32,
+	height: u32,
+) -> TextureView {
+	device
+		.create_texture(&wgpu::TextureDescriptor {
+			label: Some(label),
+			size: wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+			view_formats: &[format],
+		})
+		.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_input_bind_group(
+	device: &Device,
+	ssao: &SsaoRender,
+	depth_view: &TextureView,
+	normal_view: &TextureView,
+	occlusion_view: &TextureView,
+) -> BindGroup {
+	device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("SSAO Input Bind Group"),
+		layout: &ssao.input_bind_group_layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: ssao.settings_buffer.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: wgpu::BindingResource::TextureView(depth_view),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: wgpu::BindingResource::TextureView(normal_view),
+			},
+			wgpu::BindGroupEntry {
+				binding: 3,
+				resource: wgpu::BindingResource::TextureView(occlusion_view),
+			},
+		],
+	})
+}
+
+fn create_output_bind_group(
+	device: &Device,
+	layout: &BindGroupLayout,
+	occlusion_view: &TextureView,
+) -> BindGroup {
+	device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("SSAO Output Bind Group"),
+		layout,
+		entries: &[wgpu::BindGroupEntry {
+			binding: 0,
+			resource: wgpu::BindingResource::TextureView(occlusion_view),
+		}],
+	})
+}
+
+fn texture_entry(binding: u32, sample_type: wgpu::TextureSampleType) -> wgpu::BindGroupLayoutEntry {
+	wgpu::BindGroupLayoutEntry {
+		binding,
+		visibility: wgpu::ShaderStages::FRAGMENT,
+		ty: wgpu::BindingType::Texture {
+			sample_type,
+			view_dimension: wgpu::TextureViewDimension::D2,
+			multisampled: false,
+		},
+		count: None,
+	}
+}
+
+fn create_input_bind_group_layout(device: &Device) -> BindGroupLayout {
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("SSAO Input Bind Group Layout"),
+		entries: &[
+			wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Uniform,
+					has_dynamic_offset: false,
+					min_binding_size: wgpu::BufferSize::new(size_of::<SsaoSettings>() as _),
+				},
+				count: None,
+			},
+			texture_entry(1, wgpu::TextureSampleType::Depth),
+			texture_entry(2, wgpu::TextureSampleType::Float { filterable: false }),
+			texture_entry(3, wgpu::TextureSampleType::Float { filterable: false }),
+		],
+	})
+}
+
+fn create_output_bind_group_layout(device: &Device) -> BindGroupLayout {
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("SSAO Output Bind Group Layout"),
+		entries: &[texture_entry(
+			0,
+			wgpu::TextureSampleType::Float { filterable: false },
+		)],
+	})
+}
+
+fn create_prepass_pipeline(
+	device: &Device,
+	uniform: &UniformBinding,
+	draw_data: &DrawBinding,
+) -> RenderPipeline {
+	let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+		label: Some("SSAO Prepass Shader"),
+		source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(PREPASS_SHADER_SOURCE)),
+	});
+
+	let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+		label: Some("SSAO Prepass Pipeline Layout"),
+		bind_group_layouts: &[&uniform.bind_group_layout, &draw_data.bind_group_layout],
+		push_constant_ranges: &[],
+	});
+
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some("SSAO Prepass Pipeline"),
+		layout: Some(&pipeline_layout),
+		vertex: wgpu::VertexState {
+			module: &shader_module,
+			entry_point: "vertex_main",
+			buffers: &[create_vertex_description(&create_vertex_attributes())],
+		},
+		primitive: wgpu::PrimitiveState {
+			front_face: wgpu::FrontFace::Ccw,
+			cull_mode: Some(Face::Back),
+			..Default::default()
+		},
+		depth_stencil: Some(wgpu::DepthStencilState {
+			format: SsaoRender::DEPTH_FORMAT,
+			depth_write_enabled: true,
+			depth_compare: wgpu::CompareFunction::Less,
+			stencil: wgpu::StencilState::default(),
+			bias: wgpu::DepthBiasState::default(),
+		}),
+		multisample: wgpu::MultisampleState::default(),
+		fragment: Some(wgpu::FragmentState {
+			module: &shader_module,
+			entry_point: "fragment_main",
+			targets: &[Some(wgpu::ColorTargetState {
+				format: SsaoRender::NORMAL_FORMAT,
+				blend: None,
+				write_mask: wgpu::ColorWrites::ALL,
+			})],
+		}),
+		multiview: None,
+	})
+}
+
+fn create_fullscreen_pipeline(
+	device: &Device,
+	label: &str,
+	fragment_entry_point: &str,
+	bind_group_layouts: &[&BindGroupLayout],
+) -> RenderPipeline {
+	let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+		label: Some(&format!("{label} Shader")),
+		source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(OCCLUSION_SHADER_SOURCE)),
+	});
+
+	let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+		label: Some(&format!("{label} Pipeline Layout")),
+		bind_group_layouts,
+		push_constant_ranges: &[],
+	});
+
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some(&format!("{label} Pipeline")),
+		layout: Some(&pipeline_layout),
+		vertex: wgpu::VertexState {
+			module: &shader_module,
+			entry_point: "vertex_main",
+			buffers: &[],
+		},
+		primitive: wgpu::PrimitiveState::default(),
+		depth_stencil: None,
+		multisample: wgpu::MultisampleState::default(),
+		fragment: Some(wgpu::FragmentState {
+			module: &shader_module,
+			entry_point: fragment_entry_point,
+			targets: &[Some(wgpu::ColorTargetState {
+				format: SsaoRender::OCCLUSION_FORMAT,
+				blend: None,
+				write_mask: wgpu::ColorWrites::ALL,
+			})],
+		}),
+		multiview: None,
+	})
+}
+
+const PREPASS_SHADER_SOURCE: &str = "
+struct Light {
+    position: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Uniform {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    light: Light,
+    inverse_view_projection: mat4x4<f32>,
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> ubo: Uniform;
+
+struct DrawData {
+    model: mat4x4<f32>,
+    joint_offset: u32,
+    padding_0: u32,
+    padding_1: u32,
+    padding_2: u32,
+};
+
+@group(1) @binding(0)
+var<storage, read> draws: array<DrawData>;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv_0: vec2<f32>,
+    @location(3) uv_1: vec2<f32>,
+    @location(4) joint_0: vec4<f32>,
+    @location(5) weight_0: vec4<f32>,
+    @location(6) color_0: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) view_normal: vec3<f32>,
+};
+
+@vertex
+fn vertex_main(vert: VertexInput, @builtin(instance_index) draw_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let model_view = ubo.view * draws[draw_index].model;
+    out.position = ubo.projection * model_view * vec4(vert.position, 1.0);
+    out.view_normal = (model_view * vec4(vert.normal, 0.0)).xyz;
+    return out;
+}
+
+@fragment
+fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(normalize(in.view_normal), 1.0);
+}
+";
+
+const OCCLUSION_SHADER_SOURCE: &str = "
+struct Light {
+    position: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Uniform {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    light: Light,
+    inverse_view_projection: mat4x4<f32>,
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> ubo: Uniform;
+
+struct SsaoSettings {
+    radius: f32,
+    intensity: f32,
+    sample_count: u32,
+    padding: u32,
+};
+
+@group(1) @binding(0)
+var<uniform> settings: SsaoSettings;
+
+@group(1) @binding(1)
+var prepass_depth: texture_depth_2d;
+
+@group(1) @binding(2)
+var prepass_normals: texture_2d<f32>;
+
+@group(1) @binding(3)
+var occlusion: texture_2d<f32>;
+
+@vertex
+fn vertex_main(@builtin(vertex_index) vertex_index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn view_position(pixel: vec2<f32>, depth: f32) -> vec3<f32> {
+    let uv = (pixel - ubo.viewport.xy) / ubo.viewport.zw;
+    let ndc = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world = ubo.inverse_view_projection * ndc;
+    return (ubo.view * vec4(world.xyz / world.w, 1.0)).xyz;
+}
+
+fn view_depth(pixel: vec2<i32>) -> f32 {
+    let depth = textureLoad(prepass_depth, pixel, 0);
+    return view_position(vec2<f32>(pixel) + 0.5, depth).z;
+}
+
+// Spreads samples over the hemisphere along a golden angle spiral,
+// weighted towards the normal and packed closer to the center
+fn hemisphere_sample(index: u32, rotation: f32) -> vec3<f32> {
+    let t = (f32(index) + 0.5) / f32(settings.sample_count);
+    let phi = f32(index) * 2.39996323 + rotation;
+    let sin_theta = sqrt(t);
+    let cos_theta = sqrt(1.0 - t);
+    let scale = mix(0.1, 1.0, t * t);
+    return vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta) * scale;
+}
+
+@fragment
+fn occlusion_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    let depth = textureLoad(prepass_depth, pixel, 0);
+    if (depth >= 1.0) {
+        return vec4<f32>(1.0);
+    }
+
+    let origin = view_position(position.xy, depth);
+    let normal = normalize(textureLoad(prepass_normals, pixel, 0).xyz);
+
+    // Interleaved gradient noise rotates the spiral per pixel, which the blur then smooths out
+    let noise = fract(52.9829189 * fract(dot(position.xy, vec2(0.06711056, 0.00583715))));
+    var up = vec3(0.0, 1.0, 0.0);
+    if (abs(normal.y) > 0.99) {
+        up = vec3(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+
+    let bias = settings.radius * 0.025;
+    var occluded = 0.0;
+    for (var index = 0u; index < settings.sample_count; index = index + 1u) {
+        let direction = hemisphere_sample(index, noise * 6.2831853);
+        let sample_position = origin
+            + (tangent * direction.x + bitangent * direction.y + normal * direction.z)
+            * settings.radius;
+
+        let clip = ubo.projection * vec4(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let uv = vec2(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        if (clip.w <= 0.0 || any(uv < vec2(0.0)) || any(uv > vec2(1.0))) {
+            continue;
+        }
+
+        let sample_pixel = vec2<i32>(ubo.viewport.xy + uv * ubo.viewport.zw);
+        let scene_depth = view_depth(sample_pixel);
+        let range = smoothstep(0.0, 1.0, settings.radius / max(abs(origin.z - scene_depth), 0.0001));
+        if (scene_depth >= sample_position.z + bias) {
+            occluded = occluded + range;
+        }
+    }
+
+    let unoccluded = 1.0 - occluded / f32(settings.sample_count) * settings.intensity;
+    return vec4<f32>(clamp(unoccluded, 0.0, 1.0));
+}
+
+// Averages nearby occlusion, ignoring pixels across depth discontinuities
+// so occlusion doesn't bleed over silhouettes
+@fragment
+fn blur_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(position.xy);
+    if (textureLoad(prepass_depth, pixel, 0) >= 1.0) {
+        return vec4<f32>(1.0);
+    }
+
+    let center_depth = view_depth(pixel);
+    let minimum = vec2<i32>(ubo.viewport.xy);
+    let maximum = vec2<i32>(ubo.viewport.xy + ubo.viewport.zw) - vec2(1, 1);
+    var total = 0.0;
+    var total_weight = 0.0;
+    for (var y = -2; y <= 2; y = y + 1) {
+        for (var x = -2; x <= 2; x = x + 1) {
+            let sample_pixel = clamp(pixel + vec2(x, y), minimum, maximum);
+            let difference = abs(view_depth(sample_pixel) - center_depth);
+            let weight = max(1.0 - difference / max(settings.radius, 0.0001), 0.0);
+            total = total + textureLoad(occlusion, sample_pixel, 0).r * weight;
+            total_weight = total_weight + weight;
+        }
+    }
+    return vec4<f32>(total / total_weight);
+}
+";
@@ -0,0 +1,127 @@
+use super::gui::GuiRender;
+use egui::TextureId;
+use phantom_world::RenderTarget;
+use wgpu::{self, Device, TextureFormat};
+
+pub struct OffscreenTarget {
+	pub target: RenderTarget,
+	pub width: u32,
+	pub height: u32,
+	pub color_texture: wgpu::Texture,
+	pub color_view: wgpu::TextureView,
+	pub depth_view: wgpu::TextureView,
+	pub texture_id: TextureId,
+}
+
+impl OffscreenTarget {
+	pub fn new(
+		device: &Device,
+		gui: &mut GuiRender,
+		color_format: TextureFormat,
+		depth_format: TextureFormat,
+		target: RenderTarget,
+	) -> Self {
+		let (width, height) = target_dimensions(&target);
+		let (color_texture, color_view) = create_color_texture(device, color_format, width, height);
+		let depth_view = create_depth_texture(device, depth_format, width, height);
+		let texture_id =
+			gui.renderer
+				.register_native_texture(device, &color_view, wgpu::FilterMode::Linear);
+		Self {
+			target,
+			width,
+			height,
+			color_texture,
+			color_view,
+			depth_view,
+			texture_id,
+		}
+	}
+
+	/// Recreates the target's textures and points its gui texture at the new color texture
+	pub fn resize(
+		&mut self,
+		device: &Device,
+		gui: &mut GuiRender,
+		color_format: TextureFormat,
+		depth_format: TextureFormat,
+		target: RenderTarget,
+	) {
+		let (width, height) = target_dimensions(&target);
+		let (color_texture, color_view) = create_color_texture(device, color_format, width, height);
+		self.depth_view = create_depth_texture(device, depth_format, width, height);
+		gui.renderer.update_egui_texture_from_wgpu_texture(
+			device,
+			&color_view,
+			wgpu::FilterMode::Linear,
+			self.texture_id,
+		);
+		self.color_texture = color_texture;
+		self.color_view = color_view;
+		self.width = width;
+		self.height = height;
+		self.target = target;
+	}
+}
+
+fn target_dimensions(target: &RenderTarget) -> (u32, u32) {
+	match target {
+		RenderTarget::Surface => (1, 1),
+		RenderTarget::Texture { width, height, .. } => ((*width).max(1), (*height).max(1)),
+	}
+}
+
+fn create_color_texture(
+	device: &Device,
+	format: TextureFormat,
+	width: u32,
+	height: u32,
+) -> (wgpu::Texture, wgpu::TextureView) {
+	let texture = device.create_texture(&wgpu::TextureDescriptor {
+		label: Some("Offscreen Color Texture"),
+		size: wgpu::Extent3d {
+			width,
+			height,
+			depth_or_array_layers: 1,
+		},
+		mip_level_count: 1,
+		sample_count: 1,
+		dimension: wgpu::TextureDimension::D2,
+		format,
+		usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+			| wgpu::TextureUsages::TEXTURE_BINDING
+			| wgpu::TextureUsages::COPY_SRC,
+		view_formats: &[format],
+	});
+	let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+	(texture, view)
+}
+
+pub fn create_depth_texture(
+	device: &Device,
+	format: TextureFormat,
+	width: u32,
+	height: u32,
+) -> wgpu::TextureView {
+	let size = wgpu::Extent3d {
+		width,
+		height,
+		depth_or_array_layers: 1,
+	};
+
+	let texture_descriptor = wgpu::TextureDescriptor {
+		label: Some("Depth Texture"),
+		size,
+		mip_level_count: 1,
+		sample_count: 1,
+		dimension: wgpu::TextureDimension::D2,
+		format,
+		// Depth is sampled after the scene pass to soften particles against geometry
+		usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+		view_formats: &[format],
+	};
+
+	let texture = device.create_texture(&texture_descriptor);
+
+	texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
@@ -0,0 +1,612 @@
+use super::{
+	world::UniformBinding,
+	world_texture::{create_rgba_texture, WorldTextures},
+};
+use nalgebra_glm as glm;
+use phantom_world::{
+	legion::IntoQuery, terrain_chunk_indices, BoundingBox, Entity, RenderTarget, Terrain, Viewport,
+	World, TERRAIN_LAYER_COUNT,
+};
+use std::{borrow::Cow, collections::HashMap, mem::size_of, ops::Range};
+use wgpu::{
+	self,
+	util::{BufferInitDescriptor, DeviceExt},
+	BindGroup, BindGroupLayout, Buffer, BufferAddress, Device, Face, Queue, RenderPass,
+	RenderPipeline, TextureFormat, TextureView,
+};
+
+/// The number of coarser neighbour masks, one bit per chunk edge
+const NUMBER_OF_NEIGHBOUR_MASKS: usize = 16;
+
+pub struct TerrainRender {
+	pub pipeline: RenderPipeline,
+	pub bind_group_layout: BindGroupLayout,
+	pub layer_sampler: wgpu::Sampler,
+	pub splat_sampler: wgpu::Sampler,
+	pub white_texture_view: TextureView,
+	pub first_layer_splat_view: TextureView,
+	pub terrains: HashMap<Entity, TerrainBinding>,
+}
+
+impl TerrainRender {
+	pub fn new(
+		device: &Device,
+		queue: &Queue,
+		surface_format: TextureFormat,
+		uniform: &UniformBinding,
+		environment_bind_group_layout: &BindGroupLayout,
+	) -> Self {
+		let bind_group_layout = create_bind_group_layout(device);
+		let pipeline = create_pipeline(
+			device,
+			surface_format,
+			&[
+				&uniform.bind_group_layout,
+				&bind_group_layout,
+				environment_bind_group_layout,
+			],
+		);
+		let layer_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("Terrain Layer Sampler"),
+			address_mode_u: wgpu::AddressMode::Repeat,
+			address_mode_v: wgpu::AddressMode::Repeat,
+			mag_filter: wgpu::FilterMode::Linear,
+			min_filter: wgpu::FilterMode::Linear,
+			..Default::default()
+		});
+		let splat_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("Terrain Splat Sampler"),
+			mag_filter: wgpu::FilterMode::Linear,
+			min_filter: wgpu::FilterMode::Linear,
+			..Default::default()
+		});
+		let white_texture_view = create_rgba_texture(
+			device,
+			queue,
+			"Terrain Texture",
+			TextureFormat::Rgba8UnormSrgb,
+			(1, 1),
+			&[255, 255, 255, 255],
+		)
+		.create_view(&wgpu::TextureViewDescriptor::default());
+		let first_layer_splat_view = create_rgba_texture(
+			device,
+			queue,
+			"Terrain Texture",
+			TextureFormat::Rgba8Unorm,
+			(1, 1),
+			&[255, 0, 0, 0],
+		)
+		.create_view(&wgpu::TextureViewDescriptor::default());
+		Self {
+			pipeline,
+			bind_group_layout,
+			layer_sampler,
+			splat_sampler,
+			white_texture_view,
+			first_layer_splat_view,
+			terrains: HashMap::new(),
+		}
+	}
+
+	/// Creates, recreates and frees terrain bindings to match the world,
+	/// then picks the chunk levels of detail for each view's camera position
+	pub fn update(
+		&mut self,
+		device: &Device,
+		queue: &Queue,
+		world: &World,
+		textures: &mut WorldTextures,
+		camera_positions: &[glm::Vec3],
+	) {
+		let mut query = <(Entity, &Terrain)>::query();
+		let terrains = query
+			.iter(&world.ecs)
+			.filter(|(_, terrain)| match terrain.validate() {
+				Ok(()) => true,
+				Err(error) => {
+					log::warn!("Skipping invalid terrain: {error}");
+					false
+				}
+			})
+			.collect::<Vec<_>>();
+
+		self.terrains.retain(|entity, _| {
+			terrains
+				.iter()
+				.any(|(terrain_entity, _)| *terrain_entity == entity)
+		});
+
+		for (entity, terrain) in terrains.into_iter() {
+			let model = match world.entity_global_transform_matrix(*entity) {
+				Ok(model) => model,
+				Err(error) => {
+					log::warn!("Failed to get terrain transform: {error}");
+					continue;
+				}
+			};
+
+			let needs_binding = match self.terrains.get(entity) {
+				Some(binding) => binding.terrain != *terrain,
+				None => true,
+			};
+			if needs_binding {
+				let splat_view = textures.view(
+					device,
+					queue,
+					world,
+					terrain.splat_map,
+					TextureFormat::Rgba8Unorm,
+					"Terrain",
+				);
+				let layer_views = terrain
+					.layers
+					.iter()
+					.map(|layer| {
+						textures.view(
+							device,
+							queue,
+							world,
+							layer.texture,
+							TextureFormat::Rgba8UnormSrgb,
+							"Terrain",
+						)
+					})
+					.collect::<Vec<_>>();
+				let binding = TerrainBinding::new(device, self, terrain, splat_view, &layer_views);
+				self.terrains.insert(*entity, binding);
+			}
+
+			if let Some(binding) = self.terrains.get_mut(entity) {
+				let uniform = TerrainUniform::new(model, terrain);
+				queue.write_buffer(&binding.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+				let inverse_model = glm::inverse(&model);
+				binding.chunk_draws = camera_positions
+					.iter()
+					.map(|position| {
+						let local_position = (inverse_model
+							* glm::vec4(position.x, position.y, position.z, 1.0))
+						.xyz();
+						binding.chunk_draws_from(&local_position)
+					})
+					.collect();
+			}
+		}
+	}
+
+	pub fn render<'rp>(
+		&'rp self,
+		render_pass: &mut RenderPass<'rp>,
+		uniform: &'rp UniformBinding,
+		views: &[(RenderTarget, Viewport)],
+		target: &RenderTarget,
+		environment_bind_group: &'rp BindGroup,
+	) {
+		if self.terrains.is_empty() {
+			return;
+		}
+
+		render_pass.set_pipeline(&self.pipeline);
+		render_pass.set_bind_group(2, environment_bind_group, &[]);
+
+		for (view_index, (view_target, viewport)) in views.iter().enumerate() {
+			if view_target != target {
+				continue;
+			}
+			render_pass.set_viewport(
+				viewport.x,
+				viewport.y,
+				viewport.width,
+				viewport.height,
+				0.0,
+				1.0,
+			);
+			let view_offset = uniform.view_offset(view_index);
+			render_pass.set_bind_group(0, &uniform.bind_group, &[view_offset]);
+
+			for binding in self.terrains.values() {
+				let chunk_draws = match binding.chunk_draws.get(view_index) {
+					Some(chunk_draws) => chunk_draws,
+					None => continue,
+				};
+				render_pass.set_bind_group(1, &binding.bind_group, &[]);
+				render_pass.set_vertex_buffer(0, binding.vertex_buffer.slice(..));
+				render_pass
+					.set_index_buffer(binding.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+				for (chunk_index, index_range) in chunk_draws.iter().enumerate() {
+					let base_vertex = (chunk_index as u32 * binding.vertices_per_chunk) as i32;
+					render_pass.draw_indexed(index_range.clone(), base_vertex, 0..1);
+				}
+			}
+		}
+	}
+}
+
+pub struct TerrainBinding {
+	/// The terrain the buffers were built from
+	pub terrain: Terrain,
+	pub vertex_buffer: Buffer,
+	pub vertices_per_chunk: u32,
+	/// Chunk triangulations for every level of detail and coarser neighbour mask
+	pub index_buffer: Buffer,
+	pub index_ranges: Vec<Range<u32>>,
+	pub chunk_bounding_boxes: Vec<BoundingBox>,
+	pub uniform_buffer: Buffer,
+	pub bind_group: BindGroup,
+	/// The index range drawn for every chunk, per view
+	pub chunk_draws: Vec<Vec<Range<u32>>>,
+}
+
+impl TerrainBinding {
+	fn new(
+		device: &Device,
+		terrain_render: &TerrainRender,
+		terrain: &Terrain,
+		splat_view: Option<TextureView>,
+		layer_views: &[Option<TextureView>],
+	) -> Self {
+		let (chunks_x, chunks_z) = terrain.number_of_chunks();
+		let chunks = (0..chunks_z)
+			.flat_map(|chunk_z| (0..chunks_x).map(move |chunk_x| (chunk_x, chunk_z)))
+			.collect::<Vec<_>>();
+
+		let vertices = chunks
+			.iter()
+			.flat_map(|(chunk_x, chunk_z)| terrain.chunk_vertices(*chunk_x, *chunk_z))
+			.map(|(position, normal)| TerrainVertex { position, normal })
+			.collect::<Vec<_>>();
+		let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+			label: Some("Terrain Vertex Buffer"),
+			contents: bytemuck::cast_slice(&vertices),
+			usage: wgpu::BufferUsages::VERTEX,
+		});
+
+		let mut indices = Vec::new();
+		let mut index_ranges = Vec::new();
+		for lod in 0..terrain.number_of_lods() {
+			for mask in 0..NUMBER_OF_NEIGHBOUR_MASKS as u32 {
+				let start = indices.len() as u32;
+				indices.extend(terrain_chunk_indices(terrain.chunk_size, lod, mask));
+				index_ranges.push(start..indices.len() as u32);
+			}
+		}
+		let index_buffer = device.create_buffer_init(&BufferInitDescriptor {
+			label: Some("Terrain Index Buffer"),
+			contents: bytemuck::cast_slice(&indices),
+			usage: wgpu::BufferUsages::INDEX,
+		});
+
+		let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Terrain Uniform Buffer"),
+			size: size_of::<TerrainUniform>() as BufferAddress,
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		let layer_view = |layer: usize| {
+			layer_views
+				.get(layer)
+				.and_then(|view| view.as_ref())
+				.unwrap_or(&terrain_render.white_texture_view)
+		};
+		let mut entries = vec![
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: uniform_buffer.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: wgpu::BindingResource::TextureView(
+					splat_view
+						.as_ref()
+						.unwrap_or(&terrain_render.first_layer_splat_view),
+				),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: wgpu::BindingResource::Sampler(&terrain_render.splat_sampler),
+			},
+			wgpu::BindGroupEntry {
+				binding: 3,
+				resource: wgpu::BindingResource::Sampler(&terrain_render.layer_sampler),
+			},
+		];
+		entries.extend((0..TERRAIN_LAYER_COUNT).map(|layer| wgpu::BindGroupEntry {
+			binding: 4 + layer as u32,
+			resource: wgpu::BindingResource::TextureView(layer_view(layer)),
+		}));
+		let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Terrain Bind Group"),
+			layout: &terrain_render.bind_group_layout,
+			entries: &entries,
+		});
+
+		let chunk_bounding_boxes = chunks
+			.iter()
+			.map(|(chunk_x, chunk_z)| terrain.chunk_bounding_box(*chunk_x, *chunk_z))
+			.collect();
+
+		Self {
+			terrain: terrain.clone(),
+			vertex_buffer,
+			vertices_per_chunk: (terrain.chunk_size + 1).pow(2),
+			index_buffer,
+			index_ranges,
+			chunk_bounding_boxes,
+			uniform_buffer,
+			bind_group,
+			chunk_draws: Vec::new(),
+		}
+	}
+
+	/// The index range of every chunk for a camera position local to the terrain
+	fn chunk_draws_from(&self, local_camera_position: &glm::Vec3) -> Vec<Range<u32>> {
+		let (chunks_x, chunks_z) = self.terrain.number_of_chunks();
+		let lods = self
+			.terrain
+			.select_chunk_lods(&self.chunk_bounding_boxes, local_camera_position);
+		(0..chunks_z)
+			.flat_map(|chunk_z| (0..chunks_x).map(move |chunk_x| (chunk_x, chunk_z)))
+			.map(|(chunk_x, chunk_z)| {
+				let lod = lods[chunk_z * chunks_x + chunk_x] as usize;
+				let mask = self.terrain.coarser_neighbour_mask(&lods, chunk_x, chunk_z) as usize;
+				self.index_ranges[lod * NUMBER_OF_NEIGHBOUR_MASKS + mask].clone()
+			})
+			.collect()
+	}
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainVertex {
+	pub position: glm::Vec3,
+	pub normal: glm::Vec3,
+}
+
+#[repr(C)]
+#[derive(Default, Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TerrainUniform {
+	pub model: glm::Mat4,
+	pub layer_colors: [glm::Vec4; TERRAIN_LAYER_COUNT],
+	pub layer_uv_scales: glm::Vec4,
+	/// The terrain's extent along the X and Z axes
+	pub size: glm::Vec4,
+}
+
+impl TerrainUniform {
+	pub fn new(model: glm::Mat4, terrain: &Terrain) -> Self {
+		Self {
+			model,
+			layer_colors: std::array::from_fn(|layer| terrain.layers[layer].color),
+			layer_uv_scales: glm::Vec4::from_fn(|layer, _| terrain.layers[layer].uv_scale),
+			size: glm::vec4(terrain.size.x, terrain.size.y, 0.0, 0.0),
+		}
+	}
+}
+
+fn create_bind_group_layout(device: &Device) -> BindGroupLayout {
+	let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+		binding,
+		visibility: wgpu::ShaderStages::FRAGMENT,
+		ty: wgpu::BindingType::Texture {
+			sample_type: wgpu::TextureSampleType::Float { filterable: true },
+			view_dimension: wgpu::TextureViewDimension::D2,
+			multisampled: false,
+		},
+		count: None,
+	};
+	let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+		binding,
+		visibility: wgpu::ShaderStages::FRAGMENT,
+		ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+		count: None,
+	};
+	let mut entries = vec![
+		wgpu::BindGroupLayoutEntry {
+			binding: 0,
+			visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+			ty: wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Uniform,
+				has_dynamic_offset: false,
+				min_binding_size: wgpu::BufferSize::new(size_of::<TerrainUniform>() as _),
+			},
+			count: None,
+		},
+		texture_entry(1),
+		sampler_entry(2),
+		sampler_entry(3),
+	];
+	entries.extend((0..TERRAIN_LAYER_COUNT).map(|layer| texture_entry(4 + layer as u32)));
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Terrain Bind Group Layout"),
+		entries: &entries,
+	})
+}
+
+fn create_pipeline(
+	device: &Device,
+	surface_format: TextureFormat,
+	bind_group_layouts: &[&BindGroupLayout],
+) -> RenderPipeline {
+	let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+		label: Some("Terrain Shader"),
+		source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER_SOURCE)),
+	});
+
+	let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+		label: Some("Terrain Pipeline Layout"),
+		bind_group_layouts,
+		push_constant_ranges: &[],
+	});
+
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some("Terrain Pipeline"),
+		layout: Some(&pipeline_layout),
+		vertex: wgpu::VertexState {
+			module: &shader_module,
+			entry_point: "vertex_main",
+			buffers: &[wgpu::VertexBufferLayout {
+				array_stride: size_of::<TerrainVertex>() as wgpu::BufferAddress,
+				step_mode: wgpu::VertexStepMode::Vertex,
+				attributes: &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3],
+			}],
+		},
+		primitive: wgpu::PrimitiveState {
+			front_face: wgpu::FrontFace::Ccw,
+			cull_mode: Some(Face::Back),
+			..Default::default()
+		},
+		depth_stencil: Some(wgpu::DepthStencilState {
+			format: wgpu::TextureFormat::Depth32Float,
+			depth_write_enabled: true,
+			depth_compare: wgpu::CompareFunction::Less,
+			stencil: wgpu::StencilState::default(),
+			bias: wgpu::DepthBiasState::default(),
+		}),
+		multisample: wgpu::MultisampleState::default(),
+		fragment: Some(wgpu::FragmentState {
+			module: &shader_module,
+			entry_point: "fragment_main",
+			targets: &[Some(wgpu::ColorTargetState {
+				format: surface_format,
+				blend: Some(wgpu::BlendState::REPLACE),
+				write_mask: wgpu::ColorWrites::ALL,
+			})],
+		}),
+		multiview: None,
+	})
+}
+
+const SHADER_SOURCE: &str = "
+struct Light {
+    position: vec4<f32>,
+    color: vec4<f32>,
+};
+
+struct Uniform {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    camera_position: vec4<f32>,
+    light: Light,
+    inverse_view_projection: mat4x4<f32>,
+    viewport: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> ubo: Uniform;
+
+struct Terrain {
+    model: mat4x4<f32>,
+    layer_colors: array<vec4<f32>, 4>,
+    layer_uv_scales: vec4<f32>,
+    size: vec4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> terrain: Terrain;
+
+@group(1) @binding(1)
+var splat_map: texture_2d<f32>;
+
+@group(1) @binding(2)
+var splat_sampler: sampler;
+
+@group(1) @binding(3)
+var layer_sampler: sampler;
+
+@group(1) @binding(4)
+var layer_0: texture_2d<f32>;
+
+@group(1) @binding(5)
+var layer_1: texture_2d<f32>;
+
+@group(1) @binding(6)
+var layer_2: texture_2d<f32>;
+
+@group(1) @binding(7)
+var layer_3: texture_2d<f32>;
+
+struct Environment {
+    ambient: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_parameters: vec4<f32>,
+    fog_mode: u32,
+    padding_0: u32,
+    padding_1: u32,
+    padding_2: u32,
+};
+
+@group(2) @binding(0)
+var<uniform> environment: Environment;
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let offset = world_position - ubo.camera_position.xyz;
+    let distance = length(offset);
+    let parameters = environment.fog_parameters;
+    var visibility = 1.0;
+    switch (environment.fog_mode) {
+        case 1u: {
+            let range = max(parameters.y - parameters.x, 0.0001);
+            visibility = clamp((parameters.y - distance) / range, 0.0, 1.0);
+        }
+        case 2u: {
+            visibility = exp(-parameters.x * distance);
+        }
+        case 3u: {
+            // Integrates the density along the view ray as it thins out with height
+            let camera_height = ubo.camera_position.y - parameters.y;
+            let camera_density = parameters.x * exp(-parameters.z * camera_height);
+            let rise = parameters.z * offset.y;
+            var thinning = 1.0;
+            if (abs(rise) > 0.0001) {
+                thinning = (1.0 - exp(-rise)) / rise;
+            }
+            visibility = exp(-camera_density * distance * thinning);
+        }
+        default: {}
+    }
+    return mix(environment.fog_color.rgb, color, visibility);
+}
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) local_position: vec3<f32>,
+};
+
+@vertex
+fn vertex_main(@location(0) position: vec3<f32>, @location(1) normal: vec3<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    let world_position = terrain.model * vec4(position, 1.0);
+    out.position = ubo.projection * ubo.view * world_position;
+    out.normal = (terrain.model * vec4(normal, 0.0)).xyz;
+    out.world_position = world_position.xyz;
+    out.local_position = position;
+    return out;
+}
+
+@fragment
+fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let splat_uv = in.local_position.xz / terrain.size.xy + 0.5;
+    let weights = textureSample(splat_map, splat_sampler, splat_uv);
+    let total_weight = max(weights.r + weights.g + weights.b + weights.a, 0.0001);
+
+    let uv = in.local_position.xz;
+    let scales = terrain.layer_uv_scales;
+    let base_color =
+        textureSample(layer_0, layer_sampler, uv * scales.x) * terrain.layer_colors[0] * weights.r
+        + textureSample(layer_1, layer_sampler, uv * scales.y) * terrain.layer_colors[1] * weights.g
+        + textureSample(layer_2, layer_sampler, uv * scales.z) * terrain.layer_colors[2] * weights.b
+        + textureSample(layer_3, layer_sampler, uv * scales.w) * terrain.layer_colors[3] * weights.a;
+
+    let normal = normalize(in.normal);
+    let ambient_color = environment.ambient.rgb;
+    let light_dir = normalize(ubo.light.position.xyz - in.world_position);
+    let diffuse_color = ubo.light.color.rgb * max(dot(normal, light_dir), 0.0);
+
+    let result = base_color.rgb / total_weight * (ambient_color + diffuse_color);
+    return vec4(apply_fog(result, in.world_position), 1.0);
+}
+";
@@ -1,102 +1,539 @@
+use super::{
+	decals::DecalRender,
+	indirect::IndirectDraws,
+	material::MaterialRender,
+	normal_map::NormalMaps,
+	occlusion::{begin_disoccluded_pass, CullObject, CullPhase, OcclusionCulling},
+	outline::{begin_mask_pass, OutlineRender},
+	particles::ParticleRender,
+	sprites::SpriteRender,
+	ssao::{begin_prepass, SsaoRender},
+	terrain::TerrainRender,
+	world_texture::WorldTextures,
+};
 use anyhow::Result;
 use nalgebra_glm as glm;
-use phantom_world::{Vertex, World};
+use phantom_world::{
+	CameraView, Entity, EntityMetadata, Environment, Fog, RenderTarget, Skin, Vertex, Viewport,
+	World,
+};
 use std::{
 	borrow::Cow,
+	collections::HashMap,
 	mem::{self, size_of},
 };
 use wgpu::{
 	self,
 	util::{BufferInitDescriptor, DeviceExt},
-	vertex_attr_array, Buffer, BufferAddress, Device, Face, Queue, RenderPass, RenderPipeline,
-	TextureFormat, VertexAttribute,
+	vertex_attr_array, Buffer, BufferAddress, CommandEncoder, Device, Face, Queue, RenderPass,
+	RenderPipeline, TextureFormat, VertexAttribute,
 };
 
 pub struct WorldRender {
 	pub geometry: Geometry,
 	pub uniform: UniformBinding,
-	pub dynamic_uniform: DynamicUniformBinding,
+	pub draw_data: DrawBinding,
+	pub indirect: IndirectDraws,
+	pub environment: EnvironmentBinding,
 	pub pipeline: RenderPipeline,
+	pub depth_bind_group_layout: wgpu::BindGroupLayout,
+	pub decals: DecalRender,
+	pub particles: ParticleRender,
+	pub ssao: SsaoRender,
+	pub terrain: TerrainRender,
+	pub sprites: SpriteRender,
+	pub materials: MaterialRender,
+	pub outline: OutlineRender,
+	pub occlusion: OcclusionCulling,
+	pub normal_maps: NormalMaps,
+	pub textures: WorldTextures,
+	pub views: Vec<(RenderTarget, Viewport)>,
+	pub lod_levels: HashMap<Entity, usize>,
+	/// The primitives drawn this frame, built once in `update` and shared by every pass
+	pub metadata: Vec<EntityMetadata>,
 }
 
 impl WorldRender {
-	pub fn new(device: &Device, surface_format: TextureFormat, world: &World) -> Self {
+	pub fn new(
+		device: &Device,
+		queue: &Queue,
+		surface_format: TextureFormat,
+		world: &World,
+	) -> Self {
 		let geometry = Geometry::new(device, &world.geometry.vertices, &world.geometry.indices);
 		let uniform = UniformBinding::new(device);
-		let dynamic_uniform = DynamicUniformBinding::new(device);
-		let pipeline = create_pipeline(device, surface_format, &uniform, &dynamic_uniform);
+		let draw_data = DrawBinding::new(device);
+		let indirect = IndirectDraws::new(device);
+		let environment = EnvironmentBinding::new(device);
+		let ssao = SsaoRender::new(device, queue, &uniform, &draw_data);
+		let normal_maps = NormalMaps::new(device, queue);
+		let pipeline = create_pipeline(
+			device,
+			surface_format,
+			&uniform,
+			&draw_data,
+			&ssao.output_bind_group_layout,
+			&normal_maps.bind_group_layout,
+		);
+		let depth_bind_group_layout = create_depth_bind_group_layout(device);
+		let decals = DecalRender::new(
+			device,
+			queue,
+			surface_format,
+			&uniform,
+			&depth_bind_group_layout,
+			&environment.bind_group_layout,
+		);
+		let particles = ParticleRender::new(
+			device,
+			queue,
+			surface_format,
+			&uniform,
+			&depth_bind_group_layout,
+			&environment.bind_group_layout,
+		);
+		let terrain = TerrainRender::new(
+			device,
+			queue,
+			surface_format,
+			&uniform,
+			&environment.bind_group_layout,
+		);
+		let sprites = SpriteRender::new(
+			device,
+			queue,
+			surface_format,
+			&uniform,
+			&environment.bind_group_layout,
+		);
+		let materials = MaterialRender::new(
+			device,
+			surface_format,
+			&uniform,
+			&draw_data,
+			&environment.bind_group_layout,
+		);
+		let outline = OutlineRender::new(
+			device,
+			surface_format,
+			&uniform,
+			&draw_data,
+			&depth_bind_group_layout,
+		);
+		let occlusion = OcclusionCulling::new(device, queue);
 		Self {
 			geometry,
 			uniform,
-			dynamic_uniform,
+			draw_data,
+			indirect,
+			environment,
 			pipeline,
+			depth_bind_group_layout,
+			decals,
+			particles,
+			ssao,
+			terrain,
+			sprites,
+			materials,
+			outline,
+			occlusion,
+			normal_maps,
+			textures: WorldTextures::default(),
+			views: Vec::new(),
+			lod_levels: HashMap::new(),
+			metadata: Vec::new(),
 		}
 	}
 
-	pub fn render<'rp>(&'rp self, render_pass: &mut RenderPass<'rp>, world: &World) -> Result<()> {
-		let metadata = world.get_metadata();
+	pub fn render<'rp>(
+		&'rp self,
+		render_pass: &mut RenderPass<'rp>,
+		target: &RenderTarget,
+	) -> Result<()> {
+		render_pass.set_pipeline(&self.pipeline);
+		render_pass.set_bind_group(2, self.ssao.output_bind_group(target), &[]);
+		self.draw_geometry(render_pass, target, CullPhase::Previous, true);
+		if self
+			.metadata
+			.iter()
+			.any(|entity_metadata| entity_metadata.custom_material.is_some())
+		{
+			render_pass.set_bind_group(1, &self.draw_data.bind_group, &[]);
+			render_pass.set_bind_group(3, &self.environment.bind_group, &[]);
+			self.materials.render(
+				render_pass,
+				&self.uniform,
+				&self.views,
+				target,
+				&self.metadata,
+			);
+		}
+		self.terrain.render(
+			render_pass,
+			&self.uniform,
+			&self.views,
+			target,
+			&self.environment.bind_group,
+		);
+		self.sprites.render(
+			render_pass,
+			&self.uniform,
+			&self.views,
+			target,
+			&self.environment.bind_group,
+		);
+		Ok(())
+	}
 
+	/// Draws the primitives the first culling phase hid that are visible against the target's
+	/// rebuilt depth pyramid, so objects revealed since the last frame don't appear a frame late.
+	/// The ambient occlusion prepass has already run, so they go without it for that frame.
+	pub fn render_disoccluded(
+		&self,
+		device: &Device,
+		encoder: &mut CommandEncoder,
+		target: &RenderTarget,
+		color_view: &wgpu::TextureView,
+		depth_view: &wgpu::TextureView,
+	) {
+		if !self.occlusion.enabled {
+			return;
+		}
+		self.occlusion.cull_disoccluded(device, encoder, target);
+		let mut render_pass = begin_disoccluded_pass(encoder, color_view, depth_view);
 		render_pass.set_pipeline(&self.pipeline);
-		render_pass.set_bind_group(0, &self.uniform.bind_group, &[]);
+		render_pass.set_bind_group(2, self.ssao.output_bind_group(target), &[]);
+		self.draw_geometry(&mut render_pass, target, CullPhase::Disoccluded, true);
+	}
+
+	/// Renders the normal and depth prepass and the ambient occlusion passes for a target
+	/// that has been prepared with `SsaoRender::prepare_target`
+	pub fn render_ambient_occlusion(&self, encoder: &mut CommandEncoder, target: &RenderTarget) {
+		let ssao_target = match self.ssao.target(target) {
+			Some(ssao_target) if self.ssao.enabled => ssao_target,
+			_ => return,
+		};
+		{
+			let mut render_pass = begin_prepass(encoder, ssao_target);
+			render_pass.set_pipeline(&self.ssao.prepass_pipeline);
+			self.draw_geometry(&mut render_pass, target, CullPhase::Previous, false);
+		}
+		self.ssao
+			.render(encoder, &self.uniform, &self.views, target);
+	}
+
+	/// Renders the silhouettes of outlined entities and jump floods them for a target
+	/// that has been prepared with `OutlineRender::prepare_target`
+	pub fn render_outline(
+		&self,
+		encoder: &mut CommandEncoder,
+		target: &RenderTarget,
+		depth_bind_group: &wgpu::BindGroup,
+	) {
+		let outline_target = match self.outline.target(target) {
+			Some(outline_target) if self.outline.enabled => outline_target,
+			_ => return,
+		};
+		{
+			let mut render_pass = begin_mask_pass(encoder, outline_target);
+			render_pass.set_pipeline(&self.outline.mask_pipeline);
+			render_pass.set_bind_group(1, &self.draw_data.bind_group, &[]);
+			render_pass.set_bind_group(2, depth_bind_group, &[]);
+			let (vertex_buffer_slice, index_buffer_slice) = self.geometry.slices();
+			render_pass.set_vertex_buffer(0, vertex_buffer_slice);
+			render_pass.set_index_buffer(index_buffer_slice, wgpu::IndexFormat::Uint32);
+			for (view_index, (view_target, viewport)) in self.views.iter().enumerate() {
+				if view_target != target {
+					continue;
+				}
+				render_pass.set_viewport(
+					viewport.x,
+					viewport.y,
+					viewport.width,
+					viewport.height,
+					0.0,
+					1.0,
+				);
+				let view_offset = self.uniform.view_offset(view_index);
+				render_pass.set_bind_group(0, &self.uniform.bind_group, &[view_offset]);
+				for (draw_index, entity_metadata) in self.metadata.iter().enumerate() {
+					if !self.outline.entities.contains(&entity_metadata.entity) {
+						continue;
+					}
+					let draw_index = draw_index as u32;
+					render_pass.draw_indexed(
+						entity_metadata.index_range.clone(),
+						0,
+						draw_index..draw_index + 1,
+					);
+				}
+			}
+		}
+		self.outline
+			.render_jump_flood(encoder, &self.uniform, &self.views, target);
+	}
 
+	/// Draws the primitives a culling phase kept for the views rendering to the target
+	/// with the bound pipeline, binding each batch's normal map at group 3 if it reads them
+	fn draw_geometry<'rp>(
+		&'rp self,
+		render_pass: &mut RenderPass<'rp>,
+		target: &RenderTarget,
+		phase: CullPhase,
+		normal_maps: bool,
+	) {
+		render_pass.set_bind_group(1, &self.draw_data.bind_group, &[]);
 		let (vertex_buffer_slice, index_buffer_slice) = self.geometry.slices();
 		render_pass.set_vertex_buffer(0, vertex_buffer_slice);
 		render_pass.set_index_buffer(index_buffer_slice, wgpu::IndexFormat::Uint32);
 
-		for entity_metadata in metadata.iter() {
-			let offset = (entity_metadata.offset as wgpu::DynamicOffset)
-				* self.dynamic_uniform.alignment as wgpu::DynamicOffset;
-			render_pass.set_bind_group(1, &self.dynamic_uniform.bind_group, &[offset]);
-			render_pass.draw_indexed(entity_metadata.index_range.clone(), 0, 0..1);
+		for (view_index, (view_target, viewport)) in self.views.iter().enumerate() {
+			if view_target != target {
+				continue;
+			}
+			render_pass.set_viewport(
+				viewport.x,
+				viewport.y,
+				viewport.width,
+				viewport.height,
+				0.0,
+				1.0,
+			);
+			let view_offset = self.uniform.view_offset(view_index);
+			render_pass.set_bind_group(0, &self.uniform.bind_group, &[view_offset]);
+			if !normal_maps {
+				let draws = 0..self.indirect.number_of_draws;
+				self.indirect.draw(
+					render_pass,
+					&self.metadata,
+					&self.occlusion,
+					phase,
+					view_index,
+					draws,
+				);
+				continue;
+			}
+			for (texture_index, draws) in self.normal_maps.batches.iter() {
+				let bind_group = match self.normal_maps.bind_groups.get(texture_index) {
+					Some(bind_group) => bind_group,
+					None => continue,
+				};
+				render_pass.set_bind_group(3, bind_group, &[]);
+				self.indirect.draw(
+					render_pass,
+					&self.metadata,
+					&self.occlusion,
+					phase,
+					view_index,
+					draws.clone(),
+				);
+			}
 		}
+	}
 
-		Ok(())
+	/// Binds a target's scene depth for passes that read it back
+	pub fn create_depth_bind_group(
+		&self,
+		device: &Device,
+		depth_view: &wgpu::TextureView,
+	) -> wgpu::BindGroup {
+		device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Scene Depth Bind Group"),
+			layout: &self.depth_bind_group_layout,
+			entries: &[wgpu::BindGroupEntry {
+				binding: 0,
+				resource: wgpu::BindingResource::TextureView(depth_view),
+			}],
+		})
 	}
 
-	pub fn update(&mut self, queue: &Queue, aspect_ratio: f32, world: &World) {
-		let (projection, view) = world.active_camera_matrices(aspect_ratio).unwrap();
-		let camera_entity = world.active_camera().unwrap();
-		let camera_transform = world.entity_global_transform(camera_entity).unwrap();
-		let camera_position = glm::vec3_to_vec4(&camera_transform.translation);
+	/// Renders decals, particles and the selection outline for the views drawing to the target,
+	/// after the scene has been rendered into the target's depth texture
+	pub fn render_overlays<'rp>(
+		&'rp self,
+		render_pass: &mut RenderPass<'rp>,
+		target: &RenderTarget,
+		depth_bind_group: &'rp wgpu::BindGroup,
+	) {
+		self.decals.render(
+			render_pass,
+			&self.uniform,
+			&self.views,
+			target,
+			depth_bind_group,
+			&self.environment.bind_group,
+		);
+		self.particles.render(
+			render_pass,
+			&self.uniform,
+			&self.views,
+			target,
+			depth_bind_group,
+			&self.environment.bind_group,
+		);
+		self.outline
+			.composite(render_pass, &self.uniform, &self.views, target);
+	}
 
+	pub fn update(&mut self, device: &Device, queue: &Queue, views: &[CameraView], world: &World) {
 		let lights = world.components::<phantom_world::Light>().unwrap();
 		let (transform, light) = lights.first().unwrap();
 		let light = Light::new(transform.translation, light.color);
 
-		self.uniform.upload_uniform_data(
-			queue,
-			0,
-			Uniform {
-				view,
-				projection,
-				camera_position,
-				light,
-			},
-		);
+		self.views.clear();
+		let mut culled_views = Vec::new();
+		let mut camera_positions = Vec::new();
+		let visible_views = views
+			.iter()
+			.filter(|camera_view| {
+				camera_view.viewport.width > 0.0 && camera_view.viewport.height > 0.0
+			})
+			.collect::<Vec<_>>();
+		if visible_views.len() > UniformBinding::MAX_NUMBER_OF_VIEWS {
+			log::warn!(
+				"Only the first {} of {} camera views are rendered",
+				UniformBinding::MAX_NUMBER_OF_VIEWS,
+				visible_views.len()
+			);
+		}
+		for (view_index, camera_view) in visible_views
+			.into_iter()
+			.take(UniformBinding::MAX_NUMBER_OF_VIEWS)
+			.enumerate()
+		{
+			self.uniform.upload_uniform_data(
+				queue,
+				view_index as BufferAddress * self.uniform.stride,
+				Uniform {
+					view: camera_view.view,
+					projection: camera_view.projection,
+					camera_position: glm::vec3_to_vec4(&camera_view.position),
+					light,
+					inverse_view_projection: glm::inverse(
+						&(camera_view.projection * camera_view.view),
+					),
+					viewport: camera_view.viewport.as_glm_vec(),
+				},
+			);
+			self.views
+				.push((camera_view.target.clone(), camera_view.viewport));
+			culled_views.push((
+				camera_view.target.clone(),
+				camera_view.viewport,
+				camera_view.projection * camera_view.view,
+			));
+			camera_positions.push(camera_view.position);
+		}
 
-		let mut mesh_ubos =
-			vec![DynamicUniform::default(); DynamicUniformBinding::MAX_NUMBER_OF_MESHES];
-		let mut ubo_offset = 0;
+		self.ssao.retain_targets(&self.views);
+		self.outline.retain_targets(&self.views);
+		self.environment.upload(queue, &world.scene.environment);
+
+		// Joints are laid out in scene graph order, matching `World::joint_matrices`
+		let mut models = Vec::new();
+		let mut joint_offsets = Vec::new();
+		let mut number_of_joints = 0;
 		for graph in world.scene.graphs.iter() {
 			graph
 				.walk(|node_index| {
-					let model = world.global_transform(graph, node_index)?;
-					mesh_ubos[ubo_offset] = DynamicUniform { model };
-					ubo_offset += 1;
+					models.push(world.global_transform(graph, node_index)?);
+					joint_offsets.push(number_of_joints as u32);
+					let entry = world.ecs.entry_ref(graph[node_index])?;
+					if let Ok(skin) = entry.get_component::<Skin>() {
+						number_of_joints += skin.joints.len();
+					}
 					Ok(())
 				})
 				.unwrap();
 		}
-		self.dynamic_uniform
-			.upload_uniform_data(queue, 0, &mesh_ubos);
+
+		world.select_lods(views, &mut self.lod_levels);
+		let mut metadata = world.get_metadata(&self.lod_levels);
+		self.normal_maps
+			.update(device, queue, world, &self.environment, &mut metadata);
+
+		let draws = metadata
+			.iter()
+			.map(|entity_metadata| {
+				let material = entity_metadata
+					.material
+					.and_then(|material_index| world.materials.get(material_index));
+				DrawData {
+					model: models[entity_metadata.offset as usize],
+					joint_offset: joint_offsets[entity_metadata.offset as usize],
+					normal_scale: material.map_or(1.0, |material| material.normal_texture_scale),
+					normal_uv_set: material
+						.map_or(0, |material| material.normal_texture_set.max(0) as u32),
+					padding: 0,
+				}
+			})
+			.collect::<Vec<_>>();
+		let has_skinned_materials = metadata.iter().any(|entity_metadata| {
+			entity_metadata.skinned && entity_metadata.custom_material.is_some()
+		});
+		let joint_matrices = if has_skinned_materials {
+			world.joint_matrices().unwrap()
+		} else {
+			Vec::new()
+		};
+		self.draw_data
+			.upload(device, queue, &draws, &joint_matrices);
+		self.indirect.update(device, queue, &metadata);
+		self.materials.update(device, queue, world, &metadata);
+
+		let cull_objects = if self.occlusion.enabled {
+			metadata
+				.iter()
+				.zip(draws.iter())
+				.map(|(entity_metadata, draw)| CullObject {
+					model: draw.model,
+					bounding_box_min: glm::vec3_to_vec4(&entity_metadata.bounding_box.min),
+					bounding_box_max: glm::vec3_to_vec4(&entity_metadata.bounding_box.max),
+					first_index: entity_metadata.index_range.start,
+					number_of_indices: entity_metadata.index_range.len() as u32,
+					instance_count: u32::from(entity_metadata.custom_material.is_none()),
+					padding: 0,
+				})
+				.collect()
+		} else {
+			Vec::new()
+		};
+		self.occlusion
+			.update(device, queue, &culled_views, &cull_objects);
+		self.metadata = metadata;
+
+		self.decals.update(device, queue, world, &mut self.textures);
+		self.particles
+			.update(device, queue, world, &mut self.textures);
+		self.terrain
+			.update(device, queue, world, &mut self.textures, &camera_positions);
+		self.sprites.update(device, queue, world);
 	}
 }
 
+fn create_depth_bind_group_layout(device: &Device) -> wgpu::BindGroupLayout {
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Scene Depth Bind Group Layout"),
+		entries: &[wgpu::BindGroupLayoutEntry {
+			binding: 0,
+			visibility: wgpu::ShaderStages::FRAGMENT,
+			ty: wgpu::BindingType::Texture {
+				sample_type: wgpu::TextureSampleType::Depth,
+				view_dimension: wgpu::TextureViewDimension::D2,
+				multisampled: false,
+			},
+			count: None,
+		}],
+	})
+}
+
 fn create_pipeline(
 	device: &Device,
 	surface_format: TextureFormat,
 	uniform: &UniformBinding,
-	dynamic_uniform: &DynamicUniformBinding,
+	draw_data: &DrawBinding,
+	ambient_occlusion_bind_group_layout: &wgpu::BindGroupLayout,
+	normal_map_bind_group_layout: &wgpu::BindGroupLayout,
 ) -> RenderPipeline {
 	let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
 		label: None,
@@ -107,7 +544,9 @@ fn create_pipeline(
 		label: None,
 		bind_group_layouts: &[
 			&uniform.bind_group_layout,
-			&dynamic_uniform.bind_group_layout,
+			&draw_data.bind_group_layout,
+			ambient_occlusion_bind_group_layout,
+			normal_map_bind_group_layout,
 		],
 		push_constant_ranges: &[],
 	});
@@ -159,6 +598,7 @@ pub fn create_vertex_attributes() -> Vec<VertexAttribute> {
 	4 => Float32x4, // joint_0
 	5 => Float32x4, // weight_0
 	6 => Float32x3, // color_0
+	7 => Float32x4, // tangent
 	]
 	.to_vec()
 }
@@ -226,17 +666,25 @@ impl Geometry {
 }
 
 pub struct UniformBinding {
+	/// Bytes between views' uniforms, the uniform's size rounded up to the offset alignment
+	pub stride: wgpu::BufferAddress,
 	pub buffer: wgpu::Buffer,
 	pub bind_group_layout: wgpu::BindGroupLayout,
 	pub bind_group: wgpu::BindGroup,
 }
 
 impl UniformBinding {
+	pub const MAX_NUMBER_OF_VIEWS: usize = 16;
+
 	pub fn new(device: &wgpu::Device) -> Self {
-		let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+		let alignment = device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress;
+		let stride = wgpu::util::align_to(size_of::<Uniform>() as wgpu::BufferAddress, alignment);
+
+		let buffer = device.create_buffer(&wgpu::BufferDescriptor {
 			label: Some("Uniform Buffer"),
-			contents: bytemuck::cast_slice(&[Uniform::default()]),
+			size: (Self::MAX_NUMBER_OF_VIEWS as wgpu::BufferAddress) * stride,
 			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+			mapped_at_creation: false,
 		});
 
 		let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -245,8 +693,8 @@ impl UniformBinding {
 				visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
 				ty: wgpu::BindingType::Buffer {
 					ty: wgpu::BufferBindingType::Uniform,
-					has_dynamic_offset: false,
-					min_binding_size: None,
+					has_dynamic_offset: true,
+					min_binding_size: wgpu::BufferSize::new(size_of::<Uniform>() as _),
 				},
 				count: None,
 			}],
@@ -257,18 +705,28 @@ impl UniformBinding {
 			layout: &bind_group_layout,
 			entries: &[wgpu::BindGroupEntry {
 				binding: 0,
-				resource: buffer.as_entire_binding(),
+				resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+					buffer: &buffer,
+					offset: 0,
+					size: wgpu::BufferSize::new(size_of::<Uniform>() as _),
+				}),
 			}],
 			label: Some("Uniform Buffer Bind Group"),
 		});
 
 		Self {
+			stride,
 			buffer,
 			bind_group_layout,
 			bind_group,
 		}
 	}
 
+	/// The dynamic offset of a view's uniform
+	pub fn view_offset(&self, view_index: usize) -> wgpu::DynamicOffset {
+		(view_index as BufferAddress * self.stride) as wgpu::DynamicOffset
+	}
+
 	pub fn upload_uniform_data(&self, queue: &Queue, offset: BufferAddress, data: Uniform) {
 		queue.write_buffer(&self.buffer, offset, bytemuck::cast_slice(&[data]));
 	}
@@ -281,24 +739,23 @@ pub struct Uniform {
 	pub projection: glm::Mat4,
 	pub camera_position: glm::Vec4,
 	pub light: Light,
+	/// Reconstructs world positions from the depth buffer
+	pub inverse_view_projection: glm::Mat4,
+	/// The view's rectangle in target pixels
+	pub viewport: glm::Vec4,
 }
 
-pub struct DynamicUniformBinding {
-	pub alignment: wgpu::BufferAddress,
+pub struct EnvironmentBinding {
 	pub buffer: wgpu::Buffer,
 	pub bind_group_layout: wgpu::BindGroupLayout,
 	pub bind_group: wgpu::BindGroup,
 }
 
-impl DynamicUniformBinding {
-	pub const MAX_NUMBER_OF_MESHES: usize = 10_000;
-
+impl EnvironmentBinding {
 	pub fn new(device: &wgpu::Device) -> Self {
-		let alignment = device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress;
-
 		let buffer = device.create_buffer(&wgpu::BufferDescriptor {
-			label: Some("Dynamic Uniform Buffer"),
-			size: (Self::MAX_NUMBER_OF_MESHES as wgpu::BufferAddress) * alignment,
+			label: Some("Environment Buffer"),
+			size: size_of::<EnvironmentUniform>() as wgpu::BufferAddress,
 			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
 			mapped_at_creation: false,
 		});
@@ -306,52 +763,204 @@ impl DynamicUniformBinding {
 		let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
 			entries: &[wgpu::BindGroupLayoutEntry {
 				binding: 0,
-				visibility: wgpu::ShaderStages::VERTEX,
+				visibility: wgpu::ShaderStages::FRAGMENT,
 				ty: wgpu::BindingType::Buffer {
 					ty: wgpu::BufferBindingType::Uniform,
-					has_dynamic_offset: true,
-					min_binding_size: wgpu::BufferSize::new(size_of::<DynamicUniform>() as _),
+					has_dynamic_offset: false,
+					min_binding_size: wgpu::BufferSize::new(size_of::<EnvironmentUniform>() as _),
 				},
 				count: None,
 			}],
-			label: Some("Dynamic Uniform Buffer Bind Group Layout"),
+			label: Some("Environment Bind Group Layout"),
 		});
 
 		let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
 			layout: &bind_group_layout,
 			entries: &[wgpu::BindGroupEntry {
 				binding: 0,
-				resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-					buffer: &buffer,
-					offset: 0,
-					size: wgpu::BufferSize::new(size_of::<DynamicUniform>() as _),
-				}),
+				resource: buffer.as_entire_binding(),
 			}],
-			label: Some("World Uniform Buffer Bind Group"),
+			label: Some("Environment Bind Group"),
 		});
 
 		Self {
-			alignment,
 			buffer,
 			bind_group_layout,
 			bind_group,
 		}
 	}
 
-	pub fn upload_uniform_data(&self, queue: &Queue, offset: BufferAddress, data: &[impl Copy]) {
-		queue.write_buffer(&self.buffer, offset, unsafe {
-			std::slice::from_raw_parts(
-				data.as_ptr() as *const u8,
-				data.len() * self.alignment as usize,
-			)
+	pub fn upload(&self, queue: &Queue, environment: &Environment) {
+		let data = EnvironmentUniform::new(environment);
+		queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[data]));
+	}
+}
+
+#[repr(C)]
+#[derive(Default, Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct EnvironmentUniform {
+	pub ambient: glm::Vec4,
+	pub fog_color: glm::Vec4,
+	/// Start and end distances for linear fog,
+	/// otherwise the density, base height and height falloff
+	pub fog_parameters: glm::Vec4,
+	/// Zero without fog, then one, two and three for linear, exponential and height fog
+	pub fog_mode: u32,
+	pub padding: [u32; 3],
+}
+
+impl EnvironmentUniform {
+	pub fn new(environment: &Environment) -> Self {
+		let (fog_mode, fog_parameters) = match environment.fog {
+			Fog::None => (0, glm::Vec4::zeros()),
+			Fog::Linear { start, end, .. } => (1, glm::vec4(start, end, 0.0, 0.0)),
+			Fog::Exponential { density, .. } => (2, glm::vec4(density, 0.0, 0.0, 0.0)),
+			Fog::Height {
+				density,
+				base_height,
+				falloff,
+				..
+			} => (3, glm::vec4(density, base_height, falloff, 0.0)),
+		};
+		Self {
+			ambient: glm::vec3_to_vec4(&environment.ambient_light()),
+			fog_color: glm::vec3_to_vec4(&environment.fog.color()),
+			fog_parameters,
+			fog_mode,
+			padding: [0; 3],
+		}
+	}
+}
+
+/// Per-draw data in a storage buffer, looked up by the instance index each draw starts at,
+/// along with the joint matrices of skinned meshes
+pub struct DrawBinding {
+	pub capacity: usize,
+	pub joint_capacity: usize,
+	pub buffer: wgpu::Buffer,
+	pub joint_buffer: wgpu::Buffer,
+	pub bind_group_layout: wgpu::BindGroupLayout,
+	pub bind_group: wgpu::BindGroup,
+}
+
+impl DrawBinding {
+	const INITIAL_CAPACITY: usize = 1024;
+
+	pub fn new(device: &wgpu::Device) -> Self {
+		let storage_entry = |binding, element_size| wgpu::BindGroupLayoutEntry {
+			binding,
+			visibility: wgpu::ShaderStages::VERTEX,
+			ty: wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Storage { read_only: true },
+				has_dynamic_offset: false,
+				min_binding_size: wgpu::BufferSize::new(element_size as _),
+			},
+			count: None,
+		};
+		let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			entries: &[
+				storage_entry(0, size_of::<DrawData>()),
+				storage_entry(1, size_of::<glm::Mat4>()),
+			],
+			label: Some("Draw Data Bind Group Layout"),
 		});
+		let capacity = Self::INITIAL_CAPACITY;
+		let joint_capacity = Self::INITIAL_CAPACITY;
+		let buffer = create_storage_buffer::<DrawData>(device, "Draw Data Buffer", capacity);
+		let joint_buffer =
+			create_storage_buffer::<glm::Mat4>(device, "Joint Matrix Buffer", joint_capacity);
+		let bind_group =
+			Self::create_bind_group(device, &bind_group_layout, &buffer, &joint_buffer);
+		Self {
+			capacity,
+			joint_capacity,
+			buffer,
+			joint_buffer,
+			bind_group_layout,
+			bind_group,
+		}
+	}
+
+	/// Uploads the data of this frame's draws and joints,
+	/// growing the buffers when they no longer fit
+	pub fn upload(
+		&mut self,
+		device: &wgpu::Device,
+		queue: &Queue,
+		draws: &[DrawData],
+		joint_matrices: &[glm::Mat4],
+	) {
+		let mut resized = false;
+		if draws.len() > self.capacity {
+			self.capacity = draws.len().next_power_of_two();
+			self.buffer =
+				create_storage_buffer::<DrawData>(device, "Draw Data Buffer", self.capacity);
+			resized = true;
+		}
+		if joint_matrices.len() > self.joint_capacity {
+			self.joint_capacity = joint_matrices.len().next_power_of_two();
+			self.joint_buffer = create_storage_buffer::<glm::Mat4>(
+				device,
+				"Joint Matrix Buffer",
+				self.joint_capacity,
+			);
+			resized = true;
+		}
+		if resized {
+			self.bind_group = Self::create_bind_group(
+				device,
+				&self.bind_group_layout,
+				&self.buffer,
+				&self.joint_buffer,
+			);
+		}
+		queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(draws));
+		queue.write_buffer(&self.joint_buffer, 0, bytemuck::cast_slice(joint_matrices));
 	}
+
+	fn create_bind_group(
+		device: &wgpu::Device,
+		bind_group_layout: &wgpu::BindGroupLayout,
+		buffer: &wgpu::Buffer,
+		joint_buffer: &wgpu::Buffer,
+	) -> wgpu::BindGroup {
+		device.create_bind_group(&wgpu::BindGroupDescriptor {
+			layout: bind_group_layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: buffer.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: joint_buffer.as_entire_binding(),
+				},
+			],
+			label: Some("Draw Data Bind Group"),
+		})
+	}
+}
+
+fn create_storage_buffer<T>(device: &wgpu::Device, label: &str, capacity: usize) -> wgpu::Buffer {
+	device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some(label),
+		size: (capacity * size_of::<T>()) as BufferAddress,
+		usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	})
 }
 
-#[repr(C, align(256))]
-#[derive(Default, Copy, Clone, Debug, bytemuck::Zeroable)]
-pub struct DynamicUniform {
+#[repr(C)]
+#[derive(Default, Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DrawData {
 	pub model: glm::Mat4,
+	/// The first of the skin's joint matrices, for custom materials drawing skinned meshes
+	pub joint_offset: u32,
+	/// Scales the normal map's tangent space x and y, as glTF's `normalTexture.scale`
+	pub normal_scale: f32,
+	/// Which of the vertex texture coordinates the normal map is sampled with
+	pub normal_uv_set: u32,
+	pub padding: u32,
 }
 
 const SHADER_SOURCE: &str = "
@@ -365,17 +974,74 @@ struct Uniform {
     projection: mat4x4<f32>,
     camera_position: vec4<f32>,
     light: Light,
+    inverse_view_projection: mat4x4<f32>,
+    viewport: vec4<f32>,
 };
 
 @group(0) @binding(0)
 var<uniform> ubo: Uniform;
 
-struct DynamicUniform {
+struct DrawData {
     model: mat4x4<f32>,
+    joint_offset: u32,
+    normal_scale: f32,
+    normal_uv_set: u32,
+    padding: u32,
 };
 
 @group(1) @binding(0)
-var<uniform> mesh_ubo: DynamicUniform;
+var<storage, read> draws: array<DrawData>;
+
+@group(2) @binding(0)
+var ambient_occlusion: texture_2d<f32>;
+
+struct Environment {
+    ambient: vec4<f32>,
+    fog_color: vec4<f32>,
+    fog_parameters: vec4<f32>,
+    fog_mode: u32,
+    padding_0: u32,
+    padding_1: u32,
+    padding_2: u32,
+};
+
+@group(3) @binding(0)
+var<uniform> environment: Environment;
+
+@group(3) @binding(1)
+var normal_texture: texture_2d<f32>;
+
+@group(3) @binding(2)
+var normal_sampler: sampler;
+
+fn apply_fog(color: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let offset = world_position - ubo.camera_position.xyz;
+    let distance = length(offset);
+    let parameters = environment.fog_parameters;
+    var visibility = 1.0;
+    switch (environment.fog_mode) {
+        case 1u: {
+            let range = max(parameters.y - parameters.x, 0.0001);
+            visibility = clamp((parameters.y - distance) / range, 0.0, 1.0);
+        }
+        case 2u: {
+            visibility = exp(-parameters.x * distance);
+        }
+        case 3u: {
+            // Integrates the density along the view ray as it thins out with height
+            let camera_height = ubo.camera_position.y - parameters.y;
+            let camera_density = parameters.x * exp(-parameters.z * camera_height);
+            let rise = parameters.z * offset.y;
+            var thinning = 1.0;
+            if (abs(rise) > 0.0001) {
+                thinning = (1.0 - exp(-rise)) / rise;
+            }
+            visibility = exp(-camera_density * distance * thinning);
+        }
+        default: {}
+    }
+    return mix(environment.fog_color.rgb, color, visibility);
+}
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
@@ -385,41 +1051,160 @@ struct VertexInput {
     @location(4) joint_0: vec4<f32>,
     @location(5) weight_0: vec4<f32>,
     @location(6) color_0: vec3<f32>,
+    @location(7) tangent: vec4<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) normal: vec3<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) tangent: vec4<f32>,
+    @location(3) uv: vec2<f32>,
+    @location(4) @interpolate(flat) draw_index: u32,
 };
 
 @vertex
-fn vertex_main(vert: VertexInput) -> VertexOutput {
+fn vertex_main(vert: VertexInput, @builtin(instance_index) draw_index: u32) -> VertexOutput {
     var out: VertexOutput;
-    let mvp = ubo.projection * ubo.view * mesh_ubo.model;
-    out.position = mvp * vec4(vert.position, 1.0);
-    out.normal = vec4((mvp * vec4(vert.normal, 0.0)).xyz, 1.0).xyz;
+    let draw = draws[draw_index];
+    let world_position = draw.model * vec4(vert.position, 1.0);
+    out.position = ubo.projection * ubo.view * world_position;
+    out.world_position = world_position.xyz;
+    out.normal = (draw.model * vec4(vert.normal, 0.0)).xyz;
+    // The handedness in w decides which way the bitangent points
+    out.tangent = vec4((draw.model * vec4(vert.tangent.xyz, 0.0)).xyz, vert.tangent.w);
+    out.uv = select(vert.uv_0, vert.uv_1, draw.normal_uv_set == 1u);
+    out.draw_index = draw_index;
     return out;
 };
 
+// Perturbs the interpolated normal by the normal map in the tangent frame
+fn surface_normal(in_normal: vec3<f32>, in_tangent: vec4<f32>, uv: vec2<f32>, scale: f32) -> vec3<f32> {
+    // Sampled before branching, since sampling needs uniform control flow
+    let sampled = textureSample(normal_texture, normal_sampler, uv).xyz * 2.0 - 1.0;
+    let normal = normalize(in_normal);
+    if (length(in_tangent.xyz) < 0.0001) {
+        return normal;
+    }
+
+    // Re-orthogonalize after interpolation
+    let tangent = normalize(in_tangent.xyz - normal * dot(normal, in_tangent.xyz));
+    let bitangent = cross(normal, tangent) * sign(in_tangent.w);
+    let tbn = mat3x3<f32>(tangent, bitangent, normal);
+    return normalize(tbn * vec3(sampled.xy * scale, sampled.z));
+}
+
 @fragment
 fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
     let object_color: vec4<f32> = vec4(0.2, 0.3, 0.4, 1.0);
+    let normal_scale = draws[in.draw_index].normal_scale;
+    let normal = surface_normal(in.normal, in.tangent, in.uv, normal_scale);
 
-    let ambient_strength = 0.1;
-    let ambient_color = ubo.light.color.rgb * ambient_strength;
+    let occlusion_size = vec2<i32>(textureDimensions(ambient_occlusion));
+    let occlusion_pixel = min(vec2<i32>(in.position.xy), occlusion_size - vec2(1, 1));
+    let occlusion = textureLoad(ambient_occlusion, occlusion_pixel, 0).r;
+    let ambient_color = environment.ambient.rgb * occlusion;
 
-    let light_dir = normalize(in.position.xyz - ubo.light.position.xyz);
-    let diffuse_strength = max(dot(in.normal, light_dir), 0.0);
+    let light_dir = normalize(ubo.light.position.xyz - in.world_position);
+    let diffuse_strength = max(dot(normal, light_dir), 0.0);
     let diffuse_color = ubo.light.color.rgb * diffuse_strength;
 
-    let view_dir = normalize(ubo.camera_position.xyz - in.position.xyz);
+    let view_dir = normalize(ubo.camera_position.xyz - in.world_position);
     let half_dir = normalize(view_dir + light_dir);
 
-    let specular_strength = pow(max(dot(in.normal, half_dir), 0.0), 32.0);
+    let specular_strength = pow(max(dot(normal, half_dir), 0.0), 32.0);
     let specular_color = specular_strength * ubo.light.color.rgb;
 
     let result = (ambient_color + diffuse_color + specular_color) * object_color.rgb;
 
-    return vec4<f32>(result, object_color.a);
+    return vec4<f32>(apply_fog(result, in.world_position), object_color.a);
 }
 ";
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn shader_module() -> naga::Module {
+		let module = naga::front::wgsl::parse_str(SHADER_SOURCE).unwrap();
+		naga::valid::Validator::new(
+			naga::valid::ValidationFlags::all(),
+			naga::valid::Capabilities::empty(),
+		)
+		.validate(&module)
+		.unwrap();
+		module
+	}
+
+	#[test]
+	fn scene_shader_reads_vertex_tangents() {
+		let module = shader_module();
+		let vertex_main = module
+			.entry_points
+			.iter()
+			.find(|entry_point| entry_point.name == "vertex_main")
+			.unwrap();
+		let input_type = &module.types[vertex_main.function.arguments[0].ty];
+		let tangent = match &input_type.inner {
+			naga::TypeInner::Struct { members, .. } => members
+				.iter()
+				.find(|member| {
+					matches!(
+						member.binding,
+						Some(naga::Binding::Location { location: 7, .. })
+					)
+				})
+				.unwrap(),
+			_ => panic!("Vertex input is not a struct"),
+		};
+		assert!(matches!(
+			module.types[tangent.ty].inner,
+			naga::TypeInner::Vector {
+				size: naga::VectorSize::Quad,
+				..
+			}
+		));
+		assert_eq!(
+			create_vertex_attributes()[7].format,
+			wgpu::VertexFormat::Float32x4
+		);
+	}
+
+	#[test]
+	fn scene_shader_samples_the_normal_map() {
+		let module = shader_module();
+		let (normal_texture, _) = module
+			.global_variables
+			.iter()
+			.find(|(_, variable)| variable.name.as_deref() == Some("normal_texture"))
+			.unwrap();
+		let (surface_normal_handle, surface_normal) = module
+			.functions
+			.iter()
+			.find(|(_, function)| function.name.as_deref() == Some("surface_normal"))
+			.unwrap();
+		let samples_normal_map =
+			surface_normal
+				.expressions
+				.iter()
+				.any(|(_, expression)| match expression {
+					naga::Expression::ImageSample { image, .. } => matches!(
+						surface_normal.expressions[*image],
+						naga::Expression::GlobalVariable(variable) if variable == normal_texture
+					),
+					_ => false,
+				});
+		assert!(samples_normal_map);
+
+		// The fragment shader lights with the mapped normal rather than the vertex normal
+		let fragment_main = module
+			.entry_points
+			.iter()
+			.find(|entry_point| entry_point.name == "fragment_main")
+			.unwrap();
+		assert!(fragment_main.function.body.iter().any(|statement| matches!(
+			statement,
+			naga::Statement::Call { function, .. } if *function == surface_normal_handle
+		)));
+	}
+}
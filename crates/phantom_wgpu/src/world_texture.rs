@@ -0,0 +1,84 @@
+use phantom_world::{TextureFormat as WorldTextureFormat, World};
+use std::collections::HashMap;
+use wgpu::{self, util::DeviceExt, Device, Queue, TextureFormat, TextureView};
+
+/// GPU copies of the world's textures, shared by every pass that samples them.
+/// Each texture is uploaded once per format the first time a pass asks for it.
+#[derive(Default)]
+pub struct WorldTextures {
+	pub textures: HashMap<(usize, TextureFormat), wgpu::Texture>,
+}
+
+impl WorldTextures {
+	/// Returns the view of a world texture, uploading it the first time it is used.
+	/// The pass is named in the warnings logged for missing or unsupported textures.
+	pub fn view(
+		&mut self,
+		device: &Device,
+		queue: &Queue,
+		world: &World,
+		texture_index: Option<usize>,
+		format: TextureFormat,
+		pass: &str,
+	) -> Option<TextureView> {
+		let texture_index = texture_index?;
+		let key = (texture_index, format);
+		if !self.textures.contains_key(&key) {
+			let texture = match world.textures.get(texture_index) {
+				Some(texture) if texture.format == WorldTextureFormat::R8G8B8A8 => texture,
+				Some(texture) => {
+					log::warn!(
+						"{pass} texture format {:?} is not supported",
+						texture.format
+					);
+					return None;
+				}
+				None => {
+					log::warn!("{pass} texture {texture_index} not found");
+					return None;
+				}
+			};
+			let gpu_texture = create_rgba_texture(
+				device,
+				queue,
+				"World Texture",
+				format,
+				(texture.width, texture.height),
+				&texture.pixels,
+			);
+			self.textures.insert(key, gpu_texture);
+		}
+		self.textures
+			.get(&key)
+			.map(|texture| texture.create_view(&wgpu::TextureViewDescriptor::default()))
+	}
+}
+
+/// Creates a sampled texture from tightly packed 8-bit RGBA pixels
+pub fn create_rgba_texture(
+	device: &Device,
+	queue: &Queue,
+	label: &str,
+	format: TextureFormat,
+	(width, height): (u32, u32),
+	pixels: &[u8],
+) -> wgpu::Texture {
+	device.create_texture_with_data(
+		queue,
+		&wgpu::TextureDescriptor {
+			label: Some(label),
+			size: wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format,
+			usage: wgpu::TextureUsages::TEXTURE_BINDING,
+			view_formats: &[format],
+		},
+		pixels,
+	)
+}
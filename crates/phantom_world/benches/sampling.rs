@@ -0,0 +1,196 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use phantom_world::{
+	nalgebra_glm as glm, Animation, AnimationGraph, AnimationLayer, AnimationState, Channel, Ecs,
+	Interpolation, Motion, Pose, PosePool, TransformationSet,
+};
+
+const NUMBER_OF_JOINTS: usize = 60;
+const NUMBER_OF_MORPH_WEIGHTS: usize = 52;
+const FRAMES_PER_SECOND: f32 = 30.0;
+const DURATION: f32 = 120.0;
+
+/// A long motion capture clip with a keyframe every frame on every joint
+fn motion_capture_clip(ecs: &mut Ecs) -> Animation {
+	let number_of_keyframes = (DURATION * FRAMES_PER_SECOND) as usize;
+	let inputs = (0..number_of_keyframes)
+		.map(|key| key as f32 / FRAMES_PER_SECOND)
+		.collect::<Vec<_>>();
+	let wave = |key: usize, offset: usize| ((key + offset) as f32 * 0.1).sin();
+
+	let mut channels = Vec::new();
+	for joint in 0..NUMBER_OF_JOINTS {
+		let target = ecs.push(());
+		let channel = |transformations| Channel {
+			target,
+			inputs: inputs.clone(),
+			transformations,
+			interpolation: Interpolation::Linear,
+		};
+		channels.push(channel(TransformationSet::Translations(
+			(0..number_of_keyframes)
+				.map(|key| glm::vec3(wave(key, joint), 1.0, wave(key, joint * 2)))
+				.collect(),
+		)));
+		channels.push(channel(TransformationSet::Rotations(
+			(0..number_of_keyframes)
+				.map(|key| {
+					glm::quat_angle_axis(wave(key, joint), &glm::Vec3::y())
+						.coords
+						.normalize()
+				})
+				.collect(),
+		)));
+		channels.push(channel(TransformationSet::Scales(
+			(0..number_of_keyframes)
+				.map(|key| glm::vec3(1.0, 1.0 + wave(key, joint) * 0.1, 1.0))
+				.collect(),
+		)));
+	}
+	channels.push(Channel {
+		target: ecs.push(()),
+		inputs: inputs.clone(),
+		transformations: TransformationSet::MorphTargetWeights(
+			(0..number_of_keyframes * NUMBER_OF_MORPH_WEIGHTS)
+				.map(|index| wave(index, 0).abs())
+				.collect(),
+		),
+		interpolation: Interpolation::Linear,
+	});
+
+	Animation {
+		name: "Motion Capture".to_string(),
+		time: 0.0,
+		channels,
+		max_animation_time: inputs.last().copied().unwrap_or_default(),
+		root_motion_joint: None,
+		notifies: Vec::new(),
+	}
+}
+
+/// Sample times spread across the clip, as a game stepping it at 60 frames per second would visit
+fn sample_times() -> Vec<f32> {
+	(0..600)
+		.map(|frame| (frame as f32 * 0.2).rem_euclid(DURATION - 1.0))
+		.collect()
+}
+
+/// The sampler this replaced, which scanned every keyframe pair and chunked morph weights
+fn sample_linear_scan(animation: &Animation, time: f32, output: &mut Vec<glm::Vec4>) {
+	output.clear();
+	for channel in animation.channels.iter() {
+		let mut input_iter = channel.inputs.iter().enumerate().peekable();
+		while let Some((previous_key, previous_time)) = input_iter.next() {
+			let (next_key, next_time) = match input_iter.peek() {
+				Some((next_key, next_time)) => (*next_key, **next_time),
+				None => continue,
+			};
+			if time < *previous_time || time > next_time {
+				continue;
+			}
+			let amount = (time - previous_time) / (next_time - previous_time);
+			match &channel.transformations {
+				TransformationSet::Translations(values) | TransformationSet::Scales(values) => {
+					let value = glm::mix(&values[previous_key], &values[next_key], amount);
+					output.push(glm::vec4(value.x, value.y, value.z, 0.0));
+				}
+				TransformationSet::Rotations(rotations) => {
+					let start = glm::make_quat(rotations[previous_key].as_slice());
+					let end = glm::make_quat(rotations[next_key].as_slice());
+					output.push(glm::quat_slerp(&start, &end, amount).coords);
+				}
+				TransformationSet::MorphTargetWeights(weights) => {
+					let weights = weights
+						.as_slice()
+						.chunks(NUMBER_OF_MORPH_WEIGHTS)
+						.collect::<Vec<_>>();
+					for index in 0..NUMBER_OF_MORPH_WEIGHTS {
+						let weight = glm::lerp_scalar(
+							weights[previous_key][index],
+							weights[next_key][index],
+							amount,
+						);
+						output.push(glm::vec4(weight, 0.0, 0.0, 0.0));
+					}
+				}
+			}
+		}
+	}
+}
+
+fn sampling(criterion: &mut Criterion) {
+	let mut ecs = Ecs::default();
+	let animation = motion_capture_clip(&mut ecs);
+	let times = sample_times();
+
+	let mut group = criterion.benchmark_group("sample motion capture clip");
+	group.bench_function("linear scan", |bencher| {
+		let mut output = Vec::new();
+		bencher.iter(|| {
+			for time in times.iter() {
+				sample_linear_scan(&animation, black_box(*time), &mut output);
+			}
+		})
+	});
+	group.bench_function("binary search", |bencher| {
+		bencher.iter(|| {
+			for time in times.iter() {
+				black_box(animation.sample(black_box(*time)));
+			}
+		})
+	});
+	group.bench_function("binary search into reused pose", |bencher| {
+		let mut pose = Pose::default();
+		bencher.iter(|| {
+			for time in times.iter() {
+				animation.sample_into(black_box(*time), &mut pose);
+			}
+			black_box(&pose);
+		})
+	});
+	group.finish();
+}
+
+/// A graph blending two motion capture clips by a parameter, as a locomotion state would
+fn locomotion_graph() -> AnimationGraph {
+	let motion = Motion::Blend1D {
+		parameter: "speed".to_string(),
+		clips: vec![(0.0, "Walk".to_string()), (1.0, "Run".to_string())],
+	};
+	let mut graph = AnimationGraph::new(vec![AnimationLayer::new(
+		"Base",
+		vec![AnimationState::new("Locomotion", motion)],
+	)]);
+	graph.set_float("speed", 0.5);
+	graph
+}
+
+fn graph_evaluation(criterion: &mut Criterion) {
+	let mut ecs = Ecs::default();
+	let mut walk = motion_capture_clip(&mut ecs);
+	walk.name = "Walk".to_string();
+	let mut run = walk.clone();
+	run.name = "Run".to_string();
+	let animations = vec![walk, run];
+	let mut graph = locomotion_graph();
+
+	let mut group = criterion.benchmark_group("evaluate animation graph");
+	group.bench_function("fresh poses", |bencher| {
+		bencher.iter(|| {
+			graph.advance(&animations, black_box(1.0 / 60.0));
+			black_box(graph.evaluate(&animations));
+		})
+	});
+	group.bench_function("pooled poses", |bencher| {
+		let mut pool = PosePool::default();
+		let mut pose = Pose::default();
+		bencher.iter(|| {
+			graph.advance(&animations, black_box(1.0 / 60.0));
+			graph.evaluate_into(&animations, &mut pose, &mut pool);
+			black_box(&pose);
+		})
+	});
+	group.finish();
+}
+
+criterion_group!(benches, sampling, graph_evaluation);
+criterion_main!(benches);
@@ -1,10 +1,14 @@
-use crate::{Ecs, Entity, Mesh, Transform};
+use crate::{Ecs, Entity, EntitySceneGraph, Mesh, Transform};
 use legion::{
 	world::{ComponentError, EntityAccessError},
 	EntityStore,
 };
 use nalgebra_glm as glm;
 use serde::{Deserialize, Serialize};
+use std::{
+	collections::HashMap,
+	ops::{Add, Mul},
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -14,100 +18,344 @@ pub enum AnimationError {
 
 	#[error("Failed to get transform!")]
 	GetComponent(#[from] ComponentError),
+
+	#[error("Animation player has no clip named `{0}`!")]
+	UnknownClip(String),
 }
 
 type Result<T, E = AnimationError> = std::result::Result<T, E>;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Animation {
 	pub name: String,
 	pub time: f32,
 	pub channels: Vec<Channel>,
 	pub max_animation_time: f32,
+
+	/// The joint whose horizontal translation and yaw are extracted as root motion
+	/// instead of being applied, so the entity playing the clip can move by them
+	pub root_motion_joint: Option<Entity>,
+
+	/// Named moments in the clip, sorted by time
+	pub notifies: Vec<AnimationNotify>,
 }
 
 impl Animation {
+	/// Attaches a named event at a time in seconds
+	pub fn add_notify(&mut self, name: &str, time: f32) {
+		let index = self.notifies.partition_point(|notify| notify.time <= time);
+		self.notifies.insert(
+			index,
+			AnimationNotify {
+				name: name.to_string(),
+				time,
+			},
+		);
+	}
+
+	pub fn remove_notifies(&mut self, name: &str) {
+		self.notifies.retain(|notify| notify.name != name);
+	}
+
+	/// The notifies crossed playing from one time to another, in either direction.
+	/// The start time is included, and the end time only at either end of the clip,
+	/// so consecutive steps never report a notify twice.
+	pub fn notifies_between(&self, from: f32, to: f32) -> impl Iterator<Item = &AnimationNotify> {
+		let at_end = (to >= from && to >= self.max_animation_time) || (to < from && to <= 0.0);
+		self.notifies.iter().filter(move |notify| {
+			let time = notify.time;
+			if from == to {
+				false
+			} else if time == to {
+				at_end
+			} else if from < to {
+				from <= time && time < to
+			} else {
+				to < time && time <= from
+			}
+		})
+	}
+
+	/// Advances the animation's own clock, looping, and applies it.
+	/// Use an `AnimationPlayer` to control playback instead.
 	pub fn animate(&mut self, ecs: &mut Ecs, step: f32) -> Result<()> {
 		self.time += step;
-		// TODO: Allow for specifying a specific animation by name
 		if self.time > self.max_animation_time {
 			self.time = 0.0;
 		}
 		if self.time < 0.0 {
 			self.time = self.max_animation_time;
 		}
+		self.apply(ecs, self.time)
+	}
+
+	/// Poses the animation's targets at a time in seconds
+	pub fn apply(&self, ecs: &mut Ecs, time: f32) -> Result<()> {
+		self.sample(time).write(ecs)
+	}
 
-		for channel in self.channels.iter_mut() {
-			let mut input_iter = channel.inputs.iter().enumerate().peekable();
-			while let Some((previous_key, previous_time)) = input_iter.next() {
-				if let Some((next_key, next_time)) = input_iter.peek() {
-					let next_key = *next_key;
-					let next_time = **next_time;
-					let previous_time = *previous_time;
-					if self.time < previous_time || self.time > next_time {
+	/// Samples every channel at a time in seconds without touching the targets.
+	/// The root motion joint keeps its starting horizontal position and heading.
+	pub fn sample(&self, time: f32) -> Pose {
+		let mut pose = Pose::default();
+		self.sample_into(time, &mut pose);
+		pose
+	}
+
+	/// Samples into a pose reused between frames, which avoids allocating once it has
+	/// held this animation's joints
+	pub fn sample_into(&self, time: f32, pose: &mut Pose) {
+		pose.reset();
+		for channel in self.channels.iter() {
+			sample_channel(
+				channel,
+				time,
+				pose.joints.entry(channel.target).or_default(),
+			);
+		}
+		if let Some(root_joint) = self.root_motion_joint {
+			let (offset, yaw) = self.root_offset(root_joint, time);
+			if let Some(joint) = pose.joints.get_mut(&root_joint) {
+				if let Some(translation) = joint.translation.as_mut() {
+					*translation -= offset;
+				}
+				if let Some(rotation) = joint.rotation.as_mut() {
+					*rotation = glm::quat_angle_axis(-yaw, &glm::Vec3::y()) * *rotation;
+				}
+			}
+		}
+	}
+
+	/// The movement of the root motion joint between two times in seconds,
+	/// relative to its heading at the first time
+	pub fn root_motion(&self, from: f32, to: f32) -> RootMotion {
+		let root_joint = match self.root_motion_joint {
+			Some(root_joint) => root_joint,
+			None => return RootMotion::default(),
+		};
+		let (from_offset, from_yaw) = self.root_offset(root_joint, from);
+		let (to_offset, to_yaw) = self.root_offset(root_joint, to);
+		RootMotion {
+			translation: glm::rotate_y_vec3(&(to_offset - from_offset), -from_yaw),
+			yaw: wrap_angle(to_yaw - from_yaw),
+		}
+	}
+
+	/// The root joint's horizontal translation and yaw at a time, relative to the clip's start
+	fn root_offset(&self, root_joint: Entity, time: f32) -> (glm::Vec3, f32) {
+		let mut start = JointPose::default();
+		let mut current = JointPose::default();
+		for channel in self.channels.iter() {
+			if channel.target == root_joint {
+				sample_channel(channel, 0.0, &mut start);
+				sample_channel(channel, time, &mut current);
+			}
+		}
+		let offset = match (start.translation, current.translation) {
+			(Some(start), Some(current)) => {
+				glm::vec3(current.x - start.x, 0.0, current.z - start.z)
+			}
+			_ => glm::Vec3::zeros(),
+		};
+		let yaw = match (start.rotation, current.rotation) {
+			(Some(start), Some(current)) => wrap_angle(yaw(&current) - yaw(&start)),
+			_ => 0.0,
+		};
+		(offset, yaw)
+	}
+}
+
+fn sample_channel(channel: &Channel, time: f32, joint: &mut JointPose) {
+	let keyframe = match Keyframe::find(&channel.inputs, time) {
+		Some(keyframe) => keyframe,
+		None => return,
+	};
+	let interpolation = channel.interpolation;
+	let number_of_elements = channel.inputs.len() * interpolation.elements_per_keyframe();
+	if let Some(number_of_outputs) = channel.transformations.number_of_vectors() {
+		if number_of_outputs != number_of_elements {
+			log::warn!("Animation channel's outputs do not match its keyframes: (outputs) {} != (elements) {}", number_of_outputs, number_of_elements);
+			return;
+		}
+	}
+	match &channel.transformations {
+		TransformationSet::Translations(translations) => {
+			joint.translation = Some(sample(interpolation, &keyframe, glm::mix, |element| {
+				translations[element]
+			}));
+		}
+		TransformationSet::Rotations(rotations) => {
+			let rotation = sample(interpolation, &keyframe, slerp, |element| {
+				rotations[element]
+			});
+			joint.rotation = Some(glm::quat_normalize(&glm::make_quat(rotation.as_slice())));
+		}
+		TransformationSet::Scales(scales) => {
+			joint.scale = Some(sample(interpolation, &keyframe, glm::mix, |element| {
+				scales[element]
+			}));
+		}
+		TransformationSet::MorphTargetWeights(animation_weights) => {
+			if animation_weights.len() % number_of_elements != 0 {
+				log::warn!("Animation channel's weights are not a multiple of its keyframes: (channel) {} % (elements) {} != 0", animation_weights.len(), number_of_elements);
+				return;
+			}
+			let number_of_weights = animation_weights.len() / number_of_elements;
+			joint.weights.clear();
+			joint.weights.extend((0..number_of_weights).map(|index| {
+				sample(
+					interpolation,
+					&keyframe,
+					|start, end, amount| glm::lerp_scalar(*start, *end, amount),
+					|element| animation_weights[element * number_of_weights + index],
+				)
+			}));
+		}
+	}
+}
+
+/// Sampled transforms and morph target weights for the entities an animation targets
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Pose {
+	pub joints: HashMap<Entity, JointPose>,
+}
+
+/// Poses lent out while blending and given back afterwards,
+/// so sampling reuses their storage instead of allocating every tick
+#[derive(Debug, Default, Clone)]
+pub struct PosePool {
+	poses: Vec<Pose>,
+}
+
+impl PosePool {
+	/// An empty pose, reusing the storage of one given back when there is one
+	pub fn take(&mut self) -> Pose {
+		let mut pose = self.poses.pop().unwrap_or_default();
+		// Cleared rather than reset, so joints of other characters don't build up
+		pose.joints.clear();
+		pose
+	}
+
+	pub fn give_back(&mut self, pose: Pose) {
+		self.poses.push(pose);
+	}
+}
+
+/// The parts of an entity's transform that are animated
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JointPose {
+	pub translation: Option<glm::Vec3>,
+	pub rotation: Option<glm::Quat>,
+	pub scale: Option<glm::Vec3>,
+	/// Empty when the morph target weights are not animated
+	pub weights: Vec<f32>,
+}
+
+impl Pose {
+	/// The current transforms and morph target weights of entities
+	pub fn capture(ecs: &Ecs, entities: impl IntoIterator<Item = Entity>) -> Self {
+		let mut pose = Self::default();
+		for entity in entities {
+			let entry = match ecs.entry_ref(entity) {
+				Ok(entry) => entry,
+				Err(_) => continue,
+			};
+			let transform = match entry.get_component::<Transform>() {
+				Ok(transform) => transform,
+				Err(_) => continue,
+			};
+			let weights = entry
+				.get_component::<Mesh>()
+				.map_or(Vec::new(), |mesh| mesh.weights.clone());
+			pose.joints.insert(
+				entity,
+				JointPose {
+					translation: Some(transform.translation),
+					rotation: Some(transform.rotation),
+					scale: Some(transform.scale),
+					weights,
+				},
+			);
+		}
+		pose
+	}
+
+	/// Clears every part of every joint, keeping the joints and their weights' storage
+	pub fn reset(&mut self) {
+		for joint in self.joints.values_mut() {
+			joint.translation = None;
+			joint.rotation = None;
+			joint.scale = None;
+			joint.weights.clear();
+		}
+	}
+
+	/// Blends towards another pose by a weight from 0 to 1, limited to the masked entities.
+	/// Parts only one of the poses animates are taken from that pose.
+	pub fn blend(&mut self, other: &Pose, weight: f32, mask: Option<&BoneMask>) {
+		for (entity, other_joint) in other.joints.iter() {
+			if let Some(mask) = mask {
+				if !mask.contains(entity) {
+					continue;
+				}
+			}
+			let joint = self.joints.entry(*entity).or_default();
+			joint.translation =
+				blend_part(joint.translation, other_joint.translation, |start, end| {
+					glm::mix(&start, &end, weight)
+				});
+			joint.rotation = blend_part(joint.rotation, other_joint.rotation, |start, end| {
+				glm::quat_normalize(&glm::quat_lerp(&start, &nearest_quat(&start, &end), weight))
+			});
+			joint.scale = blend_part(joint.scale, other_joint.scale, |start, end| {
+				glm::mix(&start, &end, weight)
+			});
+			if joint.weights.len() == other_joint.weights.len() {
+				joint
+					.weights
+					.iter_mut()
+					.zip(other_joint.weights.iter())
+					.for_each(|(start, end)| *start = glm::lerp_scalar(*start, *end, weight));
+			} else if joint.weights.is_empty() {
+				joint.weights = other_joint.weights.clone();
+			}
+		}
+	}
+
+	/// Writes the pose into its entities' transforms and meshes
+	pub fn write(&self, ecs: &mut Ecs) -> Result<()> {
+		for (entity, joint) in self.joints.iter() {
+			let transformed =
+				joint.translation.is_some() || joint.rotation.is_some() || joint.scale.is_some();
+			if !transformed && joint.weights.is_empty() {
+				continue;
+			}
+			let mut entry = ecs.entry_mut(*entity)?;
+			if transformed {
+				let transform = entry.get_component_mut::<Transform>()?;
+				if let Some(translation) = joint.translation {
+					transform.translation = translation;
+				}
+				if let Some(rotation) = joint.rotation {
+					transform.rotation = rotation;
+				}
+				if let Some(scale) = joint.scale {
+					transform.scale = scale;
+				}
+			}
+			if joint.weights.is_empty() {
+				continue;
+			}
+			match entry.get_component_mut::<Mesh>() {
+				Ok(mesh) => {
+					if mesh.weights.len() != joint.weights.len() {
+						log::warn!("Animation channel's weights do not match the mesh's weights: (channel) {} != (mesh) {}", joint.weights.len(), mesh.weights.len());
 						continue;
 					}
-					let interpolation = (self.time - previous_time) / (next_time - previous_time);
-					// TODO: Interpolate with other methods
-					// Only Linear interpolation is used for now
-					match &channel.transformations {
-						TransformationSet::Translations(translations) => {
-							let start = translations[previous_key];
-							let end = translations[next_key];
-							let translation_vec = glm::mix(&start, &end, interpolation);
-							ecs.entry_mut(channel.target)?
-								.get_component_mut::<Transform>()?
-								.translation = translation_vec;
-						}
-						TransformationSet::Rotations(rotations) => {
-							let start = rotations[previous_key];
-							let end = rotations[next_key];
-							let start_quat = glm::make_quat(start.as_slice());
-							let end_quat = glm::make_quat(end.as_slice());
-							let rotation_quat =
-								glm::quat_slerp(&start_quat, &end_quat, interpolation);
-
-							ecs.entry_mut(channel.target)?
-								.get_component_mut::<Transform>()?
-								.rotation = rotation_quat;
-						}
-						TransformationSet::Scales(scales) => {
-							let start = scales[previous_key];
-							let end = scales[next_key];
-							let scale_vec = glm::mix(&start, &end, interpolation);
-
-							ecs.entry_mut(channel.target)?
-								.get_component_mut::<Transform>()?
-								.scale = scale_vec;
-						}
-						TransformationSet::MorphTargetWeights(animation_weights) => {
-							match ecs.entry_mut(channel.target)?.get_component_mut::<Mesh>() {
-								Ok(mesh) => {
-									let number_of_mesh_weights = mesh.weights.len();
-									if animation_weights.len() % number_of_mesh_weights != 0 {
-										log::warn!("Animation channel's weights are not a multiple of the mesh's weights: (channel) {} % (mesh) {} != 0", number_of_mesh_weights, animation_weights.len());
-										continue;
-									}
-									let weights = animation_weights
-										.as_slice()
-										.chunks(number_of_mesh_weights)
-										.collect::<Vec<_>>();
-									let start = weights[previous_key];
-									let end = weights[next_key];
-									for index in 0..number_of_mesh_weights {
-										mesh.weights[index] = glm::lerp_scalar(
-											start[index],
-											end[index],
-											interpolation,
-										);
-									}
-								}
-								Err(_) => {
-									log::warn!("Animation channel's target node animates morph target weights, but node has no mesh!");
-								}
-							}
-						}
-					}
+					mesh.weights.copy_from_slice(&joint.weights);
+				}
+				Err(_) => {
+					log::warn!("Animation channel's target node animates morph target weights, but node has no mesh!");
 				}
 			}
 		}
@@ -115,12 +363,555 @@ impl Animation {
 	}
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+fn blend_part<T: Copy>(start: Option<T>, end: Option<T>, blend: impl Fn(T, T) -> T) -> Option<T> {
+	match (start, end) {
+		(Some(start), Some(end)) => Some(blend(start, end)),
+		(start, end) => start.or(end),
+	}
+}
+
+/// Flips a rotation onto the same hemisphere as another so blending takes the short way around
+fn nearest_quat(reference: &glm::Quat, rotation: &glm::Quat) -> glm::Quat {
+	if glm::quat_dot(reference, rotation) < 0.0 {
+		-*rotation
+	} else {
+		*rotation
+	}
+}
+
+/// Movement extracted from a clip's root joint, in the frame of the entity playing it
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct RootMotion {
+	pub translation: glm::Vec3,
+	/// Radians about the up axis
+	pub yaw: f32,
+}
+
+impl RootMotion {
+	/// Follows this movement with another that starts where it ends
+	pub fn then(&self, next: &RootMotion) -> Self {
+		Self {
+			translation: self.translation + glm::rotate_y_vec3(&next.translation, self.yaw),
+			yaw: wrap_angle(self.yaw + next.yaw),
+		}
+	}
+
+	/// This movement followed by itself the given number of times in total
+	pub fn repeated(&self, times: u32) -> Self {
+		// Composed by squaring, so long runs of cycles stay cheap
+		let mut result = RootMotion::default();
+		let mut power = *self;
+		let mut times = times;
+		while times > 0 {
+			if times & 1 == 1 {
+				result = result.then(&power);
+			}
+			power = power.then(&power);
+			times >>= 1;
+		}
+		result
+	}
+
+	pub fn scaled(&self, weight: f32) -> Self {
+		Self {
+			translation: self.translation * weight,
+			yaw: self.yaw * weight,
+		}
+	}
+
+	pub fn is_zero(&self) -> bool {
+		self.translation == glm::Vec3::zeros() && self.yaw == 0.0
+	}
+
+	/// The change of rotation about the up axis
+	pub fn rotation(&self) -> glm::Quat {
+		glm::quat_angle_axis(self.yaw, &glm::Vec3::y())
+	}
+}
+
+/// The heading of a rotation about the up axis
+fn yaw(rotation: &glm::Quat) -> f32 {
+	let forward = glm::quat_rotate_vec3(rotation, &glm::Vec3::z());
+	forward.x.atan2(forward.z)
+}
+
+/// Wraps an angle into the range from -π to π
+fn wrap_angle(angle: f32) -> f32 {
+	use std::f32::consts::{PI, TAU};
+	(angle + PI).rem_euclid(TAU) - PI
+}
+
+/// The entities an animation layer affects, such as the joints of a character's upper body
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BoneMask {
+	pub entities: Vec<Entity>,
+}
+
+impl BoneMask {
+	pub fn new(entities: Vec<Entity>) -> Self {
+		Self { entities }
+	}
+
+	/// Masks a joint and every joint below it, or only the joint when it isn't in the graph
+	pub fn from_subtree(graph: &EntitySceneGraph, root: Entity) -> Self {
+		let entities = match graph.find_node(root) {
+			Some(node_index) => graph
+				.subtree(node_index)
+				.into_iter()
+				.map(|node_index| graph[node_index])
+				.collect(),
+			None => vec![root],
+		};
+		Self { entities }
+	}
+
+	pub fn contains(&self, entity: &Entity) -> bool {
+		self.entities.contains(entity)
+	}
+}
+
+/// The keyframes surrounding a time, clamped to the first and last keyframes
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Keyframe {
+	previous: usize,
+	next: usize,
+	/// How far the time is from the previous keyframe to the next, from 0 to 1
+	amount: f32,
+	/// Seconds between the keyframes
+	delta: f32,
+}
+
+impl Keyframe {
+	fn find(inputs: &[f32], time: f32) -> Option<Self> {
+		let last = inputs.len().checked_sub(1)?;
+		let clamped = |key| Self {
+			previous: key,
+			next: key,
+			amount: 0.0,
+			delta: 0.0,
+		};
+		if time <= inputs[0] {
+			return Some(clamped(0));
+		}
+		if time >= inputs[last] {
+			return Some(clamped(last));
+		}
+		let next = inputs.partition_point(|input| *input <= time);
+		let previous = next - 1;
+		let delta = inputs[next] - inputs[previous];
+		Some(Self {
+			previous,
+			next,
+			amount: (time - inputs[previous]) / delta,
+			delta,
+		})
+	}
+}
+
+/// Samples a keyframed value, reading output elements by index.
+/// Cubic spline outputs hold an in-tangent, a value and an out-tangent per keyframe.
+fn sample<T>(
+	interpolation: Interpolation,
+	keyframe: &Keyframe,
+	linear: impl Fn(&T, &T, f32) -> T,
+	element: impl Fn(usize) -> T,
+) -> T
+where
+	T: Copy + Add<Output = T> + Mul<f32, Output = T>,
+{
+	match interpolation {
+		Interpolation::Step => element(keyframe.previous),
+		Interpolation::Linear => linear(
+			&element(keyframe.previous),
+			&element(keyframe.next),
+			keyframe.amount,
+		),
+		Interpolation::CubicSpline => {
+			let t = keyframe.amount;
+			let t2 = t * t;
+			let t3 = t2 * t;
+			let start = element(keyframe.previous * 3 + 1);
+			let start_tangent = element(keyframe.previous * 3 + 2) * keyframe.delta;
+			let end = element(keyframe.next * 3 + 1);
+			let end_tangent = element(keyframe.next * 3) * keyframe.delta;
+			start * (2.0 * t3 - 3.0 * t2 + 1.0)
+				+ start_tangent * (t3 - 2.0 * t2 + t)
+				+ end * (-2.0 * t3 + 3.0 * t2)
+				+ end_tangent * (t3 - t2)
+		}
+	}
+}
+
+/// Spherically interpolates rotations stored as `xyzw` vectors
+fn slerp(start: &glm::Vec4, end: &glm::Vec4, amount: f32) -> glm::Vec4 {
+	let start = glm::make_quat(start.as_slice());
+	let end = glm::make_quat(end.as_slice());
+	glm::quat_slerp(&start, &end, amount).coords
+}
+
+/// Plays the world's animation clips, referenced by name, on an entity's hierarchy.
+/// Root motion extracted from the clips moves the entity itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimationPlayer {
+	/// The names of the world's animations this player can play
+	pub clips: Vec<String>,
+
+	/// The clip being played or paused
+	pub clip: Option<String>,
+
+	/// Seconds into the current clip
+	pub time: f32,
+
+	/// Multiplies the time step, playing backwards when negative
+	pub speed: f32,
+
+	pub mode: PlaybackMode,
+	pub state: PlaybackState,
+
+	/// Whether a ping-pong clip is currently playing back towards its start
+	pub reversed: bool,
+
+	/// Where the current clip was last found among the world's animations
+	#[serde(skip)]
+	pub clip_index: Option<usize>,
+}
+
+impl Default for AnimationPlayer {
+	fn default() -> Self {
+		Self {
+			clips: Vec::new(),
+			clip: None,
+			time: 0.0,
+			speed: 1.0,
+			mode: PlaybackMode::default(),
+			state: PlaybackState::default(),
+			reversed: false,
+			clip_index: None,
+		}
+	}
+}
+
+impl AnimationPlayer {
+	pub fn new(clips: Vec<String>) -> Self {
+		Self {
+			clips,
+			..Default::default()
+		}
+	}
+
+	/// Plays a clip from its start, or resumes it if it is already the current clip.
+	/// Prefab instance clips can also be played by the name they were imported with.
+	pub fn play(&mut self, clip: &str) -> Result<()> {
+		let clip = match self.clip_named(clip) {
+			Some(clip) => clip.to_string(),
+			None => return Err(AnimationError::UnknownClip(clip.to_string())),
+		};
+		if self.clip.as_ref() != Some(&clip) || self.state == PlaybackState::Stopped {
+			self.clip = Some(clip);
+			self.time = 0.0;
+			self.reversed = false;
+		}
+		self.state = PlaybackState::Playing;
+		Ok(())
+	}
+
+	fn clip_named(&self, clip: &str) -> Option<&String> {
+		self.clips.iter().find(|name| *name == clip).or_else(|| {
+			self.clips.iter().find(|name| {
+				name.split_once('/')
+					.map_or(false, |(_, imported_name)| imported_name == clip)
+			})
+		})
+	}
+
+	pub fn pause(&mut self) {
+		if self.state == PlaybackState::Playing {
+			self.state = PlaybackState::Paused;
+		}
+	}
+
+	pub fn resume(&mut self) {
+		if self.state == PlaybackState::Paused {
+			self.state = PlaybackState::Playing;
+		}
+	}
+
+	/// Stops playback and rewinds to the start of the clip
+	pub fn stop(&mut self) {
+		self.state = PlaybackState::Stopped;
+		self.time = 0.0;
+		self.reversed = false;
+	}
+
+	pub fn seek(&mut self, time: f32) {
+		self.time = time.max(0.0);
+	}
+
+	pub fn is_playing(&self) -> bool {
+		self.state == PlaybackState::Playing
+	}
+
+	/// The stretches of clip time the next step plays through,
+	/// split where the clip loops or bounces, for extracting root motion.
+	/// Whole cycles of the clip the step covers are counted rather than listed.
+	pub fn playback_segments(&self, duration: f32, step: f32) -> PlaybackSegments {
+		if self.state != PlaybackState::Playing || duration <= 0.0 {
+			return PlaybackSegments::default();
+		}
+		let step = step * self.speed;
+		match self.mode {
+			PlaybackMode::Loop => {
+				let (lead, cycles, tail) = period_segments(self.time, step, duration);
+				let cycle = if step >= 0.0 {
+					(0.0, duration)
+				} else {
+					(duration, 0.0)
+				};
+				PlaybackSegments {
+					lead: vec![lead],
+					cycle: vec![cycle],
+					cycles,
+					tail: tail.into_iter().collect(),
+				}
+			}
+			PlaybackMode::Once => PlaybackSegments {
+				lead: vec![(self.time, (self.time + step).clamp(0.0, duration))],
+				..Default::default()
+			},
+			PlaybackMode::PingPong => {
+				// Unfold the clip into one forwards and backwards cycle
+				let period = duration * 2.0;
+				let phase = if self.reversed {
+					period - self.time
+				} else {
+					self.time
+				};
+				let (lead, cycles, tail) = period_segments(phase, step, period);
+				let mut segments = PlaybackSegments {
+					cycle: vec![(0.0, duration), (duration, 0.0)],
+					cycles,
+					..Default::default()
+				};
+				fold_cycle(lead, duration, &mut segments.lead);
+				if let Some(tail) = tail {
+					fold_cycle(tail, duration, &mut segments.tail);
+				}
+				segments
+			}
+		}
+	}
+
+	/// Advances the clock through a clip of the given duration,
+	/// returning true when a clip playing once reaches its end
+	pub fn advance(&mut self, duration: f32, step: f32) -> bool {
+		if self.state != PlaybackState::Playing {
+			return false;
+		}
+		if duration <= 0.0 {
+			self.time = 0.0;
+			return false;
+		}
+
+		let step = step * self.speed;
+		match self.mode {
+			PlaybackMode::Loop => {
+				self.time = (self.time + step).rem_euclid(duration);
+				false
+			}
+			PlaybackMode::Once => {
+				self.time += step;
+				if (0.0..=duration).contains(&self.time) {
+					return false;
+				}
+				self.time = self.time.clamp(0.0, duration);
+				self.state = PlaybackState::Stopped;
+				true
+			}
+			PlaybackMode::PingPong => {
+				// Unfold the clip into one forwards and backwards cycle
+				let period = duration * 2.0;
+				let phase = if self.reversed {
+					period - self.time
+				} else {
+					self.time
+				};
+				let phase = (phase + step).rem_euclid(period);
+				self.reversed = phase > duration;
+				self.time = if self.reversed { period - phase } else { phase };
+				false
+			}
+		}
+	}
+}
+
+/// The stretches of clip time one step plays through, in order:
+/// the lead, the whole cycle repeated `cycles` times, then the tail
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PlaybackSegments {
+	pub lead: Vec<(f32, f32)>,
+	/// One whole pass of the clip, or a forwards and backwards pass when ping-ponging
+	pub cycle: Vec<(f32, f32)>,
+	pub cycles: u32,
+	pub tail: Vec<(f32, f32)>,
+}
+
+impl PlaybackSegments {
+	/// The root motion of the whole step
+	pub fn root_motion(&self, animation: &Animation) -> RootMotion {
+		let motion = |segments: &[(f32, f32)]| {
+			segments
+				.iter()
+				.fold(RootMotion::default(), |motion, (from, to)| {
+					motion.then(&animation.root_motion(*from, *to))
+				})
+		};
+		motion(&self.lead)
+			.then(&motion(&self.cycle).repeated(self.cycles))
+			.then(&motion(&self.tail))
+	}
+
+	/// The notifies crossed over the whole step, once for every cycle that crosses them
+	pub fn notifies<'a>(
+		&'a self,
+		animation: &'a Animation,
+	) -> impl Iterator<Item = &'a AnimationNotify> + 'a {
+		let cycles = if animation.notifies.is_empty() {
+			0
+		} else {
+			self.cycles as usize
+		};
+		let cycle = std::iter::repeat(self.cycle.iter()).take(cycles).flatten();
+		self.lead
+			.iter()
+			.chain(cycle)
+			.chain(self.tail.iter())
+			.flat_map(move |(from, to)| animation.notifies_between(*from, *to))
+	}
+}
+
+/// Finds a clip by name, checking where it was last found before searching every clip
+pub fn find_clip_index(
+	animations: &[Animation],
+	name: &str,
+	last_index: Option<usize>,
+) -> Option<usize> {
+	match last_index {
+		Some(index)
+			if animations
+				.get(index)
+				.map_or(false, |clip| clip.name == name) =>
+		{
+			Some(index)
+		}
+		_ => animations
+			.iter()
+			.position(|animation| animation.name == name),
+	}
+}
+
+/// Splits a step from a time into the stretch up to the first period boundary it crosses,
+/// the number of whole periods after that, and the stretch left in the last period.
+/// Stretches are given relative to the start of their period.
+fn period_segments(time: f32, step: f32, period: f32) -> ((f32, f32), u32, Option<(f32, f32)>) {
+	let end = time + step;
+	if step >= 0.0 {
+		let start = (time / period).floor() * period;
+		if end <= start + period {
+			return ((time - start, end - start), 0, None);
+		}
+		let remaining = end - start - period;
+		// The float to int cast saturates, so huge steps can't overflow the count
+		let cycles = (remaining / period).floor() as u32;
+		let tail = remaining.rem_euclid(period);
+		let tail = (tail > 0.0).then_some((0.0, tail));
+		((time - start, period), cycles, tail)
+	} else {
+		let start = ((time / period).ceil() - 1.0) * period;
+		if end >= start {
+			return ((time - start, end - start), 0, None);
+		}
+		let remaining = start - end;
+		let cycles = (remaining / period).floor() as u32;
+		let tail = remaining.rem_euclid(period);
+		let tail = (tail > 0.0).then_some((period, period - tail));
+		((time - start, 0.0), cycles, tail)
+	}
+}
+
+/// Folds a stretch of an unfolded ping-pong cycle back onto the clip,
+/// splitting it where it bounces off the end of the clip
+fn fold_cycle((from, to): (f32, f32), duration: f32, segments: &mut Vec<(f32, f32)>) {
+	let fold = |time: f32| {
+		if time > duration {
+			duration * 2.0 - time
+		} else {
+			time
+		}
+	};
+	if (from - duration) * (to - duration) < 0.0 {
+		segments.push((fold(from), duration));
+		segments.push((duration, fold(to)));
+	} else {
+		segments.push((fold(from), fold(to)));
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlaybackMode {
+	Loop,
+	/// Plays to the end of the clip and stops there
+	Once,
+	/// Plays forwards and backwards in turn
+	PingPong,
+}
+
+impl Default for PlaybackMode {
+	fn default() -> Self {
+		Self::Loop
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlaybackState {
+	Playing,
+	Paused,
+	Stopped,
+}
+
+impl Default for PlaybackState {
+	fn default() -> Self {
+		Self::Stopped
+	}
+}
+
+/// A named moment in a clip, such as a footstep landing or a hit window opening
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimationNotify {
+	pub name: String,
+	/// Seconds into the clip
+	pub time: f32,
+}
+
+/// Queued by the world while ticking its animation players and graphs
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnimationEvent {
+	/// A clip playing once reached its end
+	Finished { entity: Entity, clip: String },
+
+	/// Playback crossed a clip's notify
+	Notify {
+		entity: Entity,
+		clip: String,
+		name: String,
+	},
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Channel {
 	pub target: Entity,
 	pub inputs: Vec<f32>,
 	pub transformations: TransformationSet,
-	pub _interpolation: Interpolation,
+	pub interpolation: Interpolation,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -130,10 +921,395 @@ pub enum Interpolation {
 	CubicSpline,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Interpolation {
+	/// Cubic spline outputs store tangents on either side of each keyframe's value
+	pub fn elements_per_keyframe(&self) -> usize {
+		match self {
+			Self::Linear | Self::Step => 1,
+			Self::CubicSpline => 3,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TransformationSet {
 	Translations(Vec<glm::Vec3>),
 	Rotations(Vec<glm::Vec4>),
 	Scales(Vec<glm::Vec3>),
 	MorphTargetWeights(Vec<f32>),
 }
+
+impl TransformationSet {
+	/// The number of output vectors, or none for morph target weights,
+	/// which have one output per mesh weight
+	pub fn number_of_vectors(&self) -> Option<usize> {
+		match self {
+			Self::Translations(translations) => Some(translations.len()),
+			Self::Rotations(rotations) => Some(rotations.len()),
+			Self::Scales(scales) => Some(scales.len()),
+			Self::MorphTargetWeights(_) => None,
+		}
+	}
+}
+
+/// A one second clip holding joints at a height, shared by tests that need any clip
+#[cfg(test)]
+pub(crate) fn test_clip(name: &str, targets: &[Entity], height: f32) -> Animation {
+	Animation {
+		name: name.to_string(),
+		time: 0.0,
+		max_animation_time: 1.0,
+		root_motion_joint: None,
+		notifies: Vec::new(),
+		channels: targets
+			.iter()
+			.map(|target| Channel {
+				target: *target,
+				inputs: vec![0.0, 1.0],
+				transformations: TransformationSet::Translations(vec![
+					glm::vec3(0.0, height, 0.0),
+					glm::vec3(0.0, height, 0.0),
+				]),
+				interpolation: Interpolation::Linear,
+			})
+			.collect(),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn subtree_masks_cover_every_joint_below_the_root() {
+		let mut ecs = Ecs::default();
+		let [hips, spine, arm, leg] = [(); 4].map(|_| ecs.push(()));
+		let mut graph = EntitySceneGraph::new();
+		let hips_index = graph.add_root_node(hips);
+		let spine_index = graph.add_child(hips_index, spine);
+		graph.add_child(spine_index, arm);
+		graph.add_child(hips_index, leg);
+
+		let mask = BoneMask::from_subtree(&graph, spine);
+		assert!(mask.contains(&spine) && mask.contains(&arm));
+		assert!(!mask.contains(&hips) && !mask.contains(&leg));
+		assert_eq!(
+			BoneMask::from_subtree(&graph, ecs.push(())).entities.len(),
+			1
+		);
+	}
+
+	fn playing(mode: PlaybackMode) -> AnimationPlayer {
+		let mut player = AnimationPlayer::new(vec!["Walk".to_string()]);
+		player.mode = mode;
+		player.play("Walk").unwrap();
+		player
+	}
+
+	#[test]
+	fn looping_wraps_around() {
+		let mut player = playing(PlaybackMode::Loop);
+		assert!(!player.advance(2.0, 2.5));
+		assert!((player.time - 0.5).abs() < 1e-5);
+		assert!(player.is_playing());
+	}
+
+	#[test]
+	fn once_stops_on_the_last_pose() {
+		let mut player = playing(PlaybackMode::Once);
+		assert!(!player.advance(2.0, 1.5));
+		assert!(player.advance(2.0, 1.0));
+		assert_eq!(player.time, 2.0);
+		assert_eq!(player.state, PlaybackState::Stopped);
+		assert!(!player.advance(2.0, 1.0));
+	}
+
+	#[test]
+	fn ping_pong_reverses_at_the_ends() {
+		let mut player = playing(PlaybackMode::PingPong);
+		player.advance(2.0, 2.5);
+		assert!((player.time - 1.5).abs() < 1e-5);
+		assert!(player.reversed);
+		player.advance(2.0, 2.0);
+		assert!((player.time - 0.5).abs() < 1e-5);
+		assert!(!player.reversed);
+	}
+
+	fn sample_scalar(
+		interpolation: Interpolation,
+		inputs: &[f32],
+		outputs: &[f32],
+		time: f32,
+	) -> f32 {
+		let keyframe = Keyframe::find(inputs, time).unwrap();
+		sample(
+			interpolation,
+			&keyframe,
+			|start, end, amount| glm::lerp_scalar(*start, *end, amount),
+			|element| outputs[element],
+		)
+	}
+
+	#[test]
+	fn step_holds_the_previous_keyframe() {
+		let inputs = [0.0, 1.0, 2.0];
+		let outputs = [0.0, 10.0, 20.0];
+		assert_eq!(
+			sample_scalar(Interpolation::Step, &inputs, &outputs, 0.99),
+			0.0
+		);
+		assert_eq!(
+			sample_scalar(Interpolation::Step, &inputs, &outputs, 1.0),
+			10.0
+		);
+		assert_eq!(
+			sample_scalar(Interpolation::Step, &inputs, &outputs, 1.5),
+			10.0
+		);
+	}
+
+	#[test]
+	fn linear_blends_and_clamps_to_the_ends() {
+		let inputs = [1.0, 3.0];
+		let outputs = [0.0, 10.0];
+		assert_eq!(
+			sample_scalar(Interpolation::Linear, &inputs, &outputs, 2.5),
+			7.5
+		);
+		assert_eq!(
+			sample_scalar(Interpolation::Linear, &inputs, &outputs, 0.0),
+			0.0
+		);
+		assert_eq!(
+			sample_scalar(Interpolation::Linear, &inputs, &outputs, 4.0),
+			10.0
+		);
+	}
+
+	#[test]
+	fn cubic_spline_uses_tangents_scaled_by_keyframe_delta() {
+		let inputs = [0.0, 2.0];
+		// In-tangent, value and out-tangent for each keyframe
+		let flat = [5.0, 0.0, 0.0, 0.0, 1.0, 5.0];
+		let sloped = [5.0, 0.0, 1.0, 0.0, 1.0, 5.0];
+		let cubic = Interpolation::CubicSpline;
+		assert_eq!(sample_scalar(cubic, &inputs, &flat, 0.0), 0.0);
+		assert_eq!(sample_scalar(cubic, &inputs, &flat, 1.0), 0.5);
+		assert_eq!(sample_scalar(cubic, &inputs, &flat, 2.0), 1.0);
+		assert_eq!(sample_scalar(cubic, &inputs, &sloped, 1.0), 0.75);
+	}
+
+	#[test]
+	fn rotations_slerp_between_keyframes() {
+		let mut ecs = Ecs::default();
+		let target = ecs.push((Transform::default(),));
+		let quarter_turn = glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &glm::Vec3::y());
+		let animation = Animation {
+			name: "Turn".to_string(),
+			time: 0.0,
+			max_animation_time: 1.0,
+			root_motion_joint: None,
+			notifies: Vec::new(),
+			channels: vec![Channel {
+				target,
+				inputs: vec![0.0, 1.0],
+				transformations: TransformationSet::Rotations(vec![
+					glm::Quat::identity().coords,
+					quarter_turn.coords,
+				]),
+				interpolation: Interpolation::Linear,
+			}],
+		};
+		animation.apply(&mut ecs, 0.5).unwrap();
+
+		let rotation = ecs
+			.entry_ref(target)
+			.unwrap()
+			.get_component::<Transform>()
+			.unwrap()
+			.rotation;
+		let eighth_turn = glm::quat_angle_axis(std::f32::consts::FRAC_PI_4, &glm::Vec3::y());
+		assert!(glm::distance(&rotation.coords, &eighth_turn.coords) < 1e-5);
+	}
+
+	/// A one second clip walking the root two units forward while turning a quarter turn
+	fn walk_and_turn(root: Entity) -> Animation {
+		let quarter_turn = glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &glm::Vec3::y());
+		Animation {
+			name: "Walk".to_string(),
+			time: 0.0,
+			max_animation_time: 1.0,
+			root_motion_joint: Some(root),
+			notifies: Vec::new(),
+			channels: vec![
+				Channel {
+					target: root,
+					inputs: vec![0.0, 1.0],
+					transformations: TransformationSet::Translations(vec![
+						glm::vec3(0.0, 1.0, 0.0),
+						glm::vec3(0.0, 1.0, 2.0),
+					]),
+					interpolation: Interpolation::Linear,
+				},
+				Channel {
+					target: root,
+					inputs: vec![0.0, 1.0],
+					transformations: TransformationSet::Rotations(vec![
+						glm::Quat::identity().coords,
+						quarter_turn.coords,
+					]),
+					interpolation: Interpolation::Linear,
+				},
+			],
+		}
+	}
+
+	#[test]
+	fn root_motion_is_extracted_from_the_root_joint() {
+		let mut ecs = Ecs::default();
+		let root = ecs.push(());
+		let animation = walk_and_turn(root);
+
+		let pose = animation.sample(0.5);
+		let joint = &pose.joints[&root];
+		assert!(glm::distance(&joint.translation.unwrap(), &glm::vec3(0.0, 1.0, 0.0)) < 1e-5);
+		assert!(glm::quat_dot(&joint.rotation.unwrap(), &glm::Quat::identity()).abs() > 0.9999);
+
+		let motion = animation.root_motion(0.0, 1.0);
+		assert!((motion.yaw - std::f32::consts::FRAC_PI_2).abs() < 1e-5);
+		assert!(glm::distance(&motion.translation, &glm::vec3(0.0, 0.0, 2.0)) < 1e-5);
+	}
+
+	#[test]
+	fn root_motion_continues_across_loops() {
+		let mut ecs = Ecs::default();
+		let root = ecs.push(());
+		let mut animation = walk_and_turn(root);
+		if let TransformationSet::Rotations(rotations) = &mut animation.channels[1].transformations
+		{
+			rotations[1] = glm::Quat::identity().coords;
+		}
+
+		let mut player = playing(PlaybackMode::Loop);
+		player.seek(0.9);
+		let segments = player.playback_segments(1.0, 0.2);
+		assert_eq!(segments.lead.len() + segments.tail.len(), 2);
+		let motion = segments.root_motion(&animation);
+		assert!(glm::distance(&motion.translation, &glm::vec3(0.0, 0.0, 0.4)) < 1e-4);
+	}
+
+	#[test]
+	fn steps_longer_than_the_clip_play_every_period() {
+		let close = |segments: &[(f32, f32)], expected: &[(f32, f32)]| {
+			segments.len() == expected.len()
+				&& segments.iter().zip(expected).all(|(segment, expected)| {
+					(segment.0 - expected.0).abs() < 1e-5 && (segment.1 - expected.1).abs() < 1e-5
+				})
+		};
+		let played = |segments: PlaybackSegments,
+		              lead: &[(f32, f32)],
+		              cycles: u32,
+		              tail: &[(f32, f32)]| {
+			close(&segments.lead, lead) && segments.cycles == cycles && close(&segments.tail, tail)
+		};
+
+		let mut player = playing(PlaybackMode::Loop);
+		player.seek(0.5);
+		assert!(played(
+			player.playback_segments(1.0, 2.25),
+			&[(0.5, 1.0)],
+			1,
+			&[(0.0, 0.75)]
+		));
+		player.speed = -1.0;
+		assert!(played(
+			player.playback_segments(1.0, 1.75),
+			&[(0.5, 0.0)],
+			1,
+			&[(1.0, 0.75)]
+		));
+
+		let mut player = playing(PlaybackMode::PingPong);
+		player.seek(0.5);
+		assert!(played(
+			player.playback_segments(1.0, 2.0),
+			&[(0.5, 1.0), (1.0, 0.0)],
+			0,
+			&[(0.0, 0.5)]
+		));
+		assert!(played(
+			player.playback_segments(1.0, 4.0),
+			&[(0.5, 1.0), (1.0, 0.0)],
+			1,
+			&[(0.0, 0.5)]
+		));
+		player.reversed = true;
+		assert!(played(
+			player.playback_segments(1.0, 1.0),
+			&[(0.5, 0.0)],
+			0,
+			&[(0.0, 0.5)]
+		));
+	}
+
+	#[test]
+	fn huge_steps_count_cycles_instead_of_listing_them() {
+		let mut ecs = Ecs::default();
+		let mut animation = walk_and_turn(ecs.push(()));
+		if let TransformationSet::Rotations(rotations) = &mut animation.channels[1].transformations
+		{
+			rotations[1] = glm::Quat::identity().coords;
+		}
+		animation.add_notify("Step", 0.5);
+
+		let mut player = playing(PlaybackMode::Loop);
+		let segments = player.playback_segments(1.0, 1.0e9);
+		assert!(segments.cycles >= 999_999_000);
+		assert!(segments.lead.len() + segments.tail.len() <= 2);
+		let motion = segments.root_motion(&animation);
+		assert!((motion.translation.z / 2.0e9 - 1.0).abs() < 1e-3);
+
+		player.seek(0.0);
+		let segments = player.playback_segments(1.0, 3.0);
+		assert_eq!(segments.notifies(&animation).count(), 3);
+	}
+
+	#[test]
+	fn notifies_in_crossed_ranges_are_reported_once() {
+		let mut ecs = Ecs::default();
+		let mut animation = walk_and_turn(ecs.push(()));
+		animation.add_notify("RightFoot", 0.75);
+		animation.add_notify("LeftFoot", 0.25);
+		animation.add_notify("Start", 0.0);
+		let names = |from, to| {
+			animation
+				.notifies_between(from, to)
+				.map(|notify| notify.name.as_str())
+				.collect::<Vec<_>>()
+		};
+
+		assert_eq!(names(0.0, 0.9), vec!["Start", "LeftFoot", "RightFoot"]);
+		assert_eq!(names(0.1, 0.25), Vec::<&str>::new());
+		assert_eq!(names(0.25, 0.5), vec!["LeftFoot"]);
+		assert_eq!(names(0.8, 0.3), vec!["RightFoot"]);
+
+		let mut player = playing(PlaybackMode::Loop);
+		player.seek(0.7);
+		let looped = player
+			.playback_segments(1.0, 0.6)
+			.notifies(&animation)
+			.map(|notify| notify.name.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(looped, vec!["RightFoot", "Start", "LeftFoot"]);
+	}
+
+	#[test]
+	fn unknown_clips_are_rejected() {
+		let mut player = AnimationPlayer::new(vec!["Walk".to_string()]);
+		assert!(matches!(
+			player.play("Run"),
+			Err(AnimationError::UnknownClip(_))
+		));
+		assert_eq!(player.state, PlaybackState::Stopped);
+	}
+}
@@ -0,0 +1,795 @@
+use crate::{find_clip_index, Animation, BoneMask, Entity, Pose, PosePool, RootMotion};
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Blends a character's clips through layered state machines driven by named parameters.
+/// Every layer is evaluated into one pose before any transform is written,
+/// so clips affecting the same joints are mixed instead of overwriting each other.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimationGraph {
+	pub parameters: HashMap<String, AnimationParameter>,
+
+	/// Evaluated in order, each blending over the layers before it
+	pub layers: Vec<AnimationLayer>,
+
+	/// The joints' transforms before the graph first posed them, which the first layer
+	/// blends from by its weight. Captured by `World::animate_graphs`.
+	#[serde(default)]
+	pub bind_pose: Option<Pose>,
+}
+
+impl AnimationGraph {
+	pub fn new(layers: Vec<AnimationLayer>) -> Self {
+		Self {
+			parameters: HashMap::new(),
+			layers,
+			bind_pose: None,
+		}
+	}
+
+	pub fn set_float(&mut self, name: &str, value: f32) {
+		self.parameters
+			.insert(name.to_string(), AnimationParameter::Float(value));
+	}
+
+	pub fn set_bool(&mut self, name: &str, value: bool) {
+		self.parameters
+			.insert(name.to_string(), AnimationParameter::Bool(value));
+	}
+
+	/// Moves every layer forward, starting any transitions whose conditions are met.
+	/// Root motion comes from the first layer and notifies from every layer.
+	pub fn advance(&mut self, animations: &[Animation], step: f32) -> GraphAdvance {
+		let mut graph_advance = GraphAdvance::default();
+		for (index, layer) in self.layers.iter_mut().enumerate() {
+			let layer_advance = layer.advance(&self.parameters, animations, step);
+			if index == 0 {
+				graph_advance.root_motion = layer_advance.root_motion;
+			}
+			graph_advance.notifies.extend(layer_advance.notifies);
+		}
+		graph_advance
+	}
+
+	pub fn evaluate(&self, animations: &[Animation]) -> Pose {
+		let mut pool = PosePool::default();
+		let mut pose = pool.take();
+		self.evaluate_into(animations, &mut pose, &mut pool);
+		pose
+	}
+
+	/// Evaluates into a reused pose, borrowing the poses of each layer and clip from a pool
+	pub fn evaluate_into(&self, animations: &[Animation], pose: &mut Pose, pool: &mut PosePool) {
+		match self.bind_pose.as_ref() {
+			Some(bind_pose) => pose.joints.clone_from(&bind_pose.joints),
+			None => pose.joints.clear(),
+		}
+		let mut layer_pose = pool.take();
+		for layer in self.layers.iter() {
+			layer.evaluate_into(&self.parameters, animations, &mut layer_pose, pool);
+			pose.blend(&layer_pose, layer.weight, layer.mask.as_ref());
+		}
+		pool.give_back(layer_pose);
+	}
+
+	/// Every entity the clips of the graph's states animate
+	pub fn targets(&self, animations: &[Animation]) -> Vec<Entity> {
+		let mut targets = Vec::new();
+		let clips = self
+			.layers
+			.iter()
+			.flat_map(|layer| layer.states.iter())
+			.flat_map(|state| state.motion.clips());
+		for clip in clips {
+			let animation = match find_clip_index(animations, clip, None) {
+				Some(index) => &animations[index],
+				None => continue,
+			};
+			for channel in animation.channels.iter() {
+				if !targets.contains(&channel.target) {
+					targets.push(channel.target);
+				}
+			}
+		}
+		targets
+	}
+
+	/// Points the graph's masks and bind pose at other entities, as when it is copied
+	pub fn retarget(&mut self, joint: impl Fn(Entity) -> Entity) {
+		for mask in self
+			.layers
+			.iter_mut()
+			.filter_map(|layer| layer.mask.as_mut())
+		{
+			mask.entities
+				.iter_mut()
+				.for_each(|entity| *entity = joint(*entity));
+		}
+		if let Some(bind_pose) = self.bind_pose.as_mut() {
+			bind_pose.joints = bind_pose
+				.joints
+				.drain()
+				.map(|(entity, joint_pose)| (joint(entity), joint_pose))
+				.collect();
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AnimationParameter {
+	Float(f32),
+	Bool(bool),
+}
+
+/// A state machine whose pose replaces the layers below it by its weight
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimationLayer {
+	pub name: String,
+	pub states: Vec<AnimationState>,
+	pub transitions: Vec<AnimationTransition>,
+	pub weight: f32,
+
+	/// Limits the layer to some joints, such as an upper body
+	pub mask: Option<BoneMask>,
+
+	pub current: StatePlayback,
+
+	/// The state being faded out after a transition
+	pub crossfade: Option<Crossfade>,
+}
+
+impl AnimationLayer {
+	/// Creates a fully weighted layer starting in its first state
+	pub fn new(name: &str, states: Vec<AnimationState>) -> Self {
+		Self {
+			name: name.to_string(),
+			states,
+			transitions: Vec::new(),
+			weight: 1.0,
+			mask: None,
+			current: StatePlayback::default(),
+			crossfade: None,
+		}
+	}
+
+	pub fn state_index(&self, name: &str) -> Option<usize> {
+		self.states.iter().position(|state| state.name == name)
+	}
+
+	pub fn current_state(&self) -> Option<&AnimationState> {
+		self.states.get(self.current.state)
+	}
+
+	fn advance(
+		&mut self,
+		parameters: &HashMap<String, AnimationParameter>,
+		animations: &[Animation],
+		step: f32,
+	) -> GraphAdvance {
+		if let Some(transition) = self.triggered_transition(parameters) {
+			self.crossfade = if transition.duration > 0.0 {
+				Some(Crossfade {
+					from: self.current,
+					elapsed: 0.0,
+					duration: transition.duration,
+				})
+			} else {
+				None
+			};
+			self.current = StatePlayback {
+				state: transition.target,
+				phase: 0.0,
+			};
+		}
+
+		let faded_state = self.crossfade.map(|crossfade| crossfade.from.state);
+		for index in [Some(self.current.state), faded_state]
+			.into_iter()
+			.flatten()
+		{
+			if let Some(state) = self.states.get_mut(index) {
+				state.resolve_clips(animations);
+			}
+		}
+
+		let previous = self.current;
+		self.current = self.advance_playback(self.current, parameters, animations, step);
+		if let Some(mut crossfade) = self.crossfade.take() {
+			crossfade.elapsed += step;
+			if crossfade.elapsed < crossfade.duration {
+				crossfade.from =
+					self.advance_playback(crossfade.from, parameters, animations, step);
+				self.crossfade = Some(crossfade);
+			}
+		}
+		self.played(previous, parameters, animations, step)
+	}
+
+	/// Weights the root motion of each clip in the current state over the phase just played,
+	/// and collects the notifies of its most heavily weighted clip
+	fn played(
+		&self,
+		previous: StatePlayback,
+		parameters: &HashMap<String, AnimationParameter>,
+		animations: &[Animation],
+		step: f32,
+	) -> GraphAdvance {
+		let state = match self.current_state() {
+			Some(state) => state,
+			None => return GraphAdvance::default(),
+		};
+		let (from, to) = (previous.phase, self.current.phase);
+		let forwards = step * state.speed >= 0.0;
+		let segments = if !state.looping || (forwards && to >= from) || (!forwards && to <= from) {
+			[Some((from, to)), None]
+		} else if forwards {
+			[Some((from, 1.0)), Some((0.0, to))]
+		} else {
+			[Some((from, 0.0)), Some((1.0, to))]
+		};
+		let clips = || {
+			state
+				.motion
+				.clip_weights(parameters)
+				.filter_map(move |(clip, weight)| {
+					Some((state.animation(animations, clip)?, weight))
+				})
+		};
+
+		let root_motion = clips().fold(RootMotion::default(), |motion, (animation, weight)| {
+			let duration = animation.max_animation_time;
+			let clip_motion = segments
+				.iter()
+				.flatten()
+				.fold(RootMotion::default(), |clip_motion, (from, to)| {
+					clip_motion.then(&animation.root_motion(from * duration, to * duration))
+				})
+				.scaled(weight);
+			RootMotion {
+				translation: motion.translation + clip_motion.translation,
+				yaw: motion.yaw + clip_motion.yaw,
+			}
+		});
+
+		// Blended clips often share notifies, such as footsteps, so only one clip reports them
+		let notifies = clips()
+			.max_by(|(_, a), (_, b)| a.total_cmp(b))
+			.map(|(animation, _)| {
+				let duration = animation.max_animation_time;
+				segments
+					.iter()
+					.flatten()
+					.flat_map(|(from, to)| {
+						animation.notifies_between(from * duration, to * duration)
+					})
+					.map(|notify| (animation.name.to_string(), notify.name.to_string()))
+					.collect()
+			})
+			.unwrap_or_default();
+
+		GraphAdvance {
+			root_motion,
+			notifies,
+		}
+	}
+
+	fn triggered_transition(
+		&self,
+		parameters: &HashMap<String, AnimationParameter>,
+	) -> Option<TriggeredTransition> {
+		let current_state = self.current_state()?;
+		self.transitions.iter().find_map(|transition| {
+			let from_current = match transition.from.as_ref() {
+				Some(from) => *from == current_state.name,
+				None => transition.to != current_state.name,
+			};
+			if !from_current {
+				return None;
+			}
+			if let Some(exit_time) = transition.exit_time {
+				if self.current.phase < exit_time {
+					return None;
+				}
+			}
+			if !transition
+				.conditions
+				.iter()
+				.all(|condition| condition.is_met(parameters))
+			{
+				return None;
+			}
+			Some(TriggeredTransition {
+				target: self.state_index(&transition.to)?,
+				duration: transition.duration,
+			})
+		})
+	}
+
+	fn advance_playback(
+		&self,
+		playback: StatePlayback,
+		parameters: &HashMap<String, AnimationParameter>,
+		animations: &[Animation],
+		step: f32,
+	) -> StatePlayback {
+		let state = match self.states.get(playback.state) {
+			Some(state) => state,
+			None => return playback,
+		};
+		let duration = state
+			.motion
+			.clip_weights(parameters)
+			.filter_map(|(clip, weight)| {
+				Some(state.animation(animations, clip)?.max_animation_time * weight)
+			})
+			.sum::<f32>();
+		if duration <= 0.0 {
+			return playback;
+		}
+		let phase = playback.phase + step * state.speed / duration;
+		StatePlayback {
+			phase: if state.looping {
+				phase.rem_euclid(1.0)
+			} else {
+				phase.clamp(0.0, 1.0)
+			},
+			..playback
+		}
+	}
+
+	fn evaluate_into(
+		&self,
+		parameters: &HashMap<String, AnimationParameter>,
+		animations: &[Animation],
+		pose: &mut Pose,
+		pool: &mut PosePool,
+	) {
+		self.sample_playback(self.current, parameters, animations, pose, pool);
+		if let Some(crossfade) = self.crossfade.as_ref() {
+			let mut faded_pose = pool.take();
+			self.sample_playback(
+				crossfade.from,
+				parameters,
+				animations,
+				&mut faded_pose,
+				pool,
+			);
+			faded_pose.blend(pose, crossfade.elapsed / crossfade.duration, None);
+			std::mem::swap(pose, &mut faded_pose);
+			pool.give_back(faded_pose);
+		}
+	}
+
+	/// Averages the pose of each clip in a state's motion by its weight
+	fn sample_playback(
+		&self,
+		playback: StatePlayback,
+		parameters: &HashMap<String, AnimationParameter>,
+		animations: &[Animation],
+		pose: &mut Pose,
+		pool: &mut PosePool,
+	) {
+		pose.joints.clear();
+		let state = match self.states.get(playback.state) {
+			Some(state) => state,
+			None => return,
+		};
+		let mut clip_pose = pool.take();
+		let mut total_weight = 0.0;
+		for (clip, weight) in state.motion.clip_weights(parameters) {
+			let animation = match state.animation(animations, clip) {
+				Some(animation) if weight > 0.0 => animation,
+				_ => continue,
+			};
+			total_weight += weight;
+			animation.sample_into(
+				playback.phase * animation.max_animation_time,
+				&mut clip_pose,
+			);
+			pose.blend(&clip_pose, weight / total_weight, None);
+		}
+		pool.give_back(clip_pose);
+	}
+}
+
+/// The root motion and notifies played through by advancing a graph
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GraphAdvance {
+	pub root_motion: RootMotion,
+
+	/// The clip and name of each notify crossed
+	pub notifies: Vec<(String, String)>,
+}
+
+/// A state and how far through it playback is
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatePlayback {
+	pub state: usize,
+
+	/// From 0 at the start of the state's clips to 1 at their end,
+	/// keeping blended clips of different lengths in step
+	pub phase: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Crossfade {
+	pub from: StatePlayback,
+	pub elapsed: f32,
+	pub duration: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimationState {
+	pub name: String,
+	pub motion: Motion,
+	pub speed: f32,
+	pub looping: bool,
+
+	/// Where each of the motion's clips was last found among the world's animations
+	#[serde(skip)]
+	pub clip_indices: Vec<Option<usize>>,
+}
+
+impl AnimationState {
+	pub fn new(name: &str, motion: Motion) -> Self {
+		Self {
+			name: name.to_string(),
+			motion,
+			speed: 1.0,
+			looping: true,
+			clip_indices: Vec::new(),
+		}
+	}
+
+	/// Finds the motion's clips among the animations,
+	/// searching again only for clips that are no longer where they were
+	fn resolve_clips(&mut self, animations: &[Animation]) {
+		self.clip_indices.resize(self.motion.clips().count(), None);
+		for (clip, clip_index) in self.clip_indices.iter_mut().enumerate() {
+			*clip_index = self
+				.motion
+				.clip(clip)
+				.and_then(|name| find_clip_index(animations, name, *clip_index));
+		}
+	}
+
+	/// The animation a clip of the motion plays, by its index in `Motion::clips`
+	fn animation<'a>(&self, animations: &'a [Animation], clip: usize) -> Option<&'a Animation> {
+		let name = self.motion.clip(clip)?;
+		let last_index = self.clip_indices.get(clip).copied().flatten();
+		find_clip_index(animations, name, last_index).map(|index| &animations[index])
+	}
+}
+
+/// The clips a state plays, by name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Motion {
+	Clip(String),
+
+	/// Blends the two clips nearest a float parameter's value
+	Blend1D {
+		parameter: String,
+		clips: Vec<(f32, String)>,
+	},
+
+	/// Weights clips by their inverse squared distance from two float parameters' values
+	Blend2D {
+		x_parameter: String,
+		y_parameter: String,
+		clips: Vec<(glm::Vec2, String)>,
+	},
+}
+
+impl Motion {
+	/// The names of every clip the motion can play
+	pub fn clips(&self) -> impl Iterator<Item = &str> {
+		(0..).map_while(move |index| self.clip(index))
+	}
+
+	/// The name of the clip at an index into `clips`
+	pub fn clip(&self, index: usize) -> Option<&str> {
+		match self {
+			Self::Clip(clip) => (index == 0).then_some(clip.as_str()),
+			Self::Blend1D { clips, .. } => clips.get(index).map(|(_, clip)| clip.as_str()),
+			Self::Blend2D { clips, .. } => clips.get(index).map(|(_, clip)| clip.as_str()),
+		}
+	}
+
+	/// The clips to blend, by their index in `clips`, and their weights, which add up to one
+	pub fn clip_weights<'a>(
+		&'a self,
+		parameters: &HashMap<String, AnimationParameter>,
+	) -> impl Iterator<Item = (usize, f32)> + 'a {
+		// At most two clips are weighted directly, and the rest by their distance
+		let mut nearest = [None, None];
+		let mut distances = None;
+		match self {
+			Self::Clip(_) => nearest[0] = Some((0, 1.0)),
+			Self::Blend1D { parameter, clips } => {
+				let value = float_parameter(parameters, parameter);
+				// The nearest thresholds at or below the value and above it
+				let mut below: Option<(usize, f32)> = None;
+				let mut above: Option<(usize, f32)> = None;
+				for (index, (threshold, _)) in clips.iter().enumerate() {
+					if *threshold <= value {
+						if below.map_or(true, |(_, nearest)| *threshold >= nearest) {
+							below = Some((index, *threshold));
+						}
+					} else if above.map_or(true, |(_, nearest)| *threshold < nearest) {
+						above = Some((index, *threshold));
+					}
+				}
+				nearest = match (below, above) {
+					(Some((start_clip, start)), Some((end_clip, end))) => {
+						let amount = (value - start) / (end - start);
+						[Some((start_clip, 1.0 - amount)), Some((end_clip, amount))]
+					}
+					(Some((clip, _)), None) | (None, Some((clip, _))) => [Some((clip, 1.0)), None],
+					(None, None) => [None, None],
+				};
+			}
+			Self::Blend2D {
+				x_parameter,
+				y_parameter,
+				clips,
+			} => {
+				let point = glm::vec2(
+					float_parameter(parameters, x_parameter),
+					float_parameter(parameters, y_parameter),
+				);
+				match clips
+					.iter()
+					.position(|(position, _)| glm::distance2(position, &point) < f32::EPSILON)
+				{
+					Some(clip) => nearest[0] = Some((clip, 1.0)),
+					None => {
+						let total = clips
+							.iter()
+							.map(|(position, _)| 1.0 / glm::distance2(position, &point))
+							.sum::<f32>();
+						distances = Some((clips, point, total));
+					}
+				}
+			}
+		}
+		let weighted = distances.into_iter().flat_map(|(clips, point, total)| {
+			clips.iter().enumerate().map(move |(clip, (position, _))| {
+				(clip, 1.0 / glm::distance2(position, &point) / total)
+			})
+		});
+		nearest.into_iter().flatten().chain(weighted)
+	}
+}
+
+/// Moves from one state to another once every condition holds.
+/// Transitions are checked in order and the first that applies is taken.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnimationTransition {
+	/// The source state's name, or none to transition from any other state
+	pub from: Option<String>,
+	pub to: String,
+
+	/// Seconds to crossfade between the states
+	pub duration: f32,
+
+	pub conditions: Vec<TransitionCondition>,
+
+	/// The source state's phase, from 0 to 1, it must reach before transitioning
+	pub exit_time: Option<f32>,
+}
+
+impl AnimationTransition {
+	pub fn new(from: &str, to: &str, duration: f32) -> Self {
+		Self {
+			from: Some(from.to_string()),
+			to: to.to_string(),
+			duration,
+			conditions: Vec::new(),
+			exit_time: None,
+		}
+	}
+
+	pub fn when(mut self, parameter: &str, comparison: Comparison) -> Self {
+		self.conditions.push(TransitionCondition {
+			parameter: parameter.to_string(),
+			comparison,
+		});
+		self
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransitionCondition {
+	pub parameter: String,
+	pub comparison: Comparison,
+}
+
+impl TransitionCondition {
+	/// Missing parameters read as zero or false
+	pub fn is_met(&self, parameters: &HashMap<String, AnimationParameter>) -> bool {
+		match self.comparison {
+			Comparison::Greater(threshold) => {
+				float_parameter(parameters, &self.parameter) > threshold
+			}
+			Comparison::Less(threshold) => float_parameter(parameters, &self.parameter) < threshold,
+			Comparison::Is(expected) => bool_parameter(parameters, &self.parameter) == expected,
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Comparison {
+	Greater(f32),
+	Less(f32),
+	Is(bool),
+}
+
+struct TriggeredTransition {
+	target: usize,
+	duration: f32,
+}
+
+fn float_parameter(parameters: &HashMap<String, AnimationParameter>, name: &str) -> f32 {
+	match parameters.get(name) {
+		Some(AnimationParameter::Float(value)) => *value,
+		_ => 0.0,
+	}
+}
+
+fn bool_parameter(parameters: &HashMap<String, AnimationParameter>, name: &str) -> bool {
+	matches!(parameters.get(name), Some(AnimationParameter::Bool(true)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{test_clip, Ecs, Transform};
+
+	fn height(pose: &Pose, entity: Entity) -> f32 {
+		pose.joints[&entity].translation.unwrap().y
+	}
+
+	#[test]
+	fn blend_1d_weights_the_nearest_clips() {
+		let motion = Motion::Blend1D {
+			parameter: "speed".to_string(),
+			clips: vec![
+				(0.0, "Idle".to_string()),
+				(4.0, "Run".to_string()),
+				(1.0, "Walk".to_string()),
+			],
+		};
+		let mut graph = AnimationGraph::default();
+		graph.set_float("speed", 2.5);
+		let weights = |graph: &AnimationGraph| {
+			motion
+				.clip_weights(&graph.parameters)
+				.map(|(clip, weight)| (motion.clip(clip).unwrap(), weight))
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(weights(&graph), vec![("Walk", 0.5), ("Run", 0.5)]);
+		graph.set_float("speed", 10.0);
+		assert_eq!(weights(&graph), vec![("Run", 1.0)]);
+	}
+
+	#[test]
+	fn transitions_crossfade_between_states() {
+		let mut ecs = Ecs::default();
+		let joint = ecs.push(());
+		let animations = vec![
+			test_clip("Idle", &[joint], 0.0),
+			test_clip("Walk", &[joint], 1.0),
+		];
+
+		let mut layer = AnimationLayer::new(
+			"Base",
+			vec![
+				AnimationState::new("Idle", Motion::Clip("Idle".to_string())),
+				AnimationState::new("Walk", Motion::Clip("Walk".to_string())),
+			],
+		);
+		layer.transitions.push(
+			AnimationTransition::new("Idle", "Walk", 0.5).when("speed", Comparison::Greater(0.1)),
+		);
+		let mut graph = AnimationGraph::new(vec![layer]);
+
+		graph.advance(&animations, 0.1);
+		assert_eq!(height(&graph.evaluate(&animations), joint), 0.0);
+
+		graph.set_float("speed", 1.0);
+		graph.advance(&animations, 0.25);
+		assert_eq!(graph.layers[0].current.state, 1);
+		assert!((height(&graph.evaluate(&animations), joint) - 0.5).abs() < 1e-5);
+
+		graph.advance(&animations, 0.25);
+		assert_eq!(graph.layers[0].crossfade, None);
+		assert_eq!(height(&graph.evaluate(&animations), joint), 1.0);
+	}
+
+	#[test]
+	fn pooled_poses_only_hold_their_graphs_joints() {
+		let mut ecs = Ecs::default();
+		let (first_joint, second_joint) = (ecs.push(()), ecs.push(()));
+		let animations = vec![
+			test_clip("Idle", &[first_joint], 1.0),
+			test_clip("Walk", &[second_joint], 2.0),
+		];
+		let graph = |clip: &str| {
+			AnimationGraph::new(vec![AnimationLayer::new(
+				"Base",
+				vec![AnimationState::new(clip, Motion::Clip(clip.to_string()))],
+			)])
+		};
+		let (first_graph, second_graph) = (graph("Idle"), graph("Walk"));
+
+		let mut pool = PosePool::default();
+		let mut pose = pool.take();
+		first_graph.evaluate_into(&animations, &mut pose, &mut pool);
+		assert_eq!(pose, first_graph.evaluate(&animations));
+		pool.give_back(pose);
+
+		let mut pose = pool.take();
+		second_graph.evaluate_into(&animations, &mut pose, &mut pool);
+		assert_eq!(pose, second_graph.evaluate(&animations));
+		assert!(!pose.joints.contains_key(&first_joint));
+	}
+
+	#[test]
+	fn base_layers_blend_from_the_bind_pose() {
+		let mut ecs = Ecs::default();
+		let legs = ecs.push(Transform::default());
+		let arms = ecs.push(Transform::default());
+		let animations = vec![test_clip("Jump", &[legs, arms], 2.0)];
+
+		let mut base = AnimationLayer::new(
+			"Base",
+			vec![AnimationState::new(
+				"Jump",
+				Motion::Clip("Jump".to_string()),
+			)],
+		);
+		base.weight = 0.5;
+		base.mask = Some(BoneMask::new(vec![legs]));
+		let mut graph = AnimationGraph::new(vec![base]);
+		graph.bind_pose = Some(Pose::capture(&ecs, graph.targets(&animations)));
+
+		let pose = graph.evaluate(&animations);
+		assert!((height(&pose, legs) - 1.0).abs() < 1e-5);
+		assert_eq!(height(&pose, arms), 0.0);
+	}
+
+	#[test]
+	fn masked_layers_only_affect_their_joints() {
+		let mut ecs = Ecs::default();
+		let legs = ecs.push(());
+		let arms = ecs.push(());
+		let animations = vec![
+			test_clip("Walk", &[legs, arms], 1.0),
+			test_clip("Wave", &[legs, arms], 2.0),
+		];
+
+		let mut upper_body = AnimationLayer::new(
+			"Upper Body",
+			vec![AnimationState::new(
+				"Wave",
+				Motion::Clip("Wave".to_string()),
+			)],
+		);
+		upper_body.mask = Some(BoneMask::new(vec![arms]));
+		let graph = AnimationGraph::new(vec![
+			AnimationLayer::new(
+				"Base",
+				vec![AnimationState::new(
+					"Walk",
+					Motion::Clip("Walk".to_string()),
+				)],
+			),
+			upper_body,
+		]);
+
+		let pose = graph.evaluate(&animations);
+		assert_eq!(height(&pose, legs), 1.0);
+		assert_eq!(height(&pose, arms), 2.0);
+	}
+}
@@ -1,11 +1,16 @@
+use crate::Viewport;
 use nalgebra_glm as glm;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Camera {
 	pub name: String,
 	pub projection: Projection,
 	pub enabled: bool,
+	/// The region of the render target this camera draws to,
+	/// normalized so that `Viewport::full()` covers the whole target
+	pub viewport: Viewport,
+	pub target: RenderTarget,
 }
 
 impl Camera {
@@ -24,13 +29,42 @@ impl Camera {
 	}
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RenderTarget {
+	/// The window surface that is presented each frame
+	Surface,
+
+	/// A named offscreen texture that can be displayed in the gui
+	Texture {
+		name: String,
+		width: u32,
+		height: u32,
+	},
+}
+
+impl Default for RenderTarget {
+	fn default() -> Self {
+		Self::Surface
+	}
+}
+
+impl RenderTarget {
+	pub fn texture(name: &str, width: u32, height: u32) -> Self {
+		Self::Texture {
+			name: name.to_string(),
+			width,
+			height,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Projection {
 	Perspective(PerspectiveCamera),
 	Orthographic(OrthographicCamera),
 }
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct PerspectiveCamera {
 	pub aspect_ratio: Option<f32>,
 	pub y_fov_rad: f32,
@@ -54,7 +88,7 @@ impl PerspectiveCamera {
 	}
 }
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct OrthographicCamera {
 	pub x_mag: f32,
 	pub y_mag: f32,
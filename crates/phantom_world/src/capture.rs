@@ -0,0 +1,107 @@
+use image::{ImageError, ImageFormat, Rgba32FImage, RgbaImage};
+use std::path::Path;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CaptureError {
+	#[error("Failed to create image buffer from captured pixel data!")]
+	CreateImageBuffer,
+
+	#[error("Failed to save captured frame!")]
+	SaveImage(#[source] ImageError),
+}
+
+type Result<T, E = CaptureError> = std::result::Result<T, E>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CaptureFormat {
+	Png,
+	Exr,
+}
+
+/// A frame read back from the GPU as tightly packed 8-bit RGBA pixels
+#[derive(Debug, Clone)]
+pub struct FrameCapture {
+	pub width: u32,
+	pub height: u32,
+	pub pixels: Vec<u8>,
+}
+
+impl FrameCapture {
+	pub fn as_image(&self) -> Result<RgbaImage> {
+		RgbaImage::from_raw(self.width, self.height, self.pixels.to_vec())
+			.ok_or(CaptureError::CreateImageBuffer)
+	}
+
+	/// The frame with its sRGB encoded colors decoded to linear floating point values,
+	/// which is how OpenEXR files are read
+	pub fn as_linear_image(&self) -> Result<Rgba32FImage> {
+		let pixels = self
+			.pixels
+			.chunks_exact(4)
+			.flat_map(|pixel| {
+				[
+					srgb_to_linear(pixel[0]),
+					srgb_to_linear(pixel[1]),
+					srgb_to_linear(pixel[2]),
+					pixel[3] as f32 / 255.0,
+				]
+			})
+			.collect();
+		Rgba32FImage::from_raw(self.width, self.height, pixels)
+			.ok_or(CaptureError::CreateImageBuffer)
+	}
+
+	pub fn save(&self, path: impl AsRef<Path>, format: CaptureFormat) -> Result<()> {
+		match format {
+			CaptureFormat::Png => self
+				.as_image()?
+				.save_with_format(path, ImageFormat::Png)
+				.map_err(CaptureError::SaveImage),
+			CaptureFormat::Exr => self
+				.as_linear_image()?
+				.save_with_format(path, ImageFormat::OpenExr)
+				.map_err(CaptureError::SaveImage),
+		}
+	}
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+	let value = value as f32 / 255.0;
+	if value <= 0.04045 {
+		value / 12.92
+	} else {
+		((value + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn linear_to_srgb(value: f32) -> u8 {
+		let value = if value <= 0.0031308 {
+			value * 12.92
+		} else {
+			1.055 * value.powf(1.0 / 2.4) - 0.055
+		};
+		(value * 255.0).round() as u8
+	}
+
+	#[test]
+	fn exr_captures_round_trip_mid_grey() {
+		let capture = FrameCapture {
+			width: 1,
+			height: 1,
+			pixels: vec![128, 128, 128, 255],
+		};
+		let path = std::env::temp_dir().join("phantom_exr_capture_mid_grey.exr");
+		capture.save(&path, CaptureFormat::Exr).unwrap();
+		let pixel = *image::open(&path).unwrap().into_rgba32f().get_pixel(0, 0);
+		let _ = std::fs::remove_file(&path);
+
+		assert!((pixel[0] - 0.2158605).abs() < 1e-4);
+		assert_eq!(linear_to_srgb(pixel[1]), 128);
+		assert_eq!(pixel[3], 1.0);
+	}
+}
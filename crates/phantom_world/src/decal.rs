@@ -0,0 +1,67 @@
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+
+/// Projects textures onto opaque geometry inside a unit box scaled by the entity's transform.
+/// The decal is projected along its local -Z axis.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Decal {
+	pub enabled: bool,
+
+	/// Multiplied with the base color texture
+	pub color: glm::Vec4,
+
+	/// Index into the world's textures
+	pub base_color_texture: Option<usize>,
+
+	/// Index into the world's textures, in tangent space aligned with the decal's X and Y axes
+	pub normal_texture: Option<usize>,
+
+	/// Angle in radians between the surface and the projection axis
+	/// at which the decal has completely faded out
+	pub fade_angle: f32,
+}
+
+impl Default for Decal {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+			base_color_texture: None,
+			normal_texture: None,
+			fade_angle: 80_f32.to_radians(),
+		}
+	}
+}
+
+impl Decal {
+	/// Returns the distance along a ray to the decal's box, given the box's global transform
+	pub fn ray_intersection(
+		model: &glm::Mat4,
+		origin: &glm::Vec3,
+		direction: &glm::Vec3,
+	) -> Option<f32> {
+		let inverse_model = glm::inverse(model);
+		let local_origin = (inverse_model * glm::vec4(origin.x, origin.y, origin.z, 1.0)).xyz();
+		let local_direction = (inverse_model * glm::vec3_to_vec4(direction)).xyz();
+
+		let mut near = f32::MIN;
+		let mut far = f32::MAX;
+		for axis in 0..3 {
+			if local_direction[axis].abs() < f32::EPSILON {
+				if local_origin[axis].abs() > 0.5 {
+					return None;
+				}
+				continue;
+			}
+			let first = (-0.5 - local_origin[axis]) / local_direction[axis];
+			let second = (0.5 - local_origin[axis]) / local_direction[axis];
+			near = near.max(first.min(second));
+			far = far.min(first.max(second));
+		}
+
+		if near > far || far < 0.0 {
+			return None;
+		}
+		Some(near.max(0.0))
+	}
+}
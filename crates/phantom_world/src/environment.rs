@@ -0,0 +1,76 @@
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+
+/// The backdrop, ambient light and fog of a scene
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Environment {
+	/// The color behind all geometry
+	pub clear_color: glm::Vec3,
+
+	/// Light reaching every surface regardless of the scene's lights
+	pub ambient_color: glm::Vec3,
+	pub ambient_intensity: f32,
+
+	pub fog: Fog,
+}
+
+impl Default for Environment {
+	fn default() -> Self {
+		Self {
+			clear_color: glm::vec3(0.1, 0.2, 0.3),
+			ambient_color: glm::vec3(1.0, 1.0, 1.0),
+			ambient_intensity: 0.1,
+			fog: Fog::default(),
+		}
+	}
+}
+
+impl Environment {
+	/// The ambient color scaled by its intensity
+	pub fn ambient_light(&self) -> glm::Vec3 {
+		self.ambient_color * self.ambient_intensity
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Fog {
+	None,
+
+	/// Blends in linearly between two distances from the camera
+	Linear {
+		color: glm::Vec3,
+		start: f32,
+		end: f32,
+	},
+
+	/// Thickens exponentially with distance from the camera
+	Exponential {
+		color: glm::Vec3,
+		density: f32,
+	},
+
+	/// Exponential fog whose density falls off with height above a base height
+	Height {
+		color: glm::Vec3,
+		density: f32,
+		base_height: f32,
+		falloff: f32,
+	},
+}
+
+impl Default for Fog {
+	fn default() -> Self {
+		Self::None
+	}
+}
+
+impl Fog {
+	pub fn color(&self) -> glm::Vec3 {
+		match self {
+			Self::None => glm::Vec3::zeros(),
+			Self::Linear { color, .. }
+			| Self::Exponential { color, .. }
+			| Self::Height { color, .. } => *color,
+		}
+	}
+}
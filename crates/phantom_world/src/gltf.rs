@@ -1,9 +1,10 @@
 use crate::{
-	AlphaMode, Animation, BoundingBox, Camera, Channel, Ecs, Entity, EntitySceneGraph, Filter,
-	Geometry, Interpolation, Joint, Light, LightKind, Material, Mesh, MeshRender, MorphTarget,
-	Name, OrthographicCamera, PerspectiveCamera, Primitive, Projection, Sampler, Scene, Skin,
-	Texture, TextureError, TextureFormat, Transform, TransformationSet, Vertex, World,
-	WrappingMode,
+	default_lod_screen_size, generate_lods, generate_tangents, AlphaMode, Animation,
+	AnimationNotify, AnimationPlayer, BoundingBox, Camera, Channel, Ecs, Entity, EntitySceneGraph,
+	Environment, Filter, Geometry, Interpolation, Joint, Light, LightKind, Material, Mesh, MeshLod,
+	MeshRender, MorphTarget, Name, OrthographicCamera, PerspectiveCamera, Prefab, Primitive,
+	Projection, RenderTarget, Sampler, Scene, Skin, Texture, TextureError, TextureFormat,
+	Transform, TransformationSet, Vertex, Viewport, World, WrappingMode,
 };
 use gltf::{self, animation::util::ReadOutputs};
 use legion::{
@@ -12,7 +13,10 @@ use legion::{
 };
 use nalgebra_glm as glm;
 use petgraph::prelude::*;
-use std::path::Path;
+use std::{
+	collections::{HashMap, HashSet},
+	path::Path,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -85,6 +89,34 @@ fn node_transform(node: &gltf::Node) -> Transform {
 const DEFAULT_NAME: &str = "<Unnamed>";
 
 pub fn load_gltf(path: impl AsRef<Path>, world: &mut World) -> Result<()> {
+	let mut prefab = import_prefab(path.as_ref(), world)?;
+	world.ecs.move_from(&mut prefab.ecs, &legion::any());
+	world.animations.append(&mut prefab.animations);
+	world.scene.graphs.append(&mut prefab.graphs);
+	Ok(())
+}
+
+/// Imports a glTF asset's geometry, materials and textures into the world once,
+/// keeping its nodes as a prefab to instantiate with `World::instantiate_prefab`.
+/// The prefab is named after the file, and loading a file again returns the existing prefab.
+pub fn load_prefab(path: impl AsRef<Path>, world: &mut World) -> Result<usize> {
+	let name = prefab_name(path.as_ref());
+	if let Some(index) = world.prefab_index(&name) {
+		return Ok(index);
+	}
+	let prefab = import_prefab(path.as_ref(), world)?;
+	world.prefabs.push(prefab);
+	Ok(world.prefabs.len() - 1)
+}
+
+fn prefab_name(path: &Path) -> String {
+	path.file_stem().map_or(DEFAULT_NAME.to_string(), |stem| {
+		stem.to_string_lossy().to_string()
+	})
+}
+
+/// Adds the asset's shared data to the world, returning its nodes in a separate ecs
+fn import_prefab(path: &Path, world: &mut World) -> Result<Prefab> {
 	let (gltf, buffers, images) = gltf::import(path).map_err(GltfError::ImportGltfAsset)?;
 
 	let number_of_materials = world.materials.len();
@@ -111,25 +143,29 @@ pub fn load_gltf(path: impl AsRef<Path>, world: &mut World) -> Result<()> {
 		.into_iter()
 		.for_each(|texture| world.textures.push(texture));
 
-	let entities = world
-		.ecs
-		.extend((0..gltf.nodes().len()).map(|_| ()))
-		.to_vec();
+	let mut ecs = Ecs::default();
+	let entities = ecs.extend((0..gltf.nodes().len()).map(|_| ())).to_vec();
+
+	let animations = load_animations(&gltf, &buffers, &entities)?;
+	let animation_targets = animations
+		.iter()
+		.map(|animation| {
+			let targets = animation
+				.channels
+				.iter()
+				.map(|channel| channel.target)
+				.collect::<Vec<_>>();
+			(animation.name.to_string(), targets)
+		})
+		.collect::<Vec<_>>();
 
-	load_animations(&gltf, &buffers, &entities)?
-		.into_iter()
-		.for_each(|node| world.animations.push(node));
+	let mesh_names = load_nodes(&gltf, &buffers, &mut ecs, &mut world.geometry, &entities)?;
 
-	load_nodes(
-		&gltf,
-		&buffers,
-		&mut world.ecs,
-		&mut world.geometry,
-		&entities,
-	)?;
+	let msft_lods = read_msft_lods(&gltf);
+	load_lods(&mesh_names, &msft_lods, &mut world.geometry);
 
 	for entity in entities.iter() {
-		if let Ok(mesh) = world.ecs.entry_mut(*entity)?.get_component_mut::<Mesh>() {
+		if let Ok(mesh) = ecs.entry_mut(*entity)?.get_component_mut::<Mesh>() {
 			mesh.primitives.iter_mut().for_each(|primitive| {
 				if let Some(material_index) = primitive.material_index.as_mut() {
 					*material_index += number_of_materials
@@ -139,14 +175,49 @@ pub fn load_gltf(path: impl AsRef<Path>, world: &mut World) -> Result<()> {
 	}
 
 	// Only merge default scene
+	let mut graphs = Vec::new();
 	let new_scenes = load_scenes(&gltf, &entities);
 	if let Some(new_scene) = new_scenes.into_iter().next() {
-		new_scene.graphs.into_iter().for_each(|graph| {
-			world.scene.graphs.push(graph);
-		});
+		for graph in new_scene.graphs.into_iter() {
+			add_animation_player(&graph, &animation_targets, &mut ecs);
+			graphs.push(graph);
+		}
 	}
+	remove_lod_only_nodes(&mut ecs, &entities, &msft_lods, &graphs);
+
+	Ok(Prefab {
+		name: prefab_name(path),
+		ecs,
+		graphs,
+		animations,
+		instances: 0,
+	})
+}
 
-	Ok(())
+/// Gives a root node a player for the animations targeting its hierarchy
+fn add_animation_player(
+	graph: &EntitySceneGraph,
+	animation_targets: &[(String, Vec<Entity>)],
+	ecs: &mut Ecs,
+) {
+	// Each glTF root node is graphed on its own, starting at the first node
+	let root_index = NodeIndex::new(0);
+	let hierarchy = graph
+		.subtree(root_index)
+		.into_iter()
+		.map(|node_index| graph[node_index])
+		.collect::<Vec<_>>();
+	let clips = animation_targets
+		.iter()
+		.filter(|(_, targets)| targets.iter().any(|target| hierarchy.contains(target)))
+		.map(|(name, _)| name.to_string())
+		.collect::<Vec<_>>();
+	if clips.is_empty() {
+		return;
+	}
+	if let Some(mut entry) = ecs.entry(graph[root_index]) {
+		entry.add_component(AnimationPlayer::new(clips));
+	}
 }
 
 fn load_samplers(document: &gltf::Document) -> Vec<Sampler> {
@@ -297,6 +368,7 @@ fn load_scenes(gltf: &gltf::Document, entities: &[Entity]) -> Vec<Scene> {
 				.map(|node| create_scene_graph(&node, entities))
 				.collect(),
 			skybox: None,
+			environment: Environment::default(),
 		})
 		.collect::<Vec<_>>()
 }
@@ -307,7 +379,8 @@ fn load_nodes(
 	ecs: &mut Ecs,
 	geometry: &mut Geometry,
 	entities: &[Entity],
-) -> Result<()> {
+) -> Result<Vec<Option<String>>> {
+	let mut mesh_names = vec![None; entities.len()];
 	for (index, node) in gltf.nodes().enumerate() {
 		let entity = entities[index];
 
@@ -334,6 +407,7 @@ fn load_nodes(
 				mesh.name.to_string()
 			};
 			geometry.meshes.insert(name.clone(), mesh);
+			mesh_names[index] = Some(name.clone());
 			entry.add_component(MeshRender { name });
 		}
 
@@ -346,7 +420,147 @@ fn load_nodes(
 		}
 	}
 
-	Ok(())
+	Ok(mesh_names)
+}
+
+/// The `MSFT_lod` extension of a node, listing the nodes holding its lower levels of detail
+struct MsftLod {
+	node_indices: Vec<usize>,
+	screen_coverages: Vec<f32>,
+}
+
+/// Reads an animation's notifies from its extras,
+/// written as `"extras": { "events": [{ "name": "Footstep", "time": 0.25 }] }`
+fn read_animation_notifies(animation: &gltf::Animation) -> Vec<AnimationNotify> {
+	let extras = read_extras(animation.extras());
+	let events = extras["events"].as_array().cloned().unwrap_or_default();
+	let mut notifies = events
+		.iter()
+		.filter_map(|event| {
+			Some(AnimationNotify {
+				name: event["name"].as_str()?.to_string(),
+				time: event["time"].as_f64()? as f32,
+			})
+		})
+		.collect::<Vec<_>>();
+	notifies.sort_by(|a, b| a.time.total_cmp(&b.time));
+	notifies
+}
+
+/// Reads the `MSFT_lod` extension of each node that has one
+fn read_msft_lods(document: &gltf::Document) -> HashMap<usize, MsftLod> {
+	let to_vec = |value: Option<&serde_json::Value>| -> Vec<f64> {
+		value
+			.and_then(serde_json::Value::as_array)
+			.map_or(Vec::new(), |values| {
+				values
+					.iter()
+					.filter_map(serde_json::Value::as_f64)
+					.collect()
+			})
+	};
+
+	document
+		.nodes()
+		.filter_map(|node| {
+			let msft_lod = node.extensions()?.get("MSFT_lod")?;
+			let screen_coverages = read_extras(node.extras());
+			let msft_lod = MsftLod {
+				node_indices: to_vec(msft_lod.get("ids"))
+					.into_iter()
+					.map(|id| id as usize)
+					.collect(),
+				screen_coverages: to_vec(screen_coverages.get("MSFT_screencoverage"))
+					.into_iter()
+					.map(|coverage| coverage as f32)
+					.collect(),
+			};
+			Some((node.index(), msft_lod))
+		})
+		.collect()
+}
+
+/// Parses an object's extras, which are empty when missing or malformed
+fn read_extras(extras: &gltf::json::Extras) -> serde_json::Value {
+	extras
+		.as_ref()
+		.and_then(|extras| serde_json::from_str(extras.get()).ok())
+		.unwrap_or_default()
+}
+
+/// Attaches imported levels of detail to meshes, and generates them for meshes without any
+fn load_lods(
+	mesh_names: &[Option<String>],
+	msft_lods: &HashMap<usize, MsftLod>,
+	geometry: &mut Geometry,
+) {
+	let lod_mesh_name = |node_index: &usize| mesh_names.get(*node_index).cloned().flatten();
+
+	for (node_index, msft_lod) in msft_lods.iter() {
+		let name = match lod_mesh_name(node_index) {
+			Some(name) => name,
+			None => continue,
+		};
+		let lods = msft_lod
+			.node_indices
+			.iter()
+			.enumerate()
+			.filter_map(|(level, lod_node_index)| {
+				let lod_mesh = geometry.meshes.get(&lod_mesh_name(lod_node_index)?)?;
+				// The first screen coverage belongs to the full detail mesh
+				let screen_size = msft_lod
+					.screen_coverages
+					.get(level + 1)
+					.copied()
+					.unwrap_or_else(|| default_lod_screen_size(level));
+				Some(MeshLod {
+					screen_size,
+					primitives: lod_mesh.primitives.clone(),
+				})
+			})
+			.collect::<Vec<_>>();
+		if let Some(mesh) = geometry.meshes.get_mut(&name) {
+			mesh.lods = lods;
+		}
+	}
+
+	let lod_node_indices = msft_lods
+		.values()
+		.flat_map(|msft_lod| msft_lod.node_indices.iter().copied())
+		.collect::<Vec<_>>();
+	for (node_index, name) in mesh_names.iter().enumerate() {
+		let name = match name {
+			Some(name) if !lod_node_indices.contains(&node_index) => name,
+			_ => continue,
+		};
+		if let Some(mut mesh) = geometry.meshes.remove(name) {
+			generate_lods(&mut mesh, geometry);
+			geometry.meshes.insert(name.to_string(), mesh);
+		}
+	}
+}
+
+/// Removes the nodes only referenced as levels of detail, whose primitives were copied
+/// into their meshes' lods, so instances don't copy them as unparented meshes
+fn remove_lod_only_nodes(
+	ecs: &mut Ecs,
+	entities: &[Entity],
+	msft_lods: &HashMap<usize, MsftLod>,
+	graphs: &[EntitySceneGraph],
+) {
+	let graphed = graphs
+		.iter()
+		.flat_map(|graph| graph.0.node_weights().copied())
+		.collect::<HashSet<_>>();
+	let lod_entities = msft_lods
+		.values()
+		.flat_map(|msft_lod| msft_lod.node_indices.iter())
+		.filter_map(|node_index| entities.get(*node_index).copied());
+	for entity in lod_entities {
+		if !graphed.contains(&entity) {
+			ecs.remove(entity);
+		}
+	}
 }
 
 fn load_camera(camera: &gltf::Camera) -> Result<Camera> {
@@ -372,6 +586,8 @@ fn load_camera(camera: &gltf::Camera) -> Result<Camera> {
 		name: camera.name().unwrap_or(DEFAULT_NAME).to_string(),
 		projection,
 		enabled: false,
+		viewport: Viewport::full(),
+		target: RenderTarget::Surface,
 	})
 }
 
@@ -392,6 +608,7 @@ fn load_mesh(
 		name: mesh.name().unwrap_or(DEFAULT_NAME).to_string(),
 		primitives,
 		weights,
+		lods: Vec::new(),
 	})
 }
 
@@ -406,6 +623,9 @@ fn load_primitive(
 	let first_vertex = geometry.vertices.len();
 	let number_of_indices = load_primitive_indices(primitive, buffers, geometry)?;
 	let number_of_vertices = load_primitive_vertices(primitive, buffers, geometry)?;
+	if primitive.get(&gltf::Semantic::Tangents).is_none() {
+		generate_primitive_tangents(primitive, geometry, first_index, first_vertex);
+	}
 	let bounding_box = primitive.bounding_box();
 	let morph_targets = load_morph_targets(primitive, buffers)?;
 	let bounding_box = BoundingBox::new(
@@ -419,6 +639,7 @@ fn load_primitive(
 		number_of_vertices,
 		morph_targets,
 		material_index: primitive.material().index(),
+		custom_material: None,
 		bounding_box,
 	})
 }
@@ -476,6 +697,11 @@ fn load_primitive_vertices(
 		convert_weights,
 	);
 
+	let tangents = reader.read_tangents().map_or(
+		vec![glm::vec4(1.0, 0.0, 0.0, 1.0); number_of_vertices],
+		|tangents| tangents.map(glm::Vec4::from).collect::<Vec<_>>(),
+	);
+
 	let convert_colors = |colors: gltf::mesh::util::ReadColors<'_>| -> Vec<glm::Vec3> {
 		colors
 			.into_rgb_f32()
@@ -497,12 +723,43 @@ fn load_primitive_vertices(
 			joint_0: joints_0[index],
 			weight_0: weights_0[index],
 			color_0: colors_0[index],
+			tangent: tangents[index],
 		});
 	}
 
 	Ok(number_of_vertices)
 }
 
+/// Generates tangents for primitives without them, using the texture coordinates of their normal map
+fn generate_primitive_tangents(
+	primitive: &gltf::Primitive,
+	geometry: &mut Geometry,
+	first_index: usize,
+	first_vertex: usize,
+) {
+	let uv_set = primitive
+		.material()
+		.normal_texture()
+		.map_or(0, |normal_texture| normal_texture.tex_coord());
+	let has_tex_coords = primitive.get(&gltf::Semantic::TexCoords(uv_set)).is_some();
+	if primitive.mode() != gltf::mesh::Mode::Triangles || !has_tex_coords {
+		return;
+	}
+
+	let vertices = &mut geometry.vertices[first_vertex..];
+	let indices = if first_index < geometry.indices.len() {
+		geometry.indices[first_index..]
+			.iter()
+			.map(|index| index - first_vertex as u32)
+			.collect::<Vec<_>>()
+	} else {
+		(0..vertices.len() as u32).collect::<Vec<_>>()
+	};
+	if !generate_tangents(vertices, &indices, uv_set as usize) {
+		log::warn!("Failed to generate tangents for a primitive");
+	}
+}
+
 fn load_primitive_indices(
 	primitive: &gltf::Primitive,
 	buffers: &[gltf::buffer::Data],
@@ -579,7 +836,7 @@ fn load_animations(
 		let mut channels = Vec::new();
 		for channel in animation.channels() {
 			let sampler = channel.sampler();
-			let _interpolation = map_gltf_interpolation(sampler.interpolation());
+			let interpolation = map_gltf_interpolation(sampler.interpolation());
 			let target_node = channel.target().node().index();
 			let target = entities[target_node];
 			let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
@@ -616,7 +873,7 @@ fn load_animations(
 				target,
 				inputs,
 				transformations,
-				_interpolation,
+				interpolation,
 			});
 		}
 
@@ -630,6 +887,8 @@ fn load_animations(
 			time: 0.0,
 			max_animation_time,
 			name,
+			root_motion_joint: None,
+			notifies: read_animation_notifies(&animation),
 		});
 	}
 	Ok(animations)
@@ -706,3 +965,29 @@ fn map_gltf_light_kind(light: gltf::khr_lights_punctual::Kind) -> LightKind {
 		},
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn lod_only_nodes_are_removed_from_the_prefab() {
+		let mut ecs = Ecs::default();
+		let entities = ecs.extend((0..3).map(|_| ())).to_vec();
+		let mut graph = EntitySceneGraph::new();
+		let root_index = graph.add_root_node(entities[0]);
+		graph.add_child(root_index, entities[1]);
+		let msft_lods = HashMap::from([(
+			0,
+			MsftLod {
+				node_indices: vec![1, 2],
+				screen_coverages: Vec::new(),
+			},
+		)]);
+
+		remove_lod_only_nodes(&mut ecs, &entities, &msft_lods, &[graph]);
+		assert!(ecs.entry_ref(entities[0]).is_ok());
+		assert!(ecs.entry_ref(entities[1]).is_ok());
+		assert!(ecs.entry_ref(entities[2]).is_err());
+	}
+}
@@ -0,0 +1,292 @@
+use crate::Entity;
+use nalgebra::UnitQuaternion;
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+
+/// Constraints solved on an entity's skin joints after animation is sampled each tick.
+/// Targets and poles are in world space.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InverseKinematics {
+	/// Solved in order, so later constraints see the joints earlier ones moved
+	pub constraints: Vec<IkConstraint>,
+}
+
+impl InverseKinematics {
+	/// Points the constraints at other joints, such as those of a copied hierarchy
+	pub fn retarget(&mut self, joint: impl Fn(Entity) -> Entity) {
+		for constraint in self.constraints.iter_mut() {
+			match constraint {
+				IkConstraint::TwoBone(ik) => {
+					ik.root = joint(ik.root);
+					ik.middle = joint(ik.middle);
+					ik.end = joint(ik.end);
+				}
+				IkConstraint::LookAt(ik) => ik.joint = joint(ik.joint),
+				IkConstraint::Fabrik(ik) => ik
+					.joints
+					.iter_mut()
+					.for_each(|chain_joint| *chain_joint = joint(*chain_joint)),
+				IkConstraint::Foot(ik) => {
+					ik.hip = joint(ik.hip);
+					ik.knee = joint(ik.knee);
+					ik.ankle = joint(ik.ankle);
+				}
+			}
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IkConstraint {
+	TwoBone(TwoBoneIk),
+	LookAt(LookAtIk),
+	Fabrik(FabrikIk),
+	Foot(FootIk),
+}
+
+impl IkConstraint {
+	/// Every joint the constraint turns or reads
+	pub fn joints(&self) -> Vec<Entity> {
+		match self {
+			Self::TwoBone(ik) => vec![ik.root, ik.middle, ik.end],
+			Self::LookAt(ik) => vec![ik.joint],
+			Self::Fabrik(ik) => ik.joints.clone(),
+			Self::Foot(ik) => vec![ik.hip, ik.knee, ik.ankle],
+		}
+	}
+}
+
+/// Bends a limb, such as an arm or a leg, so its end reaches a target
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TwoBoneIk {
+	pub root: Entity,
+	pub middle: Entity,
+	pub end: Entity,
+	pub target: glm::Vec3,
+
+	/// The point the middle joint bends towards, or its animated position when none
+	pub pole: Option<glm::Vec3>,
+
+	/// How much the solved pose replaces the animated one, from 0 to 1
+	pub weight: f32,
+}
+
+/// Turns a joint, such as a head, towards a target
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LookAtIk {
+	pub joint: Entity,
+	pub target: glm::Vec3,
+
+	/// The direction in the joint's own space that should face the target
+	pub forward: glm::Vec3,
+
+	/// Radians the joint may turn away from its animated direction
+	pub max_angle: f32,
+
+	pub weight: f32,
+}
+
+/// Reaches a target with a chain of any length, such as a tail or a spine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FabrikIk {
+	/// Joints from the chain's root to its end
+	pub joints: Vec<Entity>,
+	pub target: glm::Vec3,
+	pub iterations: usize,
+
+	/// The distance from the target at which solving stops early
+	pub tolerance: f32,
+
+	pub weight: f32,
+}
+
+/// Plants a leg's foot on the ground below its animated position,
+/// found by casting a ray against the physics colliders
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FootIk {
+	pub hip: Entity,
+	pub knee: Entity,
+	pub ankle: Entity,
+
+	/// How far above the animated ankle the ray starts, letting feet step up
+	pub ray_height: f32,
+
+	/// How far below the animated ankle the ground is searched for, letting feet reach down
+	pub max_drop: f32,
+
+	/// The ankle's height above the ground
+	pub foot_height: f32,
+
+	pub weight: f32,
+}
+
+/// Solves a two bone chain, returning the new middle and end positions.
+/// Targets out of reach straighten the chain towards them.
+pub fn solve_two_bone(
+	root: &glm::Vec3,
+	middle: &glm::Vec3,
+	end: &glm::Vec3,
+	target: &glm::Vec3,
+	pole: &glm::Vec3,
+) -> (glm::Vec3, glm::Vec3) {
+	let upper_length = glm::distance(root, middle);
+	let lower_length = glm::distance(middle, end);
+	let to_target = target - root;
+	if upper_length <= f32::EPSILON || lower_length <= f32::EPSILON {
+		return (*middle, *end);
+	}
+	let direction = match to_target.try_normalize(f32::EPSILON) {
+		Some(direction) => direction,
+		None => return (*middle, *end),
+	};
+	let reach = glm::length(&to_target).clamp(
+		(upper_length - lower_length).abs() + 1e-4,
+		upper_length + lower_length - 1e-4,
+	);
+
+	// The law of cosines gives the angle between the upper bone and the target direction
+	let cos_angle = ((upper_length * upper_length + reach * reach - lower_length * lower_length)
+		/ (2.0 * upper_length * reach))
+		.clamp(-1.0, 1.0);
+	let sin_angle = (1.0 - cos_angle * cos_angle).sqrt();
+
+	let bend = perpendicular_towards(&direction, &(pole - root))
+		.or_else(|| perpendicular_towards(&direction, &(middle - root)))
+		.unwrap_or_else(|| any_perpendicular(&direction));
+	let solved_middle =
+		root + direction * upper_length * cos_angle + bend * upper_length * sin_angle;
+	let solved_end = root + direction * reach;
+	(solved_middle, solved_end)
+}
+
+/// Moves a chain's joint positions to reach a target, keeping the root in place
+/// and the distances between joints
+pub fn solve_fabrik(
+	positions: &mut [glm::Vec3],
+	target: &glm::Vec3,
+	iterations: usize,
+	tolerance: f32,
+) {
+	let number_of_joints = positions.len();
+	if number_of_joints < 2 {
+		return;
+	}
+	let lengths = positions
+		.windows(2)
+		.map(|pair| glm::distance(&pair[0], &pair[1]))
+		.collect::<Vec<_>>();
+	let root = positions[0];
+
+	if glm::distance(&root, target) >= lengths.iter().sum::<f32>() {
+		let direction = glm::normalize(&(target - root));
+		for index in 1..number_of_joints {
+			positions[index] = positions[index - 1] + direction * lengths[index - 1];
+		}
+		return;
+	}
+
+	for _ in 0..iterations {
+		if glm::distance(&positions[number_of_joints - 1], target) <= tolerance {
+			break;
+		}
+		positions[number_of_joints - 1] = *target;
+		for index in (0..number_of_joints - 1).rev() {
+			let direction = glm::normalize(&(positions[index] - positions[index + 1]));
+			positions[index] = positions[index + 1] + direction * lengths[index];
+		}
+		positions[0] = root;
+		for index in 1..number_of_joints {
+			let direction = glm::normalize(&(positions[index] - positions[index - 1]));
+			positions[index] = positions[index - 1] + direction * lengths[index - 1];
+		}
+	}
+}
+
+/// The rotation turning a direction towards another, by at most an angle in radians
+pub fn limited_rotation_between(from: &glm::Vec3, to: &glm::Vec3, max_angle: f32) -> glm::Quat {
+	let rotation = rotation_between(from, to);
+	let angle = glm::quat_angle(&rotation);
+	if angle <= max_angle || angle <= f32::EPSILON {
+		return rotation;
+	}
+	glm::quat_slerp(&glm::Quat::identity(), &rotation, max_angle / angle)
+}
+
+/// The shortest rotation turning a direction towards another
+pub fn rotation_between(from: &glm::Vec3, to: &glm::Vec3) -> glm::Quat {
+	match UnitQuaternion::rotation_between(from, to) {
+		Some(rotation) => rotation.into_inner(),
+		// Opposite directions turn half way around any perpendicular axis
+		None => glm::quat_angle_axis(std::f32::consts::PI, &any_perpendicular(from)),
+	}
+}
+
+/// The unit part of a vector perpendicular to a direction
+fn perpendicular_towards(direction: &glm::Vec3, vector: &glm::Vec3) -> Option<glm::Vec3> {
+	(vector - direction * glm::dot(vector, direction)).try_normalize(1e-6)
+}
+
+fn any_perpendicular(direction: &glm::Vec3) -> glm::Vec3 {
+	perpendicular_towards(direction, &glm::Vec3::x())
+		.or_else(|| perpendicular_towards(direction, &glm::Vec3::y()))
+		.unwrap_or_else(glm::Vec3::z)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn two_bone_reaches_the_target_bending_towards_the_pole() {
+		let root = glm::vec3(0.0, 2.0, 0.0);
+		let middle = glm::vec3(0.0, 1.0, 0.0);
+		let end = glm::vec3(0.0, 0.0, 0.0);
+		let target = glm::vec3(0.0, 0.5, 0.5);
+		let pole = glm::vec3(0.0, 1.0, 5.0);
+
+		let (solved_middle, solved_end) = solve_two_bone(&root, &middle, &end, &target, &pole);
+		assert!(glm::distance(&solved_end, &target) < 1e-3);
+		assert!((glm::distance(&root, &solved_middle) - 1.0).abs() < 1e-4);
+		assert!((glm::distance(&solved_middle, &solved_end) - 1.0).abs() < 1e-4);
+		assert!(solved_middle.z > 0.5);
+	}
+
+	#[test]
+	fn two_bone_straightens_towards_unreachable_targets() {
+		let root = glm::vec3(0.0, 0.0, 0.0);
+		let middle = glm::vec3(1.0, 0.0, 0.0);
+		let end = glm::vec3(1.0, 1.0, 0.0);
+		let target = glm::vec3(10.0, 0.0, 0.0);
+
+		let (solved_middle, solved_end) =
+			solve_two_bone(&root, &middle, &end, &target, &glm::Vec3::y());
+		assert!(glm::distance(&solved_middle, &glm::vec3(1.0, 0.0, 0.0)) < 0.05);
+		assert!(glm::distance(&solved_end, &glm::vec3(2.0, 0.0, 0.0)) < 1e-3);
+	}
+
+	#[test]
+	fn fabrik_reaches_the_target_keeping_lengths() {
+		let mut positions = (0..4)
+			.map(|index| glm::vec3(0.0, index as f32, 0.0))
+			.collect::<Vec<_>>();
+		let target = glm::vec3(1.5, 1.5, 0.0);
+		solve_fabrik(&mut positions, &target, 32, 1e-4);
+
+		assert_eq!(positions[0], glm::Vec3::zeros());
+		assert!(glm::distance(&positions[3], &target) < 1e-3);
+		for pair in positions.windows(2) {
+			assert!((glm::distance(&pair[0], &pair[1]) - 1.0).abs() < 1e-4);
+		}
+	}
+
+	#[test]
+	fn look_at_rotation_is_limited() {
+		let limit = std::f32::consts::FRAC_PI_4;
+		let rotation = limited_rotation_between(&glm::Vec3::z(), &glm::Vec3::x(), limit);
+		assert!((glm::quat_angle(&rotation) - limit).abs() < 1e-4);
+
+		let rotation = limited_rotation_between(&glm::Vec3::z(), &glm::vec3(0.1, 0.0, 1.0), limit);
+		let turned = glm::quat_rotate_vec3(&rotation, &glm::Vec3::z());
+		assert!(glm::distance(&turned, &glm::normalize(&glm::vec3(0.1, 0.0, 1.0))) < 1e-4);
+	}
+}
@@ -1,16 +1,29 @@
 mod animation;
+mod animation_graph;
 mod camera;
+mod capture;
+mod decal;
+mod environment;
 mod gltf;
+mod ik;
+mod lod;
+mod material;
+mod particles;
 mod physics;
+mod prefab;
 mod registry;
 mod scenegraph;
+mod sprite;
+mod tangent;
+mod terrain;
 mod texture;
 mod transform;
 mod world;
 
 pub use self::{
-	animation::*, camera::*, gltf::*, physics::*, registry::*, scenegraph::*, texture::*,
-	transform::*, world::*,
+	animation::*, animation_graph::*, camera::*, capture::*, decal::*, environment::*, gltf::*,
+	ik::*, lod::*, material::*, particles::*, physics::*, prefab::*, registry::*, scenegraph::*,
+	sprite::*, tangent::*, terrain::*, texture::*, transform::*, world::*,
 };
 use serde::{Deserialize, Serialize};
 
@@ -19,8 +32,8 @@ pub use nalgebra_glm;
 pub use petgraph;
 pub use rapier3d;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Hidden;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Name(pub String);
@@ -0,0 +1,256 @@
+use crate::{BoundingBox, CameraView, Geometry, Mesh, Primitive, Vertex};
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A lower detail version of a mesh's primitives
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeshLod {
+	/// The fraction of the viewport height covered by the mesh's bounds
+	/// below which this level is drawn
+	pub screen_size: f32,
+	pub primitives: Vec<Primitive>,
+}
+
+/// Scales an entity's projected screen size before its level of detail is picked.
+/// Values above one keep higher detail further away.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LodBias(pub f32);
+
+impl Default for LodBias {
+	fn default() -> Self {
+		Self(1.0)
+	}
+}
+
+/// The fraction of a level's screen size the projected size must move past before
+/// switching levels, so meshes hovering at a threshold don't flicker between them
+pub const LOD_HYSTERESIS: f32 = 0.1;
+
+/// Clustering grid resolutions of the levels generated at import
+const GENERATED_LOD_RESOLUTIONS: [u32; 3] = [32, 16, 8];
+
+/// Meshes with fewer triangles than this are not worth simplifying
+const MIN_SIMPLIFIED_TRIANGLES: usize = 256;
+
+impl Mesh {
+	/// Returns the primitives drawn at a level of detail, where level zero is the full mesh
+	pub fn lod_primitives(&self, level: usize) -> &[Primitive] {
+		match level {
+			0 => &self.primitives,
+			level => self
+				.lods
+				.get(level - 1)
+				.or_else(|| self.lods.last())
+				.map_or(&self.primitives, |lod| &lod.primitives),
+		}
+	}
+
+	/// Picks the level of detail for a projected screen size, staying at the current level
+	/// until the size moves outside of the hysteresis band around a threshold
+	pub fn select_lod(&self, screen_size: f32, current_level: usize) -> usize {
+		let current_level = current_level.min(self.lods.len());
+		let target_level = self
+			.lods
+			.iter()
+			.take_while(|lod| screen_size < lod.screen_size)
+			.count();
+
+		if target_level > current_level {
+			let threshold = self.lods[current_level].screen_size;
+			if screen_size > threshold * (1.0 - LOD_HYSTERESIS) {
+				return current_level;
+			}
+		} else if target_level < current_level {
+			let threshold = self.lods[current_level - 1].screen_size;
+			if screen_size < threshold * (1.0 + LOD_HYSTERESIS) {
+				return current_level;
+			}
+		}
+
+		target_level
+	}
+
+	/// The fraction of a view's height covered by the mesh's bounding sphere
+	pub fn screen_size(&self, model: &glm::Mat4, view: &CameraView) -> f32 {
+		let bounding_box = self.bounding_box();
+		let center = model
+			* glm::vec4(
+				bounding_box.center().x,
+				bounding_box.center().y,
+				bounding_box.center().z,
+				1.0,
+			);
+		let scale = glm::vec3(
+			glm::length(&glm::column(model, 0).xyz()),
+			glm::length(&glm::column(model, 1).xyz()),
+			glm::length(&glm::column(model, 2).xyz()),
+		);
+		let radius = glm::length(&bounding_box.half_extents()) * glm::comp_max(&scale);
+
+		let focal_length = view.projection[(1, 1)];
+		let is_orthographic = view.projection[(3, 3)] == 1.0;
+		if is_orthographic {
+			return radius * focal_length;
+		}
+
+		let distance = -(view.view * center).z;
+		if distance <= radius {
+			return f32::MAX;
+		}
+		radius * focal_length / distance
+	}
+}
+
+/// The screen size below which a lower level of detail is drawn when none is specified,
+/// where level zero is the first level below full detail
+pub fn default_lod_screen_size(level: usize) -> f32 {
+	0.25 * 0.4_f32.powi(level as i32)
+}
+
+/// Adds simplified levels of detail to a mesh that doesn't have any,
+/// appending their indices to the geometry
+pub fn generate_lods(mesh: &mut Mesh, geometry: &mut Geometry) {
+	let number_of_triangles = mesh
+		.primitives
+		.iter()
+		.map(|primitive| primitive.number_of_indices / 3)
+		.sum::<usize>();
+	if !mesh.lods.is_empty() || number_of_triangles < MIN_SIMPLIFIED_TRIANGLES {
+		return;
+	}
+
+	let mut previous_number_of_indices = number_of_triangles * 3;
+	for (level, grid_resolution) in GENERATED_LOD_RESOLUTIONS.into_iter().enumerate() {
+		let primitives = mesh
+			.primitives
+			.iter()
+			.map(|primitive| {
+				let range =
+					primitive.first_index..(primitive.first_index + primitive.number_of_indices);
+				let indices = simplify_indices(
+					&geometry.vertices,
+					&geometry.indices[range],
+					&primitive.bounding_box,
+					grid_resolution,
+				);
+				let mut lod_primitive = primitive.clone();
+				lod_primitive.first_index = geometry.indices.len();
+				lod_primitive.number_of_indices = indices.len();
+				geometry.indices.extend_from_slice(&indices);
+				lod_primitive
+			})
+			.collect::<Vec<_>>();
+
+		let number_of_indices = primitives
+			.iter()
+			.map(|primitive| primitive.number_of_indices)
+			.sum::<usize>();
+
+		// Stop once simplifying no longer removes a meaningful number of triangles
+		if number_of_indices == 0 || number_of_indices * 10 > previous_number_of_indices * 9 {
+			geometry
+				.indices
+				.truncate(geometry.indices.len() - number_of_indices);
+			break;
+		}
+		previous_number_of_indices = number_of_indices;
+
+		mesh.lods.push(MeshLod {
+			screen_size: default_lod_screen_size(level),
+			primitives,
+		});
+	}
+}
+
+/// Simplifies triangles by clustering their vertices into a grid over the bounding box.
+/// Each cluster is represented by the first vertex found in it and collapsed triangles are removed.
+pub fn simplify_indices(
+	vertices: &[Vertex],
+	indices: &[u32],
+	bounding_box: &BoundingBox,
+	grid_resolution: u32,
+) -> Vec<u32> {
+	let cell_size =
+		(glm::comp_max(&bounding_box.extents()) / grid_resolution.max(1) as f32).max(f32::EPSILON);
+
+	let mut representatives = HashMap::new();
+	let mut cluster = |index: u32| -> u32 {
+		let position = vertices[index as usize].position;
+		let cell = (position - bounding_box.min) / cell_size;
+		let key = (
+			cell.x.floor() as i32,
+			cell.y.floor() as i32,
+			cell.z.floor() as i32,
+		);
+		*representatives.entry(key).or_insert(index)
+	};
+
+	let mut simplified = Vec::new();
+	for triangle in indices.chunks_exact(3) {
+		let a = cluster(triangle[0]);
+		let b = cluster(triangle[1]);
+		let c = cluster(triangle[2]);
+		if a != b && b != c && a != c {
+			simplified.extend_from_slice(&[a, b, c]);
+		}
+	}
+	simplified
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn mesh_with_thresholds(thresholds: &[f32]) -> Mesh {
+		Mesh {
+			lods: thresholds
+				.iter()
+				.map(|screen_size| MeshLod {
+					screen_size: *screen_size,
+					primitives: Vec::new(),
+				})
+				.collect(),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn select_lod_picks_level_from_screen_size() {
+		let mesh = mesh_with_thresholds(&[0.5, 0.1]);
+		assert_eq!(mesh.select_lod(0.9, 0), 0);
+		assert_eq!(mesh.select_lod(0.3, 0), 1);
+		assert_eq!(mesh.select_lod(0.01, 0), 2);
+		assert_eq!(mesh.select_lod(0.9, 2), 0);
+	}
+
+	#[test]
+	fn select_lod_stays_within_hysteresis_band() {
+		let mesh = mesh_with_thresholds(&[0.5, 0.1]);
+		// Just below the threshold isn't far enough to drop detail
+		assert_eq!(mesh.select_lod(0.48, 0), 0);
+		// Just above the threshold isn't far enough to add detail back
+		assert_eq!(mesh.select_lod(0.52, 1), 1);
+		assert_eq!(mesh.select_lod(0.6, 1), 0);
+	}
+
+	#[test]
+	fn simplify_indices_removes_collapsed_triangles() {
+		let vertex = |x: f32, y: f32| Vertex {
+			position: glm::vec3(x, y, 0.0),
+			..Default::default()
+		};
+		let vertices = vec![
+			vertex(0.0, 0.0),
+			vertex(1.0, 0.0),
+			vertex(0.0, 1.0),
+			vertex(0.01, 0.01),
+		];
+		let indices = [0, 1, 2, 3, 1, 2, 0, 3, 1];
+		let bounding_box = BoundingBox::new(glm::vec3(0.0, 0.0, 0.0), glm::vec3(1.0, 1.0, 0.0));
+
+		let simplified = simplify_indices(&vertices, &indices, &bounding_box, 4);
+
+		assert_eq!(simplified, vec![0, 1, 2, 0, 1, 2]);
+	}
+}
@@ -0,0 +1,171 @@
+use crate::AlphaMode;
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CustomMaterialError {
+	#[error("Custom material has no uniform named `{0}`!")]
+	UnknownUniform(String),
+
+	#[error("Custom material uniform `{0}` has a different type!")]
+	UniformTypeMismatch(String),
+}
+
+type Result<T, E = CustomMaterialError> = std::result::Result<T, E>;
+
+/// A game-defined shader drawn in place of a primitive's glTF material.
+///
+/// The shader is appended to a prelude declaring the view uniform at group 0,
+/// the draw data at group 1, the environment at group 3, `VertexInput`, `apply_fog`
+/// and `model_matrix(vert, draw_index)`, which applies skinning for skinned meshes.
+/// It provides `vertex_main` and `fragment_main`
+/// and declares its uniform as `@group(2) @binding(0) var<uniform> material: ...;`,
+/// a struct with the material's uniform fields in order.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomMaterial {
+	pub name: String,
+	pub shader: String,
+
+	/// The uniform's fields in the order they are declared in the shader
+	pub uniforms: Vec<(String, UniformValue)>,
+
+	/// Blended materials are drawn after opaque ones without writing depth
+	pub alpha_mode: AlphaMode,
+
+	pub double_sided: bool,
+}
+
+impl CustomMaterial {
+	pub fn new(name: &str, shader: &str) -> Self {
+		Self {
+			name: name.to_string(),
+			shader: shader.to_string(),
+			uniforms: Vec::new(),
+			alpha_mode: AlphaMode::Opaque,
+			double_sided: false,
+		}
+	}
+
+	pub fn uniform(&self, name: &str) -> Option<&UniformValue> {
+		self.uniforms
+			.iter()
+			.find(|(uniform_name, _)| uniform_name == name)
+			.map(|(_, value)| value)
+	}
+
+	/// Changes the value of an existing uniform field, which keeps its type
+	pub fn set_uniform(&mut self, name: &str, value: UniformValue) -> Result<()> {
+		let uniform = self
+			.uniforms
+			.iter_mut()
+			.find(|(uniform_name, _)| uniform_name == name)
+			.map(|(_, uniform)| uniform)
+			.ok_or_else(|| CustomMaterialError::UnknownUniform(name.to_string()))?;
+		if std::mem::discriminant(uniform) != std::mem::discriminant(&value) {
+			return Err(CustomMaterialError::UniformTypeMismatch(name.to_string()));
+		}
+		*uniform = value;
+		Ok(())
+	}
+
+	/// The uniform's fields packed with WGSL's uniform buffer layout rules
+	pub fn uniform_bytes(&self) -> Vec<u8> {
+		let mut bytes = Vec::new();
+		for (_, value) in self.uniforms.iter() {
+			bytes.resize(align_to(bytes.len(), value.alignment()), 0);
+			bytes.extend_from_slice(bytemuck::cast_slice(value.as_slice()));
+		}
+		bytes.resize(align_to(bytes.len(), 16), 0);
+		bytes
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum UniformValue {
+	Float(f32),
+	Vec2(glm::Vec2),
+	Vec3(glm::Vec3),
+	Vec4(glm::Vec4),
+	Mat4(glm::Mat4),
+}
+
+impl UniformValue {
+	fn alignment(&self) -> usize {
+		match self {
+			Self::Float(_) => 4,
+			Self::Vec2(_) => 8,
+			Self::Vec3(_) | Self::Vec4(_) | Self::Mat4(_) => 16,
+		}
+	}
+
+	fn as_slice(&self) -> &[f32] {
+		match self {
+			Self::Float(value) => std::slice::from_ref(value),
+			Self::Vec2(value) => value.as_slice(),
+			Self::Vec3(value) => value.as_slice(),
+			Self::Vec4(value) => value.as_slice(),
+			Self::Mat4(value) => value.as_slice(),
+		}
+	}
+}
+
+/// Draws every primitive of the entity's mesh with a custom material, by name,
+/// overriding the primitives' own materials
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomMaterialRender {
+	pub name: String,
+}
+
+fn align_to(offset: usize, alignment: usize) -> usize {
+	(offset + alignment - 1) / alignment * alignment
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn uniform_fields_follow_wgsl_alignment() {
+		let mut material = CustomMaterial::new("Water", "");
+		material.uniforms = vec![
+			("time".to_string(), UniformValue::Float(1.0)),
+			(
+				"color".to_string(),
+				UniformValue::Vec3(glm::vec3(2.0, 3.0, 4.0)),
+			),
+			("speed".to_string(), UniformValue::Float(5.0)),
+			(
+				"direction".to_string(),
+				UniformValue::Vec2(glm::vec2(6.0, 7.0)),
+			),
+		];
+		let floats = material
+			.uniform_bytes()
+			.chunks(4)
+			.map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
+			.collect::<Vec<_>>();
+		assert_eq!(
+			floats,
+			vec![1.0, 0.0, 0.0, 0.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 0.0, 0.0]
+		);
+	}
+
+	#[test]
+	fn set_uniform_keeps_the_field_type() {
+		let mut material = CustomMaterial::new("Hologram", "");
+		material.uniforms = vec![("time".to_string(), UniformValue::Float(0.0))];
+		assert!(material
+			.set_uniform("time", UniformValue::Float(2.0))
+			.is_ok());
+		assert_eq!(material.uniform("time"), Some(&UniformValue::Float(2.0)));
+		assert!(matches!(
+			material.set_uniform("time", UniformValue::Vec2(glm::vec2(1.0, 1.0))),
+			Err(CustomMaterialError::UniformTypeMismatch(_))
+		));
+		assert!(matches!(
+			material.set_uniform("speed", UniformValue::Float(1.0)),
+			Err(CustomMaterialError::UnknownUniform(_))
+		));
+	}
+}
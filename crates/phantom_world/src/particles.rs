@@ -0,0 +1,67 @@
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+
+/// Emits camera-facing particles from an entity's position,
+/// simulated and rendered on the GPU
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParticleEmitter {
+	pub enabled: bool,
+
+	/// The most particles alive at once. The oldest particles are recycled past this limit.
+	pub max_particles: u32,
+
+	/// Particles spawned per second
+	pub spawn_rate: f32,
+
+	/// Seconds each particle lives for
+	pub lifetime: f32,
+
+	/// Initial speed of each particle
+	pub speed: f32,
+
+	/// Half angle in radians of the cone particles are launched in,
+	/// centered on the emitter's local up axis
+	pub cone_angle: f32,
+
+	/// Constant acceleration applied to each particle in world space
+	pub gravity: glm::Vec3,
+
+	pub start_color: glm::Vec4,
+	pub end_color: glm::Vec4,
+	pub start_size: f32,
+	pub end_size: f32,
+
+	/// World distance over which particles fade out as they approach opaque geometry
+	pub soft_fade_distance: f32,
+
+	/// Index into the world's textures. A soft round sprite is used when unset.
+	pub texture: Option<usize>,
+}
+
+impl Default for ParticleEmitter {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			max_particles: 1000,
+			spawn_rate: 50.0,
+			lifetime: 2.0,
+			speed: 2.0,
+			cone_angle: 25_f32.to_radians(),
+			gravity: glm::vec3(0.0, -9.81, 0.0),
+			start_color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+			end_color: glm::vec4(1.0, 1.0, 1.0, 0.0),
+			start_size: 0.1,
+			end_size: 0.05,
+			soft_fade_distance: 0.5,
+			texture: None,
+		}
+	}
+}
+
+impl ParticleEmitter {
+	/// The number of particles that must exist at once to sustain the spawn rate
+	pub fn particle_capacity(&self) -> u32 {
+		let sustained = (self.spawn_rate * self.lifetime).ceil() as u32;
+		sustained.clamp(1, self.max_particles.max(1))
+	}
+}
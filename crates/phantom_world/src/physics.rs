@@ -2,7 +2,7 @@ use rapier3d::{
 	self,
 	dynamics::{CCDSolver, IntegrationParameters, RigidBodySet},
 	geometry::{BroadPhase, ColliderSet, NarrowPhase},
-	na::Vector3,
+	na::{Quaternion, UnitQuaternion, Vector3},
 	pipeline::{PhysicsPipeline, QueryPipeline},
 	prelude::{ImpulseJointSet, IslandManager, MultibodyJointSet, RigidBodyHandle},
 };
@@ -79,6 +79,26 @@ impl WorldPhysics {
 		);
 	}
 
+	/// Moves a kinematic body by a translation and a rotation in its own frame over the next step,
+	/// returning false when the body is missing or not kinematic
+	pub fn move_kinematic_body(
+		&mut self,
+		handle: RigidBodyHandle,
+		translation: &Vector3<f32>,
+		rotation: &Quaternion<f32>,
+	) -> bool {
+		let body = match self.bodies.get_mut(handle) {
+			Some(body) if body.is_kinematic() => body,
+			_ => return false,
+		};
+		let mut position = *body.next_position();
+		position.translation.vector += translation;
+		position.rotation =
+			UnitQuaternion::new_normalize(position.rotation.quaternion() * rotation);
+		body.set_next_kinematic_position(position);
+		true
+	}
+
 	pub fn set_gravity(&mut self, gravity: Vector3<f32>) {
 		self.gravity = gravity;
 	}
@@ -0,0 +1,148 @@
+use crate::{deserialize_ecs, serialize_ecs, Animation, Ecs, EntitySceneGraph};
+use serde::{Deserialize, Serialize};
+
+/// An imported asset's nodes, kept apart from the world so they can be instantiated
+/// many times. Every instance shares the geometry, materials and textures
+/// the asset imported into the world, but gets its own entities and animation clips.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Prefab {
+	pub name: String,
+
+	/// Template entities, cloned into the world for each instance
+	#[serde(serialize_with = "serialize_ecs", deserialize_with = "deserialize_ecs")]
+	pub ecs: Ecs,
+
+	/// The template's hierarchies, one per root node
+	pub graphs: Vec<EntitySceneGraph>,
+
+	/// Clips targeting the template's entities
+	pub animations: Vec<Animation>,
+
+	/// How many times the prefab has been instantiated, numbering each instance's clips
+	pub instances: usize,
+}
+
+/// Marks the root entities of a prefab instance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrefabInstance {
+	pub prefab: String,
+
+	/// Prefixes the instance's clip names, as in `Tree#2/Sway`
+	pub name: String,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		test_clip, world_as_bytes, world_from_bytes, AnimationPlayer, Entity, Joint, Name, Skin,
+		Transform, World,
+	};
+	use legion::EntityStore;
+	use nalgebra_glm as glm;
+
+	fn tree() -> Prefab {
+		let mut ecs = Ecs::default();
+		let branch = ecs.push((Name("Branch".to_string()), Transform::default()));
+		let skin = Skin {
+			name: "Bark".to_string(),
+			joints: vec![Joint {
+				target: branch,
+				inverse_bind_matrix: glm::Mat4::identity(),
+			}],
+		};
+		let trunk = ecs.push((
+			Name("Trunk".to_string()),
+			Transform::default(),
+			skin,
+			AnimationPlayer::new(vec!["Sway".to_string()]),
+		));
+
+		let mut graph = EntitySceneGraph::new();
+		let root_index = graph.add_root_node(trunk);
+		graph.add_child(root_index, branch);
+
+		Prefab {
+			name: "Tree".to_string(),
+			ecs,
+			graphs: vec![graph],
+			animations: vec![test_clip("Sway", &[branch], 1.0)],
+			instances: 0,
+		}
+	}
+
+	fn skin_target(world: &World, entity: Entity) -> Entity {
+		let entry = world.ecs.entry_ref(entity).unwrap();
+		entry.get_component::<Skin>().unwrap().joints[0].target
+	}
+
+	#[test]
+	fn instances_get_their_own_entities_and_clips() {
+		let mut world = World::default();
+		world.prefabs.push(tree());
+		let placement = Transform {
+			translation: glm::vec3(5.0, 0.0, 0.0),
+			..Default::default()
+		};
+		let first = world.instantiate_prefab("Tree", &placement).unwrap()[0];
+		let second = world
+			.instantiate_prefab("Tree", &Transform::default())
+			.unwrap()[0];
+		assert_ne!(first, second);
+		assert_eq!(world.scene.graphs.len(), 2);
+
+		let first_branch = world.scene.graphs[0][petgraph::graph::NodeIndex::new(1)];
+		assert_eq!(skin_target(&world, first), first_branch);
+		assert_ne!(skin_target(&world, second), first_branch);
+
+		let names = world
+			.animations
+			.iter()
+			.map(|animation| animation.name.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(names, ["Tree#1/Sway", "Tree#2/Sway"]);
+		assert_eq!(world.animations[0].channels[0].target, first_branch);
+
+		let mut entry = world.ecs.entry(first).unwrap();
+		let transform = entry.get_component::<Transform>().unwrap();
+		assert_eq!(transform.translation, glm::vec3(5.0, 0.0, 0.0));
+		let player = entry.get_component_mut::<AnimationPlayer>().unwrap();
+		player.play("Sway").unwrap();
+		assert_eq!(player.clip.as_deref(), Some("Tree#1/Sway"));
+		assert!(world
+			.instantiate_prefab("Rock", &Transform::default())
+			.is_err());
+	}
+
+	#[test]
+	fn prefabs_survive_saving() {
+		let mut world = World::default();
+		world.prefabs.push(tree());
+		let root = world
+			.instantiate_prefab("Tree", &Transform::default())
+			.unwrap()[0];
+		let instance = world
+			.ecs
+			.entry_ref(root)
+			.unwrap()
+			.get_component::<PrefabInstance>()
+			.unwrap()
+			.clone();
+		assert_eq!(instance.name, "Tree#1");
+
+		let mut loaded = world_from_bytes(&world_as_bytes(&world).unwrap()).unwrap();
+		assert_eq!(loaded.prefabs[0].instances, 1);
+		assert_eq!(loaded.scene.graphs.len(), 1);
+		let second = loaded
+			.instantiate_prefab("Tree", &Transform::default())
+			.unwrap()[0];
+		let instance = loaded
+			.ecs
+			.entry_ref(second)
+			.unwrap()
+			.get_component::<PrefabInstance>()
+			.unwrap()
+			.clone();
+		assert_eq!(instance.name, "Tree#2");
+	}
+}
@@ -1,9 +1,14 @@
-use crate::{Camera, Ecs, Light, MeshRender, Name, RigidBody, Skin, Transform, World};
+use crate::{
+	AnimationGraph, AnimationPlayer, Camera, CustomMaterialRender, Decal, Ecs, Hidden,
+	InverseKinematics, Light, LodBias, MeshRender, Name, ParticleEmitter, PrefabInstance,
+	RigidBody, Skin, Sprite, Terrain, Transform, World,
+};
 use lazy_static::lazy_static;
 use legion::{
 	self,
 	serialize::{set_entity_serializer, Canon},
 	storage::Component,
+	world::Duplicate,
 	Registry,
 };
 use serde::{de::DeserializeSeed, Deserialize, Deserializer, Serialize, Serializer};
@@ -25,38 +30,87 @@ pub enum RegistryError {
 type Result<T, E = RegistryError> = std::result::Result<T, E>;
 
 lazy_static! {
-	pub static ref COMPONENT_REGISTRY: Arc<RwLock<Registry<String>>> = {
-		let mut registry = Registry::default();
-		registry.register::<Name>("name".to_string());
-		registry.register::<Transform>("transform".to_string());
-		registry.register::<Camera>("camera".to_string());
-		registry.register::<MeshRender>("mesh".to_string());
-		registry.register::<Skin>("skin".to_string());
-		registry.register::<Light>("light".to_string());
-		registry.register::<RigidBody>("rigid_body".to_string());
-		Arc::new(RwLock::new(registry))
+	pub static ref COMPONENT_REGISTRY: Arc<RwLock<ComponentRegistry>> = {
+		let mut components = ComponentRegistry::default();
+		components.register::<Name>("name");
+		components.register::<Transform>("transform");
+		components.register::<Camera>("camera");
+		components.register::<MeshRender>("mesh");
+		components.register::<Skin>("skin");
+		components.register::<Light>("light");
+		// Saved but never cloned, since each copy needs its own physics handles
+		components
+			.registry
+			.register::<RigidBody>("rigid_body".to_string());
+		components.register::<Hidden>("hidden");
+		components.register::<ParticleEmitter>("particle_emitter");
+		components.register::<Decal>("decal");
+		components.register::<LodBias>("lod_bias");
+		components.register::<Terrain>("terrain");
+		components.register::<Sprite>("sprite");
+		components.register::<CustomMaterialRender>("custom_material");
+		components.register::<AnimationPlayer>("animation_player");
+		components.register::<AnimationGraph>("animation_graph");
+		components.register::<InverseKinematics>("inverse_kinematics");
+		components.register::<PrefabInstance>("prefab_instance");
+		Arc::new(RwLock::new(components))
 	};
 	pub static ref ENTITY_SERIALIZER: Canon = Canon::default();
 }
 
-pub fn register_component<T: Component + Serialize + for<'de> Deserialize<'de>>(
+/// The components the world saves, and how copying an entity clones them
+#[derive(Default)]
+pub struct ComponentRegistry {
+	pub registry: Registry<String>,
+	duplicators: Vec<fn(&mut Duplicate)>,
+}
+
+impl ComponentRegistry {
+	pub fn register<T: Component + Clone + Serialize + for<'de> Deserialize<'de>>(
+		&mut self,
+		key: &str,
+	) {
+		self.registry.register::<T>(key.to_string());
+		self.duplicators
+			.push(|merger: &mut Duplicate| merger.register_clone::<T>());
+	}
+
+	/// A merger cloning every registered component
+	pub fn duplicator(&self) -> Duplicate {
+		let mut merger = Duplicate::default();
+		self.duplicators
+			.iter()
+			.for_each(|register| register(&mut merger));
+		merger
+	}
+}
+
+pub fn register_component<T: Component + Clone + Serialize + for<'de> Deserialize<'de>>(
 	key: &str,
 ) -> Result<()> {
-	let mut registry = COMPONENT_REGISTRY
+	let mut components = COMPONENT_REGISTRY
 		.write()
 		.map_err(|_| RegistryError::AccessComponentRegistry)?;
-	registry.register::<T>(key.to_string());
+	components.register::<T>(key);
 	Ok(())
 }
 
+/// A merger cloning every registered component, for copying entities
+pub fn component_duplicator() -> Result<Duplicate> {
+	let components = COMPONENT_REGISTRY
+		.read()
+		.map_err(|_| RegistryError::AccessComponentRegistry)?;
+	Ok(components.duplicator())
+}
+
 pub fn serialize_ecs<S>(ecs: &Ecs, serializer: S) -> Result<S::Ok, S::Error>
 where
 	S: Serializer,
 {
-	let registry = (*COMPONENT_REGISTRY)
+	let components = (*COMPONENT_REGISTRY)
 		.read()
 		.expect("Failed to get the component registry lock!");
-	ecs.as_serializable(legion::any(), &*registry, &*ENTITY_SERIALIZER)
+	ecs.as_serializable(legion::any(), &components.registry, &*ENTITY_SERIALIZER)
 		.serialize(serializer)
 }
 
@@ -67,6 +121,7 @@ where
 	(*COMPONENT_REGISTRY)
 		.read()
 		.expect("Failed to get the component registry lock!")
+		.registry
 		.as_deserialize(&*ENTITY_SERIALIZER)
 		.deserialize(deserializer)
 }
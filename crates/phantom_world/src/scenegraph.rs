@@ -3,6 +3,7 @@ use petgraph::{graph::WalkNeighbors, prelude::*};
 use serde::{Deserialize, Serialize};
 use std::{
 	cmp::PartialEq,
+	collections::HashMap,
 	fmt::Debug,
 	ops::{Index, IndexMut},
 };
@@ -76,6 +77,72 @@ where
 		let _edge_index = self.0.add_edge(parent_node, node, ());
 	}
 
+	/// Removes the edge from a node's parent, making the node a root
+	pub fn detach(&mut self, node_index: NodeIndex) {
+		if let Some(edge_index) = self.0.first_edge(node_index, Incoming) {
+			let _ = self.0.remove_edge(edge_index);
+		}
+	}
+
+	/// Copies a node and every node below it into a new graph, rooted at its first node
+	pub fn extract_subtree(&self, node_index: NodeIndex) -> Self {
+		let mut subtree = Self::new();
+		let mut subtree_indices = HashMap::new();
+		for index in self.subtree(node_index) {
+			let subtree_index = subtree.add_root_node(self[index]);
+			subtree_indices.insert(index, subtree_index);
+			// Nodes are visited after their parents, and the subtree's root has none copied
+			let parent_index = self
+				.get_parent_of(index)
+				.and_then(|parent_index| subtree_indices.get(&parent_index));
+			if let Some(parent_index) = parent_index {
+				subtree.add_edge(*parent_index, subtree_index);
+			}
+		}
+		subtree
+	}
+
+	/// Removes a node and every node below it, returning them as a new graph.
+	/// Removing nodes moves the last node into each freed index.
+	pub fn remove_subtree(&mut self, node_index: NodeIndex) -> Self {
+		let subtree = self.extract_subtree(node_index);
+		let mut indices = self.subtree(node_index);
+		// Removing the highest indices first keeps the remaining ones valid
+		indices.sort_unstable_by(|first, second| second.cmp(first));
+		for index in indices {
+			let _ = self.0.remove_node(index);
+		}
+		subtree
+	}
+
+	/// Adds another graph's nodes, placing its roots below a parent or as roots when none,
+	/// and returns the new index of each of the other graph's nodes
+	pub fn attach_graph(
+		&mut self,
+		parent_index: Option<NodeIndex>,
+		graph: &Self,
+	) -> Vec<NodeIndex> {
+		let indices = graph
+			.0
+			.node_indices()
+			.map(|index| self.add_root_node(graph[index]))
+			.collect::<Vec<_>>();
+		for edge in graph.0.raw_edges() {
+			self.add_edge(
+				indices[edge.source().index()],
+				indices[edge.target().index()],
+			);
+		}
+		if let Some(parent_index) = parent_index {
+			graph
+				.0
+				.node_indices()
+				.filter(|index| !graph.has_parents(*index))
+				.for_each(|index| self.add_edge(parent_index, indices[index.index()]));
+		}
+		indices
+	}
+
 	pub fn root_node_indices(&self) -> Result<Vec<NodeIndex>> {
 		Ok(self
 			.0
@@ -125,6 +192,16 @@ where
 		Ok(())
 	}
 
+	/// Returns the node followed by every node below it
+	pub fn subtree(&self, index: NodeIndex) -> Vec<NodeIndex> {
+		let mut nodes = Vec::new();
+		let mut dfs = Dfs::new(&self.0, index);
+		while let Some(node_index) = dfs.next(&self.0) {
+			nodes.push(node_index);
+		}
+		nodes
+	}
+
 	pub fn has_neighbors(&self, index: NodeIndex) -> bool {
 		self.has_parents(index) || self.has_children(index)
 	}
@@ -251,6 +328,55 @@ mod tests {
 		Ok(())
 	}
 
+	#[test]
+	fn remove_subtree() -> Result<()> {
+		let (mut scenegraph, first_node_index, second_node_index) = create_scenegraph();
+		let _ = scenegraph.add_child(second_node_index, 34);
+		let other_node_index = scenegraph.add_child(first_node_index, 18);
+		let _ = scenegraph.add_child(other_node_index, 21);
+
+		let subtree = scenegraph.remove_subtree(second_node_index);
+		assert_eq!(subtree[NodeIndex::new(0)], SECOND_VALUE);
+		assert_eq!(
+			subtree.get_parent_of(NodeIndex::new(1)),
+			Some(NodeIndex::new(0))
+		);
+		assert_eq!(subtree.number_of_nodes(), 2);
+
+		assert_eq!(scenegraph.number_of_nodes(), 3);
+		assert_eq!(scenegraph.find_node(34), None);
+		let other_node_index = scenegraph.find_node(18).unwrap();
+		let last_node_index = scenegraph.find_node(21).unwrap();
+		assert_eq!(
+			scenegraph.get_parent_of(last_node_index),
+			Some(other_node_index)
+		);
+		assert_eq!(scenegraph.root_node_indices()?, vec![first_node_index]);
+
+		scenegraph.attach_graph(Some(last_node_index), &subtree);
+		let moved_node_index = scenegraph.find_node(SECOND_VALUE).unwrap();
+		assert_eq!(
+			scenegraph.get_parent_of(moved_node_index),
+			Some(last_node_index)
+		);
+
+		Ok(())
+	}
+
+	#[test]
+	fn detach() -> Result<()> {
+		let (mut scenegraph, first_node_index, second_node_index) = create_scenegraph();
+
+		scenegraph.detach(second_node_index);
+		assert_eq!(scenegraph.get_parent_of(second_node_index), None);
+		assert_eq!(
+			scenegraph.root_node_indices()?,
+			vec![first_node_index, second_node_index]
+		);
+
+		Ok(())
+	}
+
 	#[test]
 	fn collect_nodes() -> Result<()> {
 		let (scenegraph, _first_node_index, _second_node_index) = create_scenegraph();
@@ -0,0 +1,120 @@
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+
+/// A textured quad that turns to face the camera
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sprite {
+	pub enabled: bool,
+
+	/// Index into the world's textures
+	pub texture: Option<usize>,
+
+	/// Multiplied with the texture
+	pub color: glm::Vec4,
+
+	/// World space width and height, scaled by the entity's transform
+	pub size: glm::Vec2,
+
+	/// The point of the quad placed at the entity's origin,
+	/// from (0, 0) at the bottom left to (1, 1) at the top right
+	pub pivot: glm::Vec2,
+
+	pub billboard: BillboardMode,
+
+	/// Pixels with an alpha below this are discarded
+	pub alpha_cutoff: f32,
+
+	pub animation: Option<SpriteAnimation>,
+}
+
+impl Default for Sprite {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			texture: None,
+			color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+			size: glm::vec2(1.0, 1.0),
+			pivot: glm::vec2(0.5, 0.5),
+			billboard: BillboardMode::default(),
+			alpha_cutoff: 0.5,
+			animation: None,
+		}
+	}
+}
+
+impl Sprite {
+	/// The offset and scale of the sprite's current frame within its texture
+	pub fn uv_rect(&self, time: f32) -> glm::Vec4 {
+		match self.animation.as_ref() {
+			Some(animation) => animation.frame_uv_rect(animation.frame(time)),
+			None => glm::vec4(0.0, 0.0, 1.0, 1.0),
+		}
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BillboardMode {
+	/// Faces the camera from every direction
+	Spherical,
+
+	/// Only turns around the entity's Y axis, for sprites standing upright such as foliage
+	Cylindrical,
+}
+
+impl Default for BillboardMode {
+	fn default() -> Self {
+		Self::Spherical
+	}
+}
+
+/// Plays frames from a sprite sheet laid out in a grid, read left to right and top to bottom
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpriteAnimation {
+	pub columns: u32,
+	pub rows: u32,
+	pub first_frame: u32,
+	pub frame_count: u32,
+	pub frames_per_second: f32,
+	pub looping: bool,
+}
+
+impl Default for SpriteAnimation {
+	fn default() -> Self {
+		Self {
+			columns: 1,
+			rows: 1,
+			first_frame: 0,
+			frame_count: 1,
+			frames_per_second: 12.0,
+			looping: true,
+		}
+	}
+}
+
+impl SpriteAnimation {
+	/// The sheet frame shown at a time in seconds since the animation started
+	pub fn frame(&self, time: f32) -> u32 {
+		let frame_count = self.frame_count.max(1);
+		let elapsed_frames = (time.max(0.0) * self.frames_per_second) as u32;
+		let frame = if self.looping {
+			elapsed_frames % frame_count
+		} else {
+			elapsed_frames.min(frame_count - 1)
+		};
+		self.first_frame + frame
+	}
+
+	/// The offset and scale of a sheet frame within the texture
+	pub fn frame_uv_rect(&self, frame: u32) -> glm::Vec4 {
+		let columns = self.columns.max(1);
+		let rows = self.rows.max(1);
+		let frame = frame % (columns * rows);
+		let scale = glm::vec2(1.0 / columns as f32, 1.0 / rows as f32);
+		glm::vec4(
+			(frame % columns) as f32 * scale.x,
+			(frame / columns) as f32 * scale.y,
+			scale.x,
+			scale.y,
+		)
+	}
+}
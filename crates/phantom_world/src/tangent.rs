@@ -0,0 +1,89 @@
+use crate::Vertex;
+use nalgebra_glm as glm;
+
+/// Generates MikkTSpace tangents for a primitive's vertices,
+/// given indices relative to its first vertex and the texture coordinate set normal maps use.
+/// Returns false when tangents could not be generated, leaving the vertices unchanged.
+pub fn generate_tangents(vertices: &mut [Vertex], indices: &[u32], uv_set: usize) -> bool {
+	if indices.len() < 3 || vertices.is_empty() {
+		return false;
+	}
+	let mut geometry = TangentGeometry {
+		vertices,
+		indices,
+		uv_set,
+	};
+	mikktspace::generate_tangents(&mut geometry)
+}
+
+struct TangentGeometry<'a> {
+	vertices: &'a mut [Vertex],
+	indices: &'a [u32],
+	uv_set: usize,
+}
+
+impl<'a> TangentGeometry<'a> {
+	fn vertex(&self, face: usize, vert: usize) -> &Vertex {
+		&self.vertices[self.indices[face * 3 + vert] as usize]
+	}
+}
+
+impl<'a> mikktspace::Geometry for TangentGeometry<'a> {
+	fn num_faces(&self) -> usize {
+		self.indices.len() / 3
+	}
+
+	fn num_vertices_of_face(&self, _face: usize) -> usize {
+		3
+	}
+
+	fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+		self.vertex(face, vert).position.into()
+	}
+
+	fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+		self.vertex(face, vert).normal.into()
+	}
+
+	fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+		let vertex = self.vertex(face, vert);
+		match self.uv_set {
+			1 => vertex.uv_1.into(),
+			_ => vertex.uv_0.into(),
+		}
+	}
+
+	fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+		let index = self.indices[face * 3 + vert] as usize;
+		self.vertices[index].tangent = glm::Vec4::from(tangent);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn tangents_follow_the_u_direction() {
+		let vertex = |x: f32, y: f32| Vertex {
+			position: glm::vec3(x, y, 0.0),
+			normal: glm::vec3(0.0, 0.0, 1.0),
+			uv_0: glm::vec2(x, y),
+			..Default::default()
+		};
+		let mut vertices = vec![
+			vertex(0.0, 0.0),
+			vertex(1.0, 0.0),
+			vertex(1.0, 1.0),
+			vertex(0.0, 1.0),
+		];
+		let indices = [0, 1, 2, 0, 2, 3];
+
+		assert!(generate_tangents(&mut vertices, &indices, 0));
+
+		for vertex in vertices.iter() {
+			assert!(glm::distance(&vertex.tangent.xyz(), &glm::vec3(1.0, 0.0, 0.0)) < 1e-4);
+			assert_eq!(vertex.tangent.w.abs(), 1.0);
+		}
+	}
+}
@@ -0,0 +1,421 @@
+use crate::{BoundingBox, Texture, TextureFormat};
+use nalgebra_glm as glm;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TerrainError {
+	#[error("Heightmap format {0:?} is not supported!")]
+	UnsupportedHeightmapFormat(TextureFormat),
+
+	#[error("Heightmap must be at least 2x2 pixels!")]
+	HeightmapTooSmall,
+
+	#[error("Terrain chunk size must be a power of two!")]
+	InvalidChunkSize,
+}
+
+type Result<T, E = TerrainError> = std::result::Result<T, E>;
+
+/// The number of material layers blended by a terrain's splat map, one per channel
+pub const TERRAIN_LAYER_COUNT: usize = 4;
+
+/// A heightfield centered on the entity's origin, spanning its size along the X and Z axes.
+/// The terrain is drawn in square chunks whose detail drops with distance from the camera.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Terrain {
+	/// Heights in the range zero to one, stored in rows along the Z axis
+	pub heights: Vec<f32>,
+	pub columns: usize,
+	pub rows: usize,
+
+	/// The extent of the terrain along the X and Z axes
+	pub size: glm::Vec2,
+
+	/// The height of a heightmap value of one
+	pub height_scale: f32,
+
+	/// The number of quads along each side of a chunk at full detail
+	pub chunk_size: u32,
+
+	/// The distance from the camera at which chunks drop to half detail.
+	/// Each doubling of the distance halves the detail again.
+	pub lod_distance: f32,
+
+	/// Index into the world's textures, whose red, green, blue and alpha channels
+	/// weigh the four layers. Without a splat map only the first layer is drawn.
+	pub splat_map: Option<usize>,
+
+	pub layers: [TerrainLayer; TERRAIN_LAYER_COUNT],
+}
+
+impl Default for Terrain {
+	fn default() -> Self {
+		Self {
+			heights: vec![0.0; 4],
+			columns: 2,
+			rows: 2,
+			size: glm::vec2(100.0, 100.0),
+			height_scale: 10.0,
+			chunk_size: 32,
+			lod_distance: 50.0,
+			splat_map: None,
+			layers: Default::default(),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TerrainLayer {
+	/// Index into the world's textures
+	pub texture: Option<usize>,
+
+	/// Multiplied with the layer's texture
+	pub color: glm::Vec4,
+
+	/// The number of times the texture repeats per world unit
+	pub uv_scale: f32,
+}
+
+impl Default for TerrainLayer {
+	fn default() -> Self {
+		Self {
+			texture: None,
+			color: glm::vec4(1.0, 1.0, 1.0, 1.0),
+			uv_scale: 0.25,
+		}
+	}
+}
+
+/// The chunk edges in the order used by terrain neighbour masks
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TerrainEdge {
+	NegativeX,
+	PositiveX,
+	NegativeZ,
+	PositiveZ,
+}
+
+impl TerrainEdge {
+	pub const ALL: [TerrainEdge; 4] = [
+		Self::NegativeX,
+		Self::PositiveX,
+		Self::NegativeZ,
+		Self::PositiveZ,
+	];
+
+	pub fn mask(&self) -> u32 {
+		1 << (*self as u32)
+	}
+}
+
+impl Terrain {
+	/// Builds a terrain from the first channel of a heightmap texture
+	pub fn from_heightmap(heightmap: &Texture, size: glm::Vec2, height_scale: f32) -> Result<Self> {
+		if heightmap.width < 2 || heightmap.height < 2 {
+			return Err(TerrainError::HeightmapTooSmall);
+		}
+
+		let bytes_per_pixel = heightmap.bytes_per_pixel() as usize;
+		let sample: fn(&[u8]) -> f32 = match heightmap.format {
+			TextureFormat::R8
+			| TextureFormat::R8G8
+			| TextureFormat::R8G8B8
+			| TextureFormat::R8G8B8A8 => |pixel| pixel[0] as f32 / u8::MAX as f32,
+			TextureFormat::B8G8R8 | TextureFormat::B8G8R8A8 => {
+				|pixel| pixel[2] as f32 / u8::MAX as f32
+			}
+			TextureFormat::R16
+			| TextureFormat::R16G16
+			| TextureFormat::R16G16B16
+			| TextureFormat::R16G16B16A16 => {
+				|pixel| u16::from_ne_bytes([pixel[0], pixel[1]]) as f32 / u16::MAX as f32
+			}
+			TextureFormat::R32F
+			| TextureFormat::R32G32F
+			| TextureFormat::R32G32B32F
+			| TextureFormat::R32G32B32A32F => {
+				|pixel| f32::from_ne_bytes([pixel[0], pixel[1], pixel[2], pixel[3]])
+			}
+			format => return Err(TerrainError::UnsupportedHeightmapFormat(format)),
+		};
+
+		let heights = heightmap
+			.pixels
+			.chunks_exact(bytes_per_pixel)
+			.take((heightmap.width * heightmap.height) as usize)
+			.map(sample)
+			.collect();
+
+		Ok(Self {
+			heights,
+			columns: heightmap.width as usize,
+			rows: heightmap.height as usize,
+			size,
+			height_scale,
+			..Default::default()
+		})
+	}
+
+	/// The local position of a heightmap sample
+	pub fn position(&self, column: usize, row: usize) -> glm::Vec3 {
+		let column = column.min(self.columns - 1);
+		let row = row.min(self.rows - 1);
+		glm::vec3(
+			(column as f32 / (self.columns - 1) as f32 - 0.5) * self.size.x,
+			self.heights[row * self.columns + column] * self.height_scale,
+			(row as f32 / (self.rows - 1) as f32 - 0.5) * self.size.y,
+		)
+	}
+
+	/// The local normal at a heightmap sample from the slope to its neighbours
+	pub fn normal(&self, column: usize, row: usize) -> glm::Vec3 {
+		let left = self.position(column.saturating_sub(1), row);
+		let right = self.position(column + 1, row);
+		let back = self.position(column, row.saturating_sub(1));
+		let front = self.position(column, row + 1);
+		glm::normalize(&glm::cross(&(front - back), &(right - left)))
+	}
+
+	/// The local height at a point on the terrain, interpolated between samples
+	pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+		let u = (x / self.size.x + 0.5) * (self.columns - 1) as f32;
+		let v = (z / self.size.y + 0.5) * (self.rows - 1) as f32;
+		if !(0.0..=(self.columns - 1) as f32).contains(&u)
+			|| !(0.0..=(self.rows - 1) as f32).contains(&v)
+		{
+			return None;
+		}
+
+		let (column, row) = (u.floor() as usize, v.floor() as usize);
+		let (s, t) = (u.fract(), v.fract());
+		let height = |column: usize, row: usize| {
+			self.heights[row.min(self.rows - 1) * self.columns + column.min(self.columns - 1)]
+		};
+		let near = glm::lerp_scalar(height(column, row), height(column + 1, row), s);
+		let far = glm::lerp_scalar(height(column, row + 1), height(column + 1, row + 1), s);
+		Some(glm::lerp_scalar(near, far, t) * self.height_scale)
+	}
+
+	/// The number of chunks along the X and Z axes
+	pub fn number_of_chunks(&self) -> (usize, usize) {
+		let chunk_size = self.chunk_size.max(1) as usize;
+		(
+			(self.columns - 1 + chunk_size - 1) / chunk_size,
+			(self.rows - 1 + chunk_size - 1) / chunk_size,
+		)
+	}
+
+	/// The number of detail levels, halving the resolution of a chunk down to a single quad
+	pub fn number_of_lods(&self) -> u32 {
+		self.chunk_size.max(1).trailing_zeros() + 1
+	}
+
+	/// The vertices of a chunk at full detail, in rows of `chunk_size + 1`.
+	/// Vertices past the edge of the heightmap are clamped onto it.
+	pub fn chunk_vertices(&self, chunk_x: usize, chunk_z: usize) -> Vec<(glm::Vec3, glm::Vec3)> {
+		let chunk_size = self.chunk_size as usize;
+		(0..=chunk_size)
+			.flat_map(|z| (0..=chunk_size).map(move |x| (x, z)))
+			.map(|(x, z)| {
+				let column = chunk_x * chunk_size + x;
+				let row = chunk_z * chunk_size + z;
+				(self.position(column, row), self.normal(column, row))
+			})
+			.collect()
+	}
+
+	pub fn chunk_bounding_box(&self, chunk_x: usize, chunk_z: usize) -> BoundingBox {
+		let mut bounding_box = BoundingBox::new_invalid();
+		self.chunk_vertices(chunk_x, chunk_z)
+			.into_iter()
+			.for_each(|(position, _)| bounding_box.fit_point(position));
+		bounding_box
+	}
+
+	/// Picks a level of detail for every chunk from its distance to a local camera position.
+	/// Neighbouring chunks never differ by more than one level so their edges can be stitched.
+	pub fn select_chunk_lods(
+		&self,
+		bounding_boxes: &[BoundingBox],
+		camera_position: &glm::Vec3,
+	) -> Vec<u32> {
+		let max_lod = self.number_of_lods() - 1;
+		let mut lods = bounding_boxes
+			.iter()
+			.map(|bounding_box| {
+				let closest = glm::clamp_vec(camera_position, &bounding_box.min, &bounding_box.max);
+				let distance = glm::distance(&closest, camera_position);
+				let ratio = distance / self.lod_distance.max(f32::EPSILON);
+				if ratio < 1.0 {
+					0
+				} else {
+					(ratio.log2().floor() as u32 + 1).min(max_lod)
+				}
+			})
+			.collect::<Vec<_>>();
+
+		let (chunks_x, chunks_z) = self.number_of_chunks();
+		let mut changed = true;
+		while changed {
+			changed = false;
+			for chunk_z in 0..chunks_z {
+				for chunk_x in 0..chunks_x {
+					let finest_neighbour = self
+						.chunk_neighbours(chunk_x, chunk_z)
+						.into_iter()
+						.flatten()
+						.map(|neighbour| lods[neighbour])
+						.min();
+					let index = chunk_z * chunks_x + chunk_x;
+					if let Some(finest_neighbour) = finest_neighbour {
+						if lods[index] > finest_neighbour + 1 {
+							lods[index] = finest_neighbour + 1;
+							changed = true;
+						}
+					}
+				}
+			}
+		}
+		lods
+	}
+
+	/// Returns a mask of the edges of a chunk whose neighbours are drawn at a coarser level
+	pub fn coarser_neighbour_mask(&self, lods: &[u32], chunk_x: usize, chunk_z: usize) -> u32 {
+		let (chunks_x, _) = self.number_of_chunks();
+		let lod = lods[chunk_z * chunks_x + chunk_x];
+		TerrainEdge::ALL
+			.iter()
+			.zip(self.chunk_neighbours(chunk_x, chunk_z))
+			.filter_map(|(edge, neighbour)| match neighbour {
+				Some(neighbour) if lods[neighbour] > lod => Some(edge.mask()),
+				_ => None,
+			})
+			.fold(0, |mask, edge| mask | edge)
+	}
+
+	/// The chunk indices next to a chunk, in the order of `TerrainEdge::ALL`
+	fn chunk_neighbours(&self, chunk_x: usize, chunk_z: usize) -> [Option<usize>; 4] {
+		let (chunks_x, chunks_z) = self.number_of_chunks();
+		let index = |x: usize, z: usize| z * chunks_x + x;
+		[
+			chunk_x.checked_sub(1).map(|x| index(x, chunk_z)),
+			(chunk_x + 1 < chunks_x).then(|| index(chunk_x + 1, chunk_z)),
+			chunk_z.checked_sub(1).map(|z| index(chunk_x, z)),
+			(chunk_z + 1 < chunks_z).then(|| index(chunk_x, chunk_z + 1)),
+		]
+	}
+
+	pub fn validate(&self) -> Result<()> {
+		if self.columns < 2 || self.rows < 2 || self.heights.len() < self.columns * self.rows {
+			return Err(TerrainError::HeightmapTooSmall);
+		}
+		if !self.chunk_size.is_power_of_two() {
+			return Err(TerrainError::InvalidChunkSize);
+		}
+		Ok(())
+	}
+}
+
+/// Triangulates a chunk of `chunk_size` quads per side at a level of detail,
+/// indexing vertices laid out as in `Terrain::chunk_vertices`.
+/// Vertices on edges in the coarser neighbour mask are snapped onto the neighbour's
+/// vertices so the chunks meet without cracks.
+pub fn terrain_chunk_indices(chunk_size: u32, lod: u32, coarser_neighbour_mask: u32) -> Vec<u32> {
+	let step = 1 << lod;
+	let row_length = chunk_size + 1;
+	let snap = |x: u32, z: u32| -> u32 {
+		let coarse_step = step * 2;
+		let snap_down = |value: u32| value - value % coarse_step;
+		let on_edge = |edge: TerrainEdge| coarser_neighbour_mask & edge.mask() != 0;
+		let (mut x, mut z) = (x, z);
+		if (x == 0 && on_edge(TerrainEdge::NegativeX))
+			|| (x == chunk_size && on_edge(TerrainEdge::PositiveX))
+		{
+			z = snap_down(z);
+		}
+		if (z == 0 && on_edge(TerrainEdge::NegativeZ))
+			|| (z == chunk_size && on_edge(TerrainEdge::PositiveZ))
+		{
+			x = snap_down(x);
+		}
+		z * row_length + x
+	};
+
+	let mut indices = Vec::new();
+	for z in (0..chunk_size).step_by(step as usize) {
+		for x in (0..chunk_size).step_by(step as usize) {
+			let corners = [
+				snap(x, z),
+				snap(x + step, z),
+				snap(x, z + step),
+				snap(x + step, z + step),
+			];
+			// Counter-clockwise when viewed from above
+			for [a, b, c] in [
+				[corners[0], corners[2], corners[1]],
+				[corners[1], corners[2], corners[3]],
+			] {
+				if a != b && b != c && a != c {
+					indices.extend_from_slice(&[a, b, c]);
+				}
+			}
+		}
+	}
+	indices
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn sloped_terrain(columns: usize, rows: usize) -> Terrain {
+		Terrain {
+			heights: (0..rows)
+				.flat_map(|_| (0..columns).map(move |column| column as f32 / (columns - 1) as f32))
+				.collect(),
+			columns,
+			rows,
+			size: glm::vec2(10.0, 10.0),
+			height_scale: 2.0,
+			chunk_size: 4,
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn height_at_interpolates_between_samples() {
+		let terrain = sloped_terrain(3, 3);
+		assert_eq!(terrain.height_at(-5.0, 0.0), Some(0.0));
+		assert_eq!(terrain.height_at(2.5, 0.0), Some(1.5));
+		assert_eq!(terrain.height_at(5.0, 5.0), Some(2.0));
+		assert_eq!(terrain.height_at(5.1, 0.0), None);
+	}
+
+	#[test]
+	fn chunk_indices_stitch_to_coarser_neighbours() {
+		let full = terrain_chunk_indices(4, 0, 0);
+		assert_eq!(full.len(), 4 * 4 * 6);
+
+		// Every other quad along the stitched edge loses a collapsed triangle
+		let stitched = terrain_chunk_indices(4, 0, TerrainEdge::NegativeZ.mask());
+		assert_eq!(stitched.len(), full.len() - 2 * 3);
+		assert!(stitched.iter().all(|index| *index > 4 || index % 2 == 0));
+	}
+
+	#[test]
+	fn neighbouring_chunks_differ_by_at_most_one_lod() {
+		let mut terrain = sloped_terrain(33, 5);
+		terrain.lod_distance = 1.0;
+		let (chunks_x, _) = terrain.number_of_chunks();
+		let bounding_boxes = (0..chunks_x)
+			.map(|chunk_x| terrain.chunk_bounding_box(chunk_x, 0))
+			.collect::<Vec<_>>();
+
+		let lods = terrain.select_chunk_lods(&bounding_boxes, &glm::vec3(-5.0, 0.0, 0.0));
+
+		assert_eq!(lods[0], 0);
+		assert!(lods.windows(2).all(|pair| pair[1] <= pair[0] + 1));
+		assert_eq!(*lods.last().unwrap(), terrain.number_of_lods() - 1);
+	}
+}
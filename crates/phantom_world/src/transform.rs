@@ -121,6 +121,34 @@ impl From<glm::Mat4> for Transform {
 	}
 }
 
+/// An entity's transform relative to the world, cached by `World::update_global_transforms`.
+/// It isn't saved, since it is recomputed from the local transforms in the hierarchy.
+#[derive(Copy, Clone, Debug)]
+pub struct GlobalTransform {
+	pub matrix: glm::Mat4,
+
+	/// The local transform the matrix was computed from, so unchanged nodes can be skipped
+	pub local: Transform,
+}
+
+impl GlobalTransform {
+	/// Whether the matrix was computed from exactly this local transform
+	pub fn is_computed_from(&self, local: &Transform) -> bool {
+		self.local.translation == local.translation
+			&& self.local.rotation == local.rotation
+			&& self.local.scale == local.scale
+	}
+
+	/// The world space rotation, with the scale of the entity and its ancestors divided out
+	pub fn rotation(&self) -> glm::Quat {
+		let mut basis = glm::mat4_to_mat3(&self.matrix);
+		basis
+			.column_iter_mut()
+			.for_each(|mut column| column.normalize_mut());
+		glm::quat_normalize(&glm::mat3_to_quat(&basis))
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use super::*;
@@ -133,6 +161,18 @@ mod tests {
 		assert_eq!(matrix, Transform::from(matrix).matrix());
 	}
 
+	#[test]
+	fn global_rotation_ignores_scale() {
+		let rotation = glm::quat_angle_axis(1.0, &glm::Vec3::y());
+		let local = Transform::new(glm::vec3(1.0, 2.0, 3.0), rotation, glm::vec3(2.0, 2.0, 2.0));
+		let global_transform = GlobalTransform {
+			matrix: local.matrix(),
+			local,
+		};
+		let difference = glm::quat_inverse(&rotation) * global_transform.rotation();
+		assert!(glm::quat_angle(&difference).abs() < 1e-4);
+	}
+
 	#[test]
 	fn round_trip_shear() {
 		let transform = Transform {
@@ -1,15 +1,21 @@
 use crate::{
-	deserialize_ecs, scenegraph, serialize_ecs, world_as_bytes, world_from_bytes, Animation,
-	Camera, Ecs, Entity, EntitySceneGraph, EntitySceneGraphNode, Material, Name, PerspectiveCamera,
-	Projection, RegistryError, RigidBody, SceneGraphError, Texture, TextureError, Transform,
-	WorldPhysics,
+	component_duplicator, deserialize_ecs, find_clip_index, limited_rotation_between,
+	rotation_between, scenegraph, serialize_ecs, solve_fabrik, solve_two_bone, world_as_bytes,
+	world_from_bytes, Animation, AnimationError, AnimationEvent, AnimationGraph, AnimationPlayer,
+	Camera, CustomMaterial, CustomMaterialRender, Decal, Ecs, Entity, EntitySceneGraph,
+	EntitySceneGraphNode, Environment, FootIk, GlobalTransform, IkConstraint, InverseKinematics,
+	LodBias, Material, MeshLod, Name, PerspectiveCamera, PlaybackState, Pose, PosePool, Prefab,
+	PrefabInstance, Projection, RegistryError, RenderTarget, RigidBody, RootMotion, SceneGraph,
+	SceneGraphError, Terrain, TerrainError, Texture, TextureError, Transform, WorldPhysics,
 };
 use bmfont::{self, BMFont, OrdinateOrientation};
 use legion::{
+	maybe_changed,
+	storage::IntoComponentSource,
 	world::{ComponentError, EntityAccessError},
 	EntityStore, IntoQuery,
 };
-use nalgebra::{Point, Point3};
+use nalgebra::{DMatrix, Point, Point3, Vector3};
 use nalgebra_glm as glm;
 use petgraph::prelude::*;
 use rapier3d::{
@@ -19,7 +25,7 @@ use rapier3d::{
 };
 use serde::{Deserialize, Serialize};
 use std::{
-	collections::HashMap,
+	collections::{HashMap, HashSet},
 	marker::{Send, Sync},
 	ops::Range,
 	path::Path,
@@ -79,15 +85,100 @@ pub enum WorldError {
 	#[error("Failed to deserialize world!")]
 	DeserializeWorld(#[source] RegistryError),
 
+	#[error("Failed to create component duplicator!")]
+	CreateComponentDuplicator(#[source] RegistryError),
+
 	#[error("Failed to find default scenegraph in scene named '`{0}`'!")]
 	FindDefaultScenegraph(String),
 
 	#[error("Failed to global transform of node in scene graph!")]
 	GetGlobalTransform(#[source] SceneGraphError),
+
+	#[error("Failed to create terrain collider!")]
+	CreateTerrainCollider(#[source] TerrainError),
+
+	#[error("Failed to apply animation!")]
+	ApplyAnimation(#[source] AnimationError),
+
+	#[error("Failed to find prefab named '`{0}`'!")]
+	FindPrefab(String),
+
+	#[error("Failed to find entity in the scene graphs!")]
+	FindSceneGraphNode,
+
+	#[error("An entity can't be parented to itself or one of its descendants!")]
+	ReparentToDescendant,
 }
 
 type Result<T, E = WorldError> = std::result::Result<T, E>;
 
+/// A copy of a clip under a new name, animating the copies of its targets
+fn retargeted_animation(
+	animation: &Animation,
+	name: String,
+	entity_map: &HashMap<Entity, Entity>,
+) -> Animation {
+	let copied = |entity: &Entity| entity_map.get(entity).copied().unwrap_or(*entity);
+	let mut animation = animation.clone();
+	animation.name = name;
+	animation
+		.channels
+		.iter_mut()
+		.for_each(|channel| channel.target = copied(&channel.target));
+	animation.root_motion_joint = animation.root_motion_joint.as_ref().map(copied);
+	animation
+}
+
+/// Recomputes and caches the global transforms of a node and everything below it
+fn update_global_transforms(
+	ecs: &mut Ecs,
+	graph: &EntitySceneGraph,
+	node_index: NodeIndex,
+	parent_transform: &glm::Mat4,
+) -> Result<()> {
+	let mut nodes = vec![(node_index, *parent_transform)];
+	while let Some((node_index, parent_transform)) = nodes.pop() {
+		let mut entry = ecs.entry(graph[node_index]).ok_or(WorldError::FindEntity)?;
+		let local = match entry.get_component::<Transform>() {
+			Ok(transform) => *transform,
+			Err(error) => return Err(WorldError::RequestTransform(error)),
+		};
+		let global_transform = GlobalTransform {
+			matrix: parent_transform * local.matrix(),
+			local,
+		};
+		match entry.get_component_mut::<GlobalTransform>() {
+			Ok(cached) => *cached = global_transform,
+			Err(_) => entry.add_component(global_transform),
+		}
+		for child_index in graph.0.neighbors_directed(node_index, Outgoing) {
+			nodes.push((child_index, global_transform.matrix));
+		}
+	}
+	Ok(())
+}
+
+/// Lists the entities in chunks whose transforms were written since it last ran,
+/// using the component versions legion tracks
+struct TransformChanges(Box<dyn FnMut(&Ecs) -> Vec<Entity> + Send + Sync>);
+
+impl Default for TransformChanges {
+	fn default() -> Self {
+		let mut query = <(Entity, &Transform)>::query().filter(maybe_changed::<Transform>());
+		Self(Box::new(move |ecs: &Ecs| {
+			query.iter(ecs).map(|(entity, _)| *entity).collect()
+		}))
+	}
+}
+
+/// Whether clips are already named with a prefix, as in `Tree#2/Sway`
+fn clip_prefix_in_use(animations: &[Animation], prefix: &str) -> bool {
+	let prefix = format!("{}/", prefix);
+	animations
+		.iter()
+		.any(|animation| animation.name.starts_with(&prefix))
+}
+
 #[derive(Default, Serialize, Deserialize)]
 pub struct World {
 	#[serde(serialize_with = "serialize_ecs", deserialize_with = "deserialize_ecs")]
@@ -95,11 +186,30 @@ pub struct World {
 	pub physics: WorldPhysics,
 	pub scene: Scene,
 	pub animations: Vec<Animation>,
+	/// Queued by animation players and graphs until drained
+	#[serde(skip)]
+	pub animation_events: Vec<AnimationEvent>,
+	/// Reused by animation players to sample without allocating every tick
+	#[serde(skip)]
+	animation_pose: Pose,
+	/// Reused by animation graphs to evaluate without allocating every tick
+	#[serde(skip)]
+	graph_poses: PosePool,
+	/// Finds the transforms written since global transforms were last updated
+	#[serde(skip)]
+	transform_changes: TransformChanges,
+	/// The animated and solved local rotations of the joints inverse kinematics last turned,
+	/// so joints animation didn't re-pose are solved again from their animated rotation
+	#[serde(skip)]
+	ik_rotations: HashMap<Entity, (glm::Quat, glm::Quat)>,
 	pub materials: Vec<Material>,
+	/// Game-defined materials, kept when the world is cleared so they only need registering once
+	pub custom_materials: Vec<CustomMaterial>,
 	pub textures: Vec<Texture>,
 	pub hdr_textures: Vec<Texture>,
 	pub geometry: Geometry,
 	pub fonts: HashMap<String, SdfFont>,
+	pub prefabs: Vec<Prefab>,
 }
 
 impl World {
@@ -139,6 +249,8 @@ impl World {
 					z_near: 0.1,
 				}),
 				enabled: true,
+				viewport: Viewport::full(),
+				target: RenderTarget::Surface,
 			},
 		));
 
@@ -179,13 +291,62 @@ impl World {
 		Err(WorldError::FindActiveCamera)
 	}
 
+	pub fn enabled_cameras(&self) -> Vec<Entity> {
+		let mut query = <(Entity, &Camera)>::query();
+		query
+			.iter(&self.ecs)
+			.filter(|(_, camera)| camera.enabled)
+			.map(|(entity, _)| *entity)
+			.collect()
+	}
+
+	/// Collects the matrices and pixel viewport of every enabled camera.
+	/// Cameras targeting the surface are scaled to the given surface dimensions,
+	/// while cameras targeting a texture are scaled to that texture's dimensions.
+	pub fn camera_views(&self, width: f32, height: f32) -> Result<Vec<CameraView>> {
+		let mut views = Vec::new();
+		for entity in self.enabled_cameras() {
+			let (viewport, target) = {
+				let entry = self.ecs.entry_ref(entity)?;
+				let camera = entry.get_component::<Camera>()?;
+				let viewport = match &camera.target {
+					RenderTarget::Surface => camera.viewport.scaled(width, height),
+					RenderTarget::Texture { width, height, .. } => {
+						camera.viewport.scaled(*width as f32, *height as f32)
+					}
+				};
+				(viewport, camera.target.clone())
+			};
+			let (projection, view) = self.camera_matrices(entity, viewport.aspect_ratio())?;
+			let position = self.entity_global_transform(entity)?.translation;
+			views.push(CameraView {
+				entity,
+				target,
+				viewport,
+				projection,
+				view,
+				position,
+			});
+		}
+		if views.is_empty() {
+			return Err(WorldError::FindActiveCamera);
+		}
+		Ok(views)
+	}
+
+	/// The cached global transform of a node, or the one computed from its ancestors
+	/// when it hasn't been cached yet
 	pub fn global_transform(
 		&self,
 		graph: &EntitySceneGraph,
 		index: NodeIndex,
 	) -> Result<glm::Mat4> {
 		let entity = graph[index];
-		let transform = match self.ecs.entry_ref(entity)?.get_component::<Transform>() {
+		let entry = self.ecs.entry_ref(entity)?;
+		if let Ok(global_transform) = entry.get_component::<GlobalTransform>() {
+			return Ok(global_transform.matrix);
+		}
+		let transform = match entry.get_component::<Transform>() {
 			Ok(transform) => transform.matrix(),
 			Err(error) => return Err(WorldError::RequestTransform(error)),
 		};
@@ -196,34 +357,113 @@ impl World {
 		}
 	}
 
+	/// The cached global transform of an entity, or the one computed from its ancestors
+	/// when it hasn't been cached yet. Transforms written through the world refresh the cache
+	/// straight away, while those written directly to the ecs are seen after the next update.
 	pub fn entity_global_transform_matrix(&self, entity: Entity) -> Result<glm::Mat4> {
-		let mut transform = glm::Mat4::identity();
-		let mut found = false;
-		for graph in self.scene.graphs.iter() {
-			graph
-				.walk(|node_index| {
-					if entity != graph[node_index] {
-						return Ok(());
+		let entry = self.ecs.entry_ref(entity)?;
+		if let Ok(global_transform) = entry.get_component::<GlobalTransform>() {
+			return Ok(global_transform.matrix);
+		}
+		match self.find_scene_graph_node(entity) {
+			Some((graph_index, node_index)) => {
+				self.global_transform(&self.scene.graphs[graph_index], node_index)
+			}
+			// Not in the scene graph, so the entity just has a local transform
+			None => Ok(entry.get_component::<Transform>()?.matrix()),
+		}
+	}
+
+	/// Caches the global transforms of graphed entities whose transform or ancestors changed.
+	/// Only entities legion saw written since the last update are checked,
+	/// and only the subtrees below those whose transform differs are recomputed.
+	/// Hierarchy edits made through the world refresh the nodes they move straight away.
+	pub fn update_global_transforms(&mut self) -> Result<()> {
+		let changed = (self.transform_changes.0)(&self.ecs);
+		let dirty = changed
+			.into_iter()
+			.filter(|entity| self.global_transform_is_stale(*entity))
+			.collect::<HashSet<_>>();
+		if dirty.is_empty() {
+			return Ok(());
+		}
+
+		let mut dirty_roots = Vec::new();
+		for (graph_index, graph) in self.scene.graphs.iter().enumerate() {
+			for node_index in graph.0.node_indices() {
+				if !dirty.contains(&graph[node_index]) {
+					continue;
+				}
+				// Descendants of dirty nodes are recomputed along with them
+				let mut parent = graph.get_parent_of(node_index);
+				let mut below_dirty_node = false;
+				while let Some(parent_index) = parent {
+					if dirty.contains(&graph[parent_index]) {
+						below_dirty_node = true;
+						break;
 					}
-					transform = self.global_transform(graph, node_index)?;
-					found = true;
-					Ok(())
-				})
-				.map_err(WorldError::WalkEntitySceneGraph)?;
-			if found {
-				break;
+					parent = graph.get_parent_of(parent_index);
+				}
+				if below_dirty_node {
+					continue;
+				}
+				let parent_transform = match graph.get_parent_of(node_index) {
+					Some(parent_index) => self.global_transform(graph, parent_index)?,
+					None => glm::Mat4::identity(),
+				};
+				dirty_roots.push((graph_index, node_index, parent_transform));
 			}
 		}
-		if !found {
-			// TODO: Maybe returning an error if the global transform of an entity that isn't in the scenegraph is better...
-			// Not found in the scenegraph, so the entity just has a local transform
-			transform = self
-				.ecs
-				.entry_ref(entity)?
-				.get_component::<Transform>()?
-				.matrix();
+		for (graph_index, node_index, parent_transform) in dirty_roots {
+			update_global_transforms(
+				&mut self.ecs,
+				&self.scene.graphs[graph_index],
+				node_index,
+				&parent_transform,
+			)?;
 		}
-		Ok(transform)
+		Ok(())
+	}
+
+	/// Whether an entity's cached global transform is missing or was computed
+	/// from a different local transform
+	fn global_transform_is_stale(&self, entity: Entity) -> bool {
+		let entry = match self.ecs.entry_ref(entity) {
+			Ok(entry) => entry,
+			Err(_) => return false,
+		};
+		match (
+			entry.get_component::<Transform>(),
+			entry.get_component::<GlobalTransform>(),
+		) {
+			(Ok(local), Ok(global_transform)) => !global_transform.is_computed_from(local),
+			(Ok(_), Err(_)) => true,
+			(Err(_), _) => false,
+		}
+	}
+
+	/// Recomputes the cached global transforms of an entity and everything below it,
+	/// for when its local transform or parent changes between updates
+	pub fn refresh_global_transforms(&mut self, entity: Entity) -> Result<()> {
+		let (graph_index, node_index) = match self.find_scene_graph_node(entity) {
+			Some(node) => node,
+			None => return Ok(()),
+		};
+		let graph = &self.scene.graphs[graph_index];
+		let parent_transform = match graph.get_parent_of(node_index) {
+			Some(parent_index) => self.global_transform(graph, parent_index)?,
+			None => glm::Mat4::identity(),
+		};
+		update_global_transforms(&mut self.ecs, graph, node_index, &parent_transform)
+	}
+
+	/// Sets an entity's local transform and refreshes the cached global transforms below it
+	pub fn set_entity_transform(&mut self, entity: Entity, transform: Transform) -> Result<()> {
+		*self
+			.ecs
+			.entry_mut(entity)?
+			.get_component_mut::<Transform>()? = transform;
+		self.refresh_global_transforms(entity)
 	}
 
 	pub fn entity_global_transform(&self, entity: Entity) -> Result<Transform> {
@@ -232,7 +472,14 @@ impl World {
 	}
 
 	pub fn active_camera_matrices(&self, aspect_ratio: f32) -> Result<(glm::Mat4, glm::Mat4)> {
-		let camera_entity = self.active_camera()?;
+		self.camera_matrices(self.active_camera()?, aspect_ratio)
+	}
+
+	pub fn camera_matrices(
+		&self,
+		camera_entity: Entity,
+		aspect_ratio: f32,
+	) -> Result<(glm::Mat4, glm::Mat4)> {
 		let transform = self.entity_global_transform(camera_entity)?;
 		let view = transform.as_view_matrix();
 		let projection = {
@@ -255,18 +502,492 @@ impl World {
 		self.scene.graphs.clear();
 		self.textures.clear();
 		self.animations.clear();
+		self.animation_events.clear();
+		self.animation_pose = Pose::default();
+		self.graph_poses = PosePool::default();
+		self.transform_changes = TransformChanges::default();
+		self.ik_rotations.clear();
 		self.materials.clear();
 		self.geometry.clear();
+		self.prefabs.clear();
 		self.initialize()?;
 		Ok(())
 	}
 
+	pub fn prefab_index(&self, name: &str) -> Option<usize> {
+		self.prefabs.iter().position(|prefab| prefab.name == name)
+	}
+
+	/// Clones a prefab's entities into the world, placing its root nodes relative to a transform,
+	/// and returns the roots. Skins and animation clips are retargeted to the new entities.
+	pub fn instantiate_prefab(&mut self, name: &str, transform: &Transform) -> Result<Vec<Entity>> {
+		let prefab_index = self
+			.prefab_index(name)
+			.ok_or_else(|| WorldError::FindPrefab(name.to_string()))?;
+		let prefab = &mut self.prefabs[prefab_index];
+		let instance = loop {
+			prefab.instances += 1;
+			let name = format!("{}#{}", prefab.name, prefab.instances);
+			if !clip_prefix_in_use(&self.animations, &name) {
+				break PrefabInstance {
+					prefab: prefab.name.to_string(),
+					name,
+				};
+			}
+		};
+
+		let mut merger = component_duplicator().map_err(WorldError::CreateComponentDuplicator)?;
+		let entity_map = self
+			.ecs
+			.clone_from(&prefab.ecs, &legion::any(), &mut merger);
+		let instance_entity = |entity: &Entity| entity_map.get(entity).copied().unwrap_or(*entity);
+		let clip_name = |clip: &str| format!("{}/{}", instance.name, clip);
+
+		for animation in prefab.animations.iter() {
+			let animation =
+				retargeted_animation(animation, clip_name(&animation.name), &entity_map);
+			self.animations.push(animation);
+		}
+
+		// Each imported root node is graphed on its own, starting at the first node
+		let root_index = NodeIndex::new(0);
+		let mut roots = Vec::new();
+		for graph in prefab.graphs.iter() {
+			let graph = SceneGraph(
+				graph
+					.0
+					.map(|_, entity| instance_entity(entity), |_, edge| *edge),
+			);
+			let root = graph[root_index];
+			let mut entry = self.ecs.entry(root).ok_or(WorldError::FindEntity)?;
+			let root_transform = *entry.get_component::<Transform>()?;
+			entry.add_component(Transform::from(
+				transform.matrix() * root_transform.matrix(),
+			));
+			entry.add_component(instance.clone());
+			self.scene.graphs.push(graph);
+			roots.push(root);
+		}
+
+		self.retarget_copies(&entity_map)?;
+		for entity in entity_map.values() {
+			let mut entry = self.ecs.entry(*entity).ok_or(WorldError::FindEntity)?;
+			if let Ok(player) = entry.get_component_mut::<AnimationPlayer>() {
+				player.clips = player.clips.iter().map(|clip| clip_name(clip)).collect();
+			}
+		}
+
+		Ok(roots)
+	}
+
+	/// The index of the scene graph containing an entity and the entity's node in it
+	pub fn find_scene_graph_node(&self, entity: Entity) -> Option<(usize, NodeIndex)> {
+		self.scene
+			.graphs
+			.iter()
+			.enumerate()
+			.find_map(|(graph_index, graph)| {
+				graph
+					.find_node(entity)
+					.map(|node_index| (graph_index, node_index))
+			})
+	}
+
+	/// The entity followed by every entity below it, or only the entity when it isn't graphed
+	pub fn entity_subtree(&self, entity: Entity) -> Vec<Entity> {
+		match self.find_scene_graph_node(entity) {
+			Some((graph_index, node_index)) => {
+				let graph = &self.scene.graphs[graph_index];
+				graph
+					.subtree(node_index)
+					.into_iter()
+					.map(|node_index| graph[node_index])
+					.collect()
+			}
+			None => vec![entity],
+		}
+	}
+
+	/// Spawns an entity below a parent in the parent's scene graph
+	pub fn spawn_child<T>(&mut self, parent: Entity, components: T) -> Result<Entity>
+	where
+		Option<T>: IntoComponentSource,
+	{
+		let (graph_index, parent_index) = self
+			.find_scene_graph_node(parent)
+			.ok_or(WorldError::FindSceneGraphNode)?;
+		let entity = self.ecs.push(components);
+		self.scene.graphs[graph_index].add_child(parent_index, entity);
+		Ok(entity)
+	}
+
+	/// Moves an entity and everything below it under a new parent, or to the root of its graph.
+	/// Keeping the global transform changes the entity's local transform so it stays in place,
+	/// otherwise the local transform is kept and rigid bodies in the hierarchy move with it.
+	pub fn reparent(
+		&mut self,
+		entity: Entity,
+		parent: Option<Entity>,
+		keep_global_transform: bool,
+	) -> Result<()> {
+		if let Some(parent) = parent {
+			if self.entity_subtree(entity).contains(&parent) {
+				return Err(WorldError::ReparentToDescendant);
+			}
+		}
+		let global_transform = self.entity_global_transform_matrix(entity)?;
+		let (graph_index, node_index) = self
+			.find_scene_graph_node(entity)
+			.ok_or(WorldError::FindSceneGraphNode)?;
+		let parent_node = match parent {
+			Some(parent) => Some(
+				self.find_scene_graph_node(parent)
+					.ok_or(WorldError::FindSceneGraphNode)?,
+			),
+			None => None,
+		};
+
+		match parent_node {
+			Some((parent_graph_index, parent_index)) if parent_graph_index != graph_index => {
+				let subtree = self.scene.graphs[graph_index].remove_subtree(node_index);
+				self.scene.graphs[parent_graph_index].attach_graph(Some(parent_index), &subtree);
+			}
+			_ => {
+				let graph = &mut self.scene.graphs[graph_index];
+				graph.detach(node_index);
+				if let Some((_, parent_index)) = parent_node {
+					graph.add_edge(parent_index, node_index);
+				}
+			}
+		}
+
+		if keep_global_transform {
+			let parent_transform = match parent {
+				Some(parent) => self.entity_global_transform_matrix(parent)?,
+				None => glm::Mat4::identity(),
+			};
+			let local_transform = glm::inverse(&parent_transform) * global_transform;
+			*self
+				.ecs
+				.entry_mut(entity)?
+				.get_component_mut::<Transform>()? = Transform::from(local_transform);
+			self.refresh_global_transforms(entity)
+		} else {
+			self.refresh_global_transforms(entity)?;
+			self.sync_subtree_rigid_bodies(entity)
+		}
+	}
+
+	/// Despawns an entity and everything below it, along with their rigid bodies and colliders,
+	/// the animation channels targeting them and the constraints and skin joints referring to them
+	pub fn despawn(&mut self, entity: Entity) -> Result<()> {
+		let (entities, parent): (Vec<Entity>, _) = match self.find_scene_graph_node(entity) {
+			Some((graph_index, node_index)) => {
+				let graph = &mut self.scene.graphs[graph_index];
+				let parent = graph
+					.get_parent_of(node_index)
+					.map(|parent_index| graph[parent_index]);
+				let entities = graph
+					.remove_subtree(node_index)
+					.0
+					.raw_nodes()
+					.iter()
+					.map(|node| node.weight)
+					.collect();
+				(entities, parent)
+			}
+			None => (vec![entity], None),
+		};
+		// The graph has already changed, so entities are removed even if one can't be found
+		for entity in entities.iter() {
+			if let Ok(entry) = self.ecs.entry_ref(*entity) {
+				if let Ok(rigid_body) = entry.get_component::<RigidBody>() {
+					self.physics.remove_rigid_body(rigid_body.handle);
+				}
+			}
+			let _ = self.ecs.remove(*entity);
+		}
+		let despawned: HashSet<Entity> = entities.into_iter().collect();
+		self.prune_animations(&despawned);
+		self.prune_joints(&despawned, parent);
+		Ok(())
+	}
+
+	/// Drops the channels, bind poses and masked joints of despawned entities,
+	/// and the clips left animating nothing, so players and graphs don't write to them
+	fn prune_animations(&mut self, despawned: &HashSet<Entity>) {
+		self.animations.retain_mut(|animation| {
+			let animated = !animation.channels.is_empty();
+			animation
+				.channels
+				.retain(|channel| !despawned.contains(&channel.target));
+			if let Some(root_joint) = animation.root_motion_joint {
+				if despawned.contains(&root_joint) {
+					animation.root_motion_joint = None;
+				}
+			}
+			!animated || !animation.channels.is_empty()
+		});
+		let mut query = <&mut AnimationGraph>::query();
+		for graph in query.iter_mut(&mut self.ecs) {
+			if let Some(bind_pose) = graph.bind_pose.as_mut() {
+				bind_pose
+					.joints
+					.retain(|entity, _| !despawned.contains(entity));
+			}
+			for mask in graph
+				.layers
+				.iter_mut()
+				.filter_map(|layer| layer.mask.as_mut())
+			{
+				mask.entities.retain(|entity| !despawned.contains(entity));
+			}
+		}
+	}
+
+	/// Drops the inverse kinematics constraints on despawned joints, and points skin joints
+	/// that were despawned at the surviving parent of the despawned subtree instead.
+	/// Skin joints keep their order, because vertices refer to them by index.
+	fn prune_joints(&mut self, despawned: &HashSet<Entity>, parent: Option<Entity>) {
+		let mut query = <&mut InverseKinematics>::query();
+		for inverse_kinematics in query.iter_mut(&mut self.ecs) {
+			inverse_kinematics.constraints.retain(|constraint| {
+				!constraint
+					.joints()
+					.iter()
+					.any(|joint| despawned.contains(joint))
+			});
+		}
+
+		let mut query = <(Entity, &mut Skin)>::query();
+		for (entity, skin) in query.iter_mut(&mut self.ecs) {
+			// Vertices bound to despawned joints follow the parent as if bound to it
+			let parent_joint = skin
+				.joints
+				.iter()
+				.find(|joint| Some(joint.target) == parent)
+				.map(|joint| joint.inverse_bind_matrix);
+			for joint in skin.joints.iter_mut() {
+				if despawned.contains(&joint.target) {
+					joint.target = parent.unwrap_or(*entity);
+					if let Some(inverse_bind_matrix) = parent_joint {
+						joint.inverse_bind_matrix = inverse_bind_matrix;
+					}
+				}
+			}
+		}
+	}
+
+	/// Copies an entity and everything below it, placing the copy beside the original.
+	/// Rigid bodies and colliders are copied, skins, inverse kinematics and animation players
+	/// are pointed at the copied joints, and clips animating the copied entities are copied too.
+	pub fn duplicate_subtree(&mut self, entity: Entity) -> Result<Entity> {
+		let mut merger = component_duplicator().map_err(WorldError::CreateComponentDuplicator)?;
+		let mut copies = Ecs::default();
+		let entity_map = self
+			.entity_subtree(entity)
+			.into_iter()
+			.map(|original| {
+				let copy = copies.clone_from_single(&self.ecs, original, &mut merger);
+				(original, copy)
+			})
+			.collect::<HashMap<_, _>>();
+		self.ecs.move_from(&mut copies, &legion::any());
+		let copy = entity_map[&entity];
+
+		for (original, copy) in entity_map.iter() {
+			self.copy_rigid_body(*original, *copy)?;
+		}
+		self.retarget_copies(&entity_map)?;
+		self.copy_player_clips(copy, &entity_map)?;
+
+		if let Some((graph_index, node_index)) = self.find_scene_graph_node(entity) {
+			let graph = &mut self.scene.graphs[graph_index];
+			let subtree = SceneGraph(graph.extract_subtree(node_index).0.map(
+				|_, entity| entity_map.get(entity).copied().unwrap_or(*entity),
+				|_, edge| *edge,
+			));
+			let parent_index = graph.get_parent_of(node_index);
+			graph.attach_graph(parent_index, &subtree);
+		}
+		Ok(copy)
+	}
+
+	/// Moves the rigid bodies of an entity and everything below it to their global transforms
+	fn sync_subtree_rigid_bodies(&mut self, entity: Entity) -> Result<()> {
+		for entity in self.entity_subtree(entity) {
+			let handle = match self.ecs.entry_ref(entity)?.get_component::<RigidBody>() {
+				Ok(rigid_body) => rigid_body.handle,
+				Err(_) => continue,
+			};
+			let isometry = self.entity_global_transform(entity)?.as_isometry();
+			if let Some(body) = self.physics.bodies.get_mut(handle) {
+				body.set_position(isometry, true);
+			}
+		}
+		Ok(())
+	}
+
+	/// Gives a copied entity its own rigid body and colliders, matching the original's
+	fn copy_rigid_body(&mut self, original: Entity, copy: Entity) -> Result<()> {
+		let (handle, colliders) = match self.ecs.entry_ref(original)?.get_component::<RigidBody>() {
+			Ok(rigid_body) => (rigid_body.handle, rigid_body.colliders.to_vec()),
+			Err(_) => return Ok(()),
+		};
+		let body = match self.physics.bodies.get(handle) {
+			Some(body) => body.clone(),
+			None => return Ok(()),
+		};
+		let copy_handle = self.physics.bodies.insert(body);
+		let mut rigid_body = RigidBody::new(copy_handle);
+		for collider_handle in colliders {
+			if let Some(collider) = self.physics.colliders.get(collider_handle).cloned() {
+				rigid_body
+					.colliders
+					.push(self.physics.colliders.insert_with_parent(
+						collider,
+						copy_handle,
+						&mut self.physics.bodies,
+					));
+			}
+		}
+		self.ecs
+			.entry(copy)
+			.ok_or(WorldError::FindEntity)?
+			.add_component(rigid_body);
+		Ok(())
+	}
+
+	/// Points the skins and inverse kinematics of copied entities at the copies of their joints
+	fn retarget_copies(&mut self, entity_map: &HashMap<Entity, Entity>) -> Result<()> {
+		let copied = |entity: Entity| entity_map.get(&entity).copied().unwrap_or(entity);
+		for copy in entity_map.values() {
+			let mut entry = self.ecs.entry(*copy).ok_or(WorldError::FindEntity)?;
+			if let Ok(skin) = entry.get_component_mut::<Skin>() {
+				skin.joints
+					.iter_mut()
+					.for_each(|joint| joint.target = copied(joint.target));
+			}
+			if let Ok(inverse_kinematics) = entry.get_component_mut::<InverseKinematics>() {
+				inverse_kinematics.retarget(copied);
+			}
+			if let Ok(graph) = entry.get_component_mut::<AnimationGraph>() {
+				graph.retarget(copied);
+			}
+		}
+		Ok(())
+	}
+
+	/// Copies the clips that copied animation players play on copied entities,
+	/// naming them after the copy as prefab instance clips are
+	fn copy_player_clips(
+		&mut self,
+		root: Entity,
+		entity_map: &HashMap<Entity, Entity>,
+	) -> Result<()> {
+		let base_name = {
+			let entry = self.ecs.entry_ref(root)?;
+			match (
+				entry.get_component::<PrefabInstance>(),
+				entry.get_component::<Name>(),
+			) {
+				(Ok(instance), _) => instance.prefab.to_string(),
+				(_, Ok(name)) => name.0.to_string(),
+				_ => "Copy".to_string(),
+			}
+		};
+		let mut number = 1;
+		let prefix = loop {
+			let prefix = format!("{}#{}", base_name, number);
+			if !clip_prefix_in_use(&self.animations, &prefix) {
+				break prefix;
+			}
+			number += 1;
+		};
+
+		let mut renamed = HashMap::new();
+		for copy in entity_map.values() {
+			let clips = match self
+				.ecs
+				.entry_ref(*copy)?
+				.get_component::<AnimationPlayer>()
+			{
+				Ok(player) => player.clips.to_vec(),
+				Err(_) => continue,
+			};
+			for clip in clips {
+				let animation = match self.animation_index(&clip) {
+					Some(animation_index) => &self.animations[animation_index],
+					None => continue,
+				};
+				let animates_copies = animation
+					.channels
+					.iter()
+					.any(|channel| entity_map.contains_key(&channel.target));
+				if !animates_copies || renamed.contains_key(&clip) {
+					continue;
+				}
+				let imported_name = clip.split_once('/').map_or(clip.as_str(), |(_, name)| name);
+				let name = format!("{}/{}", prefix, imported_name);
+				let animation = retargeted_animation(animation, name.to_string(), entity_map);
+				self.animations.push(animation);
+				renamed.insert(clip, name);
+			}
+		}
+		if renamed.is_empty() {
+			return Ok(());
+		}
+
+		let rename = |clip: &String| {
+			renamed
+				.get(clip)
+				.cloned()
+				.unwrap_or_else(|| clip.to_string())
+		};
+		for copy in entity_map.values() {
+			let mut entry = self.ecs.entry(*copy).ok_or(WorldError::FindEntity)?;
+			if let Ok(player) = entry.get_component_mut::<AnimationPlayer>() {
+				player.clips = player.clips.iter().map(rename).collect();
+				player.clip = player.clip.as_ref().map(rename);
+			}
+		}
+		if let Ok(instance) = self
+			.ecs
+			.entry_mut(root)?
+			.get_component_mut::<PrefabInstance>()
+		{
+			instance.name = prefix;
+		}
+		Ok(())
+	}
+
 	pub fn material_at_index(&self, index: usize) -> Result<&Material> {
 		self.materials
 			.get(index)
 			.ok_or(WorldError::LookupMaterial(index))
 	}
 
+	/// Adds a custom material, replacing any registered with the same name,
+	/// and returns its index
+	pub fn register_custom_material(&mut self, material: CustomMaterial) -> usize {
+		match self.custom_material_index(&material.name) {
+			Some(index) => {
+				self.custom_materials[index] = material;
+				index
+			}
+			None => {
+				self.custom_materials.push(material);
+				self.custom_materials.len() - 1
+			}
+		}
+	}
+
+	pub fn custom_material_index(&self, name: &str) -> Option<usize> {
+		self.custom_materials
+			.iter()
+			.position(|material| material.name == name)
+	}
+
 	pub fn components<T: Send + Sync + Copy + Clone + 'static>(
 		&self,
 	) -> Result<Vec<(Transform, T)>> {
@@ -308,15 +1029,8 @@ impl World {
 					let node_transform = self.global_transform(graph, node_index)?;
 					if let Ok(skin) = self.ecs.entry_ref(entity)?.get_component::<Skin>() {
 						for joint in skin.joints.iter() {
-							let joint_transform = {
-								let mut transform = glm::Mat4::identity();
-								for graph in self.scene.graphs.iter() {
-									if let Some(index) = graph.find_node(joint.target) {
-										transform = self.global_transform(graph, index)?;
-									}
-								}
-								transform
-							};
+							let joint_transform =
+								self.entity_global_transform_matrix(joint.target)?;
 							joint_matrices[offset] = glm::inverse(&node_transform)
 								* joint_transform * joint.inverse_bind_matrix;
 							offset += 1;
@@ -450,6 +1164,38 @@ impl World {
 		Ok(())
 	}
 
+	/// Adds a heightfield collider matching the entity's terrain.
+	/// Without a rigid body the collider is placed at the entity's global transform.
+	pub fn add_terrain_collider(
+		&mut self,
+		entity: Entity,
+		collision_groups: InteractionGroups,
+	) -> Result<()> {
+		let transform = self.entity_global_transform(entity)?;
+		let entry = self.ecs.entry_ref(entity)?;
+		let terrain = entry.get_component::<Terrain>()?;
+		terrain
+			.validate()
+			.map_err(WorldError::CreateTerrainCollider)?;
+
+		let heights = DMatrix::from_row_slice(
+			terrain.rows,
+			terrain.columns,
+			&terrain.heights[..terrain.rows * terrain.columns],
+		);
+		let scale = Vector3::new(terrain.size.x, terrain.height_scale, terrain.size.y)
+			.component_mul(&transform.scale);
+		let mut collider = ColliderBuilder::heightfield(heights, scale)
+			.collision_groups(collision_groups)
+			.build();
+		if entry.get_component::<RigidBody>().is_err() {
+			collider.set_position(transform.as_isometry());
+		}
+
+		self.insert_collider(entity, collider)?;
+		Ok(())
+	}
+
 	pub fn add_rigid_body(&mut self, entity: Entity, rigid_body_type: RigidBodyType) -> Result<()> {
 		let handle = {
 			let isometry =
@@ -500,7 +1246,9 @@ impl World {
 			mouse_position,
 		} = *configuration;
 
-		let mut position = mouse_position;
+		// The viewport is a rectangle in window coordinates,
+		// so the mouse position is made relative to it first
+		let mut position = mouse_position - glm::vec2(viewport.x, viewport.y);
 		position.y = viewport.height - position.y;
 
 		let near_point = glm::vec2_to_vec3(&position);
@@ -508,7 +1256,7 @@ impl World {
 		let mut far_point = near_point;
 		far_point.z = 1.0;
 
-		let viewport = viewport.as_glm_vec();
+		let viewport = glm::vec4(0.0, 0.0, viewport.width, viewport.height);
 		let p_near = glm::unproject_zo(&near_point, &view_matrix, &projection_matrix, viewport);
 		let p_far = glm::unproject_zo(&far_point, &view_matrix, &projection_matrix, viewport);
 
@@ -551,9 +1299,389 @@ impl World {
 		Ok(picked_entity)
 	}
 
+	/// Picks the nearest enabled decal whose box is under the mouse
+	pub fn pick_decal(
+		&mut self,
+		mouse_ray_configuration: &MouseRayConfiguration,
+	) -> Result<Option<Entity>> {
+		let ray = self.mouse_ray(mouse_ray_configuration)?;
+		let origin = glm::vec3(ray.origin.x, ray.origin.y, ray.origin.z);
+		let direction = glm::vec3(ray.dir.x, ray.dir.y, ray.dir.z);
+
+		let mut query = <(Entity, &Decal)>::query();
+		let decals = query
+			.iter(&self.ecs)
+			.filter(|(_, decal)| decal.enabled)
+			.map(|(entity, _)| *entity)
+			.collect::<Vec<_>>();
+
+		let mut picked_entity = None;
+		let mut nearest_distance = f32::MAX;
+		for entity in decals {
+			let model = self.entity_global_transform_matrix(entity)?;
+			if let Some(distance) = Decal::ray_intersection(&model, &origin, &direction) {
+				if distance < nearest_distance {
+					nearest_distance = distance;
+					picked_entity = Some(entity);
+				}
+			}
+		}
+
+		Ok(picked_entity)
+	}
+
 	pub fn tick(&mut self, delta_time: f32) -> Result<()> {
 		self.physics.update(delta_time);
 		self.sync_all_rigid_bodies();
+		self.animate_players(delta_time)?;
+		self.animate_graphs(delta_time)?;
+		self.update_global_transforms()?;
+		self.solve_inverse_kinematics()?;
+		Ok(())
+	}
+
+	/// Solves every entity's inverse kinematics constraints against the animated pose
+	pub fn solve_inverse_kinematics(&mut self) -> Result<()> {
+		self.restore_animated_rotations()?;
+
+		let mut query = <(Entity, &InverseKinematics)>::query();
+		let constraints = query
+			.iter(&self.ecs)
+			.flat_map(|(entity, inverse_kinematics)| {
+				inverse_kinematics
+					.constraints
+					.iter()
+					.map(move |constraint| (*entity, constraint.clone()))
+			})
+			.collect::<Vec<_>>();
+
+		for (entity, constraint) in constraints {
+			match constraint {
+				IkConstraint::TwoBone(two_bone) => {
+					let root = self.entity_global_position(two_bone.root)?;
+					let middle = self.entity_global_position(two_bone.middle)?;
+					let end = self.entity_global_position(two_bone.end)?;
+					let pole = two_bone.pole.unwrap_or(middle);
+					let (middle, end) =
+						solve_two_bone(&root, &middle, &end, &two_bone.target, &pole);
+					self.aim_chain(
+						&[two_bone.root, two_bone.middle, two_bone.end],
+						&[root, middle, end],
+						two_bone.weight,
+					)?;
+				}
+				IkConstraint::LookAt(look_at) => {
+					let position = self.entity_global_position(look_at.joint)?;
+					let rotation = self.entity_global_rotation(look_at.joint)?;
+					let forward = glm::quat_rotate_vec3(&rotation, &look_at.forward);
+					let turn = limited_rotation_between(
+						&forward,
+						&(look_at.target - position),
+						look_at.max_angle,
+					);
+					let turn = glm::quat_slerp(&glm::Quat::identity(), &turn, look_at.weight);
+					self.set_ik_rotation(look_at.joint, &(turn * rotation))?;
+				}
+				IkConstraint::Fabrik(fabrik) => {
+					let mut positions = fabrik
+						.joints
+						.iter()
+						.map(|joint| self.entity_global_position(*joint))
+						.collect::<Result<Vec<_>>>()?;
+					solve_fabrik(
+						&mut positions,
+						&fabrik.target,
+						fabrik.iterations,
+						fabrik.tolerance,
+					);
+					self.aim_chain(&fabrik.joints, &positions, fabrik.weight)?;
+				}
+				IkConstraint::Foot(foot) => self.solve_foot(entity, &foot)?,
+			}
+		}
+		Ok(())
+	}
+
+	/// Reaches a foot down or up to the ground under its animated position
+	fn solve_foot(&mut self, entity: Entity, foot: &FootIk) -> Result<()> {
+		let hip = self.entity_global_position(foot.hip)?;
+		let knee = self.entity_global_position(foot.knee)?;
+		let ankle = self.entity_global_position(foot.ankle)?;
+
+		let up = glm::Vec3::y();
+		let origin = ankle + up * foot.ray_height;
+		let ray = Ray::new(Point3::from(origin), -up);
+		let mut filter = QueryFilter::default();
+		if let Ok(rigid_body) = self.ecs.entry_ref(entity)?.get_component::<RigidBody>() {
+			filter = filter.exclude_rigid_body(rigid_body.handle);
+		}
+		let hit = self.physics.query_pipeline.cast_ray(
+			&self.physics.bodies,
+			&self.physics.colliders,
+			&ray,
+			foot.ray_height + foot.max_drop,
+			true,
+			filter,
+		);
+		let distance = match hit {
+			Some((_, distance)) => distance,
+			None => return Ok(()),
+		};
+
+		let target = origin - up * distance + up * foot.foot_height;
+		let (knee_target, ankle_target) = solve_two_bone(&hip, &knee, &ankle, &target, &knee);
+		self.aim_chain(
+			&[foot.hip, foot.knee, foot.ankle],
+			&[hip, knee_target, ankle_target],
+			foot.weight,
+		)
+	}
+
+	/// Turns each joint of a chain so the next joint points at its solved position
+	fn aim_chain(&mut self, joints: &[Entity], positions: &[glm::Vec3], weight: f32) -> Result<()> {
+		for (index, pair) in joints.windows(2).enumerate() {
+			let joint_position = self.entity_global_position(pair[0])?;
+			let child_position = self.entity_global_position(pair[1])?;
+			let turn = rotation_between(
+				&(child_position - joint_position),
+				&(positions[index + 1] - joint_position),
+			);
+			let turn = glm::quat_slerp(&glm::Quat::identity(), &turn, weight);
+			let rotation = self.entity_global_rotation(pair[0])?;
+			self.set_ik_rotation(pair[0], &(turn * rotation))?;
+		}
+		Ok(())
+	}
+
+	/// Turns a joint for inverse kinematics, remembering the rotation it was animated to
+	fn set_ik_rotation(&mut self, joint: Entity, rotation: &glm::Quat) -> Result<()> {
+		let animated = self
+			.ecs
+			.entry_ref(joint)?
+			.get_component::<Transform>()?
+			.rotation;
+		self.set_entity_global_rotation(joint, rotation)?;
+		let solved = self
+			.ecs
+			.entry_ref(joint)?
+			.get_component::<Transform>()?
+			.rotation;
+		self.ik_rotations
+			.entry(joint)
+			.or_insert((animated, solved))
+			.1 = solved;
+		Ok(())
+	}
+
+	/// Puts back the animated rotation of every joint last turned by inverse kinematics
+	/// that still has its solved rotation, because animation didn't pose it again
+	fn restore_animated_rotations(&mut self) -> Result<()> {
+		for (joint, (animated, solved)) in std::mem::take(&mut self.ik_rotations) {
+			let mut entry = match self.ecs.entry(joint) {
+				Some(entry) => entry,
+				None => continue,
+			};
+			match entry.get_component_mut::<Transform>() {
+				Ok(transform) if transform.rotation == solved => transform.rotation = animated,
+				_ => continue,
+			}
+			self.refresh_global_transforms(joint)?;
+		}
+		Ok(())
+	}
+
+	pub fn entity_global_position(&self, entity: Entity) -> Result<glm::Vec3> {
+		let matrix = self.entity_global_transform_matrix(entity)?;
+		Ok(glm::vec3(matrix.m14, matrix.m24, matrix.m34))
+	}
+
+	/// The rotation of an entity in world space, read from its cached global transform,
+	/// or composed from the rotations of it and its ancestors when it hasn't been cached yet
+	pub fn entity_global_rotation(&self, entity: Entity) -> Result<glm::Quat> {
+		let entry = self.ecs.entry_ref(entity)?;
+		if let Ok(global_transform) = entry.get_component::<GlobalTransform>() {
+			return Ok(global_transform.rotation());
+		}
+		let mut rotation = entry.get_component::<Transform>()?.rotation;
+		if let Some((graph_index, node_index)) = self.find_scene_graph_node(entity) {
+			let graph = &self.scene.graphs[graph_index];
+			let mut parent = graph.get_parent_of(node_index);
+			while let Some(parent_index) = parent {
+				let parent_rotation = self
+					.ecs
+					.entry_ref(graph[parent_index])?
+					.get_component::<Transform>()?
+					.rotation;
+				rotation = parent_rotation * rotation;
+				parent = graph.get_parent_of(parent_index);
+			}
+		}
+		Ok(rotation)
+	}
+
+	/// Sets the local rotation that gives an entity a rotation in world space
+	pub fn set_entity_global_rotation(
+		&mut self,
+		entity: Entity,
+		rotation: &glm::Quat,
+	) -> Result<()> {
+		let global_rotation = self.entity_global_rotation(entity)?;
+		let mut entry = self.ecs.entry(entity).ok_or(WorldError::FindEntity)?;
+		let transform = entry.get_component_mut::<Transform>()?;
+		let parent_rotation = global_rotation * glm::quat_inverse(&transform.rotation);
+		transform.rotation = glm::quat_normalize(&(glm::quat_inverse(&parent_rotation) * rotation));
+		self.refresh_global_transforms(entity)
+	}
+
+	/// Advances every animation graph and writes its blended pose
+	pub fn animate_graphs(&mut self, delta_time: f32) -> Result<()> {
+		self.capture_bind_poses()?;
+		let mut poses = Vec::new();
+		let mut root_motions = Vec::new();
+		let mut query = <(Entity, &mut AnimationGraph)>::query();
+		for (entity, graph) in query.iter_mut(&mut self.ecs) {
+			let graph_advance = graph.advance(&self.animations, delta_time);
+			if !graph_advance.root_motion.is_zero() {
+				root_motions.push((*entity, graph_advance.root_motion));
+			}
+			self.animation_events
+				.extend(graph_advance.notifies.into_iter().map(|(clip, name)| {
+					AnimationEvent::Notify {
+						entity: *entity,
+						clip,
+						name,
+					}
+				}));
+			let mut pose = self.graph_poses.take();
+			graph.evaluate_into(&self.animations, &mut pose, &mut self.graph_poses);
+			poses.push(pose);
+		}
+		for pose in poses {
+			let written = pose.write(&mut self.ecs);
+			self.graph_poses.give_back(pose);
+			written.map_err(WorldError::ApplyAnimation)?;
+		}
+		for (entity, root_motion) in root_motions {
+			self.apply_root_motion(entity, &root_motion)?;
+		}
+		Ok(())
+	}
+
+	/// Records the transforms of a graph's joints before it first poses them,
+	/// which its first layer blends from
+	fn capture_bind_poses(&mut self) -> Result<()> {
+		let mut uncaptured = Vec::new();
+		let mut query = <(Entity, &AnimationGraph)>::query();
+		for (entity, graph) in query.iter(&self.ecs) {
+			if graph.bind_pose.is_none() {
+				uncaptured.push((*entity, graph.targets(&self.animations)));
+			}
+		}
+		for (entity, targets) in uncaptured {
+			let bind_pose = Pose::capture(&self.ecs, targets);
+			let mut entry = self.ecs.entry_mut(entity)?;
+			entry.get_component_mut::<AnimationGraph>()?.bind_pose = Some(bind_pose);
+		}
+		Ok(())
+	}
+
+	/// Takes the queued animation events, which gameplay should do every update
+	pub fn drain_animation_events(&mut self) -> Vec<AnimationEvent> {
+		std::mem::take(&mut self.animation_events)
+	}
+
+	pub fn animation_index(&self, name: &str) -> Option<usize> {
+		self.animations
+			.iter()
+			.position(|animation| animation.name == name)
+	}
+
+	/// Advances every animation player and poses its entities,
+	/// queueing crossed notifies and finished clips in `animation_events`
+	pub fn animate_players(&mut self, delta_time: f32) -> Result<()> {
+		let mut poses = Vec::new();
+		let mut root_motions = Vec::new();
+		let mut query = <(Entity, &mut AnimationPlayer)>::query();
+		for (entity, player) in query.iter_mut(&mut self.ecs) {
+			player.clip_index = match player.clip.as_deref() {
+				Some(clip) => find_clip_index(&self.animations, clip, player.clip_index),
+				None => None,
+			};
+			let animation_index = match player.clip_index {
+				Some(index) => index,
+				None => continue,
+			};
+			let animation = &self.animations[animation_index];
+			let duration = animation.max_animation_time;
+			let segments = player.playback_segments(duration, delta_time);
+			let root_motion = segments.root_motion(animation);
+			if !root_motion.is_zero() {
+				root_motions.push((*entity, root_motion));
+			}
+			for notify in segments.notifies(animation) {
+				self.animation_events.push(AnimationEvent::Notify {
+					entity: *entity,
+					clip: animation.name.to_string(),
+					name: notify.name.to_string(),
+				});
+			}
+			let was_stopped = player.state == PlaybackState::Stopped;
+			let finished = player.advance(duration, delta_time);
+			if finished {
+				self.animation_events.push(AnimationEvent::Finished {
+					entity: *entity,
+					clip: animation.name.to_string(),
+				});
+			}
+			if finished || !was_stopped {
+				poses.push((animation_index, player.time));
+			}
+		}
+
+		for (animation_index, time) in poses {
+			self.animations[animation_index].sample_into(time, &mut self.animation_pose);
+			self.animation_pose
+				.write(&mut self.ecs)
+				.map_err(WorldError::ApplyAnimation)?;
+		}
+		for (entity, root_motion) in root_motions {
+			self.apply_root_motion(entity, &root_motion)?;
+		}
+
+		Ok(())
+	}
+
+	/// Moves an entity by root motion in its own frame,
+	/// driving its rigid body instead when it is kinematic
+	pub fn apply_root_motion(&mut self, entity: Entity, root_motion: &RootMotion) -> Result<()> {
+		let mut entry = self.ecs.entry(entity).ok_or(WorldError::FindEntity)?;
+		let rigid_body_handle = entry
+			.get_component::<RigidBody>()
+			.ok()
+			.map(|rigid_body| rigid_body.handle);
+		let transform = entry.get_component_mut::<Transform>()?;
+		let translation = glm::quat_rotate_vec3(
+			&transform.rotation,
+			&root_motion.translation.component_mul(&transform.scale),
+		);
+		let rotation = root_motion.rotation();
+
+		let handle = match rigid_body_handle {
+			Some(handle) => handle,
+			None => {
+				transform.translation += translation;
+				transform.rotation = glm::quat_normalize(&(transform.rotation * rotation));
+				return Ok(());
+			}
+		};
+		if !self
+			.physics
+			.move_kinematic_body(handle, &translation, &rotation)
+		{
+			transform.translation += translation;
+			transform.rotation = glm::quat_normalize(&(transform.rotation * rotation));
+			self.sync_rigid_body_to_transform(entity)?;
+		}
 		Ok(())
 	}
 
@@ -648,7 +1776,42 @@ impl World {
 		Ok(model)
 	}
 
-	pub fn get_metadata(&self) -> Vec<EntityMetadata> {
+	/// Picks each mesh's level of detail from its largest projected size across the views.
+	/// Entities missing from `lod_levels` start at full detail.
+	pub fn select_lods(&self, views: &[CameraView], lod_levels: &mut HashMap<Entity, usize>) {
+		let mut selected_levels = HashMap::new();
+		for graph in self.scene.graphs.iter() {
+			graph
+				.walk(|node_index| {
+					let entity = graph[node_index];
+					let entry = self.ecs.entry_ref(entity)?;
+					let mesh = match entry
+						.get_component::<MeshRender>()
+						.map(|mesh_render| self.geometry.meshes.get(&mesh_render.name))
+					{
+						Ok(Some(mesh)) if !mesh.lods.is_empty() => mesh,
+						_ => return Ok(()),
+					};
+					let bias = entry
+						.get_component::<LodBias>()
+						.map_or(1.0, |lod_bias| lod_bias.0);
+
+					let model = self.global_transform(graph, node_index)?;
+					let screen_size = views
+						.iter()
+						.map(|view| mesh.screen_size(&model, view))
+						.fold(0.0, f32::max);
+					let current_level = lod_levels.get(&entity).copied().unwrap_or_default();
+					selected_levels
+						.insert(entity, mesh.select_lod(screen_size * bias, current_level));
+					Ok(())
+				})
+				.unwrap();
+		}
+		*lod_levels = selected_levels;
+	}
+
+	pub fn get_metadata(&self, lod_levels: &HashMap<Entity, usize>) -> Vec<EntityMetadata> {
 		let mut metadata = Vec::new();
 		let mut offset = -1;
 		for graph in self.scene.graphs.iter() {
@@ -663,12 +1826,29 @@ impl World {
 						.get_component::<MeshRender>()
 						.map(|mesh_render| self.geometry.meshes.get(&mesh_render.name));
 					if let Ok(Some(mesh)) = mesh_result {
-						for primitive in mesh.primitives.iter() {
+						let level = lod_levels.get(&entity).copied().unwrap_or_default();
+						let entity_material = entry
+							.get_component::<CustomMaterialRender>()
+							.ok()
+							.and_then(|material| self.custom_material_index(&material.name));
+						let skinned = entry.get_component::<Skin>().is_ok();
+						for primitive in mesh.lod_primitives(level).iter() {
 							let start = primitive.first_index as u32;
+							let custom_material = entity_material.or_else(|| {
+								primitive
+									.custom_material
+									.as_ref()
+									.and_then(|name| self.custom_material_index(name))
+							});
 							metadata.push(EntityMetadata {
+								entity,
 								index_range: start
 									..(primitive.first_index + primitive.number_of_indices) as u32,
 								offset: offset as _,
+								bounding_box: primitive.bounding_box.clone(),
+								material: primitive.material_index,
+								custom_material,
+								skinned,
 							});
 						}
 					}
@@ -681,7 +1861,7 @@ impl World {
 	}
 }
 
-#[derive(Default, Copy, Clone)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Viewport {
 	pub x: f32,
 	pub y: f32,
@@ -690,6 +1870,56 @@ pub struct Viewport {
 }
 
 impl Viewport {
+	/// A normalized viewport covering an entire render target
+	pub fn full() -> Self {
+		Self {
+			x: 0.0,
+			y: 0.0,
+			width: 1.0,
+			height: 1.0,
+		}
+	}
+
+	/// Converts a normalized viewport to pixels on a target of the given size.
+	/// Edges are rounded to whole pixels, so viewports that share an edge stay adjacent.
+	pub fn scaled(&self, width: f32, height: f32) -> Self {
+		let left = (self.x * width).round();
+		let top = (self.y * height).round();
+		let right = ((self.x + self.width) * width).round();
+		let bottom = ((self.y + self.height) * height).round();
+		Self {
+			x: left,
+			y: top,
+			width: (right - left).max(0.0),
+			height: (bottom - top).max(0.0),
+		}
+	}
+
+	/// Splits a normalized viewport into equally sized columns and rows,
+	/// ordered left to right and top to bottom
+	pub fn split(&self, columns: u32, rows: u32) -> Vec<Self> {
+		let columns = columns.max(1);
+		let rows = rows.max(1);
+		// Edges are computed from their proportions so the last cell ends exactly on the edge
+		let edge = |start: f32, length: f32, index: u32, count: u32| {
+			start + length * index as f32 / count as f32
+		};
+		(0..rows)
+			.flat_map(|row| {
+				(0..columns).map(move |column| {
+					let x = edge(self.x, self.width, column, columns);
+					let y = edge(self.y, self.height, row, rows);
+					Self {
+						x,
+						y,
+						width: edge(self.x, self.width, column + 1, columns) - x,
+						height: edge(self.y, self.height, row + 1, rows) - y,
+					}
+				})
+			})
+			.collect()
+	}
+
 	pub fn aspect_ratio(&self) -> f32 {
 		let height = if self.height > 0.0 { self.height } else { 1.0 };
 		self.width / height
@@ -698,6 +1928,22 @@ impl Viewport {
 	pub fn as_glm_vec(&self) -> glm::Vec4 {
 		glm::vec4(self.x, self.y, self.width, self.height)
 	}
+
+	pub fn contains(&self, position: &glm::Vec2) -> bool {
+		position.x >= self.x
+			&& position.y >= self.y
+			&& position.x < self.x + self.width
+			&& position.y < self.y + self.height
+	}
+}
+
+pub struct CameraView {
+	pub entity: Entity,
+	pub target: RenderTarget,
+	pub viewport: Viewport,
+	pub projection: glm::Mat4,
+	pub view: glm::Mat4,
+	pub position: glm::Vec3,
 }
 
 pub struct MouseRayConfiguration {
@@ -712,6 +1958,7 @@ pub struct Scene {
 	pub name: String,
 	pub graphs: Vec<EntitySceneGraph>,
 	pub skybox: Option<usize>,
+	pub environment: Environment,
 }
 
 impl Default for Scene {
@@ -720,6 +1967,7 @@ impl Default for Scene {
 			name: "Unnamed Scene".to_string(),
 			graphs: vec![EntitySceneGraph::default()],
 			skybox: None,
+			environment: Environment::default(),
 		}
 	}
 }
@@ -754,13 +2002,13 @@ impl Default for LightKind {
 	}
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Skin {
 	pub name: String,
 	pub joints: Vec<Joint>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Joint {
 	pub target: Entity,
 	pub inverse_bind_matrix: glm::Mat4,
@@ -776,6 +2024,8 @@ pub struct Mesh {
 	pub name: String,
 	pub primitives: Vec<Primitive>,
 	pub weights: Vec<f32>,
+	/// Lower levels of detail, ordered from the most to the least detailed
+	pub lods: Vec<MeshLod>,
 }
 
 impl Mesh {
@@ -842,6 +2092,8 @@ pub struct Primitive {
 	pub number_of_vertices: usize,
 	pub number_of_indices: usize,
 	pub material_index: Option<usize>,
+	/// The name of a custom material drawn instead of the glTF material
+	pub custom_material: Option<String>,
 	pub morph_targets: Vec<MorphTarget>,
 	pub bounding_box: BoundingBox,
 }
@@ -883,6 +2135,8 @@ pub struct Vertex {
 	pub joint_0: glm::Vec4,
 	pub weight_0: glm::Vec4,
 	pub color_0: glm::Vec3,
+	/// The W component is the handedness of the bitangent
+	pub tangent: glm::Vec4,
 }
 
 impl Default for Vertex {
@@ -895,6 +2149,7 @@ impl Default for Vertex {
 			joint_0: glm::Vec4::default(),
 			weight_0: glm::Vec4::default(),
 			color_0: glm::vec3(1.0, 1.0, 1.0),
+			tangent: glm::vec4(1.0, 0.0, 0.0, 1.0),
 		}
 	}
 }
@@ -918,6 +2173,382 @@ impl SdfFont {
 
 #[derive(Default)]
 pub struct EntityMetadata {
+	pub entity: Entity,
 	pub index_range: Range<u32>,
 	pub offset: u32,
+	pub bounding_box: BoundingBox,
+	/// Index into the world's materials
+	pub material: Option<usize>,
+	/// Index into the world's custom materials
+	pub custom_material: Option<usize>,
+	pub skinned: bool,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{test_clip, AnimationLayer, AnimationState, BoneMask, Motion, TwoBoneIk};
+
+	fn translated(x: f32, y: f32, z: f32) -> Transform {
+		Transform {
+			translation: glm::vec3(x, y, z),
+			..Default::default()
+		}
+	}
+
+	/// A parent at x = 1 with a child two units above it, which has a rigid body and a collider
+	fn hierarchy() -> (World, Entity, Entity) {
+		let mut world = World::default();
+		let parent = world.ecs.push((translated(1.0, 0.0, 0.0),));
+		world.scene.graphs[0].add_root_node(parent);
+		let child = world
+			.spawn_child(parent, (translated(0.0, 2.0, 0.0),))
+			.unwrap();
+		world.add_rigid_body(child, RigidBodyType::Dynamic).unwrap();
+		world
+			.insert_collider(child, ColliderBuilder::ball(0.5).build())
+			.unwrap();
+		(world, parent, child)
+	}
+
+	#[test]
+	fn split_viewports_tile_the_original() {
+		let viewport = Viewport {
+			x: 0.1,
+			y: 0.2,
+			width: 0.7,
+			height: 0.5,
+		};
+		let cells = viewport.split(3, 2);
+		assert_eq!(cells.len(), 6);
+		let adjacent = |first: f32, second: f32| (first - second).abs() < 1e-6;
+		for pair in cells.windows(2).filter(|pair| pair[0].y == pair[1].y) {
+			assert!(adjacent(pair[0].x + pair[0].width, pair[1].x));
+		}
+		let last = cells[5];
+		assert!(adjacent(last.x + last.width, viewport.x + viewport.width));
+		assert!(adjacent(last.y + last.height, viewport.y + viewport.height));
+		assert!(adjacent(cells[3].y, cells[0].y + cells[0].height));
+	}
+
+	#[test]
+	fn split_clamps_to_one_cell() {
+		let cells = Viewport::full().split(0, 0);
+		assert_eq!(cells.len(), 1);
+		assert_eq!(cells[0].as_glm_vec(), Viewport::full().as_glm_vec());
+	}
+
+	#[test]
+	fn scaled_viewports_round_to_shared_pixel_edges() {
+		let cells = Viewport::full().split(3, 1);
+		let scaled = cells
+			.iter()
+			.map(|cell| cell.scaled(1001.0, 600.0))
+			.collect::<Vec<_>>();
+		assert_eq!(scaled.iter().map(|cell| cell.width).sum::<f32>(), 1001.0);
+		for pair in scaled.windows(2) {
+			assert_eq!(pair[0].x + pair[0].width, pair[1].x);
+		}
+		assert!(scaled
+			.iter()
+			.all(|cell| cell.x.fract() == 0.0 && cell.width.fract() == 0.0));
+		assert_eq!(scaled[0].height, 600.0);
+
+		let empty = Viewport {
+			width: 0.0,
+			..Viewport::full()
+		}
+		.scaled(800.0, 600.0);
+		assert_eq!(empty.width, 0.0);
+		assert_eq!(Viewport::full().scaled(0.0, 0.0).height, 0.0);
+	}
+
+	#[test]
+	fn reparenting_keeps_or_moves_the_global_transform() {
+		let (mut world, parent, child) = hierarchy();
+		let other = world.ecs.push((translated(0.0, 0.0, 5.0),));
+		world.scene.graphs.push(EntitySceneGraph::new());
+		world.scene.graphs[1].add_root_node(other);
+
+		world.reparent(child, Some(other), true).unwrap();
+		assert_eq!(world.find_scene_graph_node(child).unwrap().0, 1);
+		let position = world.entity_global_position(child).unwrap();
+		assert!(glm::distance(&position, &glm::vec3(1.0, 2.0, 0.0)) < 1e-5);
+
+		world.reparent(child, Some(parent), false).unwrap();
+		let position = world.entity_global_position(child).unwrap();
+		assert!(glm::distance(&position, &glm::vec3(2.0, 2.0, -5.0)) < 1e-5);
+		let handle = world
+			.ecs
+			.entry_ref(child)
+			.unwrap()
+			.get_component::<RigidBody>()
+			.unwrap()
+			.handle;
+		let body_position = world.physics.bodies[handle].translation();
+		assert!(glm::distance(body_position, &position) < 1e-5);
+
+		assert!(world.reparent(parent, Some(child), false).is_err());
+	}
+
+	#[test]
+	fn duplicating_copies_the_hierarchy_and_its_physics() {
+		let (mut world, parent, child) = hierarchy();
+		let copy = world.duplicate_subtree(parent).unwrap();
+		assert_ne!(copy, parent);
+		assert_eq!(world.scene.graphs[0].number_of_nodes(), 4);
+
+		let copied_children = world.entity_subtree(copy);
+		assert_eq!(copied_children.len(), 2);
+		assert!(!copied_children.contains(&child));
+		assert_eq!(world.physics.bodies.len(), 2);
+		assert_eq!(world.physics.colliders.len(), 2);
+	}
+
+	#[test]
+	fn global_transforms_refresh_when_transforms_are_written() {
+		let (mut world, parent, child) = hierarchy();
+		world.update_global_transforms().unwrap();
+		let position = |world: &World| world.entity_global_position(child).unwrap();
+		assert!(glm::distance(&position(&world), &glm::vec3(1.0, 2.0, 0.0)) < 1e-5);
+
+		world
+			.set_entity_transform(parent, translated(3.0, 0.0, 0.0))
+			.unwrap();
+		assert!(glm::distance(&position(&world), &glm::vec3(3.0, 2.0, 0.0)) < 1e-5);
+
+		world
+			.ecs
+			.entry_mut(parent)
+			.unwrap()
+			.get_component_mut::<Transform>()
+			.unwrap()
+			.translation
+			.x = 5.0;
+		world.update_global_transforms().unwrap();
+		assert!(glm::distance(&position(&world), &glm::vec3(5.0, 2.0, 0.0)) < 1e-5);
+
+		let rotation = glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &glm::Vec3::z());
+		world.set_entity_global_rotation(parent, &rotation).unwrap();
+		assert!(glm::distance(&position(&world), &glm::vec3(3.0, 0.0, 0.0)) < 1e-5);
+	}
+
+	#[test]
+	fn updates_only_recompute_changed_subtrees() {
+		let (mut world, parent, child) = hierarchy();
+		world.update_global_transforms().unwrap();
+		let cached_matrix = |world: &World, entity: Entity| {
+			world
+				.ecs
+				.entry_ref(entity)
+				.unwrap()
+				.get_component::<GlobalTransform>()
+				.unwrap()
+				.matrix
+		};
+
+		// A cache no update would have written, which is left alone while nothing changes
+		let marker = glm::translation(&glm::vec3(0.0, 100.0, 0.0));
+		world
+			.ecs
+			.entry_mut(parent)
+			.unwrap()
+			.get_component_mut::<GlobalTransform>()
+			.unwrap()
+			.matrix = marker;
+		world.update_global_transforms().unwrap();
+		assert_eq!(cached_matrix(&world, parent), marker);
+
+		world
+			.ecs
+			.entry_mut(child)
+			.unwrap()
+			.get_component_mut::<Transform>()
+			.unwrap()
+			.translation
+			.y = 4.0;
+		world.update_global_transforms().unwrap();
+		assert_eq!(cached_matrix(&world, parent), marker);
+		let position = world.entity_global_position(child).unwrap();
+		assert!(glm::distance(&position, &glm::vec3(0.0, 104.0, 0.0)) < 1e-5);
+	}
+
+	/// A straight leg hanging from a hip at a height, with joints one unit apart
+	fn leg(world: &mut World, height: f32) -> (Entity, Entity, Entity) {
+		let hip = world.ecs.push((translated(0.0, height, 0.0),));
+		world.scene.graphs[0].add_root_node(hip);
+		let knee = world
+			.spawn_child(hip, (translated(0.0, -1.0, 0.0),))
+			.unwrap();
+		let ankle = world
+			.spawn_child(knee, (translated(0.0, -1.0, 0.0),))
+			.unwrap();
+		(hip, knee, ankle)
+	}
+
+	#[test]
+	fn two_bone_ik_reaches_its_target_from_the_animated_pose() {
+		let mut world = World::default();
+		let (root, middle, end) = leg(&mut world, 2.0);
+		let target = glm::vec3(1.0, 1.0, 0.0);
+		let two_bone = |weight| {
+			IkConstraint::TwoBone(TwoBoneIk {
+				root,
+				middle,
+				end,
+				target,
+				pole: Some(glm::vec3(-1.0, 2.0, 0.0)),
+				weight,
+			})
+		};
+		let character = world.ecs.push((InverseKinematics {
+			constraints: vec![two_bone(1.0)],
+		},));
+		for _ in 0..3 {
+			world.tick(0.1).unwrap();
+			let position = world.entity_global_position(end).unwrap();
+			assert!(glm::distance(&position, &target) < 1e-3);
+		}
+
+		// Partly weighted solves blend from the animated pose rather than the last solve
+		world
+			.ecs
+			.entry_mut(character)
+			.unwrap()
+			.get_component_mut::<InverseKinematics>()
+			.unwrap()
+			.constraints = vec![two_bone(0.5)];
+		world.tick(0.1).unwrap();
+		let first = world.entity_global_position(end).unwrap();
+		assert!(glm::distance(&first, &target) > 0.1);
+		for _ in 0..3 {
+			world.tick(0.1).unwrap();
+			let position = world.entity_global_position(end).unwrap();
+			assert!(glm::distance(&position, &first) < 1e-4);
+		}
+	}
+
+	#[test]
+	fn foot_ik_plants_the_foot_on_colliders() {
+		let mut world = World::default();
+		let (hip, knee, ankle) = leg(&mut world, 2.0);
+		let ground = world.ecs.push((translated(0.0, 0.25, 0.0),));
+		world.scene.graphs[0].add_root_node(ground);
+		world.add_rigid_body(ground, RigidBodyType::Fixed).unwrap();
+		world
+			.insert_collider(ground, ColliderBuilder::cuboid(5.0, 0.25, 5.0).build())
+			.unwrap();
+		world.ecs.push((InverseKinematics {
+			constraints: vec![IkConstraint::Foot(FootIk {
+				hip,
+				knee,
+				ankle,
+				ray_height: 1.0,
+				max_drop: 0.5,
+				foot_height: 0.1,
+				weight: 1.0,
+			})],
+		},));
+
+		for _ in 0..2 {
+			world.tick(0.1).unwrap();
+			let position = world.entity_global_position(ankle).unwrap();
+			assert!(glm::distance(&position, &glm::vec3(0.0, 0.6, 0.0)) < 1e-3);
+		}
+	}
+
+	#[test]
+	fn despawning_an_animated_subtree_prunes_its_clips() {
+		let (mut world, parent, child) = hierarchy();
+		world
+			.animations
+			.push(test_clip("Wave", &[parent, child], 1.0));
+		world.animations.push(test_clip("Blink", &[child], 1.0));
+		let mut player = AnimationPlayer::new(vec!["Wave".to_string()]);
+		player.play("Wave").unwrap();
+		let graph = AnimationGraph::new(vec![AnimationLayer::new(
+			"Base",
+			vec![AnimationState::new(
+				"Wave",
+				Motion::Clip("Wave".to_string()),
+			)],
+		)]);
+		let character = world.ecs.push((Transform::default(), player, graph));
+		world.scene.graphs[0].add_root_node(character);
+		world.tick(0.1).unwrap();
+
+		world.despawn(child).unwrap();
+		assert_eq!(world.animations.len(), 1);
+		assert!(world.animations[0]
+			.channels
+			.iter()
+			.all(|channel| channel.target == parent));
+		world.tick(0.1).unwrap();
+	}
+
+	#[test]
+	fn despawning_a_joint_prunes_what_refers_to_it() {
+		let mut world = World::default();
+		let (hip, knee, ankle) = leg(&mut world, 2.0);
+		let knee_bind_matrix = glm::translation(&glm::vec3(0.0, -1.0, 0.0));
+		let skin = Skin {
+			name: "Leg".to_string(),
+			joints: vec![
+				Joint {
+					target: knee,
+					inverse_bind_matrix: knee_bind_matrix,
+				},
+				Joint {
+					target: ankle,
+					inverse_bind_matrix: glm::Mat4::identity(),
+				},
+			],
+		};
+		let mut layer = AnimationLayer::new("Base", Vec::new());
+		layer.mask = Some(BoneMask::new(vec![knee, ankle]));
+		let character = world.ecs.push((
+			Transform::default(),
+			skin,
+			AnimationGraph::new(vec![layer]),
+			InverseKinematics {
+				constraints: vec![IkConstraint::TwoBone(TwoBoneIk {
+					root: hip,
+					middle: knee,
+					end: ankle,
+					target: glm::vec3(1.0, 1.0, 0.0),
+					pole: None,
+					weight: 1.0,
+				})],
+			},
+		));
+		world.scene.graphs[0].add_root_node(character);
+		world.tick(0.1).unwrap();
+
+		world.despawn(ankle).unwrap();
+		world.tick(0.1).unwrap();
+		let entry = world.ecs.entry_ref(character).unwrap();
+		assert!(entry
+			.get_component::<InverseKinematics>()
+			.unwrap()
+			.constraints
+			.is_empty());
+		let joints = &entry.get_component::<Skin>().unwrap().joints;
+		assert_eq!(joints.len(), 2);
+		assert_eq!(joints[1].target, knee);
+		assert_eq!(joints[1].inverse_bind_matrix, knee_bind_matrix);
+		let graph = entry.get_component::<AnimationGraph>().unwrap();
+		assert_eq!(graph.layers[0].mask.as_ref().unwrap().entities, vec![knee]);
+		world.joint_matrices().unwrap();
+	}
+
+	#[test]
+	fn despawning_removes_the_hierarchy_and_its_physics() {
+		let (mut world, parent, child) = hierarchy();
+		world.despawn(parent).unwrap();
+		assert!(world.ecs.entry_ref(parent).is_err());
+		assert!(world.ecs.entry_ref(child).is_err());
+		assert_eq!(world.scene.graphs[0].number_of_nodes(), 0);
+		assert_eq!(world.physics.bodies.len(), 0);
+		assert_eq!(world.physics.colliders.len(), 0);
+	}
 }